@@ -26,6 +26,7 @@ set -ex
   cargo build --no-default-features --features=dpe_profile_p384_sha384
   cargo build --release
   cargo test
+  cargo test --features=openssl
   cargo test --no-default-features --features=dpe_profile_p384_sha384
   cargo fmt -- --check
   cargo clippy -- --deny=warnings
@@ -40,6 +41,22 @@ set -ex
   cargo fmt -- --check
   cargo clippy -- --deny=warnings
 )
+( cd client
+  cargo build
+  cargo build --no-default-features --features=dpe_profile_p384_sha384
+  cargo test
+  cargo fmt -- --check
+  cargo clippy -- --deny=warnings
+)
+( cd conformance
+  cargo build
+  cargo build --no-default-features --features=dpe_profile_p384_sha384
+  cargo test
+  cargo test --no-default-features --features=dpe_profile_p384_sha384
+  cargo fmt -- --check
+  cargo clippy -- --deny=warnings
+  cargo run -- --simulator ../simulator/target/debug/simulator
+)
 ( cd verification
   test -z "$(gofmt -l .)"
   go test
@@ -0,0 +1,567 @@
+/*++
+Licensed under the Apache-2.0 license.
+
+Abstract:
+    Typed client for DPE instances.
+--*/
+
+//! Host side client for DPE.
+//!
+//! A `Client` serializes commands, sends them through a `Transport` and checks that each response
+//! has the right magic, profile and status before handing it back as the response type of the
+//! command. The `transport` module has transports for the simulator socket and for a `DpeInstance`
+//! in the same process, so firmware tests and host agents can share the same client.
+
+use dpe::{
+    commands::{
+        BatchCmd, CertifyKeyCmd, Command, DeriveChildCmd, DestroyCtxCmd, ExtendTciCmd,
+        GetCertificateChainCmd, GetEatCmd, GetEventLogCmd, GetTaggedTciCmd, GetTaggedTciInfoCmd,
+        GetTaggedTciInfoFlags, InitCtxCmd, QuoteCmd, RotateCtxCmd, SealCmd, SignCmd,
+        SignMessageCmd, SignStreamFinalCmd, SignStreamInitCmd, SignStreamUpdateCmd, TagTciCmd,
+        UnsealCmd,
+    },
+    response::{
+        BatchResp, CertifyKeyResp, DeriveChildResp, DpeErrorCode, GetCertificateChainResp,
+        GetContextTableResp, GetEatResp, GetEventLogResp, GetLocalityQuotaResp, GetProfileResp,
+        GetTaggedTciInfoResp, GetTaggedTciResp, NewHandleResp, QuoteResp, Response, SealResp,
+        SignResp, SignStreamFinalResp, UnsealResp,
+    },
+    DPE_PROFILE,
+};
+use platform::MAX_CHUNK_SIZE;
+use std::fmt;
+
+pub mod transport;
+
+pub use transport::{InProcessTransport, SocketTransport, Transport};
+
+/// Largest serialized command the client sends, which is the size of the simulator's request
+/// buffer.
+const MAX_COMMAND_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The transport failed to deliver the command or the response.
+    Transport(std::io::Error),
+    /// The command could not be serialized.
+    InvalidCommand(DpeErrorCode),
+    /// The response could not be parsed.
+    InvalidResponse(DpeErrorCode),
+    /// DPE returned an error status.
+    Status(u32),
+    /// The response is for a different profile than the client was built for.
+    ProfileMismatch(u32),
+    /// The response parsed, but not as the response to the command that was sent.
+    UnexpectedResponse,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(err) => write!(f, "transport error: {err}"),
+            ClientError::InvalidCommand(err) => write!(f, "invalid command: {err:?}"),
+            ClientError::InvalidResponse(err) => write!(f, "invalid response: {err:?}"),
+            ClientError::Status(status) => write!(f, "DPE returned status {status:#x}"),
+            ClientError::ProfileMismatch(profile) => {
+                write!(f, "response is for profile {profile:#x}")
+            }
+            ClientError::UnexpectedResponse => write!(f, "unexpected response type"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<std::io::Error> for ClientError {
+    fn from(err: std::io::Error) -> Self {
+        ClientError::Transport(err)
+    }
+}
+
+pub struct Client<T: Transport> {
+    transport: T,
+    locality: u32,
+}
+
+impl<T: Transport> Client<T> {
+    /// Creates a client that sends commands through `transport` from `locality`.
+    pub fn new(transport: T, locality: u32) -> Self {
+        Client {
+            transport,
+            locality,
+        }
+    }
+
+    pub fn locality(&self) -> u32 {
+        self.locality
+    }
+
+    /// Sends the following commands from `locality`.
+    pub fn set_locality(&mut self, locality: u32) {
+        self.locality = locality;
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Sends `command` and returns its response.
+    ///
+    /// Any response with a non-zero status is returned as `ClientError::Status`, including a
+    /// Batch response whose status was set by one of its entries.
+    pub fn execute(&mut self, command: &Command) -> Result<Response, ClientError> {
+        let mut cmd = [0u8; MAX_COMMAND_SIZE];
+        let size = command
+            .serialize(&mut cmd)
+            .map_err(ClientError::InvalidCommand)?;
+        let bytes = self.transport.send(self.locality, &cmd[..size])?;
+
+        let response =
+            Response::deserialize(command.into(), &bytes).map_err(ClientError::InvalidResponse)?;
+        let resp_hdr = response.resp_hdr();
+        if resp_hdr.profile != DPE_PROFILE as u32 {
+            return Err(ClientError::ProfileMismatch(resp_hdr.profile));
+        }
+        if resp_hdr.status != DpeErrorCode::NoError as u32 {
+            return Err(ClientError::Status(resp_hdr.status));
+        }
+        Ok(response)
+    }
+
+    pub fn get_profile(&mut self) -> Result<GetProfileResp, ClientError> {
+        match self.execute(&Command::GetProfile)? {
+            Response::GetProfile(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn initialize_context(&mut self, cmd: InitCtxCmd) -> Result<NewHandleResp, ClientError> {
+        match self.execute(&Command::InitCtx(cmd))? {
+            Response::InitCtx(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Derives a child context. Use `execute` to export the CDI, which has its own response.
+    pub fn derive_child(&mut self, cmd: DeriveChildCmd) -> Result<DeriveChildResp, ClientError> {
+        match self.execute(&Command::DeriveChild(cmd))? {
+            Response::DeriveChild(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn certify_key(&mut self, cmd: CertifyKeyCmd) -> Result<CertifyKeyResp, ClientError> {
+        match self.execute(&Command::CertifyKey(cmd))? {
+            Response::CertifyKey(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn sign(&mut self, cmd: SignCmd) -> Result<SignResp, ClientError> {
+        match self.execute(&Command::Sign(cmd))? {
+            Response::Sign(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn rotate_context_handle(
+        &mut self,
+        cmd: RotateCtxCmd,
+    ) -> Result<NewHandleResp, ClientError> {
+        match self.execute(&Command::RotateCtx(cmd))? {
+            Response::RotateCtx(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn destroy_context(&mut self, cmd: DestroyCtxCmd) -> Result<(), ClientError> {
+        match self.execute(&Command::DestroyCtx(cmd))? {
+            Response::DestroyCtx(_) => Ok(()),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn extend_tci(&mut self, cmd: ExtendTciCmd) -> Result<NewHandleResp, ClientError> {
+        match self.execute(&Command::ExtendTci(cmd))? {
+            Response::ExtendTci(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn tag_tci(&mut self, cmd: TagTciCmd) -> Result<NewHandleResp, ClientError> {
+        match self.execute(&Command::TagTci(cmd))? {
+            Response::TagTci(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn get_tagged_tci(&mut self, tag: u32) -> Result<GetTaggedTciResp, ClientError> {
+        match self.execute(&Command::GetTaggedTci(GetTaggedTciCmd { tag }))? {
+            Response::GetTaggedTci(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Reads `size` bytes of the certificate chain starting at `offset`.
+    pub fn get_certificate_chain(
+        &mut self,
+        offset: u32,
+        size: u32,
+    ) -> Result<GetCertificateChainResp, ClientError> {
+        match self.execute(&Command::GetCertificateChain(GetCertificateChainCmd {
+            offset,
+            size,
+        }))? {
+            Response::GetCertificateChain(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Reads the whole certificate chain, one chunk at a time.
+    pub fn get_full_certificate_chain(&mut self) -> Result<Vec<u8>, ClientError> {
+        let mut chain = vec![];
+        loop {
+            let resp = match self.get_certificate_chain(chain.len() as u32, MAX_CHUNK_SIZE as u32) {
+                Ok(resp) => resp,
+                // A chain that is a multiple of the chunk size ends with a full chunk, and DPE
+                // refuses to read past the end.
+                Err(ClientError::Status(_)) if !chain.is_empty() => return Ok(chain),
+                Err(err) => return Err(err),
+            };
+            let chunk = resp
+                .certificate_chain
+                .get(..resp.certificate_size as usize)
+                .ok_or(ClientError::InvalidResponse(DpeErrorCode::InvalidArgument))?;
+            chain.extend_from_slice(chunk);
+            if chunk.len() < MAX_CHUNK_SIZE {
+                return Ok(chain);
+            }
+        }
+    }
+
+    pub fn seal(&mut self, cmd: SealCmd) -> Result<SealResp, ClientError> {
+        match self.execute(&Command::Seal(cmd))? {
+            Response::Seal(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn unseal(&mut self, cmd: UnsealCmd) -> Result<UnsealResp, ClientError> {
+        match self.execute(&Command::Unseal(cmd))? {
+            Response::Unseal(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn sign_message(&mut self, cmd: SignMessageCmd) -> Result<SignResp, ClientError> {
+        match self.execute(&Command::SignMessage(cmd))? {
+            Response::Sign(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn sign_stream_init(
+        &mut self,
+        cmd: SignStreamInitCmd,
+    ) -> Result<NewHandleResp, ClientError> {
+        match self.execute(&Command::SignStreamInit(cmd))? {
+            Response::SignStreamInit(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn sign_stream_update(
+        &mut self,
+        cmd: SignStreamUpdateCmd,
+    ) -> Result<NewHandleResp, ClientError> {
+        match self.execute(&Command::SignStreamUpdate(cmd))? {
+            Response::SignStreamUpdate(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn sign_stream_final(
+        &mut self,
+        cmd: SignStreamFinalCmd,
+    ) -> Result<SignStreamFinalResp, ClientError> {
+        match self.execute(&Command::SignStreamFinal(cmd))? {
+            Response::SignStreamFinal(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn get_tagged_tci_info(
+        &mut self,
+        tag: u32,
+        flags: GetTaggedTciInfoFlags,
+    ) -> Result<GetTaggedTciInfoResp, ClientError> {
+        match self.execute(&Command::GetTaggedTciInfo(GetTaggedTciInfoCmd {
+            tag,
+            flags,
+        }))? {
+            Response::GetTaggedTciInfo(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn get_context_table(&mut self) -> Result<GetContextTableResp, ClientError> {
+        match self.execute(&Command::GetContextTable)? {
+            Response::GetContextTable(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Sends a batch of commands.
+    ///
+    /// A batch that stopped at a failing entry is returned as `ClientError::Status`, like any
+    /// other response with a non-zero status.
+    pub fn batch(&mut self, cmd: BatchCmd) -> Result<BatchResp, ClientError> {
+        match self.execute(&Command::Batch(cmd))? {
+            Response::Batch(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Returns the quota of the client's locality.
+    pub fn get_locality_quota(&mut self) -> Result<GetLocalityQuotaResp, ClientError> {
+        match self.execute(&Command::GetLocalityQuota)? {
+            Response::GetLocalityQuota(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Reads `size` bytes of the event log starting at `offset`.
+    pub fn get_event_log(
+        &mut self,
+        offset: u32,
+        size: u32,
+    ) -> Result<GetEventLogResp, ClientError> {
+        match self.execute(&Command::GetEventLog(GetEventLogCmd { offset, size }))? {
+            Response::GetEventLog(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Reads the whole event log, one chunk at a time.
+    ///
+    /// Starts over if the oldest events are dropped while reading, since that moves the offsets.
+    pub fn get_full_event_log(&mut self) -> Result<Vec<u8>, ClientError> {
+        'restart: loop {
+            // An empty read returns the size of the log.
+            let resp = self.get_event_log(0, 0)?;
+            let log_size = resp.log_size as usize;
+            let mut log = vec![];
+            while log.len() < log_size {
+                let size = (log_size - log.len()).min(resp.chunk.len());
+                let chunk = self.get_event_log(log.len() as u32, size as u32)?;
+                if chunk.first_recnum != resp.first_recnum {
+                    continue 'restart;
+                }
+                let chunk = chunk
+                    .chunk
+                    .get(..chunk.chunk_size as usize)
+                    .filter(|chunk| !chunk.is_empty())
+                    .ok_or(ClientError::InvalidResponse(DpeErrorCode::InvalidArgument))?;
+                log.extend_from_slice(chunk);
+            }
+            return Ok(log);
+        }
+    }
+
+    pub fn quote(&mut self, cmd: QuoteCmd) -> Result<QuoteResp, ClientError> {
+        match self.execute(&Command::Quote(cmd))? {
+            Response::Quote(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub fn get_eat(&mut self, cmd: GetEatCmd) -> Result<GetEatResp, ClientError> {
+        match self.execute(&Command::GetEat(cmd))? {
+            Response::GetEat(resp) => Ok(resp),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::OpensslCrypto;
+    use dpe::{
+        commands::{CertifyKeyFlags, DeriveChildFlags, DestroyCtxFlags, SignFlags},
+        context::ContextHandle,
+        dpe_instance::{DpeEnv, DpeTypes},
+        response::ResponseHdr,
+        support::Support,
+        DpeInstance,
+    };
+    use platform::{default::DefaultPlatform, Platform};
+
+    pub struct TestTypes;
+
+    impl DpeTypes for TestTypes {
+        type Crypto<'a> = OpensslCrypto;
+        type Platform<'a> = DefaultPlatform;
+        type EventSink<'a> = ();
+    }
+
+    const OTHER_LOCALITY: u32 = u32::from_be_bytes(*b"OTHR");
+
+    fn new_client(support: Support) -> Client<InProcessTransport<'static, TestTypes>> {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let dpe = DpeInstance::new(&mut env, support).unwrap();
+        Client::new(InProcessTransport::new(dpe, env), 0)
+    }
+
+    #[test]
+    fn test_in_process_client() {
+        let mut client = new_client(Support::AUTO_INIT | Support::X509 | Support::SIMULATION);
+
+        let profile = client.get_profile().unwrap();
+        assert_eq!(DPE_PROFILE as u32, profile.resp_hdr.profile);
+        assert_eq!(
+            (Support::AUTO_INIT | Support::X509 | Support::SIMULATION).bits(),
+            profile.flags
+        );
+
+        let derive = client
+            .derive_child(DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [1; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::MAKE_DEFAULT | DeriveChildFlags::INPUT_ALLOW_X509,
+                tci_type: 7,
+                target_locality: 0,
+            })
+            .unwrap();
+        assert_eq!(ContextHandle::default(), derive.handle);
+
+        let certify = client
+            .certify_key(CertifyKeyCmd {
+                handle: ContextHandle::default(),
+                flags: CertifyKeyFlags::empty(),
+                label: [2; DPE_PROFILE.get_hash_size()],
+                format: CertifyKeyCmd::FORMAT_X509,
+            })
+            .unwrap();
+        assert_ne!(0, certify.cert_size);
+
+        client
+            .sign(SignCmd {
+                handle: ContextHandle::default(),
+                label: [2; DPE_PROFILE.get_hash_size()],
+                flags: SignFlags::empty(),
+                digest: [3; DPE_PROFILE.get_hash_size()],
+            })
+            .unwrap();
+
+        let simulation = client
+            .initialize_context(InitCtxCmd::new_simulation())
+            .unwrap();
+        client
+            .destroy_context(DestroyCtxCmd {
+                handle: simulation.handle,
+                flags: DestroyCtxFlags::empty(),
+            })
+            .unwrap();
+
+        let mut expected_chain = vec![];
+        let mut chunk = [0; MAX_CHUNK_SIZE];
+        loop {
+            let len = DefaultPlatform::new()
+                .get_certificate_chain(
+                    expected_chain.len() as u32,
+                    MAX_CHUNK_SIZE as u32,
+                    &mut chunk,
+                )
+                .unwrap() as usize;
+            expected_chain.extend_from_slice(&chunk[..len]);
+            if len < MAX_CHUNK_SIZE {
+                break;
+            }
+        }
+        assert_eq!(expected_chain, client.get_full_certificate_chain().unwrap());
+    }
+
+    /// Answers GetCertificateChain the way DPE does for a platform with `chain`.
+    struct ChainTransport {
+        chain: Vec<u8>,
+    }
+
+    impl Transport for ChainTransport {
+        fn send(&mut self, _: u32, cmd: &[u8]) -> Result<Vec<u8>, ClientError> {
+            let cmd = match Command::deserialize(cmd) {
+                Ok(Command::GetCertificateChain(cmd)) => cmd,
+                _ => panic!("unexpected command"),
+            };
+            let offset = cmd.offset as usize;
+            let response = if offset >= self.chain.len() {
+                Response::Error(ResponseHdr::new(DpeErrorCode::InvalidArgument))
+            } else {
+                let chunk = &self.chain[offset..self.chain.len().min(offset + cmd.size as usize)];
+                let mut resp = GetCertificateChainResp {
+                    certificate_size: chunk.len() as u32,
+                    ..Default::default()
+                };
+                resp.certificate_chain[..chunk.len()].copy_from_slice(chunk);
+                Response::GetCertificateChain(resp)
+            };
+            Ok(response.serialize().to_vec())
+        }
+    }
+
+    #[test]
+    fn test_full_certificate_chain() {
+        for len in [
+            1,
+            100,
+            MAX_CHUNK_SIZE - 1,
+            MAX_CHUNK_SIZE,
+            MAX_CHUNK_SIZE + 1,
+        ] {
+            let chain: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let mut client = Client::new(
+                ChainTransport {
+                    chain: chain.clone(),
+                },
+                0,
+            );
+            assert_eq!(chain, client.get_full_certificate_chain().unwrap(), "{len}");
+        }
+
+        // An empty chain fails on the first read.
+        let mut client = Client::new(ChainTransport { chain: vec![] }, 0);
+        assert!(matches!(
+            client.get_full_certificate_chain(),
+            Err(ClientError::Status(status)) if status == DpeErrorCode::InvalidArgument as u32
+        ));
+    }
+
+    #[test]
+    fn test_error_status() {
+        let mut client = new_client(Support::AUTO_INIT);
+
+        // The default context belongs to locality 0.
+        client.set_locality(OTHER_LOCALITY);
+        assert!(matches!(
+            client.derive_child(DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [0; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::empty(),
+                tci_type: 0,
+                target_locality: 0,
+            }),
+            Err(ClientError::Status(status)) if status == DpeErrorCode::InvalidLocality as u32
+        ));
+
+        // Unsupported commands fail with the status from DPE.
+        client.set_locality(0);
+        assert!(matches!(
+            client.initialize_context(InitCtxCmd::new_simulation()),
+            Err(ClientError::Status(status)) if status == DpeErrorCode::ArgumentNotSupported as u32
+        ));
+    }
+}
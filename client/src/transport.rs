@@ -0,0 +1,124 @@
+// Licensed under the Apache-2.0 license.
+
+//! Transports that carry serialized commands to a DPE instance.
+
+use crate::ClientError;
+use dpe::{
+    dpe_instance::{DpeEnv, DpeTypes},
+    response::{Response, ResponseHdr},
+    DpeInstance,
+};
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+/// Socket the simulator listens on.
+pub const SIMULATOR_SOCKET_PATH: &str = "/tmp/dpe-sim.socket";
+
+pub trait Transport {
+    /// Sends a serialized command on behalf of `locality` and returns the serialized response.
+    fn send(&mut self, locality: u32, cmd: &[u8]) -> Result<Vec<u8>, ClientError>;
+}
+
+/// Talks to a DPE over a Unix socket, one connection per command.
+///
+/// Each request is the locality as a little-endian u32 followed by the command. The server
+/// writes the response and closes the connection.
+pub struct SocketTransport {
+    path: PathBuf,
+}
+
+impl SocketTransport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SocketTransport { path: path.into() }
+    }
+
+    /// Connects to the simulator at its default socket.
+    pub fn simulator() -> Self {
+        Self::new(SIMULATOR_SOCKET_PATH)
+    }
+}
+
+impl Transport for SocketTransport {
+    fn send(&mut self, locality: u32, cmd: &[u8]) -> Result<Vec<u8>, ClientError> {
+        let mut stream = UnixStream::connect(&self.path)?;
+
+        // The simulator reads the request with a single read, so send it in one write.
+        let mut request = locality.to_le_bytes().to_vec();
+        request.extend_from_slice(cmd);
+        stream.write_all(&request)?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        Ok(response)
+    }
+}
+
+/// Runs commands on a `DpeInstance` in the same process.
+pub struct InProcessTransport<'a, T: DpeTypes + 'a> {
+    pub dpe: DpeInstance,
+    pub env: DpeEnv<'a, T>,
+}
+
+impl<'a, T: DpeTypes + 'a> InProcessTransport<'a, T> {
+    pub fn new(dpe: DpeInstance, env: DpeEnv<'a, T>) -> Self {
+        InProcessTransport { dpe, env }
+    }
+}
+
+impl<'a, T: DpeTypes + 'a> Transport for InProcessTransport<'a, T> {
+    fn send(&mut self, locality: u32, cmd: &[u8]) -> Result<Vec<u8>, ClientError> {
+        // A DPE behind a real transport answers commands it can't parse with an error response,
+        // so do the same here.
+        let response = self
+            .dpe
+            .execute_serialized_command(&mut self.env, locality, cmd)
+            .unwrap_or_else(|err| Response::Error(ResponseHdr::new(err)));
+        Ok(response.serialize().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use dpe::{commands::Command, response::GetProfileResp};
+    use std::{os::unix::net::UnixListener, thread};
+
+    #[test]
+    fn test_socket_transport() {
+        let path =
+            std::env::temp_dir().join(format!("dpe-client-test-{}.socket", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 64];
+            let len = stream.read(&mut request).unwrap();
+            let response = Response::GetProfile(GetProfileResp::new(0x10, 0x20, 0x30));
+            stream.write_all(response.serialize()).unwrap();
+            request[..len].to_vec()
+        });
+
+        let mut client = Client::new(SocketTransport::new(&path), 0x1234);
+        let profile = client.get_profile().unwrap();
+        assert_eq!(0x10, profile.flags);
+        assert_eq!(0x20, profile.vendor_id);
+
+        let request = server.join().unwrap();
+        assert_eq!(&0x1234u32.to_le_bytes(), &request[..4]);
+        let mut expected = [0u8; 64];
+        let size = Command::GetProfile.serialize(&mut expected).unwrap();
+        assert_eq!(&expected[..size], &request[4..]);
+        std::fs::remove_file(&path).unwrap();
+
+        // Nothing listening.
+        assert!(matches!(
+            client.get_profile(),
+            Err(ClientError::Transport(_))
+        ));
+    }
+}
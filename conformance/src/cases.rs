@@ -0,0 +1,1435 @@
+// Licensed under the Apache-2.0 license.
+
+//! Test cases.
+//!
+//! Every case checks that what the DPE claims to support behaves as the profile describes, and
+//! that what it doesn't support is refused with the right error.
+
+use crate::{TestCase, TestError};
+use dpe::{
+    commands::{
+        BatchCmd, BatchEntryFlags, BatchEntryHdr, BatchFlags, CertifyKeyCmd, CertifyKeyFlags,
+        Command, DeriveChildCmd, DeriveChildFlags, DestroyCtxCmd, DestroyCtxFlags, ExtendTciCmd,
+        GetEatCmd, GetTaggedTciInfoFlags, InitCtxCmd, QuoteCmd, RotateCtxCmd, RotateCtxFlags,
+        SealCmd, SealedBlob, SignCmd, SignFlags, SignMessageCmd, SignStreamFinalCmd,
+        SignStreamInitCmd, SignStreamUpdateCmd, TagTciCmd, UnsealCmd,
+    },
+    context::ContextHandle,
+    event_log::EVENT_RECORD_SIZE,
+    response::{BatchResp, DeriveChildResp, DpeErrorCode, Quote, Response, TciNodeInfoFlags},
+    support::Support,
+    DpeProfile, DPE_PROFILE,
+};
+use dpe_client::{Client, ClientError, Transport};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey, PointConversionForm},
+    ecdsa::EcdsaSig,
+    hash::{hash, MessageDigest},
+    nid::Nid,
+    pkey::Public,
+    x509::X509,
+};
+use std::mem::size_of;
+use zerocopy::{AsBytes, FromBytes};
+
+const DATA: [u8; DPE_PROFILE.get_hash_size()] = [0x5a; DPE_PROFILE.get_hash_size()];
+const LABEL: [u8; DPE_PROFILE.get_hash_size()] = [0x1b; DPE_PROFILE.get_hash_size()];
+const DIGEST: [u8; DPE_PROFILE.get_hash_size()] = [0xd1; DPE_PROFILE.get_hash_size()];
+const TAG: u32 = 0x7467_0001;
+const OTHER_TAG: u32 = 0x7467_0002;
+/// A handle no DPE hands out, since it only generates random ones.
+const UNKNOWN_HANDLE: ContextHandle = ContextHandle([0xa5; ContextHandle::SIZE]);
+/// A command ID no profile defines.
+const UNKNOWN_COMMAND: u32 = 0xdead_beef;
+const MESSAGE: &[u8] = b"A message long enough to be streamed in more than one chunk.";
+/// Where MESSAGE is split when it is streamed.
+const CHUNK_SPLIT: usize = 10;
+const NONCE: u8 = 0x4e;
+
+pub fn all<T: Transport>() -> Vec<TestCase<T>> {
+    vec![
+        TestCase {
+            name: "get_profile",
+            run: get_profile,
+        },
+        TestCase {
+            name: "unknown_command",
+            run: unknown_command,
+        },
+        TestCase {
+            name: "initialize_context",
+            run: initialize_context,
+        },
+        TestCase {
+            name: "derive_child",
+            run: derive_child,
+        },
+        TestCase {
+            name: "certify_key",
+            run: certify_key,
+        },
+        TestCase {
+            name: "sign",
+            run: sign,
+        },
+        TestCase {
+            name: "rotate_context",
+            run: rotate_context,
+        },
+        TestCase {
+            name: "destroy_context",
+            run: destroy_context,
+        },
+        TestCase {
+            name: "extend_tci",
+            run: extend_tci,
+        },
+        TestCase {
+            name: "tag_tci",
+            run: tag_tci,
+        },
+        TestCase {
+            name: "get_certificate_chain",
+            run: get_certificate_chain,
+        },
+        TestCase {
+            name: "seal",
+            run: seal,
+        },
+        TestCase {
+            name: "sign_message",
+            run: sign_message,
+        },
+        TestCase {
+            name: "sign_stream",
+            run: sign_stream,
+        },
+        TestCase {
+            name: "batch",
+            run: batch,
+        },
+        TestCase {
+            name: "get_tagged_tci_info",
+            run: get_tagged_tci_info,
+        },
+        TestCase {
+            name: "get_context_table",
+            run: get_context_table,
+        },
+        TestCase {
+            name: "get_locality_quota",
+            run: get_locality_quota,
+        },
+        TestCase {
+            name: "get_event_log",
+            run: get_event_log,
+        },
+        TestCase {
+            name: "quote",
+            run: quote,
+        },
+        TestCase {
+            name: "get_eat",
+            run: get_eat,
+        },
+    ]
+}
+
+/// Checks that `result` is the error status `expected`.
+fn expect_error<R>(
+    result: Result<R, ClientError>,
+    expected: DpeErrorCode,
+) -> Result<(), TestError> {
+    match result {
+        Err(ClientError::Status(status)) => {
+            ensure!(
+                status == expected as u32,
+                "expected {expected:?}, got status {status:#x}"
+            );
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(TestError::Check(format!(
+            "expected {expected:?}, but the command succeeded"
+        ))),
+    }
+}
+
+/// Runs `f` from a locality that doesn't own any of the contexts.
+fn from_other_locality<T: Transport, R>(
+    client: &mut Client<T>,
+    f: impl FnOnce(&mut Client<T>) -> R,
+) -> R {
+    let locality = client.locality();
+    client.set_locality(locality.wrapping_add(1));
+    let result = f(client);
+    client.set_locality(locality);
+    result
+}
+
+/// Makes sure the caller's locality has a default context.
+fn init_default<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    if !support.auto_init() {
+        let resp = client.initialize_context(InitCtxCmd::new_use_default())?;
+        ensure!(
+            resp.handle.is_default(),
+            "default context has handle {:?}",
+            resp.handle
+        );
+    }
+    Ok(())
+}
+
+fn derive_cmd(handle: ContextHandle, flags: DeriveChildFlags) -> DeriveChildCmd {
+    DeriveChildCmd {
+        handle,
+        data: DATA,
+        flags,
+        tci_type: 0x5443_4931,
+        target_locality: 0,
+    }
+}
+
+/// Derives a non-default child of the default context.
+fn derive_non_default<T: Transport>(client: &mut Client<T>) -> Result<DeriveChildResp, TestError> {
+    let resp = client.derive_child(derive_cmd(
+        ContextHandle::default(),
+        DeriveChildFlags::RETAIN_PARENT,
+    ))?;
+    ensure!(
+        !resp.handle.is_default(),
+        "non-default child has the default handle"
+    );
+    ensure!(
+        resp.parent_handle.is_default(),
+        "default parent has handle {:?}",
+        resp.parent_handle
+    );
+    Ok(resp)
+}
+
+fn certify_cmd(handle: ContextHandle, flags: CertifyKeyFlags, format: u32) -> CertifyKeyCmd {
+    CertifyKeyCmd {
+        handle,
+        flags,
+        label: LABEL,
+        format,
+    }
+}
+
+fn sign_cmd(handle: ContextHandle, flags: SignFlags) -> SignCmd {
+    SignCmd {
+        handle,
+        label: LABEL,
+        flags,
+        digest: DIGEST,
+    }
+}
+
+fn seal_cmd(handle: ContextHandle, data: &[u8]) -> SealCmd {
+    let mut cmd = SealCmd::new_zeroed();
+    cmd.handle = handle;
+    cmd.label = LABEL;
+    cmd.data_size = data.len() as u32;
+    cmd.data[..data.len()].copy_from_slice(data);
+    cmd
+}
+
+fn unseal_cmd(handle: ContextHandle, blob: &SealedBlob) -> UnsealCmd {
+    let mut cmd = UnsealCmd::new_zeroed();
+    cmd.handle = handle;
+    cmd.label = LABEL;
+    cmd.blob.as_bytes_mut().copy_from_slice(blob.as_bytes());
+    cmd
+}
+
+fn sign_message_cmd(handle: ContextHandle, flags: SignFlags, message: &[u8]) -> SignMessageCmd {
+    let mut cmd = SignMessageCmd::new_zeroed();
+    cmd.handle = handle;
+    cmd.label = LABEL;
+    cmd.flags = flags;
+    cmd.message_size = message.len() as u32;
+    cmd.message[..message.len()].copy_from_slice(message);
+    cmd
+}
+
+fn sign_stream_update_cmd(handle: ContextHandle, chunk: &[u8]) -> SignStreamUpdateCmd {
+    let mut cmd = SignStreamUpdateCmd::new_zeroed();
+    cmd.handle = handle;
+    cmd.chunk_size = chunk.len() as u32;
+    cmd.chunk[..chunk.len()].copy_from_slice(chunk);
+    cmd
+}
+
+fn sign_stream_final_cmd(handle: ContextHandle) -> SignStreamFinalCmd {
+    SignStreamFinalCmd {
+        handle,
+        label: LABEL,
+        flags: SignFlags::empty(),
+    }
+}
+
+fn quote_cmd(handle: ContextHandle) -> QuoteCmd {
+    let mut cmd = QuoteCmd::new_zeroed();
+    cmd.handle = handle;
+    cmd.label = LABEL;
+    cmd.nonce.fill(NONCE);
+    cmd
+}
+
+fn get_eat_cmd(handle: ContextHandle) -> GetEatCmd {
+    let mut cmd = GetEatCmd::new_zeroed();
+    cmd.handle = handle;
+    cmd.label = LABEL;
+    cmd.nonce.fill(NONCE);
+    cmd
+}
+
+/// Builds a batch from `(handle_ref, command)` entries.
+fn batch_cmd(flags: BatchFlags, entries: Vec<(u32, Command)>) -> Result<BatchCmd, TestError> {
+    let mut batch = BatchCmd::new_zeroed();
+    batch.flags = flags;
+    for (handle_ref, command) in entries {
+        let offset = batch.data_size as usize;
+        let cmd_offset = offset + size_of::<BatchEntryHdr>();
+        let cmd_size = command
+            .serialize(&mut batch.data[cmd_offset..])
+            .map_err(ClientError::InvalidCommand)?;
+        let entry = BatchEntryHdr {
+            cmd_size: cmd_size as u32,
+            handle_ref,
+            flags: BatchEntryFlags::empty(),
+        };
+        batch.data[offset..cmd_offset].copy_from_slice(entry.as_bytes());
+        batch.data_size = (cmd_offset + cmd_size) as u32;
+        batch.num_commands += 1;
+    }
+    Ok(batch)
+}
+
+/// Parses the responses to a batch, given the IDs of the commands in it.
+fn batch_responses(resp: &BatchResp, cmd_ids: &[u32]) -> Result<Vec<Response>, TestError> {
+    ensure!(
+        resp.num_responses as usize == cmd_ids.len(),
+        "batch returned {} responses, expected {}",
+        resp.num_responses,
+        cmd_ids.len()
+    );
+    let mut data = resp
+        .data
+        .get(..resp.data_size as usize)
+        .ok_or_else(|| TestError::Check(format!("batch data size {} too big", resp.data_size)))?;
+    let mut responses = vec![];
+    for cmd_id in cmd_ids {
+        let size = u32::read_from_prefix(data)
+            .ok_or_else(|| TestError::Check("truncated batch response size".into()))?
+            as usize;
+        let bytes = data
+            .get(size_of::<u32>()..size_of::<u32>() + size)
+            .ok_or_else(|| TestError::Check("batch response runs past its data".into()))?;
+        responses
+            .push(Response::deserialize(*cmd_id, bytes).map_err(ClientError::InvalidResponse)?);
+        data = &data[size_of::<u32>() + size..];
+    }
+    ensure!(data.is_empty(), "batch has data after its last response");
+    Ok(responses)
+}
+
+fn ec_group() -> Result<EcGroup, TestError> {
+    let nid = match DPE_PROFILE {
+        DpeProfile::P256Sha256 => Nid::X9_62_PRIME256V1,
+        DpeProfile::P384Sha384 => Nid::SECP384R1,
+    };
+    Ok(EcGroup::from_curve_name(nid)?)
+}
+
+/// Hashes `data` with the profile's hash algorithm.
+fn profile_hash(data: &[u8]) -> Result<[u8; DPE_PROFILE.get_hash_size()], TestError> {
+    let digest = match DPE_PROFILE {
+        DpeProfile::P256Sha256 => MessageDigest::sha256(),
+        DpeProfile::P384Sha384 => MessageDigest::sha384(),
+    };
+    hash(digest, data)?
+        .as_ref()
+        .try_into()
+        .map_err(|_| TestError::Check("digest has the wrong size".into()))
+}
+
+fn ec_key(x: &[u8], y: &[u8]) -> Result<EcKey<Public>, TestError> {
+    let group = ec_group()?;
+    let x = BigNum::from_slice(x)?;
+    let y = BigNum::from_slice(y)?;
+    Ok(EcKey::from_public_key_affine_coordinates(&group, &x, &y)?)
+}
+
+/// The public key DPE signs with for `handle` and LABEL, which only CertifyKey returns.
+fn signing_key<T: Transport>(
+    client: &mut Client<T>,
+    handle: ContextHandle,
+) -> Result<EcKey<Public>, TestError> {
+    let key = client.certify_key(certify_cmd(
+        handle,
+        CertifyKeyFlags::empty(),
+        CertifyKeyCmd::FORMAT_X509,
+    ))?;
+    ec_key(&key.derived_pubkey_x, &key.derived_pubkey_y)
+}
+
+fn verify_sig(key: &EcKey<Public>, r: &[u8], s: &[u8], digest: &[u8]) -> Result<bool, TestError> {
+    let sig = EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+    Ok(sig.verify(digest, key)?)
+}
+
+/// Splits a chain of concatenated DER certificates.
+fn parse_chain(mut chain: &[u8]) -> Result<Vec<X509>, TestError> {
+    let mut certs = vec![];
+    while !chain.is_empty() {
+        // A certificate is a SEQUENCE with a short or long form length.
+        let len = match chain {
+            [0x30, len, ..] if *len < 0x80 => 2 + *len as usize,
+            [0x30, len @ 0x81..=0x84, rest @ ..] => {
+                let size = (*len & 0x7f) as usize;
+                let bytes = rest
+                    .get(..size)
+                    .ok_or_else(|| TestError::Check("truncated certificate length".into()))?;
+                2 + size + bytes.iter().fold(0, |len, b| len << 8 | *b as usize)
+            }
+            _ => return Err(TestError::Check("certificate is not a DER SEQUENCE".into())),
+        };
+        ensure!(
+            len <= chain.len(),
+            "certificate runs past the end of the chain"
+        );
+        let (cert, rest) = chain.split_at(len);
+        certs.push(X509::from_der(cert)?);
+        chain = rest;
+    }
+    Ok(certs)
+}
+
+fn get_profile<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    let profile = client.get_profile()?;
+    ensure!(
+        profile.flags == support.bits(),
+        "profile flags {:#x}, expected {:#x}",
+        profile.flags,
+        support.bits()
+    );
+    ensure!(profile.max_tci_nodes > 0, "profile allows no TCI nodes");
+    Ok(())
+}
+
+fn unknown_command<T: Transport>(client: &mut Client<T>, _: &Support) -> Result<(), TestError> {
+    let mut cmd = [0u8; 64];
+    let size = Command::GetProfile
+        .serialize(&mut cmd)
+        .map_err(ClientError::InvalidCommand)?;
+    // The command ID follows the magic in the header.
+    cmd[4..8].copy_from_slice(&UNKNOWN_COMMAND.to_le_bytes());
+
+    let locality = client.locality();
+    let bytes = client.transport().send(locality, &cmd[..size])?;
+    let response =
+        Response::deserialize(UNKNOWN_COMMAND, &bytes).map_err(ClientError::InvalidResponse)?;
+    ensure!(
+        response.resp_hdr().status == DpeErrorCode::InvalidCommand as u32,
+        "unknown command returned status {:#x}",
+        response.resp_hdr().status
+    );
+    Ok(())
+}
+
+fn initialize_context<T: Transport>(
+    client: &mut Client<T>,
+    support: &Support,
+) -> Result<(), TestError> {
+    init_default(client, support)?;
+    // The default context can only be initialized once.
+    expect_error(
+        client.initialize_context(InitCtxCmd::new_use_default()),
+        DpeErrorCode::ArgumentNotSupported,
+    )?;
+    expect_error(
+        client.initialize_context(InitCtxCmd::empty()),
+        DpeErrorCode::InvalidArgument,
+    )?;
+
+    if !support.simulation() {
+        return expect_error(
+            client.initialize_context(InitCtxCmd::new_simulation()),
+            DpeErrorCode::ArgumentNotSupported,
+        );
+    }
+    let simulation = client.initialize_context(InitCtxCmd::new_simulation())?;
+    ensure!(
+        !simulation.handle.is_default(),
+        "simulation context has the default handle"
+    );
+    client.destroy_context(DestroyCtxCmd {
+        handle: simulation.handle,
+        flags: DestroyCtxFlags::empty(),
+    })?;
+    Ok(())
+}
+
+fn derive_child<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let resp = client.derive_child(derive_cmd(
+        ContextHandle::default(),
+        DeriveChildFlags::MAKE_DEFAULT,
+    ))?;
+    ensure!(
+        resp.handle.is_default(),
+        "MAKE_DEFAULT child has handle {:?}",
+        resp.handle
+    );
+    derive_non_default(client)?;
+
+    expect_error(
+        client.derive_child(derive_cmd(UNKNOWN_HANDLE, DeriveChildFlags::empty())),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.derive_child(derive_cmd(
+                ContextHandle::default(),
+                DeriveChildFlags::empty(),
+            ))
+        }),
+        DpeErrorCode::InvalidLocality,
+    )?;
+
+    let unsupported = [
+        (
+            support.internal_info(),
+            DeriveChildFlags::INTERNAL_INPUT_INFO,
+        ),
+        (
+            support.internal_dice(),
+            DeriveChildFlags::INTERNAL_INPUT_DICE,
+        ),
+        (support.is_ca(), DeriveChildFlags::INPUT_ALLOW_CA),
+        (support.x509(), DeriveChildFlags::INPUT_ALLOW_X509),
+        (support.cdi_export(), DeriveChildFlags::INPUT_ALLOW_EXPORT),
+    ];
+    for (_, flag) in unsupported.into_iter().filter(|(supported, _)| !supported) {
+        expect_error(
+            client.derive_child(derive_cmd(ContextHandle::default(), flag)),
+            DpeErrorCode::ArgumentNotSupported,
+        )?;
+    }
+    Ok(())
+}
+
+fn certify_key<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.x509() {
+        expect_error(
+            client.certify_key(certify_cmd(
+                handle,
+                CertifyKeyFlags::empty(),
+                CertifyKeyCmd::FORMAT_X509,
+            )),
+            DpeErrorCode::ArgumentNotSupported,
+        )?;
+    } else {
+        let resp = client.certify_key(certify_cmd(
+            handle,
+            CertifyKeyFlags::empty(),
+            CertifyKeyCmd::FORMAT_X509,
+        ))?;
+        ensure!(
+            resp.new_context_handle.is_default(),
+            "default context handle changed to {:?}",
+            resp.new_context_handle
+        );
+        let der = resp
+            .cert
+            .get(..resp.cert_size as usize)
+            .ok_or_else(|| TestError::Check(format!("cert size {} too big", resp.cert_size)))?;
+        let cert = X509::from_der(der)?;
+
+        let group = ec_group()?;
+        let mut ctx = BigNumContext::new()?;
+        let cert_key = cert.public_key()?.ec_key()?.public_key().to_bytes(
+            &group,
+            PointConversionForm::UNCOMPRESSED,
+            &mut ctx,
+        )?;
+        let derived_key = [
+            &[0x04][..],
+            &resp.derived_pubkey_x[..],
+            &resp.derived_pubkey_y[..],
+        ]
+        .concat();
+        ensure!(
+            cert_key == derived_key,
+            "certificate key doesn't match the derived public key"
+        );
+    }
+
+    if !support.is_ca() {
+        expect_error(
+            client.certify_key(certify_cmd(
+                handle,
+                CertifyKeyFlags::IS_CA,
+                CertifyKeyCmd::FORMAT_X509,
+            )),
+            DpeErrorCode::ArgumentNotSupported,
+        )?;
+    }
+    if !support.csr() {
+        expect_error(
+            client.certify_key(certify_cmd(
+                handle,
+                CertifyKeyFlags::empty(),
+                CertifyKeyCmd::FORMAT_CSR,
+            )),
+            DpeErrorCode::ArgumentNotSupported,
+        )?;
+    }
+    expect_error(
+        client.certify_key(certify_cmd(handle, CertifyKeyFlags::empty(), 0x1234)),
+        DpeErrorCode::InvalidArgument,
+    )?;
+    expect_error(
+        client.certify_key(certify_cmd(
+            UNKNOWN_HANDLE,
+            CertifyKeyFlags::empty(),
+            CertifyKeyCmd::FORMAT_X509,
+        )),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.certify_key(certify_cmd(
+                handle,
+                CertifyKeyFlags::empty(),
+                CertifyKeyCmd::FORMAT_X509,
+            ))
+        }),
+        DpeErrorCode::InvalidLocality,
+    )
+}
+
+fn sign<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    let resp = client.sign(sign_cmd(handle, SignFlags::empty()))?;
+    ensure!(
+        resp.new_context_handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.new_context_handle
+    );
+    if support.x509() {
+        let key = signing_key(client, handle)?;
+        ensure!(
+            verify_sig(&key, &resp.sig_r_or_hmac, &resp.sig_s, &DIGEST)?,
+            "signature doesn't verify with the certified key"
+        );
+    }
+
+    if !support.is_symmetric() {
+        expect_error(
+            client.sign(sign_cmd(handle, SignFlags::IS_SYMMETRIC)),
+            DpeErrorCode::InvalidArgument,
+        )?;
+    } else {
+        let first = client.sign(sign_cmd(handle, SignFlags::IS_SYMMETRIC))?;
+        let second = client.sign(sign_cmd(handle, SignFlags::IS_SYMMETRIC))?;
+        ensure!(
+            first.sig_r_or_hmac == second.sig_r_or_hmac,
+            "HMAC of the same digest changed"
+        );
+        ensure!(
+            first.sig_s.iter().all(|b| *b == 0),
+            "HMAC response has a non-zero S"
+        );
+    }
+
+    expect_error(
+        client.sign(sign_cmd(UNKNOWN_HANDLE, SignFlags::empty())),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.sign(sign_cmd(handle, SignFlags::empty()))
+        }),
+        DpeErrorCode::InvalidLocality,
+    )
+}
+
+fn rotate_context<T: Transport>(
+    client: &mut Client<T>,
+    support: &Support,
+) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let child = derive_non_default(client)?.handle;
+    let rotate_cmd = |handle, flags| RotateCtxCmd {
+        handle,
+        flags,
+        target_locality: 0,
+    };
+
+    if !support.rotate_context() {
+        return expect_error(
+            client.rotate_context_handle(rotate_cmd(child, RotateCtxFlags::empty())),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+    let rotated = client
+        .rotate_context_handle(rotate_cmd(child, RotateCtxFlags::empty()))?
+        .handle;
+    ensure!(
+        rotated != child && !rotated.is_default(),
+        "rotated handle is {rotated:?}"
+    );
+    expect_error(
+        client.rotate_context_handle(rotate_cmd(child, RotateCtxFlags::empty())),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    // The locality already has a default context.
+    expect_error(
+        client.rotate_context_handle(rotate_cmd(rotated, RotateCtxFlags::TARGET_IS_DEFAULT)),
+        DpeErrorCode::InvalidArgument,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.rotate_context_handle(rotate_cmd(rotated, RotateCtxFlags::empty()))
+        }),
+        DpeErrorCode::InvalidLocality,
+    )
+}
+
+fn destroy_context<T: Transport>(
+    client: &mut Client<T>,
+    support: &Support,
+) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let destroy_cmd = |handle, flags| DestroyCtxCmd { handle, flags };
+
+    let child = derive_non_default(client)?.handle;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.destroy_context(destroy_cmd(child, DestroyCtxFlags::empty()))
+        }),
+        DpeErrorCode::InvalidLocality,
+    )?;
+    client.destroy_context(destroy_cmd(child, DestroyCtxFlags::empty()))?;
+    expect_error(
+        client.destroy_context(destroy_cmd(child, DestroyCtxFlags::empty())),
+        DpeErrorCode::InvalidHandle,
+    )?;
+
+    // Destroying a context with DESTROY_CHILDREN takes its children with it.
+    let parent = derive_non_default(client)?.handle;
+    let resp = client.derive_child(derive_cmd(parent, DeriveChildFlags::RETAIN_PARENT))?;
+    client.destroy_context(destroy_cmd(
+        resp.parent_handle,
+        DestroyCtxFlags::DESTROY_CHILDREN_FLAG_MASK,
+    ))?;
+    expect_error(
+        client.destroy_context(destroy_cmd(resp.handle, DestroyCtxFlags::empty())),
+        DpeErrorCode::InvalidHandle,
+    )
+}
+
+fn extend_tci<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.extend_tci() {
+        return expect_error(
+            client.extend_tci(ExtendTciCmd { handle, data: DATA }),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+    // Tagging is the only way to read a TCI back.
+    let before = if support.tagging() {
+        client.tag_tci(TagTciCmd { handle, tag: TAG })?;
+        Some(client.get_tagged_tci(TAG)?)
+    } else {
+        None
+    };
+    let resp = client.extend_tci(ExtendTciCmd { handle, data: DATA })?;
+    ensure!(
+        resp.handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.handle
+    );
+    if let Some(before) = before {
+        let after = client.get_tagged_tci(TAG)?;
+        ensure!(
+            after.tci_current.0 == DATA,
+            "current TCI isn't the extended data"
+        );
+        ensure!(
+            after.tci_cumulative.0 != before.tci_cumulative.0,
+            "cumulative TCI didn't change"
+        );
+    }
+
+    expect_error(
+        client.extend_tci(ExtendTciCmd {
+            handle: UNKNOWN_HANDLE,
+            data: DATA,
+        }),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.extend_tci(ExtendTciCmd { handle, data: DATA })
+        }),
+        DpeErrorCode::InvalidLocality,
+    )
+}
+
+fn tag_tci<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.tagging() {
+        expect_error(
+            client.tag_tci(TagTciCmd { handle, tag: TAG }),
+            DpeErrorCode::InvalidCommand,
+        )?;
+        return expect_error(client.get_tagged_tci(TAG), DpeErrorCode::InvalidCommand);
+    }
+    let resp = client.tag_tci(TagTciCmd { handle, tag: TAG })?;
+    ensure!(
+        resp.handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.handle
+    );
+    client.get_tagged_tci(TAG)?;
+    expect_error(client.get_tagged_tci(OTHER_TAG), DpeErrorCode::BadTag)?;
+
+    // Tags are unique, and a context only gets one.
+    let child = derive_non_default(client)?.handle;
+    expect_error(
+        client.tag_tci(TagTciCmd {
+            handle: child,
+            tag: TAG,
+        }),
+        DpeErrorCode::BadTag,
+    )?;
+    expect_error(
+        client.tag_tci(TagTciCmd {
+            handle,
+            tag: OTHER_TAG,
+        }),
+        DpeErrorCode::BadTag,
+    )
+}
+
+fn get_certificate_chain<T: Transport>(
+    client: &mut Client<T>,
+    _: &Support,
+) -> Result<(), TestError> {
+    let chain = client.get_full_certificate_chain()?;
+    let certs = parse_chain(&chain)?;
+    ensure!(!certs.is_empty(), "certificate chain is empty");
+
+    expect_error(
+        client.get_certificate_chain(chain.len() as u32, 1),
+        DpeErrorCode::InvalidArgument,
+    )
+}
+
+fn seal<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.seal() {
+        expect_error(
+            client.seal(seal_cmd(handle, &DATA)),
+            DpeErrorCode::InvalidCommand,
+        )?;
+        return expect_error(
+            client.unseal(unseal_cmd(handle, &SealedBlob::default())),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+    let sealed = client.seal(seal_cmd(handle, &DATA))?;
+    ensure!(
+        sealed.new_context_handle.is_default(),
+        "default context handle changed to {:?}",
+        sealed.new_context_handle
+    );
+    ensure!(
+        sealed.blob.hdr.data_size == DATA.len() as u32,
+        "sealed blob holds {} bytes",
+        sealed.blob.hdr.data_size
+    );
+    ensure!(
+        sealed.blob.data[..DATA.len()] != DATA,
+        "sealed data is not encrypted"
+    );
+    let unsealed = client.unseal(unseal_cmd(handle, &sealed.blob))?;
+    ensure!(
+        unsealed.data.get(..unsealed.data_size as usize) == Some(&DATA[..]),
+        "unsealed data doesn't match what was sealed"
+    );
+
+    // The blob only unseals with the same label, unmodified.
+    let mut cmd = unseal_cmd(handle, &sealed.blob);
+    cmd.label = DIGEST;
+    expect_error(client.unseal(cmd), DpeErrorCode::CryptoError)?;
+    let mut cmd = unseal_cmd(handle, &sealed.blob);
+    cmd.blob.data[0] ^= 1;
+    expect_error(client.unseal(cmd), DpeErrorCode::CryptoError)?;
+
+    let mut cmd = seal_cmd(handle, &DATA);
+    cmd.data_size = cmd.data.len() as u32 + 1;
+    expect_error(client.seal(cmd), DpeErrorCode::InvalidArgument)?;
+    expect_error(
+        client.seal(seal_cmd(UNKNOWN_HANDLE, &DATA)),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| client.seal(seal_cmd(handle, &DATA))),
+        DpeErrorCode::InvalidLocality,
+    )?;
+
+    // Simulation contexts can't seal, or they could unseal what real contexts sealed.
+    if support.simulation() {
+        let simulation = client.initialize_context(InitCtxCmd::new_simulation())?;
+        expect_error(
+            client.seal(seal_cmd(simulation.handle, &DATA)),
+            DpeErrorCode::InvalidArgument,
+        )?;
+    }
+    // Blobs are bound to the TCIs of the context.
+    if support.extend_tci() {
+        client.extend_tci(ExtendTciCmd { handle, data: DATA })?;
+        expect_error(
+            client.unseal(unseal_cmd(handle, &sealed.blob)),
+            DpeErrorCode::CryptoError,
+        )?;
+    }
+    Ok(())
+}
+
+fn sign_message<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.sign_message() {
+        return expect_error(
+            client.sign_message(sign_message_cmd(handle, SignFlags::empty(), MESSAGE)),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+
+    let resp = client.sign_message(sign_message_cmd(handle, SignFlags::empty(), MESSAGE))?;
+    ensure!(
+        resp.new_context_handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.new_context_handle
+    );
+    let digest = profile_hash(MESSAGE)?;
+    if support.x509() {
+        let key = signing_key(client, handle)?;
+        ensure!(
+            verify_sig(&key, &resp.sig_r_or_hmac, &resp.sig_s, &digest)?,
+            "signature doesn't verify over the hash of the message"
+        );
+    }
+    if support.is_symmetric() {
+        let hmac =
+            client.sign_message(sign_message_cmd(handle, SignFlags::IS_SYMMETRIC, MESSAGE))?;
+        let expected = client.sign(SignCmd {
+            handle,
+            label: LABEL,
+            flags: SignFlags::IS_SYMMETRIC,
+            digest,
+        })?;
+        ensure!(
+            hmac.sig_r_or_hmac == expected.sig_r_or_hmac,
+            "HMAC of the message differs from the HMAC of its hash"
+        );
+    }
+
+    let mut cmd = sign_message_cmd(handle, SignFlags::empty(), MESSAGE);
+    cmd.message_size = cmd.message.len() as u32 + 1;
+    expect_error(client.sign_message(cmd), DpeErrorCode::InvalidArgument)?;
+    expect_error(
+        client.sign_message(sign_message_cmd(
+            UNKNOWN_HANDLE,
+            SignFlags::empty(),
+            MESSAGE,
+        )),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.sign_message(sign_message_cmd(handle, SignFlags::empty(), MESSAGE))
+        }),
+        DpeErrorCode::InvalidLocality,
+    )
+}
+
+fn sign_stream<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.sign_message() {
+        return expect_error(
+            client.sign_stream_init(SignStreamInitCmd { handle }),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+
+    // Nothing to update or sign before the stream is started.
+    expect_error(
+        client.sign_stream_update(sign_stream_update_cmd(handle, MESSAGE)),
+        DpeErrorCode::InvalidArgument,
+    )?;
+    expect_error(
+        client.sign_stream_final(sign_stream_final_cmd(handle)),
+        DpeErrorCode::InvalidArgument,
+    )?;
+
+    let resp = client.sign_stream_init(SignStreamInitCmd { handle })?;
+    ensure!(
+        resp.handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.handle
+    );
+    let mut cmd = sign_stream_update_cmd(handle, MESSAGE);
+    cmd.chunk_size = cmd.chunk.len() as u32 + 1;
+    expect_error(
+        client.sign_stream_update(cmd),
+        DpeErrorCode::InvalidArgument,
+    )?;
+
+    // The signed digest is the hash of the whole message, however it was split.
+    let (first, second) = MESSAGE.split_at(CHUNK_SPLIT);
+    for chunk in [first, second] {
+        client.sign_stream_update(sign_stream_update_cmd(handle, chunk))?;
+    }
+    let resp = client.sign_stream_final(sign_stream_final_cmd(handle))?;
+    ensure!(
+        resp.new_context_handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.new_context_handle
+    );
+    ensure!(
+        resp.digest == profile_hash(MESSAGE)?,
+        "signed digest isn't the hash of the message"
+    );
+    if support.x509() {
+        let key = signing_key(client, handle)?;
+        ensure!(
+            verify_sig(&key, &resp.sig_r_or_hmac, &resp.sig_s, &resp.digest)?,
+            "signature doesn't verify over the returned digest"
+        );
+    }
+
+    // The stream is consumed by the final command.
+    expect_error(
+        client.sign_stream_final(sign_stream_final_cmd(handle)),
+        DpeErrorCode::InvalidArgument,
+    )?;
+    expect_error(
+        client.sign_stream_init(SignStreamInitCmd {
+            handle: UNKNOWN_HANDLE,
+        }),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| {
+            client.sign_stream_init(SignStreamInitCmd { handle })
+        }),
+        DpeErrorCode::InvalidLocality,
+    )
+}
+
+fn batch<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+    let derive = || {
+        Command::DeriveChild(derive_cmd(
+            ContextHandle::default(),
+            DeriveChildFlags::RETAIN_PARENT,
+        ))
+    };
+
+    // The second entry signs with the child the first one derived.
+    let resp = client.batch(batch_cmd(
+        BatchFlags::empty(),
+        vec![
+            (BatchEntryHdr::NO_HANDLE_REF, derive()),
+            (
+                0,
+                Command::Sign(sign_cmd(UNKNOWN_HANDLE, SignFlags::empty())),
+            ),
+        ],
+    )?)?;
+    let responses = batch_responses(&resp, &[Command::DERIVE_CHILD, Command::SIGN])?;
+    let child = match &responses[0] {
+        Response::DeriveChild(resp) => resp.handle,
+        _ => return Err(ClientError::UnexpectedResponse.into()),
+    };
+    let signed = match &responses[1] {
+        Response::Sign(resp) => resp.new_context_handle,
+        _ => return Err(ClientError::UnexpectedResponse.into()),
+    };
+    ensure!(
+        !child.is_default() && !signed.is_default() && signed != child,
+        "batch returned handles {child:?} and {signed:?}"
+    );
+
+    // A failed ALL_OR_NOTHING batch leaves nothing behind. Otherwise the entries before the
+    // failure stay done.
+    let failing = || {
+        vec![
+            (BatchEntryHdr::NO_HANDLE_REF, derive()),
+            (
+                BatchEntryHdr::NO_HANDLE_REF,
+                Command::Sign(sign_cmd(UNKNOWN_HANDLE, SignFlags::empty())),
+            ),
+        ]
+    };
+    let used = client.get_locality_quota()?.used_contexts;
+    expect_error(
+        client.batch(batch_cmd(BatchFlags::ALL_OR_NOTHING, failing())?),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    let after = client.get_locality_quota()?.used_contexts;
+    ensure!(
+        after == used,
+        "ALL_OR_NOTHING batch left {} contexts behind",
+        after - used
+    );
+    expect_error(
+        client.batch(batch_cmd(BatchFlags::empty(), failing())?),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    let after = client.get_locality_quota()?.used_contexts;
+    ensure!(
+        after == used + 1,
+        "batch kept {} contexts, expected 1",
+        after - used
+    );
+
+    let mut cmd = batch_cmd(
+        BatchFlags::empty(),
+        vec![(
+            BatchEntryHdr::NO_HANDLE_REF,
+            Command::Sign(sign_cmd(handle, SignFlags::empty())),
+        )],
+    )?;
+    cmd.num_commands = u32::MAX;
+    expect_error(client.batch(cmd), DpeErrorCode::InvalidArgument)
+}
+
+fn get_tagged_tci_info<T: Transport>(
+    client: &mut Client<T>,
+    support: &Support,
+) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.tagging() {
+        return expect_error(
+            client.get_tagged_tci_info(TAG, GetTaggedTciInfoFlags::empty()),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+    client.tag_tci(TagTciCmd { handle, tag: TAG })?;
+    let info = client.get_tagged_tci_info(TAG, GetTaggedTciInfoFlags::INCLUDE_CHAIN)?;
+    ensure!(
+        info.node_count == 1,
+        "root has {} nodes in its chain",
+        info.node_count
+    );
+    ensure!(
+        info.nodes[0].tag == TAG
+            && info.nodes[0]
+                .flags
+                .contains(TciNodeInfoFlags::HAS_TAG | TciNodeInfoFlags::IS_ROOT),
+        "root node is {:?}",
+        info.nodes[0]
+    );
+
+    expect_error(
+        client.get_tagged_tci_info(OTHER_TAG, GetTaggedTciInfoFlags::empty()),
+        DpeErrorCode::BadTag,
+    )?;
+    client.derive_child(derive_cmd(handle, DeriveChildFlags::MAKE_DEFAULT))?;
+    client.tag_tci(TagTciCmd {
+        handle,
+        tag: OTHER_TAG,
+    })?;
+
+    let info = client.get_tagged_tci_info(OTHER_TAG, GetTaggedTciInfoFlags::empty())?;
+    ensure!(
+        info.node_count == 1,
+        "{} nodes returned without INCLUDE_CHAIN",
+        info.node_count
+    );
+    let info = client.get_tagged_tci_info(OTHER_TAG, GetTaggedTciInfoFlags::INCLUDE_CHAIN)?;
+    ensure!(
+        info.node_count == 2,
+        "child has {} nodes in its chain",
+        info.node_count
+    );
+    ensure!(
+        info.nodes[0].tci.tci_current.0 == DATA
+            && info.nodes[0].parent_tag == TAG
+            && info.nodes[0]
+                .flags
+                .contains(TciNodeInfoFlags::PARENT_HAS_TAG),
+        "child node is {:?}",
+        info.nodes[0]
+    );
+    ensure!(
+        info.nodes[1].tag == TAG && info.nodes[1].flags.contains(TciNodeInfoFlags::RETIRED),
+        "retired parent node is {:?}",
+        info.nodes[1]
+    );
+    Ok(())
+}
+
+fn get_context_table<T: Transport>(
+    client: &mut Client<T>,
+    support: &Support,
+) -> Result<(), TestError> {
+    init_default(client, support)?;
+
+    if !support.context_table() {
+        return expect_error(client.get_context_table(), DpeErrorCode::InvalidCommand);
+    }
+    let locality = client.locality();
+    let count_active = |client: &mut Client<T>| -> Result<usize, TestError> {
+        let table = client.get_context_table()?;
+        let contexts = table
+            .contexts
+            .get(..table.num_contexts as usize)
+            .ok_or_else(|| TestError::Check(format!("table has {} slots", table.num_contexts)))?;
+        // 1 is Active.
+        Ok(contexts
+            .iter()
+            .filter(|info| info.state == 1 && info.locality == locality)
+            .count())
+    };
+    let before = count_active(client)?;
+    ensure!(before > 0, "default context is missing from the table");
+    derive_non_default(client)?;
+    let after = count_active(client)?;
+    ensure!(
+        after == before + 1,
+        "derived context is missing from the table"
+    );
+    Ok(())
+}
+
+fn get_locality_quota<T: Transport>(
+    client: &mut Client<T>,
+    support: &Support,
+) -> Result<(), TestError> {
+    init_default(client, support)?;
+
+    let quota = client.get_locality_quota()?;
+    ensure!(
+        quota.locality == client.locality(),
+        "quota is for locality {:#x}",
+        quota.locality
+    );
+    ensure!(
+        quota.used_contexts > 0 && quota.used_contexts <= quota.max_contexts,
+        "locality uses {} of {} contexts",
+        quota.used_contexts,
+        quota.max_contexts
+    );
+    derive_non_default(client)?;
+    let used = client.get_locality_quota()?.used_contexts;
+    ensure!(
+        used == quota.used_contexts + 1,
+        "derived context isn't counted against the quota"
+    );
+
+    // Other localities have their own quota.
+    let other = from_other_locality(client, |client| client.get_locality_quota())?;
+    ensure!(
+        other.locality != quota.locality && other.used_contexts == 0,
+        "other locality {:#x} uses {} contexts",
+        other.locality,
+        other.used_contexts
+    );
+    Ok(())
+}
+
+fn get_event_log<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+
+    if !support.event_log() {
+        return expect_error(client.get_event_log(0, 0), DpeErrorCode::InvalidCommand);
+    }
+    let before = client.get_full_event_log()?;
+    ensure!(
+        before.len() % EVENT_RECORD_SIZE == 0,
+        "log size {} isn't a whole number of records",
+        before.len()
+    );
+    derive_non_default(client)?;
+    let after = client.get_full_event_log()?;
+    ensure!(
+        after.len() == before.len() + EVENT_RECORD_SIZE,
+        "log grew from {} to {} bytes",
+        before.len(),
+        after.len()
+    );
+    ensure!(
+        after[before.len()..]
+            .windows(DATA.len())
+            .any(|window| window == DATA),
+        "newest record doesn't hold the derived measurement"
+    );
+    // Events are never dropped from a supported log.
+    let first_recnum = client.get_event_log(0, 0)?.first_recnum;
+    ensure!(
+        first_recnum == 0,
+        "log starts at recnum {first_recnum} instead of 0"
+    );
+
+    expect_error(
+        client.get_event_log(after.len() as u32 + 1, 0),
+        DpeErrorCode::InvalidArgument,
+    )?;
+    expect_error(
+        client.get_event_log(0, u32::MAX),
+        DpeErrorCode::InvalidArgument,
+    )
+}
+
+fn quote<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.quote() {
+        return expect_error(
+            client.quote(quote_cmd(handle)),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+    let resp = client.quote(quote_cmd(handle))?;
+    ensure!(
+        resp.new_context_handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.new_context_handle
+    );
+    let quote = &resp.quote;
+    ensure!(
+        quote.magic == Quote::MAGIC,
+        "quote magic {:#x}",
+        quote.magic
+    );
+    ensure!(
+        quote.support == support.bits(),
+        "quote support {:#x}, expected {:#x}",
+        quote.support,
+        support.bits()
+    );
+    ensure!(
+        quote.nonce.iter().all(|b| *b == NONCE),
+        "quote doesn't hold the nonce"
+    );
+    ensure!(quote.tci_count > 0, "quote has no TCIs");
+    let key = ec_key(&resp.derived_pubkey_x, &resp.derived_pubkey_y)?;
+    ensure!(
+        verify_sig(
+            &key,
+            &resp.sig_r,
+            &resp.sig_s,
+            &profile_hash(quote.as_bytes())?
+        )?,
+        "quote signature doesn't verify"
+    );
+    if support.x509() {
+        // The quote key has its own certificate and isn't the key Sign uses.
+        let certified = client.certify_key(certify_cmd(
+            handle,
+            CertifyKeyFlags::QUOTE_KEY,
+            CertifyKeyCmd::FORMAT_X509,
+        ))?;
+        ensure!(
+            certified.derived_pubkey_x == resp.derived_pubkey_x
+                && certified.derived_pubkey_y == resp.derived_pubkey_y,
+            "quote key doesn't match its certificate"
+        );
+        let signing = client.certify_key(certify_cmd(
+            handle,
+            CertifyKeyFlags::empty(),
+            CertifyKeyCmd::FORMAT_X509,
+        ))?;
+        ensure!(
+            signing.derived_pubkey_x != resp.derived_pubkey_x,
+            "quotes are signed with the Sign key"
+        );
+    }
+
+    expect_error(
+        client.quote(quote_cmd(UNKNOWN_HANDLE)),
+        DpeErrorCode::InvalidHandle,
+    )?;
+    expect_error(
+        from_other_locality(client, |client| client.quote(quote_cmd(handle))),
+        DpeErrorCode::InvalidLocality,
+    )
+}
+
+fn get_eat<T: Transport>(client: &mut Client<T>, support: &Support) -> Result<(), TestError> {
+    init_default(client, support)?;
+    let handle = ContextHandle::default();
+
+    if !support.eat() {
+        return expect_error(
+            client.get_eat(get_eat_cmd(handle)),
+            DpeErrorCode::InvalidCommand,
+        );
+    }
+    // The token carries a certificate for its signing key.
+    if !support.x509() {
+        return expect_error(
+            client.get_eat(get_eat_cmd(handle)),
+            DpeErrorCode::ArgumentNotSupported,
+        );
+    }
+    let resp = client.get_eat(get_eat_cmd(handle))?;
+    ensure!(
+        resp.new_context_handle.is_default(),
+        "default context handle changed to {:?}",
+        resp.new_context_handle
+    );
+    let token = resp
+        .token
+        .get(..resp.token_size as usize)
+        .ok_or_else(|| TestError::Check(format!("token size {} too big", resp.token_size)))?;
+    // Tag 18 is COSE_Sign1.
+    ensure!(
+        token.first() == Some(&0xd2),
+        "token isn't a tagged COSE_Sign1"
+    );
+    let nonce = get_eat_cmd(handle).nonce;
+    ensure!(
+        token.windows(nonce.len()).any(|window| window == nonce),
+        "token doesn't hold the nonce"
+    );
+
+    // A context that may not certify keys can't get a token either.
+    let child = derive_non_default(client)?.handle;
+    expect_error(
+        client.get_eat(get_eat_cmd(child)),
+        DpeErrorCode::InvalidArgument,
+    )?;
+    expect_error(
+        client.get_eat(get_eat_cmd(UNKNOWN_HANDLE)),
+        DpeErrorCode::InvalidHandle,
+    )
+}
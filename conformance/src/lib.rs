@@ -0,0 +1,149 @@
+/*++
+Licensed under the Apache-2.0 license.
+
+Abstract:
+    Conformance tests for DPE implementations.
+--*/
+
+//! Conformance suite for DPE.
+//!
+//! Each test case talks to a DPE only through a `dpe_client::Client`, so the suite runs against
+//! anything a `Transport` can reach. A `Target` restarts its DPE with a given set of `Support`
+//! flags before every test case; `run_suite` runs every case for each support combination it is
+//! given. This crate has targets for a `DpeInstance` in the same process and for the simulator.
+//! Other DPEs, such as a hardware emulator, only need their own `Target` to run the suite.
+
+use dpe::support::Support;
+use dpe_client::{Client, ClientError, Transport};
+use std::fmt;
+
+/// Fails the test case with a formatted message unless `cond` holds.
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::TestError::Check(format!($($arg)+)));
+        }
+    };
+}
+
+pub mod cases;
+pub mod target;
+
+pub use target::{InProcessTarget, SimulatorTarget, Target};
+
+#[derive(Debug)]
+pub enum TestError {
+    /// A command failed that was expected to succeed, or the DPE could not be reached.
+    Client(ClientError),
+    /// DPE answered, but not the way the profile requires.
+    Check(String),
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestError::Client(err) => write!(f, "{err}"),
+            TestError::Check(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for TestError {}
+
+impl From<ClientError> for TestError {
+    fn from(err: ClientError) -> Self {
+        TestError::Client(err)
+    }
+}
+
+impl From<openssl::error::ErrorStack> for TestError {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        TestError::Check(format!("openssl: {err}"))
+    }
+}
+
+pub struct TestCase<T: Transport> {
+    pub name: &'static str,
+    /// Runs the case against a freshly started DPE with the given support flags.
+    pub run: fn(&mut Client<T>, &Support) -> Result<(), TestError>,
+}
+
+pub struct TestResult {
+    pub name: &'static str,
+    pub support: Support,
+    pub result: Result<(), TestError>,
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [support {:#010x}]: ", self.name, self.support.bits())?;
+        match &self.result {
+            Ok(()) => write!(f, "ok"),
+            Err(err) => write!(f, "FAILED: {err}"),
+        }
+    }
+}
+
+/// Support combinations worth running the suite with: nothing, AUTO_INIT alone, each other flag
+/// next to AUTO_INIT, and everything.
+pub fn support_matrix() -> Vec<Support> {
+    let mut matrix = vec![Support::empty(), Support::AUTO_INIT];
+    matrix.extend(
+        Support::all()
+            .iter()
+            .filter(|flag| !flag.auto_init())
+            .map(|flag| flag | Support::AUTO_INIT),
+    );
+    matrix.push(Support::all());
+    matrix
+}
+
+/// Runs every test case on `target` once for each entry of `matrix`.
+pub fn run_suite<T: Target>(target: &mut T, matrix: &[Support]) -> Vec<TestResult> {
+    let mut results = vec![];
+    for support in matrix {
+        for case in cases::all::<T::Transport>() {
+            let result = target
+                .start(support)
+                .map_err(TestError::from)
+                .and_then(|mut client| (case.run)(&mut client, support));
+            results.push(TestResult {
+                name: case.name,
+                support: Support::from_bits_retain(support.bits()),
+                result,
+            });
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_support_matrix() {
+        let matrix = support_matrix();
+        assert_eq!(Support::all().iter().count() + 2, matrix.len());
+        assert!(matrix.iter().skip(1).all(|support| support.auto_init()));
+        assert_eq!(
+            Support::all().bits(),
+            matrix.iter().fold(0, |bits, support| bits | support.bits())
+        );
+    }
+
+    #[test]
+    fn test_in_process() {
+        let results = run_suite(&mut InProcessTarget, &support_matrix());
+        let failures: Vec<String> = results
+            .iter()
+            .filter(|result| result.result.is_err())
+            .map(|result| result.to_string())
+            .collect();
+        assert!(failures.is_empty(), "{failures:#?}");
+        assert_eq!(
+            support_matrix().len() * cases::all::<<InProcessTarget as Target>::Transport>().len(),
+            results.len()
+        );
+    }
+}
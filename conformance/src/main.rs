@@ -0,0 +1,35 @@
+// Licensed under the Apache-2.0 license
+
+use clap::Parser;
+use dpe_conformance::{run_suite, support_matrix, SimulatorTarget};
+use std::{path::PathBuf, process::ExitCode};
+
+/// Runs the DPE conformance suite against the simulator, once for each support combination.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Path to the simulator binary.
+    #[arg(long, default_value = "../simulator/target/debug/simulator")]
+    simulator: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let mut target = SimulatorTarget::new(args.simulator);
+    let results = run_suite(&mut target, &support_matrix());
+    let failures = results
+        .iter()
+        .filter(|result| result.result.is_err())
+        .count();
+    for result in &results {
+        println!("{result}");
+    }
+    println!("{} passed, {failures} failed", results.len() - failures);
+
+    if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,172 @@
+// Licensed under the Apache-2.0 license.
+
+//! DPEs the suite can run against.
+
+use crypto::OpensslCrypto;
+use dpe::{
+    dpe_instance::{DpeEnv, DpeTypes},
+    support::Support,
+    DpeInstance,
+};
+use dpe_client::{
+    transport::SIMULATOR_SOCKET_PATH, Client, ClientError, InProcessTransport, SocketTransport,
+    Transport,
+};
+use platform::default::{DefaultPlatform, AUTO_INIT_LOCALITY};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub trait Target {
+    type Transport: Transport;
+
+    /// Starts a DPE with `support` and returns a client for its auto-init locality.
+    ///
+    /// Whatever the target started before is stopped first, so every test case begins with a DPE
+    /// in its initial state.
+    fn start(&mut self, support: &Support) -> Result<Client<Self::Transport>, ClientError>;
+}
+
+pub struct ConformanceTypes;
+
+impl DpeTypes for ConformanceTypes {
+    type Crypto<'a> = OpensslCrypto;
+    type Platform<'a> = DefaultPlatform;
+    type EventSink<'a> = ();
+}
+
+/// Runs a `DpeInstance` in the same process.
+pub struct InProcessTarget;
+
+impl Target for InProcessTarget {
+    type Transport = InProcessTransport<'static, ConformanceTypes>;
+
+    fn start(&mut self, support: &Support) -> Result<Client<Self::Transport>, ClientError> {
+        let mut env = DpeEnv::<ConformanceTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let dpe = DpeInstance::new(&mut env, Support::from_bits_retain(support.bits()))
+            .map_err(|err| ClientError::Status(err as u32))?;
+        Ok(Client::new(
+            InProcessTransport::new(dpe, env),
+            AUTO_INIT_LOCALITY,
+        ))
+    }
+}
+
+/// Simulator arguments that turn on each support flag.
+const SIMULATOR_ARGS: [(Support, &str); 18] = [
+    (Support::SIMULATION, "--supports-simulation"),
+    (Support::EXTEND_TCI, "--supports-extend-tci"),
+    (Support::AUTO_INIT, "--supports-auto-init"),
+    (Support::TAGGING, "--supports-tagging"),
+    (Support::ROTATE_CONTEXT, "--supports-rotate-context"),
+    (Support::X509, "--supports-x509"),
+    (Support::CSR, "--supports-csr"),
+    (Support::IS_SYMMETRIC, "--supports-is-symmetric"),
+    (Support::INTERNAL_INFO, "--supports-internal-info"),
+    (Support::INTERNAL_DICE, "--supports-internal-dice"),
+    (Support::IS_CA, "--supports-is-ca"),
+    (Support::SEAL, "--supports-seal"),
+    (Support::CDI_EXPORT, "--supports-cdi-export"),
+    (Support::CONTEXT_TABLE, "--supports-context-table"),
+    (Support::EVENT_LOG, "--supports-event-log"),
+    (Support::QUOTE, "--supports-quote"),
+    (Support::EAT, "--supports-eat"),
+    (Support::SIGN_MESSAGE, "--supports-sign-message"),
+];
+
+/// How long a freshly started simulator gets to open its socket.
+const SIMULATOR_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the simulator binary, restarting it with the matching `--supports-*` arguments for
+/// every test case.
+pub struct SimulatorTarget {
+    path: PathBuf,
+    process: Option<Child>,
+}
+
+impl SimulatorTarget {
+    /// Creates a target that runs the simulator binary at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SimulatorTarget {
+            path: path.into(),
+            process: None,
+        }
+    }
+
+    fn args(support: &Support) -> impl Iterator<Item = &'static str> + '_ {
+        SIMULATOR_ARGS
+            .iter()
+            .filter(|(flag, _)| support.bits() & flag.bits() != 0)
+            .map(|(_, arg)| *arg)
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+        // A killed simulator leaves its socket behind.
+        let _ = fs::remove_file(SIMULATOR_SOCKET_PATH);
+    }
+}
+
+impl Target for SimulatorTarget {
+    type Transport = SocketTransport;
+
+    fn start(&mut self, support: &Support) -> Result<Client<Self::Transport>, ClientError> {
+        self.stop();
+        let process = self
+            .process
+            .insert(Command::new(&self.path).args(Self::args(support)).spawn()?);
+
+        let started = Instant::now();
+        while !Path::new(SIMULATOR_SOCKET_PATH).exists() {
+            if let Some(status) = process.try_wait()? {
+                return Err(
+                    Error::other(format!("simulator exited during startup: {status}")).into(),
+                );
+            }
+            if started.elapsed() > SIMULATOR_START_TIMEOUT {
+                return Err(Error::new(ErrorKind::TimedOut, "simulator did not start").into());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(Client::new(
+            SocketTransport::simulator(),
+            AUTO_INIT_LOCALITY,
+        ))
+    }
+}
+
+impl Drop for SimulatorTarget {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulator_args() {
+        assert_eq!(
+            Support::all().iter().count(),
+            SimulatorTarget::args(&Support::all()).count()
+        );
+        assert_eq!(0, SimulatorTarget::args(&Support::empty()).count());
+        assert_eq!(
+            vec!["--supports-auto-init", "--supports-x509"],
+            SimulatorTarget::args(&(Support::AUTO_INIT | Support::X509)).collect::<Vec<_>>()
+        );
+    }
+}
@@ -33,6 +33,23 @@ impl AlgLen {
     }
 }
 
+/// Size of the IV used by the AEAD functions.
+pub const AEAD_IV_SIZE: usize = 12;
+
+/// Size of the authentication tag produced by the AEAD functions.
+pub const AEAD_TAG_SIZE: usize = 16;
+
+/// Size of the opaque handle that refers to an exported CDI.
+pub const EXPORTED_CDI_HANDLE_SIZE: usize = 32;
+
+/// Opaque handle that refers to a CDI exported from DPE. The holder of the handle can retrieve
+/// the CDI through the crypto implementation and run its own DICE layer with it.
+pub type ExportedCdiHandle = [u8; EXPORTED_CDI_HANDLE_SIZE];
+
+/// Size of a saved running hash. Large enough for the widest supported hash: eight 64-bit state
+/// words, the byte count and a 128-byte partial block.
+pub const HASH_STATE_SIZE: usize = 200;
+
 #[derive(Debug)]
 pub enum CryptoError {
     AbstractionLayer,
@@ -55,6 +72,14 @@ pub trait Hasher: Sized {
     /// Once this function has been called, the object can no longer be used and
     /// a new one must be created to hash more data.
     fn finish(self) -> Result<Digest, CryptoError>;
+
+    /// Save the state of a running hash so it can be continued later with
+    /// [`Crypto::hash_resume`], for example across commands.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - Buffer to write the state to.
+    fn save(self, state: &mut [u8; HASH_STATE_SIZE]) -> Result<(), CryptoError>;
 }
 
 pub type Digest = CryptoBuf;
@@ -123,6 +148,18 @@ pub trait Crypto {
     /// * `algs` - Which length of algorithm to use.
     fn hash_initialize(&mut self, algs: AlgLen) -> Result<Self::Hasher<'_>, CryptoError>;
 
+    /// Continue a running hash from a state saved with [`Hasher::save`].
+    ///
+    /// # Arguments
+    ///
+    /// * `algs` - Which length of algorithm to use. Must match the saved hash.
+    /// * `state` - State of the running hash.
+    fn hash_resume(
+        &mut self,
+        algs: AlgLen,
+        state: &[u8; HASH_STATE_SIZE],
+    ) -> Result<Self::Hasher<'_>, CryptoError>;
+
     /// Derive a CDI based on the current base CDI and measurements
     ///
     /// # Arguments
@@ -137,6 +174,67 @@ pub trait Crypto {
         info: &[u8],
     ) -> Result<Self::Cdi, CryptoError>;
 
+    /// Derive a CDI for a simulation context.
+    ///
+    /// Instead of the base CDI, this is rooted in a secret that is randomly generated once per
+    /// boot, so simulation contexts can never derive the same secrets as real ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `algs` - Which length of algorithms to use.
+    /// * `measurement` - A digest of the measurements which should be used for CDI derivation
+    /// * `info` - Caller-supplied info string to use in CDI derivation
+    fn derive_simulation_cdi(
+        &mut self,
+        algs: AlgLen,
+        measurement: &Digest,
+        info: &[u8],
+    ) -> Result<Self::Cdi, CryptoError>;
+
+    /// Derive a CDI that will be exported from DPE, based on the current base CDI and
+    /// measurements
+    ///
+    /// Returns a handle that refers to the exported CDI.
+    ///
+    /// # Arguments
+    ///
+    /// * `algs` - Which length of algorithms to use.
+    /// * `measurement` - A digest of the measurements which should be used for CDI derivation
+    /// * `info` - Caller-supplied info string to use in CDI derivation
+    fn derive_exported_cdi(
+        &mut self,
+        algs: AlgLen,
+        measurement: &Digest,
+        info: &[u8],
+    ) -> Result<ExportedCdiHandle, CryptoError>;
+
+    /// Removes the CDI referred to by an exported CDI handle and returns it, so that the platform
+    /// can hand it to the next layer. The handle can't be used afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `exported_handle` - Handle returned by `derive_exported_cdi`
+    fn take_exported_cdi(
+        &mut self,
+        exported_handle: &ExportedCdiHandle,
+    ) -> Result<Self::Cdi, CryptoError>;
+
+    /// Derives a key pair from an exported CDI using a cryptographically secure KDF
+    ///
+    /// # Arguments
+    ///
+    /// * `algs` - Which length of algorithms to use.
+    /// * `exported_handle` - Handle of the exported CDI to use in key derivation
+    /// * `label` - Caller-supplied label to use in asymmetric key derivation
+    /// * `info` - Caller-supplied info string to use in asymmetric key derivation
+    fn derive_key_pair_exported(
+        &mut self,
+        algs: AlgLen,
+        exported_handle: &ExportedCdiHandle,
+        label: &[u8],
+        info: &[u8],
+    ) -> Result<(Self::PrivKey, EcdsaPub), CryptoError>;
+
     /// Derives a key pair using a cryptographically secure KDF
     ///
     /// # Arguments
@@ -200,6 +298,77 @@ pub trait Crypto {
         info: &[u8],
         digest: &Digest,
     ) -> Result<HmacSig, CryptoError>;
+
+    /// Compute an HMAC over `data` with a key derived from a secret that is not a CDI, such as a
+    /// platform secret. The key is derived with HKDF: extracted from `secret`, then expanded with
+    /// `info`.
+    ///
+    /// # Arguments
+    ///
+    /// * `algs` - Which length of algorithms to use.
+    /// * `secret` - Secret to derive the key from.
+    /// * `info` - Caller-supplied info string to use in key derivation
+    /// * `data` - Data to compute the HMAC over.
+    fn hmac_with_secret(
+        &mut self,
+        algs: AlgLen,
+        secret: &[u8],
+        info: &[u8],
+        data: &[u8],
+    ) -> Result<HmacSig, CryptoError>;
+
+    /// Encrypt `data` in place with an AES-256-GCM key derived from the CDI.
+    ///
+    /// Returns the authentication tag over `aad` and the ciphertext.
+    ///
+    /// # Arguments
+    ///
+    /// * `algs` - Which length of algorithms to use.
+    /// * `cdi` - CDI from which to derive the encryption key
+    /// * `label` - Caller-supplied label to use in symmetric key derivation
+    /// * `info` - Caller-supplied info string to use in symmetric key derivation
+    /// * `iv` - Unique IV for this encryption.
+    /// * `aad` - Additional data to authenticate but not encrypt.
+    /// * `data` - Plaintext to be encrypted. Replaced with the ciphertext.
+    #[allow(clippy::too_many_arguments)]
+    fn aead_encrypt_with_derived(
+        &mut self,
+        algs: AlgLen,
+        cdi: &Self::Cdi,
+        label: &[u8],
+        info: &[u8],
+        iv: &[u8; AEAD_IV_SIZE],
+        aad: &[u8],
+        data: &mut [u8],
+    ) -> Result<[u8; AEAD_TAG_SIZE], CryptoError>;
+
+    /// Decrypt `data` in place with an AES-256-GCM key derived from the CDI.
+    ///
+    /// Fails if `tag` does not authenticate `aad` and the ciphertext. `data` must not be
+    /// used if this function fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `algs` - Which length of algorithms to use.
+    /// * `cdi` - CDI from which to derive the encryption key
+    /// * `label` - Caller-supplied label to use in symmetric key derivation
+    /// * `info` - Caller-supplied info string to use in symmetric key derivation
+    /// * `iv` - IV that was used for the encryption.
+    /// * `aad` - Additional data that was authenticated during encryption.
+    /// * `data` - Ciphertext to be decrypted. Replaced with the plaintext.
+    /// * `tag` - Authentication tag returned by the encryption.
+    #[allow(clippy::too_many_arguments)]
+    fn aead_decrypt_with_derived(
+        &mut self,
+        algs: AlgLen,
+        cdi: &Self::Cdi,
+        label: &[u8],
+        info: &[u8],
+        iv: &[u8; AEAD_IV_SIZE],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8; AEAD_TAG_SIZE],
+    ) -> Result<(), CryptoError>;
 }
 #[cfg(test)]
 mod tests {
@@ -1,6 +1,9 @@
 // Licensed under the Apache-2.0 license
 
-use crate::{AlgLen, Crypto, CryptoBuf, CryptoError, Digest, EcdsaPub, Hasher, HmacSig};
+use crate::{
+    AlgLen, Crypto, CryptoBuf, CryptoError, Digest, EcdsaPub, ExportedCdiHandle, Hasher, HmacSig,
+    AEAD_IV_SIZE, AEAD_TAG_SIZE, EXPORTED_CDI_HANDLE_SIZE, HASH_STATE_SIZE,
+};
 use hkdf::Hkdf;
 use openssl::{
     bn::{BigNum, BigNumContext},
@@ -11,31 +14,186 @@ use openssl::{
     nid::Nid,
     pkey::{PKey, Private},
     sign::Signer,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
 };
-use sha2::{Sha256, Sha384};
+use sha2::{digest::generic_array::GenericArray, Sha256, Sha384};
+
+/// Initial hash value for SHA-256 (FIPS 180-4, 5.3.3).
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Initial hash value for SHA-384 (FIPS 180-4, 5.3.4).
+const SHA384_IV: [u64; 8] = [
+    0xcbbb9d5dc1059ed8,
+    0x629a292a367cd507,
+    0x9159015a3070dd17,
+    0x152fecd8f70e5939,
+    0x67332667ffc00b31,
+    0x8eb44a8768581511,
+    0xdb0c2e0d64f98fa7,
+    0x47b5481dbefa4fa4,
+];
+
+const SHA256_BLOCK_SIZE: usize = 64;
+const SHA384_BLOCK_SIZE: usize = 128;
+
+/// SHA-2 running hash whose intermediate state can be saved with [`Hasher::save`] and picked up
+/// again with [`Crypto::hash_resume`].
+///
+/// The state is laid out as the eight state words (big-endian, 8 bytes each), the number of bytes
+/// hashed so far (big-endian) and the pending partial block.
+pub struct Sha2Hasher {
+    algs: AlgLen,
+    state: [u64; 8],
+    len: u64,
+    block: [u8; SHA384_BLOCK_SIZE],
+}
+
+impl Sha2Hasher {
+    fn new(algs: AlgLen) -> Self {
+        let state = match algs {
+            AlgLen::Bit256 => SHA256_IV.map(u64::from),
+            AlgLen::Bit384 => SHA384_IV,
+        };
+        Self {
+            algs,
+            state,
+            len: 0,
+            block: [0; SHA384_BLOCK_SIZE],
+        }
+    }
+
+    fn resume(algs: AlgLen, saved: &[u8; HASH_STATE_SIZE]) -> Result<Self, CryptoError> {
+        let mut hasher = Self::new(algs);
+        let (words, rest) = saved.split_at(64);
+        let (len, block) = rest.split_at(8);
+        for (word, bytes) in hasher.state.iter_mut().zip(words.chunks_exact(8)) {
+            *word = u64::from_be_bytes(bytes.try_into().unwrap());
+        }
+        hasher.len = u64::from_be_bytes(len.try_into().unwrap());
+        hasher.block.copy_from_slice(block);
+        if matches!(algs, AlgLen::Bit256) && hasher.state.iter().any(|w| *w > u32::MAX as u64) {
+            return Err(CryptoError::HashError);
+        }
+        Ok(hasher)
+    }
 
-pub struct OpensslHasher(openssl::hash::Hasher, AlgLen);
+    fn block_size(&self) -> usize {
+        match self.algs {
+            AlgLen::Bit256 => SHA256_BLOCK_SIZE,
+            AlgLen::Bit384 => SHA384_BLOCK_SIZE,
+        }
+    }
+
+    fn compress(&mut self) {
+        match self.algs {
+            AlgLen::Bit256 => {
+                let mut state = self.state.map(|w| w as u32);
+                let block = GenericArray::from_slice(&self.block[..SHA256_BLOCK_SIZE]);
+                sha2::compress256(&mut state, core::slice::from_ref(block));
+                self.state = state.map(u64::from);
+            }
+            AlgLen::Bit384 => {
+                let block = GenericArray::from_slice(&self.block[..]);
+                sha2::compress512(&mut self.state, core::slice::from_ref(block));
+            }
+        }
+    }
+}
 
-impl Hasher for OpensslHasher {
-    fn update(&mut self, bytes: &[u8]) -> Result<(), CryptoError> {
-        self.0
-            .update(bytes)
-            .map_err(|_| CryptoError::CryptoLibError)
+impl Hasher for Sha2Hasher {
+    fn update(&mut self, mut bytes: &[u8]) -> Result<(), CryptoError> {
+        let block_size = self.block_size();
+        while !bytes.is_empty() {
+            let pos = (self.len % block_size as u64) as usize;
+            let n = bytes.len().min(block_size - pos);
+            self.block[pos..pos + n].copy_from_slice(&bytes[..n]);
+            self.len = self.len.checked_add(n as u64).ok_or(CryptoError::HashError)?;
+            bytes = &bytes[n..];
+            if pos + n == block_size {
+                self.compress();
+            }
+        }
+        Ok(())
     }
 
     fn finish(mut self) -> Result<Digest, CryptoError> {
-        Digest::new(
-            &self.0.finish().map_err(|_| CryptoError::CryptoLibError)?,
-            self.1,
-        )
+        let block_size = self.block_size();
+        // The message length in bits goes at the end of the last block, big-endian. SHA-384 has
+        // room for a 128-bit length but messages here never come close to 2^64 bits.
+        let len_size = block_size / 8;
+        let bit_len = self
+            .len
+            .checked_mul(8)
+            .ok_or(CryptoError::HashError)?
+            .to_be_bytes();
+
+        let mut pos = (self.len % block_size as u64) as usize;
+        self.block[pos] = 0x80;
+        pos += 1;
+        if pos > block_size - len_size {
+            self.block[pos..block_size].fill(0);
+            self.compress();
+            pos = 0;
+        }
+        self.block[pos..block_size].fill(0);
+        self.block[block_size - 8..block_size].copy_from_slice(&bit_len);
+        self.compress();
+
+        let mut out = [0; SHA384_BLOCK_SIZE / 2];
+        match self.algs {
+            AlgLen::Bit256 => {
+                for (bytes, word) in out.chunks_exact_mut(4).zip(self.state) {
+                    bytes.copy_from_slice(&(word as u32).to_be_bytes());
+                }
+            }
+            AlgLen::Bit384 => {
+                for (bytes, word) in out.chunks_exact_mut(8).zip(self.state) {
+                    bytes.copy_from_slice(&word.to_be_bytes());
+                }
+            }
+        }
+        Digest::new(&out[..self.algs.size()], self.algs)
+    }
+
+    fn save(self, saved: &mut [u8; HASH_STATE_SIZE]) -> Result<(), CryptoError> {
+        let (words, rest) = saved.split_at_mut(64);
+        let (len, block) = rest.split_at_mut(8);
+        for (bytes, word) in words.chunks_exact_mut(8).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        len.copy_from_slice(&self.len.to_be_bytes());
+        block.copy_from_slice(&self.block);
+        Ok(())
     }
 }
 
-pub struct OpensslCrypto;
+/// Most exported CDIs kept at once. Exporting another one drops the oldest, so CDIs that are never
+/// collected don't pile up.
+pub const MAX_EXPORTED_CDIS: usize = 8;
+
+pub struct OpensslCrypto {
+    /// Oldest first.
+    exported_cdis: Vec<(ExportedCdiHandle, OpensslCdi)>,
+    simulation_root: Option<[u8; AlgLen::Bit384.size()]>,
+}
 
 impl OpensslCrypto {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            exported_cdis: Vec::new(),
+            simulation_root: None,
+        }
+    }
+
+    /// Get the CDI referred to by an exported CDI handle without consuming it. The next layer
+    /// collects its CDI with `Crypto::take_exported_cdi`.
+    pub fn get_exported_cdi(&self, exported_handle: &ExportedCdiHandle) -> Option<&[u8]> {
+        self.exported_cdis
+            .iter()
+            .find(|(handle, _)| handle == exported_handle)
+            .map(|(_, cdi)| cdi.as_slice())
     }
 
     fn get_digest(algs: AlgLen) -> MessageDigest {
@@ -68,6 +226,25 @@ impl OpensslCrypto {
 
         EcKey::from_private_components(&group, priv_key_bn, &pub_point)
     }
+
+    const AEAD_KEY_SIZE: usize = 32;
+
+    fn derive_aead_key(
+        algs: AlgLen,
+        cdi: &OpensslCdi,
+        label: &[u8],
+        info: &[u8],
+    ) -> Result<[u8; Self::AEAD_KEY_SIZE], CryptoError> {
+        let mut key = [0u8; Self::AEAD_KEY_SIZE];
+        let info = [info, label].concat();
+        match algs {
+            AlgLen::Bit256 => Hkdf::<Sha256>::new(None, cdi).expand(&info, &mut key),
+            AlgLen::Bit384 => Hkdf::<Sha384>::new(None, cdi).expand(&info, &mut key),
+        }
+        .map_err(|_| CryptoError::CryptoLibError)?;
+
+        Ok(key)
+    }
 }
 
 type OpensslCdi = Vec<u8>;
@@ -76,7 +253,7 @@ type OpensslPrivKey = CryptoBuf;
 
 impl Crypto for OpensslCrypto {
     type Cdi = OpensslCdi;
-    type Hasher<'c> = OpensslHasher where Self: 'c;
+    type Hasher<'c> = Sha2Hasher where Self: 'c;
     type PrivKey = OpensslPrivKey;
 
     #[cfg(feature = "deterministic_rand")]
@@ -93,11 +270,15 @@ impl Crypto for OpensslCrypto {
     }
 
     fn hash_initialize(&mut self, algs: AlgLen) -> Result<Self::Hasher<'_>, CryptoError> {
-        let md = Self::get_digest(algs);
-        Ok(OpensslHasher(
-            openssl::hash::Hasher::new(md).map_err(|_| CryptoError::CryptoLibError)?,
-            algs,
-        ))
+        Ok(Sha2Hasher::new(algs))
+    }
+
+    fn hash_resume(
+        &mut self,
+        algs: AlgLen,
+        state: &[u8; HASH_STATE_SIZE],
+    ) -> Result<Self::Hasher<'_>, CryptoError> {
+        Sha2Hasher::resume(algs, state)
     }
 
     fn derive_cdi(
@@ -126,6 +307,90 @@ impl Crypto for OpensslCrypto {
         }
     }
 
+    fn derive_simulation_cdi(
+        &mut self,
+        algs: AlgLen,
+        measurement: &Digest,
+        info: &[u8],
+    ) -> Result<Self::Cdi, CryptoError> {
+        let root = match self.simulation_root {
+            Some(root) => root,
+            None => {
+                let mut root = [0u8; AlgLen::Bit384.size()];
+                self.rand_bytes(&mut root)?;
+                self.simulation_root = Some(root);
+                root
+            }
+        };
+        let ikm = [&root, measurement.bytes()].concat();
+
+        match algs {
+            AlgLen::Bit256 => {
+                let hk = Hkdf::<Sha256>::new(Some(info), &ikm);
+                let mut cdi = [0u8; AlgLen::Bit256.size()];
+                hk.expand(measurement.bytes(), &mut cdi)
+                    .map_err(|_| CryptoError::CryptoLibError)?;
+
+                Ok(cdi.to_vec())
+            }
+            AlgLen::Bit384 => {
+                let hk = Hkdf::<Sha384>::new(Some(info), &ikm);
+                let mut cdi = [0u8; AlgLen::Bit384.size()];
+                hk.expand(measurement.bytes(), &mut cdi)
+                    .map_err(|_| CryptoError::CryptoLibError)?;
+
+                Ok(cdi.to_vec())
+            }
+        }
+    }
+
+    fn derive_exported_cdi(
+        &mut self,
+        algs: AlgLen,
+        measurement: &Digest,
+        info: &[u8],
+    ) -> Result<ExportedCdiHandle, CryptoError> {
+        let cdi = self.derive_cdi(algs, measurement, info)?;
+        let mut exported_handle = [0u8; EXPORTED_CDI_HANDLE_SIZE];
+        self.rand_bytes(&mut exported_handle)?;
+
+        // Replace the CDI if the handle is already in use.
+        self.exported_cdis
+            .retain(|(handle, _)| handle != &exported_handle);
+        if self.exported_cdis.len() >= MAX_EXPORTED_CDIS {
+            self.exported_cdis.remove(0);
+        }
+        self.exported_cdis.push((exported_handle, cdi));
+
+        Ok(exported_handle)
+    }
+
+    fn take_exported_cdi(
+        &mut self,
+        exported_handle: &ExportedCdiHandle,
+    ) -> Result<Self::Cdi, CryptoError> {
+        let idx = self
+            .exported_cdis
+            .iter()
+            .position(|(handle, _)| handle == exported_handle)
+            .ok_or(CryptoError::AbstractionLayer)?;
+        Ok(self.exported_cdis.remove(idx).1)
+    }
+
+    fn derive_key_pair_exported(
+        &mut self,
+        algs: AlgLen,
+        exported_handle: &ExportedCdiHandle,
+        label: &[u8],
+        info: &[u8],
+    ) -> Result<(Self::PrivKey, EcdsaPub), CryptoError> {
+        let cdi = self
+            .get_exported_cdi(exported_handle)
+            .ok_or(CryptoError::AbstractionLayer)?
+            .to_vec();
+        self.derive_key_pair(algs, &cdi, label, info)
+    }
+
     fn derive_key_pair(
         &mut self,
         algs: AlgLen,
@@ -225,4 +490,182 @@ impl Crypto for OpensslCrypto {
 
         Ok(HmacSig::new(&hmac, algs).unwrap())
     }
+
+    fn hmac_with_secret(
+        &mut self,
+        algs: AlgLen,
+        secret: &[u8],
+        info: &[u8],
+        data: &[u8],
+    ) -> Result<HmacSig, CryptoError> {
+        let mut key = [0u8; AlgLen::MAX_ALG_LEN_BYTES];
+        let key = &mut key[..algs.size()];
+        match algs {
+            AlgLen::Bit256 => Hkdf::<Sha256>::new(None, secret).expand(info, key),
+            AlgLen::Bit384 => Hkdf::<Sha384>::new(None, secret).expand(info, key),
+        }
+        .map_err(|_| CryptoError::CryptoLibError)?;
+
+        let hmac_key = PKey::hmac(key).map_err(|_| CryptoError::CryptoLibError)?;
+        let mut signer = Signer::new(Self::get_digest(algs), &hmac_key)
+            .map_err(|_| CryptoError::CryptoLibError)?;
+        signer
+            .update(data)
+            .map_err(|_| CryptoError::CryptoLibError)?;
+        let hmac = signer
+            .sign_to_vec()
+            .map_err(|_| CryptoError::CryptoLibError)?;
+        HmacSig::new(&hmac, algs)
+    }
+
+    fn aead_encrypt_with_derived(
+        &mut self,
+        algs: AlgLen,
+        cdi: &Self::Cdi,
+        label: &[u8],
+        info: &[u8],
+        iv: &[u8; AEAD_IV_SIZE],
+        aad: &[u8],
+        data: &mut [u8],
+    ) -> Result<[u8; AEAD_TAG_SIZE], CryptoError> {
+        let key = Self::derive_aead_key(algs, cdi, label, info)?;
+        let mut tag = [0u8; AEAD_TAG_SIZE];
+        let ciphertext = encrypt_aead(Cipher::aes_256_gcm(), &key, Some(iv), aad, data, &mut tag)
+            .map_err(|_| CryptoError::CryptoLibError)?;
+        data.copy_from_slice(&ciphertext);
+
+        Ok(tag)
+    }
+
+    fn aead_decrypt_with_derived(
+        &mut self,
+        algs: AlgLen,
+        cdi: &Self::Cdi,
+        label: &[u8],
+        info: &[u8],
+        iv: &[u8; AEAD_IV_SIZE],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8; AEAD_TAG_SIZE],
+    ) -> Result<(), CryptoError> {
+        let key = Self::derive_aead_key(algs, cdi, label, info)?;
+        let plaintext = decrypt_aead(Cipher::aes_256_gcm(), &key, Some(iv), aad, data, tag)
+            .map_err(|_| CryptoError::CryptoLibError)?;
+        data.copy_from_slice(&plaintext);
+
+        Ok(())
+    }
+}
+
+// The deterministic RNG hands out the same handle every time, so these need the real one.
+#[cfg(all(test, not(feature = "deterministic_rand")))]
+mod tests {
+    use super::*;
+
+    fn export(crypto: &mut OpensslCrypto, i: u8) -> ExportedCdiHandle {
+        let measurement = Digest::new(&[i; AlgLen::Bit256.size()], AlgLen::Bit256).unwrap();
+        crypto
+            .derive_exported_cdi(AlgLen::Bit256, &measurement, b"DPE")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_hash_matches_openssl() {
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+        let mut crypto = OpensslCrypto::new();
+        for algs in [AlgLen::Bit256, AlgLen::Bit384] {
+            let md = OpensslCrypto::get_digest(algs);
+            for len in [0, 1, 55, 56, 63, 64, 65, 111, 112, 127, 128, 129, 300] {
+                let expected = openssl::hash::hash(md, &data[..len]).unwrap();
+                let digest = crypto.hash(algs, &data[..len]).unwrap();
+                assert_eq!(digest.bytes(), &expected[..], "{algs:?} {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_save_and_resume() {
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 13) as u8).collect();
+        let mut crypto = OpensslCrypto::new();
+        for algs in [AlgLen::Bit256, AlgLen::Bit384] {
+            let expected = crypto.hash(algs, &data).unwrap();
+            for split in [0, 1, 63, 64, 100, 128, 250, 300] {
+                let mut state = [0; HASH_STATE_SIZE];
+                let mut hasher = crypto.hash_initialize(algs).unwrap();
+                hasher.update(&data[..split]).unwrap();
+                hasher.save(&mut state).unwrap();
+
+                let mut hasher = crypto.hash_resume(algs, &state).unwrap();
+                hasher.update(&data[split..]).unwrap();
+                let digest = hasher.finish().unwrap();
+                assert_eq!(digest.bytes(), expected.bytes(), "{algs:?} {split}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_hmac_with_secret() {
+        let mut crypto = OpensslCrypto::new();
+        for algs in [AlgLen::Bit256, AlgLen::Bit384] {
+            let mac = crypto.hmac_with_secret(algs, b"secret", b"info", b"data").unwrap();
+            assert_eq!(algs.size(), mac.bytes().len());
+            for (secret, info, data) in [
+                (b"Secret", b"info", b"data"),
+                (b"secret", b"Info", b"data"),
+                (b"secret", b"info", b"Data"),
+            ] {
+                let other = crypto.hmac_with_secret(algs, secret, info, data).unwrap();
+                assert_ne!(mac.bytes(), other.bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_derive_aead_key() {
+        // The CDI is the only keying material. Info and label are both HKDF info, not salt.
+        let cdi = vec![0x11; AlgLen::Bit384.size()];
+        let mut expected = [0u8; OpensslCrypto::AEAD_KEY_SIZE];
+        Hkdf::<Sha384>::new(None, &cdi)
+            .expand(b"infolabel", &mut expected)
+            .unwrap();
+        let key = OpensslCrypto::derive_aead_key(AlgLen::Bit384, &cdi, b"label", b"info").unwrap();
+        assert_eq!(expected, key);
+    }
+
+    #[test]
+    fn test_exported_cdis_are_capped() {
+        let mut crypto = OpensslCrypto::new();
+        let handles: Vec<_> = (0..MAX_EXPORTED_CDIS as u8 + 3)
+            .map(|i| export(&mut crypto, i))
+            .collect();
+        assert_eq!(MAX_EXPORTED_CDIS, crypto.exported_cdis.len());
+
+        // The oldest ones were dropped to make room.
+        let (dropped, kept) = handles.split_at(3);
+        assert!(dropped
+            .iter()
+            .all(|handle| crypto.get_exported_cdi(handle).is_none()));
+        assert!(kept
+            .iter()
+            .all(|handle| crypto.get_exported_cdi(handle).is_some()));
+    }
+
+    #[test]
+    fn test_take_exported_cdi() {
+        let mut crypto = OpensslCrypto::new();
+        let handle = export(&mut crypto, 1);
+        let other = export(&mut crypto, 2);
+        let cdi = crypto.get_exported_cdi(&handle).unwrap().to_vec();
+
+        assert_eq!(Some(cdi), crypto.take_exported_cdi(&handle).ok());
+        assert_eq!(None, crypto.get_exported_cdi(&handle));
+        assert!(crypto.take_exported_cdi(&handle).is_err());
+        assert!(crypto
+            .derive_key_pair_exported(AlgLen::Bit256, &handle, b"label", b"info")
+            .is_err());
+
+        // Other exported CDIs are unaffected.
+        assert!(crypto.get_exported_cdi(&other).is_some());
+        assert_eq!(1, crypto.exported_cdis.len());
+    }
 }
@@ -28,6 +28,7 @@ struct SimTypes {}
 impl DpeTypes for SimTypes {
     type Crypto<'a> = OpensslCrypto;
     type Platform = DefaultPlatform;
+    type EventSink<'a> = ();
 }
 
 // Although fuzzers use persistent mode, using an internal worker shortens the lifetime.
@@ -57,7 +58,8 @@ fn harness(data: &[u8]) {
 
     let mut env = DpeEnv::<SimTypes> {
         crypto: OpensslCrypto::new(),
-        platform: DefaultPlatform,
+        platform: DefaultPlatform::new(),
+        event_sink: (),
     };
     let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
 
@@ -65,6 +67,8 @@ fn harness(data: &[u8]) {
     let response = dpe
         .execute_serialized_command(&mut env, AUTO_INIT_LOCALITY, data)
         .unwrap();
+    // No command may leave the context table inconsistent.
+    dpe.validate().unwrap();
 
     let response_code = match response {
         Response::GetProfile(ref res) => res.resp_hdr.status,
@@ -78,6 +82,19 @@ fn harness(data: &[u8]) {
         Response::TagTci(ref res) => res.resp_hdr.status,
         Response::GetTaggedTci(ref res) => res.resp_hdr.status,
         Response::GetCertificateChain(ref res) => res.resp_hdr.status,
+        Response::Seal(ref res) => res.resp_hdr.status,
+        Response::Unseal(ref res) => res.resp_hdr.status,
+        Response::SignStreamInit(ref res) => res.resp_hdr.status,
+        Response::SignStreamUpdate(ref res) => res.resp_hdr.status,
+        Response::SignStreamFinal(ref res) => res.resp_hdr.status,
+        Response::DeriveChildExportedCdi(ref res) => res.resp_hdr.status,
+        Response::GetTaggedTciInfo(ref res) => res.resp_hdr.status,
+        Response::GetContextTable(ref res) => res.resp_hdr.status,
+        Response::Batch(ref res) => res.resp_hdr.status,
+        Response::GetLocalityQuota(ref res) => res.resp_hdr.status,
+        Response::GetEventLog(ref res) => res.resp_hdr.status,
+        Response::Quote(ref res) => res.resp_hdr.status,
+        Response::GetEat(ref res) => res.resp_hdr.status,
         Response::Error(ref resp_hdr) => resp_hdr.status,
     };
     // There are a few vendor error codes starting at 0x1000, so this can be a 2 bytes.
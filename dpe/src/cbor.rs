@@ -0,0 +1,403 @@
+// Licensed under the Apache-2.0 license
+
+//! Lightweight CBOR encoding routines for DPE
+//!
+//! DPE emits Entity Attestation Tokens (EAT) as CBOR Web Tokens (CWT) wrapped in a COSE_Sign1.
+//! This module provides the deterministic CBOR encoding (RFC 8949 section 4.2) needed to build
+//! them in a no_std environment.
+
+use crate::{
+    response::{DpeErrorCode, GetProfileResp},
+    tci::TciNodeData,
+    DpeProfile, DPE_PROFILE,
+};
+
+/// The EAT claims DPE puts in a token.
+///
+/// `nonce` and `ueid` use the registered EAT claim keys. DPE specific claims use keys from the
+/// CWT private use range.
+pub struct EatClaims<'a> {
+    pub nonce: &'a [u8],
+    pub ueid: &'a [u8],
+    pub profile: &'a GetProfileResp,
+    pub tci_nodes: &'a [TciNodeData],
+    /// Whether the token is signed by a key belonging to a simulation context.
+    pub is_simulation: bool,
+}
+
+pub struct CborWriter<'a> {
+    buf: &'a mut [u8],
+    offset: usize,
+}
+
+impl CborWriter<'_> {
+    const MAJOR_UINT: u8 = 0;
+    const MAJOR_NINT: u8 = 1;
+    const MAJOR_BSTR: u8 = 2;
+    const MAJOR_TSTR: u8 = 3;
+    const MAJOR_ARRAY: u8 = 4;
+    const MAJOR_MAP: u8 = 5;
+    const MAJOR_TAG: u8 = 6;
+    const MAJOR_SIMPLE: u8 = 7;
+
+    const SIMPLE_TRUE: u8 = 21;
+
+    // RFC 9052 COSE_Sign1 tag
+    pub const COSE_SIGN1_TAG: u64 = 18;
+
+    // RFC 9052 header parameters
+    const COSE_HEADER_ALG: i64 = 1;
+    // RFC 9360 x5chain
+    const COSE_HEADER_X5CHAIN: i64 = 33;
+
+    const COSE_ALG: i64 = match DPE_PROFILE {
+        // ES256
+        DpeProfile::P256Sha256 => -7,
+        // ES384
+        DpeProfile::P384Sha384 => -35,
+    };
+
+    const SIG_STRUCTURE_CONTEXT: &'static str = "Signature1";
+
+    // RFC 9711 eat_nonce and ueid
+    pub const EAT_CLAIM_NONCE: i64 = 10;
+    pub const EAT_CLAIM_UEID: i64 = 256;
+
+    // DPE claims, from the CWT private use range (less than -65536)
+    pub const DPE_CLAIM_PROFILE: i64 = -70000;
+    pub const DPE_CLAIM_TCI_CHAIN: i64 = -70001;
+    pub const DPE_CLAIM_SIMULATION: i64 = -70002;
+
+    // Keys of the dpe-profile map
+    pub const PROFILE_KEY_PROFILE: i64 = 1;
+    pub const PROFILE_KEY_MAJOR_VERSION: i64 = 2;
+    pub const PROFILE_KEY_MINOR_VERSION: i64 = 3;
+    pub const PROFILE_KEY_VENDOR_ID: i64 = 4;
+    pub const PROFILE_KEY_VENDOR_SKU: i64 = 5;
+    pub const PROFILE_KEY_MAX_TCI_NODES: i64 = 6;
+    pub const PROFILE_KEY_FLAGS: i64 = 7;
+
+    // Keys of each map in the dpe-tci-chain array
+    pub const TCI_KEY_TYPE: i64 = 1;
+    pub const TCI_KEY_CUMULATIVE: i64 = 2;
+    pub const TCI_KEY_CURRENT: i64 = 3;
+    pub const TCI_KEY_LOCALITY: i64 = 4;
+
+    /// Build new CborWriter that writes output to `buf`
+    pub fn new(buf: &mut [u8]) -> CborWriter<'_> {
+        CborWriter { buf, offset: 0 }
+    }
+
+    fn encode_bytes(&mut self, bytes: &[u8]) -> Result<usize, DpeErrorCode> {
+        let end = self
+            .offset
+            .checked_add(bytes.len())
+            .ok_or(DpeErrorCode::InternalError)?;
+        if end > self.buf.len() {
+            return Err(DpeErrorCode::InternalError);
+        }
+
+        self.buf[self.offset..end].copy_from_slice(bytes);
+        self.offset = end;
+
+        Ok(bytes.len())
+    }
+
+    /// Encode the initial byte and argument of a data item, using the shortest form
+    fn encode_head(&mut self, major: u8, arg: u64) -> Result<usize, DpeErrorCode> {
+        let major = major << 5;
+        if arg < 24 {
+            self.encode_bytes(&[major | arg as u8])
+        } else if arg <= u8::MAX as u64 {
+            self.encode_bytes(&[major | 24, arg as u8])
+        } else if arg <= u16::MAX as u64 {
+            Ok(self.encode_bytes(&[major | 25])?
+                + self.encode_bytes(&(arg as u16).to_be_bytes())?)
+        } else if arg <= u32::MAX as u64 {
+            Ok(self.encode_bytes(&[major | 26])?
+                + self.encode_bytes(&(arg as u32).to_be_bytes())?)
+        } else {
+            Ok(self.encode_bytes(&[major | 27])? + self.encode_bytes(&arg.to_be_bytes())?)
+        }
+    }
+
+    pub fn encode_uint(&mut self, val: u64) -> Result<usize, DpeErrorCode> {
+        self.encode_head(Self::MAJOR_UINT, val)
+    }
+
+    pub fn encode_int(&mut self, val: i64) -> Result<usize, DpeErrorCode> {
+        if val >= 0 {
+            self.encode_head(Self::MAJOR_UINT, val as u64)
+        } else {
+            // A negative integer n is encoded as -1 - n
+            self.encode_head(Self::MAJOR_NINT, !(val as u64))
+        }
+    }
+
+    pub fn encode_bstr(&mut self, bytes: &[u8]) -> Result<usize, DpeErrorCode> {
+        Ok(self.encode_head(Self::MAJOR_BSTR, bytes.len() as u64)? + self.encode_bytes(bytes)?)
+    }
+
+    /// Encode the head of a byte string of `len` bytes. The bytes are written next.
+    pub fn encode_bstr_head(&mut self, len: usize) -> Result<usize, DpeErrorCode> {
+        self.encode_head(Self::MAJOR_BSTR, len as u64)
+    }
+
+    pub fn encode_tstr(&mut self, s: &str) -> Result<usize, DpeErrorCode> {
+        Ok(
+            self.encode_head(Self::MAJOR_TSTR, s.len() as u64)?
+                + self.encode_bytes(s.as_bytes())?,
+        )
+    }
+
+    /// Encode the head of an array with `len` items. The items are encoded next.
+    pub fn encode_array(&mut self, len: usize) -> Result<usize, DpeErrorCode> {
+        self.encode_head(Self::MAJOR_ARRAY, len as u64)
+    }
+
+    /// Encode the head of a map with `len` pairs. The keys and values are encoded next.
+    pub fn encode_map(&mut self, len: usize) -> Result<usize, DpeErrorCode> {
+        self.encode_head(Self::MAJOR_MAP, len as u64)
+    }
+
+    pub fn encode_tag(&mut self, tag: u64) -> Result<usize, DpeErrorCode> {
+        self.encode_head(Self::MAJOR_TAG, tag)
+    }
+
+    pub fn encode_true(&mut self) -> Result<usize, DpeErrorCode> {
+        self.encode_head(Self::MAJOR_SIMPLE, Self::SIMPLE_TRUE as u64)
+    }
+
+    fn encode_profile(&mut self, profile: &GetProfileResp) -> Result<usize, DpeErrorCode> {
+        let mut bytes_written = self.encode_map(7)?;
+        bytes_written += self.encode_int(Self::PROFILE_KEY_PROFILE)?;
+        bytes_written += self.encode_uint(profile.resp_hdr.profile.into())?;
+        bytes_written += self.encode_int(Self::PROFILE_KEY_MAJOR_VERSION)?;
+        bytes_written += self.encode_uint(profile.major_version.into())?;
+        bytes_written += self.encode_int(Self::PROFILE_KEY_MINOR_VERSION)?;
+        bytes_written += self.encode_uint(profile.minor_version.into())?;
+        bytes_written += self.encode_int(Self::PROFILE_KEY_VENDOR_ID)?;
+        bytes_written += self.encode_uint(profile.vendor_id.into())?;
+        bytes_written += self.encode_int(Self::PROFILE_KEY_VENDOR_SKU)?;
+        bytes_written += self.encode_uint(profile.vendor_sku.into())?;
+        bytes_written += self.encode_int(Self::PROFILE_KEY_MAX_TCI_NODES)?;
+        bytes_written += self.encode_uint(profile.max_tci_nodes.into())?;
+        bytes_written += self.encode_int(Self::PROFILE_KEY_FLAGS)?;
+        bytes_written += self.encode_uint(profile.flags.into())?;
+
+        Ok(bytes_written)
+    }
+
+    fn encode_tci_node(&mut self, node: &TciNodeData) -> Result<usize, DpeErrorCode> {
+        let mut bytes_written = self.encode_map(4)?;
+        bytes_written += self.encode_int(Self::TCI_KEY_TYPE)?;
+        bytes_written += self.encode_uint(node.tci_type.into())?;
+        bytes_written += self.encode_int(Self::TCI_KEY_CUMULATIVE)?;
+        bytes_written += self.encode_bstr(&node.tci_cumulative.0)?;
+        bytes_written += self.encode_int(Self::TCI_KEY_CURRENT)?;
+        bytes_written += self.encode_bstr(&node.tci_current.0)?;
+        bytes_written += self.encode_int(Self::TCI_KEY_LOCALITY)?;
+        bytes_written += self.encode_uint(node.locality.into())?;
+
+        Ok(bytes_written)
+    }
+
+    /// Encode the CWT claims map of an EAT
+    ///
+    /// The TCI chain is ordered from the attested context up to the root, like the
+    /// MultiTcbInfo extension in DPE certificates.
+    pub fn encode_eat_claims(&mut self, claims: &EatClaims) -> Result<usize, DpeErrorCode> {
+        let num_claims = if claims.is_simulation { 5 } else { 4 };
+        let mut bytes_written = self.encode_map(num_claims)?;
+
+        bytes_written += self.encode_int(Self::EAT_CLAIM_NONCE)?;
+        bytes_written += self.encode_bstr(claims.nonce)?;
+        bytes_written += self.encode_int(Self::EAT_CLAIM_UEID)?;
+        bytes_written += self.encode_bstr(claims.ueid)?;
+
+        bytes_written += self.encode_int(Self::DPE_CLAIM_PROFILE)?;
+        bytes_written += self.encode_profile(claims.profile)?;
+
+        bytes_written += self.encode_int(Self::DPE_CLAIM_TCI_CHAIN)?;
+        bytes_written += self.encode_array(claims.tci_nodes.len())?;
+        for node in claims.tci_nodes {
+            bytes_written += self.encode_tci_node(node)?;
+        }
+
+        if claims.is_simulation {
+            bytes_written += self.encode_int(Self::DPE_CLAIM_SIMULATION)?;
+            bytes_written += self.encode_true()?;
+        }
+
+        Ok(bytes_written)
+    }
+
+    /// Encode the COSE protected header map, which only names the profile's signing algorithm
+    pub fn encode_cose_protected_header(&mut self) -> Result<usize, DpeErrorCode> {
+        let mut bytes_written = self.encode_map(1)?;
+        bytes_written += self.encode_int(Self::COSE_HEADER_ALG)?;
+        bytes_written += self.encode_int(Self::COSE_ALG)?;
+
+        Ok(bytes_written)
+    }
+
+    /// Encode the COSE Sig_structure for a COSE_Sign1 up to, but not including, the payload
+    /// bytes
+    ///
+    /// The payload is left out so it can be hashed straight from where it was encoded instead of
+    /// being copied after this prefix.
+    pub fn encode_sig_structure_prefix(
+        &mut self,
+        protected: &[u8],
+        payload_len: usize,
+    ) -> Result<usize, DpeErrorCode> {
+        let mut bytes_written = self.encode_array(4)?;
+        bytes_written += self.encode_tstr(Self::SIG_STRUCTURE_CONTEXT)?;
+        bytes_written += self.encode_bstr(protected)?;
+        // external_aad
+        bytes_written += self.encode_bstr(&[])?;
+        bytes_written += self.encode_bstr_head(payload_len)?;
+
+        Ok(bytes_written)
+    }
+
+    /// Encode a tagged COSE_Sign1 up to, but not including, the certificate in its x5chain
+    /// header
+    ///
+    /// The certificate, payload and signature follow as byte strings, so each can be written in
+    /// place.
+    ///
+    /// # Arguments
+    ///
+    /// * `protected` - The encoded protected header map.
+    pub fn encode_cose_sign1_prefix(&mut self, protected: &[u8]) -> Result<usize, DpeErrorCode> {
+        let mut bytes_written = self.encode_tag(Self::COSE_SIGN1_TAG)?;
+        bytes_written += self.encode_array(4)?;
+        bytes_written += self.encode_bstr(protected)?;
+
+        // Unprotected header
+        bytes_written += self.encode_map(1)?;
+        bytes_written += self.encode_int(Self::COSE_HEADER_X5CHAIN)?;
+
+        Ok(bytes_written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::Value;
+
+    fn encode(f: impl FnOnce(&mut CborWriter) -> Result<usize, DpeErrorCode>) -> Vec<u8> {
+        let mut buf = [0u8; 64];
+        let mut w = CborWriter::new(&mut buf);
+        let bytes_written = f(&mut w).unwrap();
+        buf[..bytes_written].to_vec()
+    }
+
+    #[test]
+    fn test_rfc8949_examples() {
+        // RFC 8949 Appendix A
+        assert_eq!(vec![0x00], encode(|w| w.encode_uint(0)));
+        assert_eq!(vec![0x17], encode(|w| w.encode_uint(23)));
+        assert_eq!(vec![0x18, 0x18], encode(|w| w.encode_uint(24)));
+        assert_eq!(vec![0x18, 0x64], encode(|w| w.encode_uint(100)));
+        assert_eq!(vec![0x19, 0x03, 0xe8], encode(|w| w.encode_uint(1000)));
+        assert_eq!(
+            vec![0x1a, 0x00, 0x0f, 0x42, 0x40],
+            encode(|w| w.encode_uint(1000000))
+        );
+        assert_eq!(
+            vec![0x1b, 0x00, 0x00, 0x00, 0xe8, 0xd4, 0xa5, 0x10, 0x00],
+            encode(|w| w.encode_uint(1000000000000))
+        );
+        assert_eq!(vec![0x20], encode(|w| w.encode_int(-1)));
+        assert_eq!(vec![0x29], encode(|w| w.encode_int(-10)));
+        assert_eq!(vec![0x38, 0x63], encode(|w| w.encode_int(-100)));
+        assert_eq!(vec![0x39, 0x03, 0xe7], encode(|w| w.encode_int(-1000)));
+        assert_eq!(vec![0x40], encode(|w| w.encode_bstr(&[])));
+        assert_eq!(
+            vec![0x44, 0x01, 0x02, 0x03, 0x04],
+            encode(|w| w.encode_bstr(&[1, 2, 3, 4]))
+        );
+        assert_eq!(
+            vec![0x64, b'I', b'E', b'T', b'F'],
+            encode(|w| w.encode_tstr("IETF"))
+        );
+        assert_eq!(vec![0x80], encode(|w| w.encode_array(0)));
+        assert_eq!(vec![0xa0], encode(|w| w.encode_map(0)));
+        assert_eq!(vec![0xf5], encode(|w| w.encode_true()));
+        assert_eq!(vec![0xd2], encode(|w| w.encode_tag(18)));
+    }
+
+    #[test]
+    fn test_buffer_too_small() {
+        let mut buf = [0u8; 4];
+        let mut w = CborWriter::new(&mut buf);
+        assert_eq!(Err(DpeErrorCode::InternalError), w.encode_bstr(&[0; 4]));
+    }
+
+    #[test]
+    fn test_eat_claims() {
+        let profile = GetProfileResp::new(0x1234, 0xc0ffee, 0xf00d);
+        let node = TciNodeData {
+            tci_type: 0xdeadbeef,
+            locality: 7,
+            ..TciNodeData::new()
+        };
+        let claims = EatClaims {
+            nonce: &[0x5a; 32],
+            ueid: &[1, 2, 3],
+            profile: &profile,
+            tci_nodes: &[node, TciNodeData::new()],
+            is_simulation: true,
+        };
+
+        let mut buf = [0u8; 1024];
+        let mut w = CborWriter::new(&mut buf);
+        let len = w.encode_eat_claims(&claims).unwrap();
+
+        let value: Value = ciborium::de::from_reader(&buf[..len]).unwrap();
+        let claims = value.as_map().unwrap();
+        assert_eq!(5, claims.len());
+        let claim = |key: i64| {
+            &claims
+                .iter()
+                .find(|(k, _)| *k == Value::from(key))
+                .unwrap()
+                .1
+        };
+        assert_eq!(
+            &[0x5a; 32][..],
+            claim(CborWriter::EAT_CLAIM_NONCE).as_bytes().unwrap()
+        );
+        assert_eq!(
+            &[1, 2, 3][..],
+            claim(CborWriter::EAT_CLAIM_UEID).as_bytes().unwrap()
+        );
+        assert_eq!(&Value::Bool(true), claim(CborWriter::DPE_CLAIM_SIMULATION));
+
+        let profile_map = claim(CborWriter::DPE_CLAIM_PROFILE).as_map().unwrap();
+        assert!(profile_map.contains(&(
+            Value::from(CborWriter::PROFILE_KEY_VENDOR_ID),
+            Value::from(0xc0ffee)
+        )));
+        assert!(profile_map.contains(&(
+            Value::from(CborWriter::PROFILE_KEY_FLAGS),
+            Value::from(0x1234)
+        )));
+
+        let chain = claim(CborWriter::DPE_CLAIM_TCI_CHAIN).as_array().unwrap();
+        assert_eq!(2, chain.len());
+        let first = chain[0].as_map().unwrap();
+        assert!(first.contains(&(
+            Value::from(CborWriter::TCI_KEY_TYPE),
+            Value::from(0xdeadbeef_u32)
+        )));
+        assert!(first.contains(&(Value::from(CborWriter::TCI_KEY_LOCALITY), Value::from(7))));
+        assert!(first.contains(&(
+            Value::from(CborWriter::TCI_KEY_CUMULATIVE),
+            Value::Bytes(vec![0; DPE_PROFILE.get_tci_size()])
+        )));
+    }
+}
@@ -0,0 +1,579 @@
+// Licensed under the Apache-2.0 license.
+
+//! Batched command execution.
+//!
+//! A batch carries up to `MAX_BATCH_COMMANDS` serialized commands, each prefixed with a
+//! `BatchEntryHdr`. An entry can ask for its context handle to be replaced with a handle returned
+//! by an earlier entry, so a whole chain like InitCtx -> DeriveChild -> CertifyKey can be sent in
+//! one round-trip even when the intermediate handles are not known ahead of time.
+//!
+//! The responses are concatenated in the same order, each serialized with `Response::serialize`
+//! and prefixed with its size as a u32.
+use super::{Command, CommandExecution};
+use crate::{
+    context::ContextHandle,
+    dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
+    response::{BatchResp, DpeErrorCode, Response, ResponseHdr},
+    MAX_BATCH_COMMANDS, MAX_BATCH_DATA_SIZE,
+};
+use bitflags::bitflags;
+use core::mem::size_of;
+use zerocopy::{AsBytes, FromBytes};
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct BatchFlags(u32);
+
+bitflags! {
+    impl BatchFlags: u32 {
+        const ALL_OR_NOTHING = 1u32 << 31;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct BatchEntryFlags(u32);
+
+bitflags! {
+    impl BatchEntryFlags: u32 {
+        /// Use the parent handle returned by DeriveChild instead of the child handle.
+        const USE_PARENT_HANDLE = 1u32 << 31;
+    }
+}
+
+/// Header that precedes each serialized command in a batch.
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct BatchEntryHdr {
+    /// Size of the serialized command that follows, including its `CommandHdr`.
+    pub cmd_size: u32,
+    /// Index of an earlier entry whose returned handle replaces this command's handle, or
+    /// `BatchEntryHdr::NO_HANDLE_REF`.
+    pub handle_ref: u32,
+    pub flags: BatchEntryFlags,
+}
+
+impl BatchEntryHdr {
+    pub const NO_HANDLE_REF: u32 = u32::MAX;
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct BatchCmd {
+    pub flags: BatchFlags,
+    pub num_commands: u32,
+    pub data_size: u32,
+    pub data: [u8; MAX_BATCH_DATA_SIZE],
+}
+
+impl BatchCmd {
+    const fn is_all_or_nothing(&self) -> bool {
+        self.flags.contains(BatchFlags::ALL_OR_NOTHING)
+    }
+
+    /// Iterate over the (entry header, serialized command) pairs in the batch.
+    fn entries(&self) -> impl Iterator<Item = Result<(BatchEntryHdr, &[u8]), DpeErrorCode>> {
+        let mut data = self.data.get(..self.data_size as usize).unwrap_or(&[]);
+        (0..self.num_commands).map(move |_| {
+            let entry =
+                BatchEntryHdr::read_from_prefix(data).ok_or(DpeErrorCode::InvalidArgument)?;
+            let cmd = data
+                .get(size_of::<BatchEntryHdr>()..)
+                .and_then(|rest| rest.get(..entry.cmd_size as usize))
+                .ok_or(DpeErrorCode::InvalidArgument)?;
+            data = &data[size_of::<BatchEntryHdr>() + cmd.len()..];
+            Ok((entry, cmd))
+        })
+    }
+
+    /// Deserialize a single entry, substituting its handle if it references an earlier entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The entry header.
+    /// * `cmd` - The serialized command following the header.
+    /// * `handles` - The (new handle, parent handle) returned by each earlier entry, if any.
+    fn parse_entry(
+        entry: &BatchEntryHdr,
+        cmd: &[u8],
+        handles: &[Option<(ContextHandle, ContextHandle)>],
+    ) -> Result<Command, DpeErrorCode> {
+        let mut command = Command::deserialize(cmd)?;
+        if entry.handle_ref != BatchEntryHdr::NO_HANDLE_REF {
+            let (new_handle, parent_handle) = handles
+                .get(entry.handle_ref as usize)
+                .copied()
+                .flatten()
+                .ok_or(DpeErrorCode::InvalidArgument)?;
+            let handle = command.handle_mut().ok_or(DpeErrorCode::InvalidArgument)?;
+            *handle = if entry.flags.contains(BatchEntryFlags::USE_PARENT_HANDLE) {
+                parent_handle
+            } else {
+                new_handle
+            };
+        }
+        Ok(command)
+    }
+
+    /// Execute the entries in order until one fails.
+    ///
+    /// Returns the status of the failing entry, if any.
+    fn execute_entries(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+        response: &mut BatchResp,
+    ) -> Result<(), u32> {
+        let mut handles = [None; MAX_BATCH_COMMANDS];
+        for (i, entry) in self.entries().enumerate() {
+            let (entry, cmd) = entry.map_err(|err_code| err_code as u32)?;
+            let sub_response = match Self::parse_entry(&entry, cmd, &handles[..i])
+                .and_then(|command| dpe.execute_batch_entry(env, locality, command))
+            {
+                Ok(sub_response) => sub_response,
+                Err(err_code) => Response::Error(ResponseHdr::new(err_code)),
+            };
+            response
+                .append(&sub_response)
+                .map_err(|err_code| err_code as u32)?;
+
+            let status = sub_response.status();
+            if status != DpeErrorCode::NoError as u32 {
+                return Err(status);
+            }
+            handles[i] = sub_response.handles();
+        }
+        Ok(())
+    }
+}
+
+impl CommandExecution for BatchCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        if self.num_commands as usize > MAX_BATCH_COMMANDS
+            || self.data_size as usize > MAX_BATCH_DATA_SIZE
+        {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        // Check the framing up front so a malformed batch is rejected before anything runs.
+        for entry in self.entries() {
+            entry?;
+        }
+
+        // Only the DPE state is restored on failure. Side effects in the crypto and platform
+        // layers, like exported CDIs, are not rolled back.
+        let snapshot = if self.is_all_or_nothing() {
+            Some(DpeInstance::read_from(dpe.as_bytes()).ok_or(DpeErrorCode::InternalError)?)
+        } else {
+            None
+        };
+
+        let mut response = BatchResp::default();
+        if let Err(status) = self.execute_entries(dpe, env, locality, &mut response) {
+            if let Some(snapshot) = snapshot {
+                *dpe = snapshot;
+            }
+            response.resp_hdr.status = status;
+        }
+
+        Ok(Response::Batch(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{
+            CertifyKeyCmd, CertifyKeyFlags, CommandHdr, DeriveChildCmd, DeriveChildFlags,
+            InitCtxCmd, SignCmd, SignFlags,
+        },
+        context::ContextState,
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        event_log::TciEventType,
+        event_sink::{DpeEvent, RecordingSink},
+        response::{CertifyKeyResp, DeriveChildResp, NewHandleResp},
+        support::Support,
+        DPE_PROFILE, MAX_CERT_SIZE,
+    };
+    use crypto::OpensslCrypto;
+    use platform::default::DefaultPlatform;
+
+    fn append_entry(
+        batch: &mut BatchCmd,
+        handle_ref: u32,
+        flags: BatchEntryFlags,
+        hdr: &CommandHdr,
+        cmd: &[u8],
+    ) {
+        let entry = BatchEntryHdr {
+            cmd_size: (size_of::<CommandHdr>() + cmd.len()) as u32,
+            handle_ref,
+            flags,
+        };
+        let mut offset = batch.data_size as usize;
+        for bytes in [entry.as_bytes(), hdr.as_bytes(), cmd] {
+            batch.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            offset += bytes.len();
+        }
+        batch.data_size = offset as u32;
+        batch.num_commands += 1;
+    }
+
+    fn new_batch(flags: BatchFlags) -> BatchCmd {
+        BatchCmd {
+            flags,
+            num_commands: 0,
+            data_size: 0,
+            data: [0; MAX_BATCH_DATA_SIZE],
+        }
+    }
+
+    fn derive_child(flags: DeriveChildFlags) -> DeriveChildCmd {
+        DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [0; DPE_PROFILE.get_hash_size()],
+            flags,
+            tci_type: 0,
+            target_locality: 0,
+        }
+    }
+
+    /// Split the concatenated responses back into their serialized form.
+    fn split_responses(resp: &BatchResp) -> Vec<&[u8]> {
+        let mut responses = vec![];
+        let mut data = &resp.data[..resp.data_size as usize];
+        while !data.is_empty() {
+            let size = u32::read_from_prefix(data).unwrap() as usize;
+            responses.push(&data[size_of::<u32>()..size_of::<u32>() + size]);
+            data = &data[size_of::<u32>() + size..];
+        }
+        assert_eq!(resp.num_responses as usize, responses.len());
+        responses
+    }
+
+    #[test]
+    fn test_deserialize_batch() {
+        let mut batch = new_batch(BatchFlags::ALL_OR_NOTHING);
+        append_entry(
+            &mut batch,
+            BatchEntryHdr::NO_HANDLE_REF,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::INITIALIZE_CONTEXT),
+            InitCtxCmd::new_use_default().as_bytes(),
+        );
+        let mut command = CommandHdr::new_for_test(Command::BATCH).as_bytes().to_vec();
+        command.extend(batch.as_bytes());
+        assert_eq!(Ok(Command::Batch(batch)), Command::deserialize(&command));
+    }
+
+    #[test]
+    fn test_batch_with_handle_refs() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::SIMULATION | Support::X509).unwrap();
+
+        // InitCtx(simulation) -> DeriveChild(retain parent) -> CertifyKey on the parent.
+        let mut batch = new_batch(BatchFlags::empty());
+        append_entry(
+            &mut batch,
+            BatchEntryHdr::NO_HANDLE_REF,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::INITIALIZE_CONTEXT),
+            InitCtxCmd::new_simulation().as_bytes(),
+        );
+        append_entry(
+            &mut batch,
+            0,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::DERIVE_CHILD),
+            derive_child(DeriveChildFlags::MAKE_DEFAULT | DeriveChildFlags::INPUT_ALLOW_X509)
+                .as_bytes(),
+        );
+        append_entry(
+            &mut batch,
+            1,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::CERTIFY_KEY),
+            CertifyKeyCmd {
+                handle: SIMULATION_HANDLE,
+                flags: CertifyKeyFlags::empty(),
+                label: [0; DPE_PROFILE.get_hash_size()],
+                format: CertifyKeyCmd::FORMAT_X509,
+            }
+            .as_bytes(),
+        );
+
+        let resp = match batch
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap()
+        {
+            Response::Batch(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(DpeErrorCode::NoError as u32, resp.resp_hdr.status);
+
+        let responses = split_responses(&resp);
+        assert_eq!(3, responses.len());
+        let init = NewHandleResp::read_from(responses[0]).unwrap();
+        assert_eq!(SIMULATION_HANDLE, init.handle);
+        let derive = DeriveChildResp::read_from(responses[1]).unwrap();
+        assert_eq!(ContextHandle::default(), derive.handle);
+        // The certificate buffer is cut short after the certificate.
+        let mut certify = vec![0; size_of::<CertifyKeyResp>()];
+        certify[..responses[2].len()].copy_from_slice(responses[2]);
+        let certify = CertifyKeyResp::read_from(certify.as_slice()).unwrap();
+        assert_eq!(DpeErrorCode::NoError as u32, certify.resp_hdr.status);
+        assert_ne!(0, certify.cert_size);
+        assert_eq!(
+            size_of::<CertifyKeyResp>() - MAX_CERT_SIZE + certify.cert_size as usize,
+            responses[2].len()
+        );
+    }
+
+    #[test]
+    fn test_batch_stop_on_first_error() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+
+        // The second command fails because the default context is already retired.
+        let mut batch = new_batch(BatchFlags::empty());
+        for _ in 0..3 {
+            append_entry(
+                &mut batch,
+                BatchEntryHdr::NO_HANDLE_REF,
+                BatchEntryFlags::empty(),
+                &CommandHdr::new_for_test(Command::DERIVE_CHILD),
+                derive_child(DeriveChildFlags::empty()).as_bytes(),
+            );
+        }
+
+        let resp = match batch
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap()
+        {
+            Response::Batch(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(DpeErrorCode::InvalidHandle as u32, resp.resp_hdr.status);
+        let responses = split_responses(&resp);
+        assert_eq!(2, responses.len());
+        assert_eq!(
+            ResponseHdr::new(DpeErrorCode::InvalidHandle).as_bytes(),
+            responses[1]
+        );
+
+        // The first command was kept.
+        assert_eq!(ContextState::Retired, dpe.contexts[0].state);
+        assert_eq!(ContextState::Active, dpe.contexts[1].state);
+    }
+
+    #[test]
+    fn test_batch_all_or_nothing() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+        let before = dpe.as_bytes().to_vec();
+
+        let mut batch = new_batch(BatchFlags::ALL_OR_NOTHING);
+        append_entry(
+            &mut batch,
+            BatchEntryHdr::NO_HANDLE_REF,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::DERIVE_CHILD),
+            derive_child(DeriveChildFlags::MAKE_DEFAULT).as_bytes(),
+        );
+        // Symmetric signing is not supported.
+        append_entry(
+            &mut batch,
+            0,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::SIGN),
+            SignCmd {
+                handle: ContextHandle::default(),
+                label: [0; DPE_PROFILE.get_hash_size()],
+                flags: SignFlags::IS_SYMMETRIC,
+                digest: [0; DPE_PROFILE.get_hash_size()],
+            }
+            .as_bytes(),
+        );
+
+        let resp = match batch
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap()
+        {
+            Response::Batch(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(DpeErrorCode::InvalidArgument as u32, resp.resp_hdr.status);
+        assert_eq!(2, split_responses(&resp).len());
+        assert_eq!(before, dpe.as_bytes());
+    }
+
+    #[test]
+    fn test_batch_events() {
+        struct RecordingTypes;
+        impl DpeTypes for RecordingTypes {
+            type Crypto<'a> = OpensslCrypto;
+            type Platform<'a> = DefaultPlatform;
+            type EventSink<'a> = RecordingSink<8>;
+        }
+
+        let mut env = DpeEnv::<RecordingTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: RecordingSink::new(),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+
+        let mut run = |batch: &BatchCmd| {
+            let mut command = CommandHdr::new_for_test(Command::BATCH).as_bytes().to_vec();
+            command.extend(batch.as_bytes());
+            dpe.execute_serialized_command(&mut env, TEST_LOCALITIES[0], &command)
+                .unwrap();
+            let events = env.event_sink.events().copied().collect::<Vec<_>>();
+            env.event_sink.clear();
+            events
+        };
+        let executed = |command_id, status| DpeEvent::CommandExecuted {
+            command_id,
+            locality: TEST_LOCALITIES[0],
+            status,
+        };
+
+        // The DeriveChild is rolled back when the Sign fails, so its changes are not reported.
+        let mut batch = new_batch(BatchFlags::ALL_OR_NOTHING);
+        append_entry(
+            &mut batch,
+            BatchEntryHdr::NO_HANDLE_REF,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::DERIVE_CHILD),
+            derive_child(DeriveChildFlags::MAKE_DEFAULT).as_bytes(),
+        );
+        append_entry(
+            &mut batch,
+            0,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::SIGN),
+            SignCmd {
+                handle: ContextHandle::default(),
+                label: [0; DPE_PROFILE.get_hash_size()],
+                flags: SignFlags::IS_SYMMETRIC,
+                digest: [0; DPE_PROFILE.get_hash_size()],
+            }
+            .as_bytes(),
+        );
+        assert_eq!(
+            vec![
+                executed(Command::DERIVE_CHILD, DpeErrorCode::NoError),
+                executed(Command::SIGN, DpeErrorCode::InvalidArgument),
+                executed(Command::BATCH, DpeErrorCode::NoError),
+            ],
+            run(&batch)
+        );
+
+        // Without ALL_OR_NOTHING the DeriveChild is kept and reported along with the batch.
+        batch.flags = BatchFlags::empty();
+        let events = run(&batch);
+        assert_eq!(5, events.len());
+        assert_eq!(
+            [
+                executed(Command::DERIVE_CHILD, DpeErrorCode::NoError),
+                executed(Command::SIGN, DpeErrorCode::InvalidArgument),
+            ],
+            events[..2]
+        );
+        assert!(matches!(events[2], DpeEvent::ContextCreated { .. }));
+        assert!(matches!(
+            events[3],
+            DpeEvent::MeasurementExtended {
+                event_type: TciEventType::Derive,
+                ..
+            }
+        ));
+        assert_eq!(executed(Command::BATCH, DpeErrorCode::NoError), events[4]);
+    }
+
+    #[test]
+    fn test_batch_bad_entries() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+
+        let run = |batch: &BatchCmd, dpe: &mut DpeInstance, env: &mut DpeEnv<TestTypes>| match batch
+            .execute(dpe, env, TEST_LOCALITIES[0])
+            .unwrap()
+        {
+            Response::Batch(resp) => resp.resp_hdr.status,
+            _ => panic!("Incorrect response type"),
+        };
+
+        // References must point to an earlier entry.
+        let mut batch = new_batch(BatchFlags::empty());
+        append_entry(
+            &mut batch,
+            0,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::DERIVE_CHILD),
+            derive_child(DeriveChildFlags::MAKE_DEFAULT).as_bytes(),
+        );
+        assert_eq!(
+            DpeErrorCode::InvalidArgument as u32,
+            run(&batch, &mut dpe, &mut env)
+        );
+
+        // Only commands that take a handle can reference one.
+        let mut batch = new_batch(BatchFlags::empty());
+        append_entry(
+            &mut batch,
+            BatchEntryHdr::NO_HANDLE_REF,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::GET_PROFILE),
+            &[],
+        );
+        append_entry(
+            &mut batch,
+            0,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::GET_PROFILE),
+            &[],
+        );
+        assert_eq!(
+            DpeErrorCode::InvalidArgument as u32,
+            run(&batch, &mut dpe, &mut env)
+        );
+
+        // Entries cannot run past the end of the data.
+        let mut batch = new_batch(BatchFlags::empty());
+        append_entry(
+            &mut batch,
+            BatchEntryHdr::NO_HANDLE_REF,
+            BatchEntryFlags::empty(),
+            &CommandHdr::new_for_test(Command::GET_PROFILE),
+            &[],
+        );
+        batch.num_commands = 2;
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            batch.execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+    }
+}
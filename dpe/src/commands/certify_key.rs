@@ -1,7 +1,7 @@
 // Licensed under the Apache-2.0 license.
-use super::CommandExecution;
+use super::{quote::QUOTE_KEY_INFO, CommandExecution};
 use crate::{
-    context::ContextHandle,
+    context::{ContextHandle, ContextType},
     dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
     response::{CertifyKeyResp, DpeErrorCode, Response, ResponseHdr},
     tci::TciNodeData,
@@ -9,7 +9,7 @@ use crate::{
     DPE_PROFILE, MAX_CERT_SIZE, MAX_HANDLES,
 };
 use bitflags::bitflags;
-use crypto::Crypto;
+use crypto::{Crypto, EcdsaPub};
 use platform::{Platform, PlatformError, MAX_CHUNK_SIZE};
 
 #[repr(C)]
@@ -19,6 +19,8 @@ pub struct CertifyKeyFlags(u32);
 bitflags! {
     impl CertifyKeyFlags: u32 {
         const IS_CA = 1u32 << 31;
+        /// Certify the key Quote signs with instead of the key Sign uses.
+        const QUOTE_KEY = 1u32 << 30;
     }
 }
 
@@ -38,6 +40,10 @@ impl CertifyKeyCmd {
     const fn uses_is_ca(&self) -> bool {
         self.flags.contains(CertifyKeyFlags::IS_CA)
     }
+
+    const fn uses_quote_key(&self) -> bool {
+        self.flags.contains(CertifyKeyFlags::QUOTE_KEY)
+    }
 }
 
 impl CommandExecution for CertifyKeyCmd {
@@ -56,6 +62,13 @@ impl CommandExecution for CertifyKeyCmd {
         if self.uses_is_ca() && !context.allow_ca() {
             return Err(DpeErrorCode::InvalidArgument);
         }
+        if self.uses_quote_key() && !dpe.support.quote() {
+            return Err(DpeErrorCode::ArgumentNotSupported);
+        }
+        // The quote key only signs quotes, so it can't issue certificates.
+        if self.uses_quote_key() && self.uses_is_ca() {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
 
         if self.format == Self::FORMAT_X509 {
             if !dpe.support.x509() {
@@ -72,22 +85,15 @@ impl CommandExecution for CertifyKeyCmd {
         }
 
         let algs = DPE_PROFILE.alg_len();
-        let digest = dpe.compute_measurement_hash(env, idx)?;
-        let cdi = env
-            .crypto
-            .derive_cdi(DPE_PROFILE.alg_len(), &digest, b"DPE")
-            .map_err(|_| DpeErrorCode::CryptoError)?;
+        let cdi = dpe.derive_cdi(env, idx)?;
+        let (info, subject_cn): (&[u8], &[u8]) = if self.uses_quote_key() {
+            (QUOTE_KEY_INFO, b"DPE Quote")
+        } else {
+            (b"ECC", b"DPE Leaf")
+        };
         let (_, pub_key) = env
             .crypto
-            .derive_key_pair(algs, &cdi, &self.label, b"ECC")
-            .map_err(|_| DpeErrorCode::CryptoError)?;
-
-        let mut subject_name = Name {
-            cn: b"DPE Leaf",
-            serial: [0u8; DPE_PROFILE.get_hash_size() * 2],
-        };
-        env.crypto
-            .get_pubkey_serial(DPE_PROFILE.alg_len(), &pub_key, &mut subject_name.serial)
+            .derive_key_pair(algs, &cdi, &self.label, info)
             .map_err(|_| DpeErrorCode::CryptoError)?;
 
         // Get TCI Nodes
@@ -101,50 +107,13 @@ impl CommandExecution for CertifyKeyCmd {
             label: &self.label,
             tci_nodes: &nodes[..tcb_count],
             is_ca: self.uses_is_ca(),
+            is_simulation: dpe.contexts[idx].context_type == ContextType::Simulation,
         };
 
-        let mut issuer_name = [0u8; MAX_CHUNK_SIZE];
-        let issuer_len =
-            env.platform
-                .get_issuer_name(&mut issuer_name)
-                .map_err(|platform_error| match platform_error {
-                    PlatformError::IssuerNameError => DpeErrorCode::InvalidArgument,
-                    _ => DpeErrorCode::PlatformError,
-                })?;
-
         let mut cert = [0u8; MAX_CERT_SIZE];
         let cert_size = match self.format {
             Self::FORMAT_X509 => {
-                let mut tbs_buffer = [0u8; MAX_CERT_SIZE];
-                let mut tbs_writer = X509CertWriter::new(&mut tbs_buffer, true);
-                if issuer_len > MAX_CHUNK_SIZE {
-                    return Err(DpeErrorCode::InternalError);
-                }
-                let mut bytes_written = tbs_writer.encode_ecdsa_tbs(
-                    /*serial=*/
-                    &subject_name.serial[..20], // Serial number must be truncated to 20 bytes
-                    &issuer_name[..issuer_len],
-                    &subject_name,
-                    &pub_key,
-                    &measurements,
-                )?;
-                if bytes_written > MAX_CERT_SIZE {
-                    return Err(DpeErrorCode::InternalError);
-                }
-
-                let tbs_digest = env
-                    .crypto
-                    .hash(DPE_PROFILE.alg_len(), &tbs_buffer[..bytes_written])
-                    .map_err(|_| DpeErrorCode::HashError)?;
-                let sig = env
-                    .crypto
-                    .ecdsa_sign_with_alias(DPE_PROFILE.alg_len(), &tbs_digest)
-                    .map_err(|_| DpeErrorCode::CryptoError)?;
-
-                let mut cert_writer = X509CertWriter::new(&mut cert, true);
-                bytes_written =
-                    cert_writer.encode_ecdsa_certificate(&tbs_buffer[..bytes_written], &sig)?;
-                u32::try_from(bytes_written).map_err(|_| DpeErrorCode::InternalError)?
+                create_x509_certificate(env, subject_cn, &pub_key, &measurements, &mut cert)?
             }
             Self::FORMAT_CSR => {
                 if !dpe.support.csr() {
@@ -155,21 +124,25 @@ impl CommandExecution for CertifyKeyCmd {
             _ => return Err(DpeErrorCode::InvalidArgument),
         };
 
+        let derived_pubkey_x = pub_key
+            .x
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+        let derived_pubkey_y = pub_key
+            .y
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+
         // Rotate handle if it isn't the default
-        dpe.roll_onetime_use_handle(env, idx)?;
+        let new_context_handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.contexts[idx].handle = new_context_handle;
 
         Ok(Response::CertifyKey(CertifyKeyResp {
-            new_context_handle: dpe.contexts[idx].handle,
-            derived_pubkey_x: pub_key
-                .x
-                .bytes()
-                .try_into()
-                .map_err(|_| DpeErrorCode::InternalError)?,
-            derived_pubkey_y: pub_key
-                .y
-                .bytes()
-                .try_into()
-                .map_err(|_| DpeErrorCode::InternalError)?,
+            new_context_handle,
+            derived_pubkey_x,
+            derived_pubkey_y,
             cert_size,
             cert,
             resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
@@ -177,12 +150,78 @@ impl CommandExecution for CertifyKeyCmd {
     }
 }
 
+/// Creates an X.509 certificate for `pub_key` signed by the platform alias key.
+///
+/// Returns the size of the certificate written to `cert`.
+///
+/// # Arguments
+///
+/// * `subject_cn` - Common name of the certificate's subject, which tells verifiers what the key
+///   is for.
+/// * `pub_key` - The key to certify.
+/// * `measurements` - DPE measurement data to include in the certificate.
+/// * `cert` - Output buffer for the DER encoded certificate.
+pub(crate) fn create_x509_certificate(
+    env: &mut DpeEnv<impl DpeTypes>,
+    subject_cn: &[u8],
+    pub_key: &EcdsaPub,
+    measurements: &MeasurementData,
+    cert: &mut [u8; MAX_CERT_SIZE],
+) -> Result<u32, DpeErrorCode> {
+    let mut subject_name = Name {
+        cn: subject_cn,
+        serial: [0u8; DPE_PROFILE.get_hash_size() * 2],
+    };
+    env.crypto
+        .get_pubkey_serial(DPE_PROFILE.alg_len(), pub_key, &mut subject_name.serial)
+        .map_err(|_| DpeErrorCode::CryptoError)?;
+
+    let mut issuer_name = [0u8; MAX_CHUNK_SIZE];
+    let issuer_len = env
+        .platform
+        .get_issuer_name(&mut issuer_name)
+        .map_err(|platform_error| match platform_error {
+            PlatformError::IssuerNameError => DpeErrorCode::InvalidArgument,
+            _ => DpeErrorCode::PlatformError,
+        })?;
+
+    let mut tbs_buffer = [0u8; MAX_CERT_SIZE];
+    let mut tbs_writer = X509CertWriter::new(&mut tbs_buffer, true);
+    if issuer_len > MAX_CHUNK_SIZE {
+        return Err(DpeErrorCode::InternalError);
+    }
+    let mut bytes_written = tbs_writer.encode_ecdsa_tbs(
+        /*serial=*/
+        &subject_name.serial[..20], // Serial number must be truncated to 20 bytes
+        &issuer_name[..issuer_len],
+        &subject_name,
+        pub_key,
+        measurements,
+    )?;
+    if bytes_written > MAX_CERT_SIZE {
+        return Err(DpeErrorCode::InternalError);
+    }
+
+    let tbs_digest = env
+        .crypto
+        .hash(DPE_PROFILE.alg_len(), &tbs_buffer[..bytes_written])
+        .map_err(|_| DpeErrorCode::HashError)?;
+    let sig = env
+        .crypto
+        .ecdsa_sign_with_alias(DPE_PROFILE.alg_len(), &tbs_digest)
+        .map_err(|_| DpeErrorCode::CryptoError)?;
+
+    let mut cert_writer = X509CertWriter::new(cert, true);
+    bytes_written = cert_writer.encode_ecdsa_certificate(&tbs_buffer[..bytes_written], &sig)?;
+    u32::try_from(bytes_written).map_err(|_| DpeErrorCode::InternalError)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
         commands::{Command, CommandHdr, InitCtxCmd},
-        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_HANDLE, TEST_LOCALITIES},
         support::Support,
     };
     use crypto::OpensslCrypto;
@@ -215,7 +254,8 @@ mod tests {
     fn test_certify_key() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::X509).unwrap();
 
@@ -255,7 +295,8 @@ mod tests {
     fn test_is_ca() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::X509 | Support::IS_CA).unwrap();
 
@@ -321,4 +362,62 @@ mod tests {
             Err(e) => panic!("x509 parsing failed: {:?}", e),
         };
     }
+
+    #[test]
+    fn test_simulation_certify_key() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::X509 | Support::SIMULATION).unwrap();
+
+        InitCtxCmd::new_use_default()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        InitCtxCmd::new_simulation()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        // The deterministic RNG used in tests always generates SIMULATION_HANDLE, so give the
+        // simulation context a different handle to let it be rotated.
+        let sim_idx = dpe
+            .get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[0])
+            .unwrap();
+        dpe.contexts[sim_idx].handle = TEST_HANDLE;
+
+        let mut certify = |handle| match (CertifyKeyCmd {
+            handle,
+            flags: CertifyKeyFlags::empty(),
+            label: [0; DPE_PROFILE.get_hash_size()],
+            format: CertifyKeyCmd::FORMAT_X509,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::CertifyKey(resp) => resp,
+            _ => panic!("Wrong response type."),
+        };
+        let real_resp = certify(ContextHandle::default());
+        let sim_resp = certify(TEST_HANDLE);
+
+        // Both contexts have the same measurements, but the simulation key comes from a
+        // different root.
+        assert_eq!(dpe.contexts[0].tci, dpe.contexts[sim_idx].tci);
+        assert_ne!(real_resp.derived_pubkey_x, sim_resp.derived_pubkey_x);
+
+        let mut parser = X509CertificateParser::new().with_deep_parse_extensions(false);
+        for (resp, is_simulation) in [(real_resp, false), (sim_resp, true)] {
+            let (_, cert) = parser
+                .parse(&resp.cert[..resp.cert_size.try_into().unwrap()])
+                .unwrap();
+            let marker = cert
+                .extensions()
+                .iter()
+                .find(|ext| ext.oid.as_bytes() == X509CertWriter::SIMULATION_OID);
+            assert_eq!(is_simulation, marker.is_some());
+            if let Some(marker) = marker {
+                assert!(marker.critical);
+            }
+        }
+    }
 }
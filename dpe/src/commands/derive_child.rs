@@ -1,17 +1,19 @@
 // Licensed under the Apache-2.0 license.
-use super::CommandExecution;
+use super::{certify_key::create_x509_certificate, CommandExecution};
 use crate::{
-    context::{ActiveContextArgs, ContextHandle, ContextState, ContextType},
+    context::{ActiveContextArgs, Context, ContextHandle, ContextState, ContextType},
     dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
-    response::{DeriveChildResp, DpeErrorCode, Response, ResponseHdr},
-    tci::TciMeasurement,
-    DPE_PROFILE,
+    event_log::TciEventType,
+    response::{DeriveChildExportedCdiResp, DeriveChildResp, DpeErrorCode, Response, ResponseHdr},
+    tci::{TciMeasurement, TciNodeData},
+    x509::MeasurementData,
+    DPE_PROFILE, MAX_CERT_SIZE, MAX_HANDLES,
 };
 use bitflags::bitflags;
+use crypto::Crypto;
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct DeriveChildFlags(u32);
 
 bitflags! {
@@ -23,12 +25,17 @@ bitflags! {
         const CHANGE_LOCALITY = 1u32 << 27;
         const INPUT_ALLOW_CA = 1u32 << 26;
         const INPUT_ALLOW_X509 = 1u32 << 25;
+        const INPUT_ALLOW_EXPORT = 1u32 << 24;
+        const EXPORT_CDI = 1u32 << 23;
+        const RECURSIVE = 1u32 << 22;
     }
 }
 
+/// Label used to derive the key certified for an exported CDI.
+const EXPORTED_KEY_LABEL: &[u8] = b"Exported ECC";
+
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct DeriveChildCmd {
     pub handle: ContextHandle,
     pub data: [u8; DPE_PROFILE.get_hash_size()],
@@ -66,6 +73,18 @@ impl DeriveChildCmd {
         self.flags.contains(DeriveChildFlags::INPUT_ALLOW_X509)
     }
 
+    const fn allows_export(&self) -> bool {
+        self.flags.contains(DeriveChildFlags::INPUT_ALLOW_EXPORT)
+    }
+
+    const fn exports_cdi(&self) -> bool {
+        self.flags.contains(DeriveChildFlags::EXPORT_CDI)
+    }
+
+    const fn is_recursive(&self) -> bool {
+        self.flags.contains(DeriveChildFlags::RECURSIVE)
+    }
+
     /// Check if this will result in two default contexts in the same locality.
     ///
     /// There can only be one default context in each locality at any given time. A default context
@@ -88,6 +107,137 @@ impl DeriveChildCmd {
         }
         true
     }
+
+    /// Derive a CDI for a layer outside of DPE instead of creating a child context.
+    ///
+    /// The exported CDI is the same one that a child context created with the same inputs would
+    /// have. The parent is left as-is apart from rotating its handle, and the context table is not
+    /// modified.
+    ///
+    /// # Arguments
+    ///
+    /// * `parent_idx` - Index of the context the CDI is derived from.
+    /// * `locality` - Locality of the caller.
+    fn export_cdi(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        parent_idx: usize,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        let parent = &dpe.contexts[parent_idx];
+        if !parent.allow_export() || parent.context_type == ContextType::Simulation {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        // No context is created, so there is nothing to make default or move to another locality.
+        if self.makes_default() || self.changes_locality() {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let mut child = Context::new();
+        child.tci.tci_type = self.tci_type;
+        child.tci.locality = locality;
+        child.uses_internal_input_info = self.uses_internal_info_input().into();
+        child.uses_internal_input_dice = self.uses_internal_dice_input().into();
+        DpeInstance::extend_tci(env, &mut child.tci, &TciMeasurement(self.data))?;
+
+        let algs = DPE_PROFILE.alg_len();
+        let digest = dpe.compute_detached_measurement_hash(env, &child, parent_idx)?;
+        let exported_cdi = env
+            .crypto
+            .derive_exported_cdi(algs, &digest, b"DPE")
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+        let (_, pub_key) = env
+            .crypto
+            .derive_key_pair_exported(algs, &exported_cdi, EXPORTED_KEY_LABEL, b"ECC")
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+
+        // The certificate covers the exported node followed by the parent's chain.
+        const INITIALIZER: TciNodeData = TciNodeData::new();
+        let mut nodes = [INITIALIZER; MAX_HANDLES + 1];
+        nodes[0] = child.tci;
+        let tcb_count = dpe.get_tcb_nodes(parent_idx, &mut nodes[1..])? + 1;
+        let measurements = MeasurementData {
+            label: EXPORTED_KEY_LABEL,
+            tci_nodes: &nodes[..tcb_count],
+            is_ca: self.allows_ca(),
+            is_simulation: false,
+        };
+
+        let mut cert = [0u8; MAX_CERT_SIZE];
+        let cert_size =
+            create_x509_certificate(env, b"DPE Leaf", &pub_key, &measurements, &mut cert)?;
+
+        let derived_pubkey_x = pub_key
+            .x
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+        let derived_pubkey_y = pub_key
+            .y
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+
+        let parent_handle = dpe.next_onetime_use_handle(env, parent_idx)?;
+        dpe.contexts[parent_idx].handle = parent_handle;
+
+        Ok(Response::DeriveChildExportedCdi(
+            DeriveChildExportedCdiResp {
+                resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+                parent_handle,
+                exported_cdi,
+                derived_pubkey_x,
+                derived_pubkey_y,
+                cert_size,
+                cert,
+            },
+        ))
+    }
+
+    /// Extend the measurement of the current context in place instead of creating a child.
+    ///
+    /// The context keeps its TCI type and capabilities, so no context slot is consumed. Only the
+    /// handle changes, and it is returned as the new context handle. Flags other than RECURSIVE
+    /// and a TCI type that differs from the context's are rejected, as they can't be applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - Index of the context to extend.
+    /// * `locality` - Locality of the caller.
+    fn derive_recursive(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        idx: usize,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // There is no new context to retain the parent for, make default, move to another
+        // locality, give inputs or capabilities to, or export the CDI of.
+        if self
+            .flags
+            .intersects(DeriveChildFlags::all().difference(DeriveChildFlags::RECURSIVE))
+            || self.tci_type != dpe.contexts[idx].tci.tci_type
+        {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.add_tci_measurement(
+            env,
+            idx,
+            &TciMeasurement(self.data),
+            locality,
+            TciEventType::Extend,
+        )?;
+        dpe.contexts[idx].handle = handle;
+
+        Ok(Response::DeriveChild(DeriveChildResp {
+            handle,
+            parent_handle: ContextHandle([0xff; ContextHandle::SIZE]),
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+        }))
+    }
 }
 
 impl CommandExecution for DeriveChildCmd {
@@ -109,25 +259,38 @@ impl CommandExecution for DeriveChildCmd {
             return Err(DpeErrorCode::ArgumentNotSupported);
         }
 
+        if !dpe.support.cdi_export() && (self.allows_export() || self.exports_cdi()) {
+            return Err(DpeErrorCode::ArgumentNotSupported);
+        }
+
         let parent_idx = dpe.get_active_context_pos(&self.handle, locality)?;
         if (!dpe.contexts[parent_idx].allow_ca() && self.allows_ca())
             || (!dpe.contexts[parent_idx].allow_x509() && self.allows_x509())
+            || (!dpe.contexts[parent_idx].allow_export() && self.allows_export())
         {
             return Err(DpeErrorCode::InvalidArgument);
         }
 
+        if self.exports_cdi() && self.is_recursive() {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        if self.exports_cdi() {
+            return self.export_cdi(dpe, env, parent_idx, locality);
+        }
+        if self.is_recursive() {
+            return self.derive_recursive(dpe, env, parent_idx, locality);
+        }
+
         let child_idx = dpe
             .get_next_inactive_context_pos()
             .ok_or(DpeErrorCode::MaxTcis)?;
 
-        dpe.contexts[parent_idx].uses_internal_input_info = self.uses_internal_info_input().into();
-        dpe.contexts[parent_idx].uses_internal_input_dice = self.uses_internal_dice_input().into();
-
         let target_locality = if !self.changes_locality() {
             locality
         } else {
             self.target_locality
         };
+        dpe.check_locality_quota(env, target_locality)?;
 
         // Make sure it can be the default if it is supposed to be.
         if self.makes_default() {
@@ -144,13 +307,36 @@ impl CommandExecution for DeriveChildCmd {
         } else {
             dpe.generate_new_handle(env)?
         };
+        let parent_handle = if !self.retains_parent() {
+            ContextHandle([0xff; ContextHandle::SIZE])
+        } else {
+            dpe.next_onetime_use_handle(env, parent_idx)?
+        };
 
+        // Measure the child before changing anything, so that nothing can fail once the context
+        // table is being modified.
+        let measurement = TciMeasurement(self.data);
+        let mut tci = TciNodeData::new();
+        tci.tci_type = self.tci_type;
+        tci.locality = target_locality;
+        DpeInstance::extend_tci(env, &mut tci, &measurement)?;
+        dpe.record_measurement(
+            child_idx,
+            TciEventType::Derive,
+            self.tci_type,
+            target_locality,
+            &measurement,
+        )?;
+
+        let parent = &mut dpe.contexts[parent_idx];
+        parent.uses_internal_input_info = self.uses_internal_info_input().into();
+        parent.uses_internal_input_dice = self.uses_internal_dice_input().into();
+        parent.handle = parent_handle;
         if !self.retains_parent() {
-            dpe.contexts[parent_idx].state = ContextState::Retired;
-            dpe.contexts[parent_idx].handle = ContextHandle([0xff; ContextHandle::SIZE]);
-        } else if !dpe.contexts[parent_idx].handle.is_default() {
-            dpe.contexts[parent_idx].handle = dpe.generate_new_handle(env)?;
+            parent.state = ContextState::Retired;
         }
+        // Add child to the parent's list of children.
+        parent.children.insert(child_idx);
 
         dpe.contexts[child_idx].activate(&ActiveContextArgs {
             context_type: ContextType::Normal,
@@ -160,16 +346,14 @@ impl CommandExecution for DeriveChildCmd {
             parent_idx: parent_idx as u8,
             allow_ca: self.allows_ca(),
             allow_x509: self.allows_x509(),
+            allow_export: self.allows_export(),
         });
 
-        dpe.add_tci_measurement(env, child_idx, &TciMeasurement(self.data), target_locality)?;
-
-        // Add child to the parent's list of children.
-        dpe.contexts[parent_idx].add_child(child_idx)?;
+        dpe.contexts[child_idx].tci = tci;
 
         Ok(Response::DeriveChild(DeriveChildResp {
             handle: child_handle,
-            parent_handle: dpe.contexts[parent_idx].handle,
+            parent_handle,
             resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
         }))
     }
@@ -212,7 +396,8 @@ mod tests {
     fn test_initial_conditions() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::default()).unwrap();
 
@@ -238,7 +423,8 @@ mod tests {
     fn test_max_tcis() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
 
@@ -273,7 +459,8 @@ mod tests {
     fn test_set_child_parent_relationship() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
 
@@ -297,7 +484,7 @@ mod tests {
         assert_eq!(parent_idx, child.parent_idx as usize);
         assert_eq!(
             child_idx,
-            dpe.contexts[parent_idx].children.trailing_zeros() as usize
+            dpe.contexts[parent_idx].children.iter().next().unwrap()
         );
         assert_eq!(7, child.tci.tci_type);
         assert_eq!(TEST_LOCALITIES[1], child.locality);
@@ -307,7 +494,8 @@ mod tests {
     fn test_set_other_values() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
 
@@ -334,7 +522,8 @@ mod tests {
     fn test_correct_child_handle() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
 
@@ -377,7 +566,8 @@ mod tests {
     fn test_correct_parent_handle() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
 
@@ -480,4 +670,265 @@ mod tests {
         // Retain default parent.
         assert!(!make_default_in_0.safe_to_make_default(parent_idx, Ok(parent_idx)));
     }
+
+    #[test]
+    fn test_export_cdi() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT | Support::CDI_EXPORT).unwrap();
+
+        let export_cmd = DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: TEST_DIGEST,
+            flags: DeriveChildFlags::EXPORT_CDI,
+            tci_type: 7,
+            target_locality: 0,
+        };
+        let resp = match export_cmd
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap()
+        {
+            Response::DeriveChildExportedCdi(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+
+        // No child context was created and the parent is still usable.
+        assert_eq!(ContextHandle::default(), resp.parent_handle);
+        let parent_idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        assert!(dpe.contexts[parent_idx].children.is_empty());
+        assert_eq!(
+            1,
+            dpe.contexts
+                .iter()
+                .filter(|c| c.state != ContextState::Inactive)
+                .count()
+        );
+
+        // The certificate is for the exported layer's key.
+        let x509 = openssl::x509::X509::from_der(&resp.cert[..resp.cert_size.try_into().unwrap()])
+            .unwrap();
+        let ec_pub_key = x509.public_key().unwrap().ec_key().unwrap();
+        let mut bn_ctx = openssl::bn::BigNumContext::new().unwrap();
+        let mut x = openssl::bn::BigNum::new().unwrap();
+        let mut y = openssl::bn::BigNum::new().unwrap();
+        ec_pub_key
+            .public_key()
+            .affine_coordinates(ec_pub_key.group(), &mut x, &mut y, &mut bn_ctx)
+            .unwrap();
+        let size = DPE_PROFILE.get_ecc_int_size() as i32;
+        assert_eq!(
+            resp.derived_pubkey_x.to_vec(),
+            x.to_vec_padded(size).unwrap()
+        );
+        assert_eq!(
+            resp.derived_pubkey_y.to_vec(),
+            y.to_vec_padded(size).unwrap()
+        );
+
+        // The exported CDI matches the CDI of a child context created with the same inputs.
+        DeriveChildCmd {
+            flags: DeriveChildFlags::RETAIN_PARENT,
+            ..export_cmd
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        let child_idx = dpe
+            .get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[0])
+            .unwrap();
+        let digest = dpe.compute_measurement_hash(&mut env, child_idx).unwrap();
+        let child_cdi = env
+            .crypto
+            .derive_cdi(DPE_PROFILE.alg_len(), &digest, b"DPE")
+            .unwrap();
+        assert_eq!(
+            child_cdi,
+            env.crypto.take_exported_cdi(&resp.exported_cdi).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_export_cdi_restrictions() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let export_cmd = DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: TEST_DIGEST,
+            flags: DeriveChildFlags::EXPORT_CDI,
+            tci_type: 0,
+            target_locality: 0,
+        };
+
+        // Unsupported.
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+        for flags in [
+            DeriveChildFlags::EXPORT_CDI,
+            DeriveChildFlags::INPUT_ALLOW_EXPORT,
+        ] {
+            assert_eq!(
+                Err(DpeErrorCode::ArgumentNotSupported),
+                DeriveChildCmd {
+                    flags,
+                    ..export_cmd
+                }
+                .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            );
+        }
+
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT | Support::CDI_EXPORT).unwrap();
+
+        // Nothing to make default or move when no context is created.
+        for flags in [
+            DeriveChildFlags::EXPORT_CDI | DeriveChildFlags::MAKE_DEFAULT,
+            DeriveChildFlags::EXPORT_CDI | DeriveChildFlags::CHANGE_LOCALITY,
+        ] {
+            assert_eq!(
+                Err(DpeErrorCode::InvalidArgument),
+                DeriveChildCmd {
+                    flags,
+                    ..export_cmd
+                }
+                .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            );
+        }
+
+        // Children can only export if they were allowed to when they were derived.
+        DeriveChildCmd {
+            flags: DeriveChildFlags::MAKE_DEFAULT,
+            ..export_cmd
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            export_cmd.execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            DeriveChildCmd {
+                flags: DeriveChildFlags::MAKE_DEFAULT | DeriveChildFlags::INPUT_ALLOW_EXPORT,
+                ..export_cmd
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+    }
+
+    #[test]
+    fn test_recursive() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+
+        let recursive = |data: u8| DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [data; DPE_PROFILE.get_tci_size()],
+            flags: DeriveChildFlags::RECURSIVE,
+            tci_type: 0,
+            target_locality: 0,
+        };
+
+        // Extending in place never consumes a new slot.
+        let mut expected = dpe.contexts[0].tci;
+        for i in 0..(MAX_HANDLES + 1) as u8 {
+            assert_eq!(
+                Ok(Response::DeriveChild(DeriveChildResp {
+                    handle: ContextHandle::default(),
+                    parent_handle: ContextHandle([0xff; ContextHandle::SIZE]),
+                    resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+                })),
+                recursive(i).execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            );
+            DpeInstance::extend_tci(
+                &mut env,
+                &mut expected,
+                &TciMeasurement([i; DPE_PROFILE.get_tci_size()]),
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            1,
+            dpe.contexts
+                .iter()
+                .filter(|c| c.state != ContextState::Inactive)
+                .count()
+        );
+        assert_eq!(ContextState::Active, dpe.contexts[0].state);
+        assert_eq!(expected, dpe.contexts[0].tci);
+        assert_eq!(0, dpe.contexts[0].tci.tci_type);
+
+        // The context must belong to the caller.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidLocality),
+            recursive(0).execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+    }
+
+    #[test]
+    fn test_recursive_rejects_new_context_inputs() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(
+            &mut env,
+            Support::AUTO_INIT
+                | Support::INTERNAL_INFO
+                | Support::INTERNAL_DICE
+                | Support::IS_CA
+                | Support::X509
+                | Support::CDI_EXPORT,
+        )
+        .unwrap();
+        let before = dpe.contexts[0].tci;
+
+        let recursive = |flags, tci_type| DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: TEST_DIGEST,
+            flags: DeriveChildFlags::RECURSIVE | flags,
+            tci_type,
+            target_locality: 0,
+        };
+
+        // Flags that only make sense for a new context are rejected.
+        for flags in [
+            DeriveChildFlags::RETAIN_PARENT,
+            DeriveChildFlags::MAKE_DEFAULT,
+            DeriveChildFlags::CHANGE_LOCALITY,
+            DeriveChildFlags::INTERNAL_INPUT_INFO,
+            DeriveChildFlags::INTERNAL_INPUT_DICE,
+            DeriveChildFlags::INPUT_ALLOW_CA,
+            DeriveChildFlags::INPUT_ALLOW_X509,
+            DeriveChildFlags::INPUT_ALLOW_EXPORT,
+            DeriveChildFlags::EXPORT_CDI,
+        ] {
+            let name = format!("{flags:?}");
+            assert_eq!(
+                Err(DpeErrorCode::InvalidArgument),
+                recursive(flags, 0).execute(&mut dpe, &mut env, TEST_LOCALITIES[0]),
+                "{name}"
+            );
+        }
+
+        // The context keeps its TCI type.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            recursive(DeriveChildFlags::empty(), 7).execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        assert_eq!(before, dpe.contexts[0].tci);
+        assert!(recursive(DeriveChildFlags::empty(), 0)
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .is_ok());
+    }
 }
@@ -1,16 +1,14 @@
 // Licensed under the Apache-2.0 license.
 use super::CommandExecution;
 use crate::{
-    context::ContextHandle,
-    dpe_instance::{flags_iter, DpeEnv, DpeInstance, DpeTypes},
+    context::{ContextBitmap, ContextHandle, ContextState},
+    dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
     response::{DpeErrorCode, Response, ResponseHdr},
-    MAX_HANDLES,
 };
 use bitflags::bitflags;
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct DestroyCtxFlags(u32);
 
 bitflags! {
@@ -20,8 +18,7 @@ bitflags! {
 }
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct DestroyCtxCmd {
     pub handle: ContextHandle,
     pub flags: DestroyCtxFlags,
@@ -49,17 +46,32 @@ impl CommandExecution for DestroyCtxCmd {
         }
 
         let to_destroy = if self.flag_is_destroy_descendants() {
-            (1 << idx) | dpe.get_descendants(context)?
+            ContextBitmap::from_idx(idx) | dpe.get_descendants(context)?
         } else {
-            1 << idx
+            ContextBitmap::from_idx(idx)
         };
 
-        for idx in flags_iter(to_destroy, MAX_HANDLES) {
-            if idx >= dpe.contexts.len() {
-                return Err(DpeErrorCode::InternalError);
-            }
+        if to_destroy.iter().any(|idx| idx >= dpe.contexts.len()) {
+            return Err(DpeErrorCode::InternalError);
+        }
+
+        let mut parent_idx = context.parent_idx as usize;
+        for idx in to_destroy.iter() {
             dpe.contexts[idx].destroy();
         }
+
+        // Unlink the destroyed node from its parent. Retired ancestors are only kept around for
+        // their children, so destroy them too once they have none left.
+        let mut child_idx = idx;
+        while let Some(parent) = dpe.contexts.get_mut(parent_idx) {
+            parent.children.remove(child_idx);
+            if parent.state != ContextState::Retired || !parent.children.is_empty() {
+                break;
+            }
+            child_idx = parent_idx;
+            parent_idx = parent.parent_idx as usize;
+            parent.destroy();
+        }
         Ok(Response::DestroyCtx(ResponseHdr::new(
             DpeErrorCode::NoError,
         )))
@@ -70,9 +82,13 @@ impl CommandExecution for DestroyCtxCmd {
 mod tests {
     use super::*;
     use crate::{
-        commands::{Command, CommandHdr},
-        dpe_instance::tests::SIMULATION_HANDLE,
+        commands::{Command, CommandHdr, DeriveChildCmd, DeriveChildFlags},
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        support::Support,
+        DPE_PROFILE,
     };
+    use crypto::OpensslCrypto;
+    use platform::default::DefaultPlatform;
     use zerocopy::AsBytes;
 
     const TEST_DESTROY_CTX_CMD: DestroyCtxCmd = DestroyCtxCmd {
@@ -91,4 +107,159 @@ mod tests {
             Command::deserialize(&command)
         );
     }
+
+    fn derive_child(
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<TestTypes>,
+        handle: ContextHandle,
+        flags: DeriveChildFlags,
+    ) {
+        DeriveChildCmd {
+            handle,
+            data: [0; DPE_PROFILE.get_tci_size()],
+            flags,
+            tci_type: 0,
+            target_locality: 0,
+        }
+        .execute(dpe, env, TEST_LOCALITIES[0])
+        .unwrap();
+    }
+
+    fn destroy(dpe: &mut DpeInstance, env: &mut DpeEnv<TestTypes>, handle: ContextHandle) {
+        assert_eq!(
+            Ok(Response::DestroyCtx(ResponseHdr::new(
+                DpeErrorCode::NoError
+            ))),
+            DestroyCtxCmd {
+                handle,
+                flags: DestroyCtxFlags::empty(),
+            }
+            .execute(dpe, env, TEST_LOCALITIES[0])
+        );
+    }
+
+    fn states(dpe: &DpeInstance) -> Vec<ContextState> {
+        dpe.contexts.iter().take(4).map(|c| c.state).collect()
+    }
+
+    #[test]
+    fn test_destroy_cleans_up_deep_chain() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+
+        // Each derivation retires the previous default context.
+        for _ in 0..3 {
+            derive_child(
+                &mut dpe,
+                &mut env,
+                ContextHandle::default(),
+                DeriveChildFlags::MAKE_DEFAULT,
+            );
+        }
+        assert_eq!(
+            vec![
+                ContextState::Retired,
+                ContextState::Retired,
+                ContextState::Retired,
+                ContextState::Active
+            ],
+            states(&dpe)
+        );
+
+        // Destroying the leaf leaves nothing for the retired ancestors to hold on to.
+        destroy(&mut dpe, &mut env, ContextHandle::default());
+        assert!(dpe
+            .contexts
+            .iter()
+            .all(|c| c.state == ContextState::Inactive && c.children.is_empty()));
+    }
+
+    #[test]
+    fn test_destroy_cleans_up_branching_tree() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+
+        // 0 (retired) -> 1 (retired) -> {2 (SIMULATION_HANDLE), 3 (default)}
+        derive_child(
+            &mut dpe,
+            &mut env,
+            ContextHandle::default(),
+            DeriveChildFlags::MAKE_DEFAULT,
+        );
+        derive_child(
+            &mut dpe,
+            &mut env,
+            ContextHandle::default(),
+            DeriveChildFlags::RETAIN_PARENT,
+        );
+        derive_child(
+            &mut dpe,
+            &mut env,
+            ContextHandle::default(),
+            DeriveChildFlags::MAKE_DEFAULT,
+        );
+        assert_eq!(SIMULATION_HANDLE, dpe.contexts[2].handle);
+        assert_eq!(
+            vec![
+                ContextState::Retired,
+                ContextState::Retired,
+                ContextState::Active,
+                ContextState::Active
+            ],
+            states(&dpe)
+        );
+        assert_eq!(
+            ContextBitmap::from_idx(2) | ContextBitmap::from_idx(3),
+            dpe.contexts[1].children
+        );
+
+        // The retired parent still has another child, so only the link is removed.
+        destroy(&mut dpe, &mut env, ContextHandle::default());
+        assert_eq!(
+            vec![
+                ContextState::Retired,
+                ContextState::Retired,
+                ContextState::Active,
+                ContextState::Inactive
+            ],
+            states(&dpe)
+        );
+        assert_eq!(ContextBitmap::from_idx(2), dpe.contexts[1].children);
+
+        // The last child goes away and takes all of its retired ancestors with it.
+        destroy(&mut dpe, &mut env, SIMULATION_HANDLE);
+        assert!(dpe
+            .contexts
+            .iter()
+            .all(|c| c.state == ContextState::Inactive && c.children.is_empty()));
+    }
+
+    #[test]
+    fn test_destroy_keeps_active_parent() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
+
+        derive_child(
+            &mut dpe,
+            &mut env,
+            ContextHandle::default(),
+            DeriveChildFlags::RETAIN_PARENT,
+        );
+        destroy(&mut dpe, &mut env, SIMULATION_HANDLE);
+        assert_eq!(ContextState::Active, dpe.contexts[0].state);
+        assert!(dpe.contexts[0].children.is_empty());
+        assert_eq!(ContextState::Inactive, dpe.contexts[1].state);
+    }
 }
@@ -3,17 +3,17 @@ use super::CommandExecution;
 use crate::{
     context::ContextHandle,
     dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
+    event_log::TciEventType,
     response::{DpeErrorCode, NewHandleResp, Response, ResponseHdr},
     tci::TciMeasurement,
     DPE_PROFILE,
 };
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct ExtendTciCmd {
-    handle: ContextHandle,
-    data: [u8; DPE_PROFILE.get_hash_size()],
+    pub handle: ContextHandle,
+    pub data: [u8; DPE_PROFILE.get_hash_size()],
 }
 
 impl CommandExecution for ExtendTciCmd {
@@ -29,12 +29,19 @@ impl CommandExecution for ExtendTciCmd {
         }
 
         let idx = dpe.get_active_context_pos(&self.handle, locality)?;
-        dpe.add_tci_measurement(env, idx, &TciMeasurement(self.data), locality)?;
-
         // Rotate the handle if it isn't the default context.
-        dpe.roll_onetime_use_handle(env, idx)?;
+        let handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.add_tci_measurement(
+            env,
+            idx,
+            &TciMeasurement(self.data),
+            locality,
+            TciEventType::Extend,
+        )?;
+        dpe.contexts[idx].handle = handle;
+
         Ok(Response::ExtendTci(NewHandleResp {
-            handle: dpe.contexts[idx].handle,
+            handle,
             resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
         }))
     }
@@ -73,7 +80,8 @@ mod tests {
     fn test_extend_tci() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::default()).unwrap();
         // Make sure it returns an error if the command is marked unsupported.
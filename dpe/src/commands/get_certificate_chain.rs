@@ -75,7 +75,8 @@ mod tests {
     fn test_fails_if_size_greater_than_max_cert_size() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
 
@@ -0,0 +1,499 @@
+// Licensed under the Apache-2.0 license.
+use super::{certify_key::create_x509_certificate, CommandExecution};
+use crate::{
+    cbor::{CborWriter, EatClaims},
+    context::{ContextHandle, ContextType},
+    dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
+    response::{DpeErrorCode, GetEatResp, Response, ResponseHdr},
+    tci::TciNodeData,
+    x509::MeasurementData,
+    DPE_PROFILE, EAT_NONCE_SIZE, MAX_CERT_SIZE, MAX_EAT_SIZE, MAX_HANDLES,
+};
+use core::slice;
+use crypto::{Crypto, EcdsaSig, Hasher};
+use platform::{Platform, MAX_UEID_SIZE};
+
+/// Key derivation info for the key tokens are signed with. It differs from the key Sign and
+/// SignMessage use, so a signature over caller-chosen data can't pass as a token.
+const EAT_KEY_INFO: &[u8] = b"EAT";
+
+/// The longest byte string head the certificate or claims in a token can need. Neither reaches
+/// 65536 bytes, so their heads take at most 3 bytes.
+const MAX_BSTR_HEAD_SIZE: usize = 3;
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct GetEatCmd {
+    pub handle: ContextHandle,
+    pub label: [u8; DPE_PROFILE.get_hash_size()],
+    pub nonce: [u8; EAT_NONCE_SIZE],
+}
+
+/// Writes a byte string at the start of `buf` whose contents `write` encodes in place.
+///
+/// The contents are written after room for the longest head they can need, then moved up against
+/// the head once their size is known. Returns the sizes of the head and the contents.
+fn encode_bstr_in_place(
+    buf: &mut [u8],
+    write: impl FnOnce(&mut [u8]) -> Result<usize, DpeErrorCode>,
+) -> Result<(usize, usize), DpeErrorCode> {
+    let size = write(
+        buf.get_mut(MAX_BSTR_HEAD_SIZE..)
+            .ok_or(DpeErrorCode::InternalError)?,
+    )?;
+    if size > u16::MAX as usize {
+        return Err(DpeErrorCode::InternalError);
+    }
+    let head_size = CborWriter::new(buf).encode_bstr_head(size)?;
+    buf.copy_within(MAX_BSTR_HEAD_SIZE..MAX_BSTR_HEAD_SIZE + size, head_size);
+    Ok((head_size, size))
+}
+
+impl CommandExecution for GetEatCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.eat() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let idx = dpe.get_active_context_pos(&self.handle, locality)?;
+
+        // The token carries an X.509 certificate for the signing key, so the context has to be
+        // allowed to certify keys.
+        if !dpe.support.x509() {
+            return Err(DpeErrorCode::ArgumentNotSupported);
+        }
+        if !dpe.contexts[idx].allow_x509() {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let profile = dpe.get_profile(&mut env.platform)?;
+
+        let mut ueid = [0u8; MAX_UEID_SIZE];
+        let ueid_size = env
+            .platform
+            .get_ueid(&mut ueid)
+            .map_err(|_| DpeErrorCode::PlatformError)?;
+        if ueid_size > MAX_UEID_SIZE {
+            return Err(DpeErrorCode::PlatformError);
+        }
+
+        const INITIALIZER: TciNodeData = TciNodeData::new();
+        let mut nodes = [INITIALIZER; MAX_HANDLES];
+        let tcb_count = dpe.get_tcb_nodes(idx, &mut nodes)?;
+        let is_simulation = dpe.contexts[idx].context_type == ContextType::Simulation;
+
+        let algs = DPE_PROFILE.alg_len();
+        let cdi = dpe.derive_cdi(env, idx)?;
+        let (priv_key, pub_key) = env
+            .crypto
+            .derive_key_pair(algs, &cdi, &self.label, EAT_KEY_INFO)
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+
+        let mut protected = [0u8; 8];
+        let protected_size = CborWriter::new(&mut protected).encode_cose_protected_header()?;
+        let protected = &protected[..protected_size];
+
+        // The token is encoded straight into the response.
+        let mut response = GetEatResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            new_context_handle: ContextHandle::default(),
+            token_size: 0,
+            token: [0; MAX_EAT_SIZE],
+        };
+        let token = &mut response.token;
+        let mut offset = CborWriter::new(token).encode_cose_sign1_prefix(protected)?;
+
+        // The claims carry the whole TCI chain, so the certificate only names the context's own
+        // TCI, whose cumulative value covers the rest.
+        let measurements = MeasurementData {
+            label: &self.label,
+            tci_nodes: slice::from_ref(&dpe.contexts[idx].tci),
+            is_ca: false,
+            is_simulation,
+        };
+        let (head_size, cert_size) = encode_bstr_in_place(
+            token.get_mut(offset..).ok_or(DpeErrorCode::InternalError)?,
+            |buf| {
+                let cert = buf
+                    .get_mut(..MAX_CERT_SIZE)
+                    .and_then(|cert| cert.try_into().ok())
+                    .ok_or(DpeErrorCode::InternalError)?;
+                let cert_size =
+                    create_x509_certificate(env, b"DPE EAT", &pub_key, &measurements, cert)?;
+                Ok(cert_size as usize)
+            },
+        )?;
+        offset += head_size + cert_size;
+
+        let (head_size, payload_size) = encode_bstr_in_place(
+            token.get_mut(offset..).ok_or(DpeErrorCode::InternalError)?,
+            |buf| {
+                CborWriter::new(buf).encode_eat_claims(&EatClaims {
+                    nonce: &self.nonce,
+                    ueid: &ueid[..ueid_size],
+                    profile: &profile,
+                    tci_nodes: &nodes[..tcb_count],
+                    is_simulation,
+                })
+            },
+        )?;
+        offset += head_size;
+        let payload = token
+            .get(offset..offset + payload_size)
+            .ok_or(DpeErrorCode::InternalError)?;
+        offset += payload_size;
+
+        // Sign the COSE Sig_structure. The payload is hashed in place after the prefix.
+        let mut prefix = [0u8; 32];
+        let prefix_size =
+            CborWriter::new(&mut prefix).encode_sig_structure_prefix(protected, payload_size)?;
+        let mut hasher = env
+            .crypto
+            .hash_initialize(algs)
+            .map_err(|_| DpeErrorCode::HashError)?;
+        hasher
+            .update(&prefix[..prefix_size])
+            .map_err(|_| DpeErrorCode::HashError)?;
+        hasher
+            .update(payload)
+            .map_err(|_| DpeErrorCode::HashError)?;
+        let digest = hasher.finish().map_err(|_| DpeErrorCode::HashError)?;
+
+        let EcdsaSig { r, s } = env
+            .crypto
+            .ecdsa_sign_with_derived(algs, &digest, &priv_key, pub_key)
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+        const ECC_INT_SIZE: usize = DPE_PROFILE.get_ecc_int_size();
+        let mut sig = [0u8; 2 * ECC_INT_SIZE];
+        sig[..ECC_INT_SIZE].copy_from_slice(r.bytes());
+        sig[ECC_INT_SIZE..].copy_from_slice(s.bytes());
+        offset += CborWriter::new(token.get_mut(offset..).ok_or(DpeErrorCode::InternalError)?)
+            .encode_bstr(&sig)?;
+
+        response.token_size = offset as u32;
+        response.new_context_handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.contexts[idx].handle = response.new_context_handle;
+
+        Ok(Response::GetEat(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{
+            tests::TEST_DIGEST, CertifyKeyCmd, CertifyKeyFlags, Command, CommandHdr,
+            DeriveChildCmd, DeriveChildFlags, InitCtxCmd,
+        },
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        support::{test::SUPPORT, Support},
+    };
+    use ciborium::Value;
+    use crypto::OpensslCrypto;
+    use openssl::{bn::BigNum, ecdsa, nid::Nid, x509::X509};
+    use platform::default::{DefaultPlatform, TEST_UEID};
+    use zerocopy::AsBytes;
+
+    const TEST_GET_EAT_CMD: GetEatCmd = GetEatCmd {
+        handle: SIMULATION_HANDLE,
+        label: TEST_DIGEST,
+        nonce: [0x5a; EAT_NONCE_SIZE],
+    };
+
+    struct DecodedToken {
+        protected: Vec<u8>,
+        cert: Vec<u8>,
+        payload: Vec<u8>,
+        sig: Vec<u8>,
+        claims: Vec<(Value, Value)>,
+    }
+
+    fn decode_token(resp: &GetEatResp) -> DecodedToken {
+        let token: Value =
+            ciborium::de::from_reader(&resp.token[..resp.token_size as usize]).unwrap();
+        let Value::Tag(tag, cose_sign1) = token else {
+            panic!("Token is not tagged");
+        };
+        assert_eq!(CborWriter::COSE_SIGN1_TAG, tag);
+        let cose_sign1 = cose_sign1.into_array().unwrap();
+        assert_eq!(4, cose_sign1.len());
+
+        let protected = cose_sign1[0].as_bytes().unwrap().clone();
+        let unprotected = cose_sign1[1].as_map().unwrap();
+        assert_eq!(1, unprotected.len());
+        assert_eq!(Value::from(33), unprotected[0].0);
+        let cert = unprotected[0].1.as_bytes().unwrap().clone();
+        let payload = cose_sign1[2].as_bytes().unwrap().clone();
+        let sig = cose_sign1[3].as_bytes().unwrap().clone();
+        let claims: Value = ciborium::de::from_reader(payload.as_slice()).unwrap();
+
+        DecodedToken {
+            protected,
+            cert,
+            payload,
+            sig,
+            claims: claims.into_map().unwrap(),
+        }
+    }
+
+    fn claim(claims: &[(Value, Value)], key: i64) -> Option<&Value> {
+        claims
+            .iter()
+            .find(|(k, _)| *k == Value::from(key))
+            .map(|(_, v)| v)
+    }
+
+    #[test]
+    fn test_deserialize_get_eat() {
+        let mut command = CommandHdr::new_for_test(Command::GET_EAT)
+            .as_bytes()
+            .to_vec();
+        command.extend(TEST_GET_EAT_CMD.as_bytes());
+        assert_eq!(
+            Ok(Command::GetEat(TEST_GET_EAT_CMD)),
+            Command::deserialize(&command)
+        );
+    }
+
+    #[test]
+    fn test_get_eat() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            GetEatCmd {
+                handle: ContextHandle::default(),
+                ..TEST_GET_EAT_CMD
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::EAT).unwrap();
+        for i in 0..3 {
+            DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [i; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::MAKE_DEFAULT | DeriveChildFlags::INPUT_ALLOW_X509,
+                tci_type: i as u32,
+                target_locality: 0,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        }
+
+        // Wrong locality.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidLocality),
+            GetEatCmd {
+                handle: ContextHandle::default(),
+                ..TEST_GET_EAT_CMD
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        let resp = match (GetEatCmd {
+            handle: ContextHandle::default(),
+            ..TEST_GET_EAT_CMD
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::GetEat(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(ContextHandle::default(), resp.new_context_handle);
+
+        let token = decode_token(&resp);
+        let protected: Value = ciborium::de::from_reader(token.protected.as_slice()).unwrap();
+        let alg = match DPE_PROFILE {
+            crate::DpeProfile::P256Sha256 => -7,
+            crate::DpeProfile::P384Sha384 => -35,
+        };
+        assert_eq!(
+            vec![(Value::from(1), Value::from(alg))],
+            protected.into_map().unwrap()
+        );
+
+        // Claims
+        assert_eq!(4, token.claims.len());
+        assert_eq!(
+            &Value::Bytes(TEST_GET_EAT_CMD.nonce.to_vec()),
+            claim(&token.claims, CborWriter::EAT_CLAIM_NONCE).unwrap()
+        );
+        assert_eq!(
+            &Value::Bytes(TEST_UEID.to_vec()),
+            claim(&token.claims, CborWriter::EAT_CLAIM_UEID).unwrap()
+        );
+        assert!(claim(&token.claims, CborWriter::DPE_CLAIM_SIMULATION).is_none());
+        let profile = claim(&token.claims, CborWriter::DPE_CLAIM_PROFILE)
+            .unwrap()
+            .as_map()
+            .unwrap();
+        assert!(profile.contains(&(
+            Value::from(CborWriter::PROFILE_KEY_PROFILE),
+            Value::from(DPE_PROFILE as u32)
+        )));
+        assert!(profile.contains(&(
+            Value::from(CborWriter::PROFILE_KEY_MINOR_VERSION),
+            Value::from(crate::CURRENT_PROFILE_MINOR_VERSION)
+        )));
+
+        let idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        const INITIALIZER: TciNodeData = TciNodeData::new();
+        let mut nodes = [INITIALIZER; MAX_HANDLES];
+        let count = dpe.get_tcb_nodes(idx, &mut nodes).unwrap();
+        let chain = claim(&token.claims, CborWriter::DPE_CLAIM_TCI_CHAIN)
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(count, chain.len());
+        for (node, value) in nodes[..count].iter().zip(chain) {
+            let value = value.as_map().unwrap();
+            assert!(value.contains(&(
+                Value::from(CborWriter::TCI_KEY_TYPE),
+                Value::from(node.tci_type)
+            )));
+            assert!(value.contains(&(
+                Value::from(CborWriter::TCI_KEY_CUMULATIVE),
+                Value::Bytes(node.tci_cumulative.0.to_vec())
+            )));
+        }
+
+        // Verify the COSE_Sign1 with the key certified in x5chain.
+        let x509 = X509::from_der(&token.cert).unwrap();
+        let ec_pub_key = x509.public_key().unwrap().ec_key().unwrap();
+        let sig_structure = Value::Array(vec![
+            Value::Text("Signature1".into()),
+            Value::Bytes(token.protected.clone()),
+            Value::Bytes(vec![]),
+            Value::Bytes(token.payload.clone()),
+        ]);
+        let mut tbs = vec![];
+        ciborium::ser::into_writer(&sig_structure, &mut tbs).unwrap();
+        let mut hasher = env.crypto.hash_initialize(DPE_PROFILE.alg_len()).unwrap();
+        hasher.update(&tbs).unwrap();
+        let digest = hasher.finish().unwrap();
+
+        let (r, s) = token.sig.split_at(DPE_PROFILE.get_ecc_int_size());
+        let sig = ecdsa::EcdsaSig::from_private_components(
+            BigNum::from_slice(r).unwrap(),
+            BigNum::from_slice(s).unwrap(),
+        )
+        .unwrap();
+        assert!(sig.verify(digest.bytes(), &ec_pub_key).unwrap());
+
+        // The token key is certified as such, and isn't the key Sign uses.
+        let cn = x509
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap();
+        assert_eq!(b"DPE EAT", cn.data().as_slice());
+        let certify_resp = match (CertifyKeyCmd {
+            handle: ContextHandle::default(),
+            flags: CertifyKeyFlags::empty(),
+            label: TEST_GET_EAT_CMD.label,
+            format: CertifyKeyCmd::FORMAT_X509,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::CertifyKey(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        let signing_cert =
+            X509::from_der(&certify_resp.cert[..certify_resp.cert_size as usize]).unwrap();
+        assert!(!x509
+            .public_key()
+            .unwrap()
+            .public_eq(&signing_cert.public_key().unwrap()));
+    }
+
+    #[test]
+    fn test_get_eat_full_chain() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::EAT).unwrap();
+        for _ in 1..MAX_HANDLES {
+            DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [0xff; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::MAKE_DEFAULT | DeriveChildFlags::INPUT_ALLOW_X509,
+                tci_type: u32::MAX,
+                target_locality: 0,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        }
+
+        // A token for the longest possible chain still fits.
+        let resp = match (GetEatCmd {
+            handle: ContextHandle::default(),
+            ..TEST_GET_EAT_CMD
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::GetEat(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        let token = decode_token(&resp);
+        let chain = claim(&token.claims, CborWriter::DPE_CLAIM_TCI_CHAIN)
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(MAX_HANDLES, chain.len());
+    }
+
+    #[test]
+    fn test_get_eat_simulation() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::EAT).unwrap();
+        InitCtxCmd::new_simulation()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+            .unwrap();
+        let idx = dpe
+            .get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[1])
+            .unwrap();
+        dpe.contexts[idx].handle = ContextHandle([0x11; ContextHandle::SIZE]);
+
+        let resp = match (GetEatCmd {
+            handle: ContextHandle([0x11; ContextHandle::SIZE]),
+            ..TEST_GET_EAT_CMD
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        .unwrap()
+        {
+            Response::GetEat(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(SIMULATION_HANDLE, resp.new_context_handle);
+
+        let token = decode_token(&resp);
+        assert_eq!(5, token.claims.len());
+        assert_eq!(
+            &Value::Bool(true),
+            claim(&token.claims, CborWriter::DPE_CLAIM_SIMULATION).unwrap()
+        );
+    }
+}
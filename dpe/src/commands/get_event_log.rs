@@ -0,0 +1,233 @@
+// Licensed under the Apache-2.0 license.
+use super::CommandExecution;
+use crate::{
+    dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
+    response::{DpeErrorCode, GetEventLogResp, Response, ResponseHdr},
+    MAX_EVENT_LOG_CHUNK_SIZE,
+};
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct GetEventLogCmd {
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl CommandExecution for GetEventLogCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        _env: &mut DpeEnv<impl DpeTypes>,
+        _locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.event_log() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+        if self.size > MAX_EVENT_LOG_CHUNK_SIZE as u32 {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        let log_size = dpe.event_log.size();
+        if self.offset as usize > log_size {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let mut chunk = [0u8; MAX_EVENT_LOG_CHUNK_SIZE];
+        let chunk_size = dpe
+            .event_log
+            .read(self.offset as usize, &mut chunk[..self.size as usize]);
+        Ok(Response::GetEventLog(GetEventLogResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            log_size: log_size as u32,
+            first_recnum: dpe.event_log.first_recnum(),
+            chunk_size: chunk_size as u32,
+            chunk,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{Command, CommandHdr, DeriveChildCmd, DeriveChildFlags},
+        context::ContextHandle,
+        dpe_instance::tests::{TestTypes, TEST_LOCALITIES},
+        event_log::{tests::parse_log, TciEventType, EVENT_RECORD_SIZE},
+        support::{test::SUPPORT, Support},
+        tci::TciMeasurement,
+        DPE_PROFILE, MAX_TCI_EVENTS,
+    };
+    use crypto::{Crypto, Hasher, OpensslCrypto};
+    use platform::default::DefaultPlatform;
+    use zerocopy::AsBytes;
+
+    const TEST_GET_EVENT_LOG_CMD: GetEventLogCmd = GetEventLogCmd {
+        offset: 0x1234,
+        size: 0x5678,
+    };
+
+    #[test]
+    fn test_deserialize_get_event_log() {
+        let mut command = CommandHdr::new_for_test(Command::GET_EVENT_LOG)
+            .as_bytes()
+            .to_vec();
+        command.extend(TEST_GET_EVENT_LOG_CMD.as_bytes());
+        assert_eq!(
+            Ok(Command::GetEventLog(TEST_GET_EVENT_LOG_CMD)),
+            Command::deserialize(&command)
+        );
+    }
+
+    #[test]
+    fn test_get_event_log() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            GetEventLogCmd { offset: 0, size: 0 }.execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::EVENT_LOG).unwrap();
+        DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [1; DPE_PROFILE.get_hash_size()],
+            flags: DeriveChildFlags::MAKE_DEFAULT,
+            tci_type: 0x1234,
+            target_locality: 0,
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        let idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        for i in 2..4 {
+            dpe.add_tci_measurement(
+                &mut env,
+                idx,
+                &TciMeasurement([i; DPE_PROFILE.get_hash_size()]),
+                TEST_LOCALITIES[0],
+                TciEventType::Extend,
+            )
+            .unwrap();
+        }
+
+        // Too big a chunk, or starting past the end of the log.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            GetEventLogCmd {
+                offset: 0,
+                size: MAX_EVENT_LOG_CHUNK_SIZE as u32 + 1,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            GetEventLogCmd {
+                offset: dpe.event_log.size() as u32 + 1,
+                size: 0,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        // Read the log in small chunks.
+        let mut log = vec![];
+        loop {
+            let cmd = GetEventLogCmd {
+                offset: log.len() as u32,
+                size: 50,
+            };
+            let resp = match cmd.execute(&mut dpe, &mut env, TEST_LOCALITIES[0]).unwrap() {
+                Response::GetEventLog(resp) => resp,
+                _ => panic!("Incorrect response type"),
+            };
+            log.extend_from_slice(&resp.chunk[..resp.chunk_size as usize]);
+            if log.len() == resp.log_size as usize {
+                break;
+            }
+        }
+
+        let events = parse_log(&log);
+        assert_eq!(3, events.len());
+        assert_eq!(TciEventType::Derive as u32, events[0].event_type);
+        for (i, event) in events.iter().enumerate() {
+            assert_eq!(i as u32, event.recnum);
+            assert_eq!(idx, event.context_idx);
+            assert_eq!(0x1234, event.tci_type);
+            assert_eq!(TEST_LOCALITIES[0], event.locality);
+        }
+        assert_eq!(TciEventType::Extend as u32, events[2].event_type);
+
+        // Replaying the log gives the context's cumulative TCI.
+        let mut cumulative = TciMeasurement::default();
+        for event in events {
+            let mut hasher = env.crypto.hash_initialize(DPE_PROFILE.alg_len()).unwrap();
+            hasher.update(&cumulative.0).unwrap();
+            hasher.update(&event.digest).unwrap();
+            cumulative
+                .0
+                .copy_from_slice(hasher.finish().unwrap().bytes());
+        }
+        assert_eq!(cumulative.0, dpe.contexts[idx].tci.tci_cumulative.0);
+    }
+
+    #[test]
+    fn test_full_event_log() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let measurement = TciMeasurement([1; DPE_PROFILE.get_hash_size()]);
+
+        // Without the event log, measurements are never refused.
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        for _ in 0..MAX_TCI_EVENTS + 1 {
+            dpe.add_tci_measurement(
+                &mut env,
+                0,
+                &measurement,
+                TEST_LOCALITIES[0],
+                TciEventType::Extend,
+            )
+            .unwrap();
+        }
+
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::EVENT_LOG).unwrap();
+        for _ in 0..MAX_TCI_EVENTS {
+            dpe.add_tci_measurement(
+                &mut env,
+                0,
+                &measurement,
+                TEST_LOCALITIES[0],
+                TciEventType::Extend,
+            )
+            .unwrap();
+        }
+        let tci = dpe.contexts[0].tci;
+        assert_eq!(
+            Err(DpeErrorCode::EventLogFull),
+            dpe.add_tci_measurement(
+                &mut env,
+                0,
+                &measurement,
+                TEST_LOCALITIES[0],
+                TciEventType::Extend,
+            )
+        );
+        assert_eq!(tci, dpe.contexts[0].tci);
+        let resp = match (GetEventLogCmd { offset: 0, size: 0 })
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap()
+        {
+            Response::GetEventLog(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(0, resp.first_recnum);
+        assert_eq!((MAX_TCI_EVENTS * EVENT_RECORD_SIZE) as u32, resp.log_size);
+    }
+}
@@ -1,15 +1,21 @@
 // Licensed under the Apache-2.0 license.
 use super::CommandExecution;
 use crate::{
+    context::{ChildToRootIter, Context, ContextState, ContextType},
     dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
-    response::{DpeErrorCode, GetTaggedTciResp, Response, ResponseHdr},
+    response::{
+        DpeErrorCode, GetTaggedTciInfoResp, GetTaggedTciResp, Response, ResponseHdr, TciNodeInfo,
+        TciNodeInfoFlags,
+    },
+    tci::TciNodeData,
+    MAX_HANDLES,
 };
+use bitflags::bitflags;
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct GetTaggedTciCmd {
-    tag: u32,
+    pub tag: u32,
 }
 
 impl CommandExecution for GetTaggedTciCmd {
@@ -39,3 +45,227 @@ impl CommandExecution for GetTaggedTciCmd {
         }))
     }
 }
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct GetTaggedTciInfoFlags(u32);
+
+bitflags! {
+    impl GetTaggedTciInfoFlags: u32 {
+        const INCLUDE_CHAIN = 1u32 << 31;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct GetTaggedTciInfoCmd {
+    pub tag: u32,
+    pub flags: GetTaggedTciInfoFlags,
+}
+
+impl GetTaggedTciInfoCmd {
+    const fn includes_chain(&self) -> bool {
+        self.flags.contains(GetTaggedTciInfoFlags::INCLUDE_CHAIN)
+    }
+
+    fn node_info(dpe: &DpeInstance, context: &Context) -> TciNodeInfo {
+        let mut flags = TciNodeInfoFlags::empty();
+        flags.set(TciNodeInfoFlags::HAS_TAG, context.has_tag());
+        flags.set(
+            TciNodeInfoFlags::RETIRED,
+            context.state == ContextState::Retired,
+        );
+        flags.set(
+            TciNodeInfoFlags::SIMULATION,
+            context.context_type == ContextType::Simulation,
+        );
+
+        let parent = dpe.contexts.get(context.parent_idx as usize);
+        let parent_tag = match parent {
+            Some(parent) if parent.has_tag() => {
+                flags |= TciNodeInfoFlags::PARENT_HAS_TAG;
+                parent.tag
+            }
+            _ => 0,
+        };
+        flags.set(TciNodeInfoFlags::IS_ROOT, parent.is_none());
+
+        TciNodeInfo {
+            tci: context.tci,
+            tag: context.tag,
+            parent_tag,
+            flags,
+        }
+    }
+}
+
+impl CommandExecution for GetTaggedTciInfoCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        _env: &mut DpeEnv<impl DpeTypes>,
+        _: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.tagging() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let idx = dpe
+            .contexts
+            .iter()
+            .position(|c| c.has_tag() && c.tag == self.tag)
+            .ok_or(DpeErrorCode::BadTag)?;
+
+        const INITIALIZER: TciNodeInfo = TciNodeInfo {
+            tci: TciNodeData::new(),
+            tag: 0,
+            parent_tag: 0,
+            flags: TciNodeInfoFlags::empty(),
+        };
+        let mut response = GetTaggedTciInfoResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            node_count: 0,
+            nodes: [INITIALIZER; MAX_HANDLES],
+        };
+
+        // Nodes are returned from the tagged node up to the root.
+        let max_nodes = if self.includes_chain() {
+            MAX_HANDLES
+        } else {
+            1
+        };
+        for (node, status) in response
+            .nodes
+            .iter_mut()
+            .zip(ChildToRootIter::new(idx, &dpe.contexts))
+            .take(max_nodes)
+        {
+            *node = Self::node_info(dpe, status?);
+            response.node_count += 1;
+        }
+
+        Ok(Response::GetTaggedTciInfo(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{Command, CommandHdr, DeriveChildCmd, DeriveChildFlags},
+        context::ContextHandle,
+        dpe_instance::tests::{TestTypes, TEST_LOCALITIES},
+        support::Support,
+        DPE_PROFILE,
+    };
+    use crypto::OpensslCrypto;
+    use platform::default::DefaultPlatform;
+    use zerocopy::AsBytes;
+
+    const TEST_GET_TAGGED_TCI_INFO_CMD: GetTaggedTciInfoCmd = GetTaggedTciInfoCmd {
+        tag: 0x1234_5678,
+        flags: GetTaggedTciInfoFlags(0x9876_5432),
+    };
+
+    #[test]
+    fn test_deserialize_get_tagged_tci_info() {
+        let mut command = CommandHdr::new_for_test(Command::GET_TAGGED_TCI_INFO)
+            .as_bytes()
+            .to_vec();
+        command.extend(TEST_GET_TAGGED_TCI_INFO_CMD.as_bytes());
+        assert_eq!(
+            Ok(Command::GetTaggedTciInfo(TEST_GET_TAGGED_TCI_INFO_CMD)),
+            Command::deserialize(&command)
+        );
+    }
+
+    #[test]
+    fn test_get_tagged_tci_info() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT | Support::TAGGING).unwrap();
+
+        // Unknown tag.
+        assert_eq!(
+            Err(DpeErrorCode::BadTag),
+            GetTaggedTciInfoCmd {
+                tag: 0,
+                flags: GetTaggedTciInfoFlags::empty(),
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        // Tag the root, then build a chain of two children below it and tag the leaf.
+        dpe.contexts[0].tag = 1;
+        dpe.contexts[0].has_tag = true.into();
+        for tci_type in [2, 3] {
+            DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [tci_type as u8; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::MAKE_DEFAULT,
+                tci_type,
+                target_locality: 0,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        }
+        let leaf_idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        dpe.contexts[leaf_idx].tag = 3;
+        dpe.contexts[leaf_idx].has_tag = true.into();
+
+        // Just the tagged node.
+        let resp = match (GetTaggedTciInfoCmd {
+            tag: 3,
+            flags: GetTaggedTciInfoFlags::empty(),
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::GetTaggedTciInfo(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(1, resp.node_count);
+        assert_eq!(dpe.contexts[leaf_idx].tci, resp.nodes[0].tci);
+        assert_eq!(3, resp.nodes[0].tci.tci_type);
+        assert_eq!(TEST_LOCALITIES[0], resp.nodes[0].tci.locality);
+        assert_eq!(3, resp.nodes[0].tag);
+        assert_eq!(TciNodeInfoFlags::HAS_TAG, resp.nodes[0].flags);
+
+        // The whole chain up to the root.
+        let resp = match (GetTaggedTciInfoCmd {
+            tag: 3,
+            flags: GetTaggedTciInfoFlags::INCLUDE_CHAIN,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::GetTaggedTciInfo(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(3, resp.node_count);
+        assert_eq!(
+            [3, 2, 0],
+            [
+                resp.nodes[0].tci.tci_type,
+                resp.nodes[1].tci.tci_type,
+                resp.nodes[2].tci.tci_type
+            ]
+        );
+        assert_eq!(
+            TciNodeInfoFlags::RETIRED | TciNodeInfoFlags::PARENT_HAS_TAG,
+            resp.nodes[1].flags
+        );
+        assert_eq!(1, resp.nodes[1].parent_tag);
+        assert_eq!(
+            TciNodeInfoFlags::RETIRED | TciNodeInfoFlags::HAS_TAG | TciNodeInfoFlags::IS_ROOT,
+            resp.nodes[2].flags
+        );
+        assert_eq!(1, resp.nodes[2].tag);
+    }
+}
@@ -8,8 +8,7 @@ use crate::{
 use bitflags::bitflags;
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct InitCtxCmd(u32);
 
 bitflags! {
@@ -24,6 +23,10 @@ impl InitCtxCmd {
         Self::DEFAULT_FLAG_MASK
     }
 
+    pub const fn new_simulation() -> InitCtxCmd {
+        Self::SIMULATION_FLAG_MASK
+    }
+
     const fn flag_is_simulation(&self) -> bool {
         self.contains(Self::SIMULATION_FLAG_MASK)
     }
@@ -31,11 +34,6 @@ impl InitCtxCmd {
     const fn flag_is_default(&self) -> bool {
         self.contains(Self::DEFAULT_FLAG_MASK)
     }
-
-    #[cfg(test)]
-    pub const fn new_simulation() -> InitCtxCmd {
-        Self::SIMULATION_FLAG_MASK
-    }
 }
 
 impl CommandExecution for InitCtxCmd {
@@ -62,14 +60,17 @@ impl CommandExecution for InitCtxCmd {
         let idx = dpe
             .get_next_inactive_context_pos()
             .ok_or(DpeErrorCode::MaxTcis)?;
+        dpe.check_locality_quota(env, locality)?;
         let (context_type, handle) = if self.flag_is_default() {
-            dpe.has_initialized = true.into();
             (ContextType::Normal, ContextHandle::default())
         } else {
             // Simulation.
             (ContextType::Simulation, dpe.generate_new_handle(env)?)
         };
 
+        if self.flag_is_default() {
+            dpe.has_initialized = true.into();
+        }
         dpe.contexts[idx].activate(&ActiveContextArgs {
             context_type,
             locality,
@@ -78,6 +79,7 @@ impl CommandExecution for InitCtxCmd {
             parent_idx: Context::ROOT_INDEX,
             allow_ca: true,
             allow_x509: true,
+            allow_export: true,
         });
         Ok(Response::InitCtx(NewHandleResp {
             handle,
@@ -117,7 +119,8 @@ mod tests {
     fn test_initialize_context() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::default()).unwrap();
 
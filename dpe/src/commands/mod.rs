@@ -5,38 +5,54 @@ Abstract:
     DPE Commands and deserialization.
 --*/
 pub use self::derive_child::{DeriveChildCmd, DeriveChildFlags};
-pub(crate) use self::destroy_context::DestroyCtxCmd;
+pub use self::destroy_context::{DestroyCtxCmd, DestroyCtxFlags};
 pub use self::get_certificate_chain::GetCertificateChainCmd;
+pub use self::get_event_log::GetEventLogCmd;
 pub use self::initialize_context::InitCtxCmd;
 
+pub use self::batch::{BatchCmd, BatchEntryFlags, BatchEntryHdr, BatchFlags};
 pub use self::certify_key::{CertifyKeyCmd, CertifyKeyFlags};
 
-use self::extend_tci::ExtendTciCmd;
-use self::get_tagged_tci::GetTaggedTciCmd;
-use self::rotate_context::RotateCtxCmd;
+pub use self::extend_tci::ExtendTciCmd;
+pub use self::get_eat::GetEatCmd;
+pub use self::get_tagged_tci::{GetTaggedTciCmd, GetTaggedTciInfoCmd, GetTaggedTciInfoFlags};
+pub use self::quote::QuoteCmd;
+pub use self::rotate_context::{RotateCtxCmd, RotateCtxFlags};
+pub use self::seal::{SealCmd, SealedBlob, SealedBlobHdr, UnsealCmd};
 pub use self::sign::{SignCmd, SignFlags};
-use self::tag_tci::TagTciCmd;
+pub use self::sign_message::{
+    SignMessageCmd, SignStreamFinalCmd, SignStreamInitCmd, SignStreamUpdateCmd,
+};
+pub use self::tag_tci::TagTciCmd;
 
 use crate::{
+    context::ContextHandle,
     dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
     response::{DpeErrorCode, Response},
     DPE_PROFILE,
 };
 use core::mem::size_of;
-use zerocopy::FromBytes;
+use zerocopy::{AsBytes, FromBytes};
 
+mod batch;
 mod certify_key;
 mod derive_child;
 mod destroy_context;
 mod extend_tci;
 mod get_certificate_chain;
+mod get_eat;
+mod get_event_log;
 mod get_tagged_tci;
 mod initialize_context;
+mod quote;
 mod rotate_context;
+mod seal;
 mod sign;
+mod sign_message;
 mod tag_tci;
 
 #[derive(Debug, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
     GetProfile,
     InitCtx(InitCtxCmd),
@@ -49,6 +65,19 @@ pub enum Command {
     TagTci(TagTciCmd),
     GetTaggedTci(GetTaggedTciCmd),
     GetCertificateChain(GetCertificateChainCmd),
+    Seal(SealCmd),
+    Unseal(UnsealCmd),
+    SignMessage(SignMessageCmd),
+    SignStreamInit(SignStreamInitCmd),
+    SignStreamUpdate(SignStreamUpdateCmd),
+    SignStreamFinal(SignStreamFinalCmd),
+    GetTaggedTciInfo(GetTaggedTciInfoCmd),
+    GetContextTable,
+    Batch(BatchCmd),
+    GetLocalityQuota,
+    GetEventLog(GetEventLogCmd),
+    Quote(QuoteCmd),
+    GetEat(GetEatCmd),
 }
 
 impl Command {
@@ -63,6 +92,19 @@ impl Command {
     pub const EXTEND_TCI: u32 = 0x81;
     pub const TAG_TCI: u32 = 0x82;
     pub const GET_TAGGED_TCI: u32 = 0x83;
+    pub const SEAL: u32 = 0x84;
+    pub const UNSEAL: u32 = 0x85;
+    pub const SIGN_MESSAGE: u32 = 0x86;
+    pub const SIGN_STREAM_INIT: u32 = 0x87;
+    pub const SIGN_STREAM_UPDATE: u32 = 0x88;
+    pub const SIGN_STREAM_FINAL: u32 = 0x89;
+    pub const GET_TAGGED_TCI_INFO: u32 = 0x8A;
+    pub const GET_CONTEXT_TABLE: u32 = 0x8B;
+    pub const BATCH: u32 = 0x8C;
+    pub const GET_LOCALITY_QUOTA: u32 = 0x8D;
+    pub const GET_EVENT_LOG: u32 = 0x8E;
+    pub const QUOTE: u32 = 0x8F;
+    pub const GET_EAT: u32 = 0x90;
 
     /// Returns the command with its parameters given a slice of bytes.
     ///
@@ -87,10 +129,129 @@ impl Command {
             Command::EXTEND_TCI => Self::parse_command(Command::ExtendTci, bytes),
             Command::TAG_TCI => Self::parse_command(Command::TagTci, bytes),
             Command::GET_TAGGED_TCI => Self::parse_command(Command::GetTaggedTci, bytes),
+            Command::SEAL => Self::parse_command(Command::Seal, bytes),
+            Command::UNSEAL => Self::parse_command(Command::Unseal, bytes),
+            Command::SIGN_MESSAGE => Self::parse_command(Command::SignMessage, bytes),
+            Command::SIGN_STREAM_INIT => Self::parse_command(Command::SignStreamInit, bytes),
+            Command::SIGN_STREAM_UPDATE => Self::parse_command(Command::SignStreamUpdate, bytes),
+            Command::SIGN_STREAM_FINAL => Self::parse_command(Command::SignStreamFinal, bytes),
+            Command::GET_TAGGED_TCI_INFO => Self::parse_command(Command::GetTaggedTciInfo, bytes),
+            Command::GET_CONTEXT_TABLE => Ok(Command::GetContextTable),
+            Command::BATCH => Self::parse_command(Command::Batch, bytes),
+            Command::GET_LOCALITY_QUOTA => Ok(Command::GetLocalityQuota),
+            Command::GET_EVENT_LOG => Self::parse_command(Command::GetEventLog, bytes),
+            Command::QUOTE => Self::parse_command(Command::Quote, bytes),
+            Command::GET_EAT => Self::parse_command(Command::GetEat, bytes),
             _ => Err(DpeErrorCode::InvalidCommand),
         }
     }
 
+    /// Serializes the command, header included, into `dst`.
+    ///
+    /// Returns the number of bytes written. This is the inverse of `deserialize`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst` - output buffer for the serialized command
+    pub fn serialize(&self, dst: &mut [u8]) -> Result<usize, DpeErrorCode> {
+        let header = CommandHdr::new(self.into());
+        let header = header.as_bytes();
+        let body = self.body();
+        let size = header.len() + body.len();
+        let dst = dst.get_mut(..size).ok_or(DpeErrorCode::InvalidArgument)?;
+        dst[..header.len()].copy_from_slice(header);
+        dst[header.len()..].copy_from_slice(body);
+        Ok(size)
+    }
+
+    /// The parameters of the command as they are sent after the header.
+    fn body(&self) -> &[u8] {
+        match self {
+            Command::GetProfile | Command::GetContextTable | Command::GetLocalityQuota => &[],
+            Command::InitCtx(cmd) => cmd.as_bytes(),
+            Command::DeriveChild(cmd) => cmd.as_bytes(),
+            Command::CertifyKey(cmd) => cmd.as_bytes(),
+            Command::Sign(cmd) => cmd.as_bytes(),
+            Command::RotateCtx(cmd) => cmd.as_bytes(),
+            Command::DestroyCtx(cmd) => cmd.as_bytes(),
+            Command::ExtendTci(cmd) => cmd.as_bytes(),
+            Command::TagTci(cmd) => cmd.as_bytes(),
+            Command::GetTaggedTci(cmd) => cmd.as_bytes(),
+            Command::GetCertificateChain(cmd) => cmd.as_bytes(),
+            Command::Seal(cmd) => cmd.as_bytes(),
+            Command::Unseal(cmd) => cmd.as_bytes(),
+            Command::SignMessage(cmd) => cmd.as_bytes(),
+            Command::SignStreamInit(cmd) => cmd.as_bytes(),
+            Command::SignStreamUpdate(cmd) => cmd.as_bytes(),
+            Command::SignStreamFinal(cmd) => cmd.as_bytes(),
+            Command::GetTaggedTciInfo(cmd) => cmd.as_bytes(),
+            Command::Batch(cmd) => cmd.as_bytes(),
+            Command::GetEventLog(cmd) => cmd.as_bytes(),
+            Command::Quote(cmd) => cmd.as_bytes(),
+            Command::GetEat(cmd) => cmd.as_bytes(),
+        }
+    }
+
+    /// The handle of the context the command operates on, if it takes one.
+    pub(crate) fn handle_mut(&mut self) -> Option<&mut ContextHandle> {
+        match self {
+            Command::DeriveChild(cmd) => Some(&mut cmd.handle),
+            Command::CertifyKey(cmd) => Some(&mut cmd.handle),
+            Command::Sign(cmd) => Some(&mut cmd.handle),
+            Command::RotateCtx(cmd) => Some(&mut cmd.handle),
+            Command::DestroyCtx(cmd) => Some(&mut cmd.handle),
+            Command::ExtendTci(cmd) => Some(&mut cmd.handle),
+            Command::TagTci(cmd) => Some(&mut cmd.handle),
+            Command::Seal(cmd) => Some(&mut cmd.handle),
+            Command::Unseal(cmd) => Some(&mut cmd.handle),
+            Command::SignMessage(cmd) => Some(&mut cmd.handle),
+            Command::SignStreamInit(cmd) => Some(&mut cmd.handle),
+            Command::SignStreamUpdate(cmd) => Some(&mut cmd.handle),
+            Command::SignStreamFinal(cmd) => Some(&mut cmd.handle),
+            Command::Quote(cmd) => Some(&mut cmd.handle),
+            Command::GetEat(cmd) => Some(&mut cmd.handle),
+            Command::GetProfile
+            | Command::InitCtx(_)
+            | Command::GetTaggedTci(_)
+            | Command::GetCertificateChain(_)
+            | Command::GetTaggedTciInfo(_)
+            | Command::GetContextTable
+            | Command::Batch(_)
+            | Command::GetLocalityQuota
+            | Command::GetEventLog(_) => None,
+        }
+    }
+
+    /// The command's flags, or 0 if it has none.
+    pub fn flags(&self) -> u32 {
+        match self {
+            Command::InitCtx(cmd) => cmd.bits(),
+            Command::DeriveChild(cmd) => cmd.flags.bits(),
+            Command::CertifyKey(cmd) => cmd.flags.bits(),
+            Command::Sign(cmd) => cmd.flags.bits(),
+            Command::RotateCtx(cmd) => cmd.flags.bits(),
+            Command::DestroyCtx(cmd) => cmd.flags.bits(),
+            Command::SignMessage(cmd) => cmd.flags.bits(),
+            Command::SignStreamFinal(cmd) => cmd.flags.bits(),
+            Command::GetTaggedTciInfo(cmd) => cmd.flags.bits(),
+            Command::Batch(cmd) => cmd.flags.bits(),
+            Command::GetProfile
+            | Command::ExtendTci(_)
+            | Command::TagTci(_)
+            | Command::GetTaggedTci(_)
+            | Command::GetCertificateChain(_)
+            | Command::Seal(_)
+            | Command::Unseal(_)
+            | Command::SignStreamInit(_)
+            | Command::SignStreamUpdate(_)
+            | Command::GetContextTable
+            | Command::GetLocalityQuota
+            | Command::GetEventLog(_)
+            | Command::Quote(_)
+            | Command::GetEat(_) => 0,
+        }
+    }
+
     fn parse_command<T: FromBytes>(
         build: impl FnOnce(T) -> Command,
         bytes: &[u8],
@@ -103,6 +264,12 @@ impl Command {
 
 impl From<Command> for u32 {
     fn from(cmd: Command) -> u32 {
+        u32::from(&cmd)
+    }
+}
+
+impl From<&Command> for u32 {
+    fn from(cmd: &Command) -> u32 {
         match cmd {
             Command::GetProfile => Command::GET_PROFILE,
             Command::InitCtx(_) => Command::INITIALIZE_CONTEXT,
@@ -115,6 +282,19 @@ impl From<Command> for u32 {
             Command::TagTci(_) => Command::TAG_TCI,
             Command::GetTaggedTci(_) => Command::GET_TAGGED_TCI,
             Command::GetCertificateChain(_) => Command::GET_CERTIFICATE_CHAIN,
+            Command::Seal(_) => Command::SEAL,
+            Command::Unseal(_) => Command::UNSEAL,
+            Command::SignMessage(_) => Command::SIGN_MESSAGE,
+            Command::SignStreamInit(_) => Command::SIGN_STREAM_INIT,
+            Command::SignStreamUpdate(_) => Command::SIGN_STREAM_UPDATE,
+            Command::SignStreamFinal(_) => Command::SIGN_STREAM_FINAL,
+            Command::GetTaggedTciInfo(_) => Command::GET_TAGGED_TCI_INFO,
+            Command::GetContextTable => Command::GET_CONTEXT_TABLE,
+            Command::Batch(_) => Command::BATCH,
+            Command::GetLocalityQuota => Command::GET_LOCALITY_QUOTA,
+            Command::GetEventLog(_) => Command::GET_EVENT_LOG,
+            Command::Quote(_) => Command::QUOTE,
+            Command::GetEat(_) => Command::GET_EAT,
         }
     }
 }
@@ -141,13 +321,17 @@ pub struct CommandHdr {
 impl CommandHdr {
     const DPE_COMMAND_MAGIC: u32 = u32::from_be_bytes(*b"DPEC");
 
-    pub fn new_for_test(cmd_id: u32) -> CommandHdr {
+    pub fn new(cmd_id: u32) -> CommandHdr {
         CommandHdr {
             magic: Self::DPE_COMMAND_MAGIC,
             cmd_id,
             profile: DPE_PROFILE as u32,
         }
     }
+
+    pub fn new_for_test(cmd_id: u32) -> CommandHdr {
+        Self::new(cmd_id)
+    }
 }
 
 impl TryFrom<&[u8]> for CommandHdr {
@@ -171,7 +355,6 @@ impl TryFrom<&[u8]> for CommandHdr {
 pub mod tests {
     use super::*;
     use crate::{DpeProfile, DPE_PROFILE};
-    use zerocopy::AsBytes;
 
     #[cfg(feature = "dpe_profile_p256_sha256")]
     pub const TEST_DIGEST: [u8; DPE_PROFILE.get_hash_size()] = [
@@ -260,4 +443,60 @@ pub mod tests {
             CommandHdr::try_from(GOOD_HEADER.as_bytes()).unwrap()
         );
     }
+
+    /// A `T` whose bytes count up from 0, so every field has a distinct non-zero value.
+    fn patterned<T: FromBytes + AsBytes>() -> T {
+        let mut t = T::new_zeroed();
+        for (i, b) in t.as_bytes_mut().iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        t
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let commands = [
+            Command::GetProfile,
+            Command::InitCtx(patterned()),
+            Command::DeriveChild(patterned()),
+            Command::CertifyKey(patterned()),
+            Command::Sign(patterned()),
+            Command::RotateCtx(patterned()),
+            Command::DestroyCtx(patterned()),
+            Command::ExtendTci(patterned()),
+            Command::TagTci(patterned()),
+            Command::GetTaggedTci(patterned()),
+            Command::GetCertificateChain(patterned()),
+            Command::Seal(patterned()),
+            Command::Unseal(patterned()),
+            Command::SignMessage(patterned()),
+            Command::SignStreamInit(patterned()),
+            Command::SignStreamUpdate(patterned()),
+            Command::SignStreamFinal(patterned()),
+            Command::GetTaggedTciInfo(patterned()),
+            Command::GetContextTable,
+            Command::Batch(patterned()),
+            Command::GetLocalityQuota,
+            Command::GetEventLog(patterned()),
+            Command::Quote(patterned()),
+            Command::GetEat(patterned()),
+        ];
+
+        let mut buf = vec![0; 8192];
+        for command in commands {
+            let size = command.serialize(&mut buf).unwrap();
+            assert_eq!(size_of::<CommandHdr>() + command.body().len(), size);
+            let header = CommandHdr::read_from_prefix(buf.as_slice()).unwrap();
+            assert_eq!(CommandHdr::new(u32::from(&command)), header);
+            assert_eq!(Ok(command), Command::deserialize(&buf[..size]));
+        }
+
+        // The output buffer has to fit the whole command.
+        let command = Command::CertifyKey(patterned());
+        let size = size_of::<CommandHdr>() + size_of::<CertifyKeyCmd>();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            command.serialize(&mut buf[..size - 1])
+        );
+    }
 }
@@ -0,0 +1,386 @@
+// Licensed under the Apache-2.0 license.
+use super::CommandExecution;
+use crate::{
+    context::{ContextHandle, ContextType},
+    dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
+    response::{DpeErrorCode, Quote, QuoteFlags, QuoteResp, Response, ResponseHdr},
+    tci::TciNodeData,
+    DPE_PROFILE, MAX_HANDLES, QUOTE_NONCE_SIZE,
+};
+use crypto::{Crypto, EcdsaSig, Hasher};
+use platform::{Platform, MAX_UEID_SIZE};
+use zerocopy::AsBytes;
+
+/// Key derivation info for the key quotes are signed with. It differs from the key Sign uses, so
+/// a signature over caller-chosen data can't pass as a quote. CertifyKey certifies it when given
+/// `CertifyKeyFlags::QUOTE_KEY`.
+pub(crate) const QUOTE_KEY_INFO: &[u8] = b"QUOTE";
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct QuoteCmd {
+    pub handle: ContextHandle,
+    pub label: [u8; DPE_PROFILE.get_hash_size()],
+    pub nonce: [u8; QUOTE_NONCE_SIZE],
+}
+
+impl QuoteCmd {
+    /// Builds the quote for the context at `idx`.
+    fn build_quote(
+        &self,
+        dpe: &DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        idx: usize,
+    ) -> Result<Quote, DpeErrorCode> {
+        let profile = dpe.get_profile(&mut env.platform)?;
+
+        let mut ueid = [0u8; MAX_UEID_SIZE];
+        let ueid_size = env
+            .platform
+            .get_ueid(&mut ueid)
+            .map_err(|_| DpeErrorCode::PlatformError)?;
+        if ueid_size > MAX_UEID_SIZE {
+            return Err(DpeErrorCode::PlatformError);
+        }
+
+        const INITIALIZER: TciNodeData = TciNodeData::new();
+        let mut tci_nodes = [INITIALIZER; MAX_HANDLES];
+        let tci_count = dpe.get_tcb_nodes(idx, &mut tci_nodes)?;
+
+        let mut flags = QuoteFlags::empty();
+        flags.set(
+            QuoteFlags::SIMULATION,
+            dpe.contexts[idx].context_type == ContextType::Simulation,
+        );
+
+        Ok(Quote {
+            magic: Quote::MAGIC,
+            profile: profile.resp_hdr.profile,
+            major_version: profile.major_version,
+            minor_version: profile.minor_version,
+            vendor_id: profile.vendor_id,
+            vendor_sku: profile.vendor_sku,
+            max_tci_nodes: profile.max_tci_nodes,
+            support: profile.flags,
+            flags,
+            nonce: self.nonce,
+            ueid_size: ueid_size as u32,
+            ueid,
+            reserved: [0; 3],
+            tci_count: tci_count as u32,
+            tci_nodes,
+        })
+    }
+}
+
+impl CommandExecution for QuoteCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.quote() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let idx = dpe.get_active_context_pos(&self.handle, locality)?;
+        let quote = self.build_quote(dpe, env, idx)?;
+
+        let algs = DPE_PROFILE.alg_len();
+        let mut hasher = env
+            .crypto
+            .hash_initialize(algs)
+            .map_err(|_| DpeErrorCode::HashError)?;
+        hasher
+            .update(quote.as_bytes())
+            .map_err(|_| DpeErrorCode::HashError)?;
+        let digest = hasher.finish().map_err(|_| DpeErrorCode::HashError)?;
+
+        let cdi = dpe.derive_cdi(env, idx)?;
+        let (priv_key, pub_key) = env
+            .crypto
+            .derive_key_pair(algs, &cdi, &self.label, QUOTE_KEY_INFO)
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+        let derived_pubkey_x = pub_key
+            .x
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+        let derived_pubkey_y = pub_key
+            .y
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+        let EcdsaSig { r, s } = env
+            .crypto
+            .ecdsa_sign_with_derived(algs, &digest, &priv_key, pub_key)
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+
+        let sig_r = r
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+        let sig_s = s
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+
+        let new_context_handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.contexts[idx].handle = new_context_handle;
+
+        Ok(Response::Quote(QuoteResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            new_context_handle,
+            derived_pubkey_x,
+            derived_pubkey_y,
+            sig_r,
+            sig_s,
+            quote,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{
+            tests::TEST_DIGEST, CertifyKeyCmd, CertifyKeyFlags, Command, CommandHdr,
+            DeriveChildCmd, DeriveChildFlags, InitCtxCmd, SignCmd, SignFlags,
+        },
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        support::{test::SUPPORT, Support},
+    };
+    use crypto::OpensslCrypto;
+    use openssl::{
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        ecdsa::EcdsaSig,
+        nid::Nid,
+        x509::X509,
+    };
+    use platform::default::{DefaultPlatform, TEST_UEID};
+
+    const TEST_QUOTE_CMD: QuoteCmd = QuoteCmd {
+        handle: SIMULATION_HANDLE,
+        label: TEST_DIGEST,
+        nonce: [0x5a; QUOTE_NONCE_SIZE],
+    };
+
+    #[test]
+    fn test_deserialize_quote() {
+        let mut command = CommandHdr::new_for_test(Command::QUOTE).as_bytes().to_vec();
+        command.extend(TEST_QUOTE_CMD.as_bytes());
+        assert_eq!(
+            Ok(Command::Quote(TEST_QUOTE_CMD)),
+            Command::deserialize(&command)
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            QuoteCmd {
+                handle: ContextHandle::default(),
+                ..TEST_QUOTE_CMD
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Err(DpeErrorCode::ArgumentNotSupported),
+            CertifyKeyCmd {
+                handle: ContextHandle::default(),
+                flags: CertifyKeyFlags::QUOTE_KEY,
+                label: TEST_QUOTE_CMD.label,
+                format: CertifyKeyCmd::FORMAT_X509,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::QUOTE).unwrap();
+        for i in 0..3 {
+            DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [i; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::MAKE_DEFAULT | DeriveChildFlags::INPUT_ALLOW_X509,
+                tci_type: i as u32,
+                target_locality: 0,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        }
+
+        // Wrong locality.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidLocality),
+            QuoteCmd {
+                handle: ContextHandle::default(),
+                ..TEST_QUOTE_CMD
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        let resp = match (QuoteCmd {
+            handle: ContextHandle::default(),
+            ..TEST_QUOTE_CMD
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::Quote(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(ContextHandle::default(), resp.new_context_handle);
+
+        let quote = &resp.quote;
+        let profile = dpe.get_profile(&mut env.platform).unwrap();
+        assert_eq!(Quote::MAGIC, quote.magic);
+        assert_eq!(DPE_PROFILE as u32, quote.profile);
+        assert_eq!(profile.vendor_id, quote.vendor_id);
+        assert_eq!(profile.max_tci_nodes, quote.max_tci_nodes);
+        assert_eq!(profile.flags, quote.support);
+        assert_eq!(QuoteFlags::empty(), quote.flags);
+        assert_eq!(TEST_QUOTE_CMD.nonce, quote.nonce);
+        assert_eq!(&TEST_UEID, &quote.ueid[..quote.ueid_size as usize]);
+
+        let idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        const INITIALIZER: TciNodeData = TciNodeData::new();
+        let mut nodes = [INITIALIZER; MAX_HANDLES];
+        let count = dpe.get_tcb_nodes(idx, &mut nodes).unwrap();
+        assert_eq!(count as u32, quote.tci_count);
+        assert_eq!(nodes, quote.tci_nodes);
+
+        // The quote is signed by the key CertifyKey certifies with QUOTE_KEY.
+        let certify_resp = match (CertifyKeyCmd {
+            handle: ContextHandle::default(),
+            flags: CertifyKeyFlags::QUOTE_KEY,
+            label: TEST_QUOTE_CMD.label,
+            format: CertifyKeyCmd::FORMAT_X509,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::CertifyKey(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(certify_resp.derived_pubkey_x, resp.derived_pubkey_x);
+        assert_eq!(certify_resp.derived_pubkey_y, resp.derived_pubkey_y);
+        let x509 = X509::from_der(&certify_resp.cert[..certify_resp.cert_size as usize]).unwrap();
+        let cn = x509
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap();
+        assert_eq!(b"DPE Quote", cn.data().as_slice());
+        let ec_pub_key = x509.public_key().unwrap().ec_key().unwrap();
+
+        let mut hasher = env.crypto.hash_initialize(DPE_PROFILE.alg_len()).unwrap();
+        hasher.update(quote.as_bytes()).unwrap();
+        let digest = hasher.finish().unwrap();
+        let sig = EcdsaSig::from_private_components(
+            BigNum::from_slice(&resp.sig_r).unwrap(),
+            BigNum::from_slice(&resp.sig_s).unwrap(),
+        )
+        .unwrap();
+        assert!(sig.verify(digest.bytes(), &ec_pub_key).unwrap());
+    }
+
+    #[test]
+    fn test_sign_cannot_forge_quote() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::QUOTE).unwrap();
+
+        let resp = match (QuoteCmd {
+            handle: ContextHandle::default(),
+            ..TEST_QUOTE_CMD
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::Quote(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        let mut hasher = env.crypto.hash_initialize(DPE_PROFILE.alg_len()).unwrap();
+        hasher.update(resp.quote.as_bytes()).unwrap();
+        let digest = hasher.finish().unwrap();
+
+        // Sign the quote's digest with the same label, as a forger holding the handle would.
+        let sign_resp = match (SignCmd {
+            handle: ContextHandle::default(),
+            label: TEST_QUOTE_CMD.label,
+            flags: SignFlags::empty(),
+            digest: digest.bytes().try_into().unwrap(),
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::Sign(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+
+        let group = EcGroup::from_curve_name(match DPE_PROFILE {
+            crate::DpeProfile::P256Sha256 => Nid::X9_62_PRIME256V1,
+            crate::DpeProfile::P384Sha384 => Nid::SECP384R1,
+        })
+        .unwrap();
+        let quote_key = EcKey::from_public_key_affine_coordinates(
+            &group,
+            &BigNum::from_slice(&resp.derived_pubkey_x).unwrap(),
+            &BigNum::from_slice(&resp.derived_pubkey_y).unwrap(),
+        )
+        .unwrap();
+        let forged = EcdsaSig::from_private_components(
+            BigNum::from_slice(&sign_resp.sig_r_or_hmac).unwrap(),
+            BigNum::from_slice(&sign_resp.sig_s).unwrap(),
+        )
+        .unwrap();
+        assert!(!forged.verify(digest.bytes(), &quote_key).unwrap());
+    }
+
+    #[test]
+    fn test_quote_simulation() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::QUOTE).unwrap();
+        InitCtxCmd::new_simulation()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+            .unwrap();
+        let idx = dpe
+            .get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[1])
+            .unwrap();
+        dpe.contexts[idx].handle = ContextHandle([0x11; ContextHandle::SIZE]);
+
+        let resp = match (QuoteCmd {
+            handle: ContextHandle([0x11; ContextHandle::SIZE]),
+            ..TEST_QUOTE_CMD
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        .unwrap()
+        {
+            Response::Quote(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        // Quoting rotates the handle like any other use of a non-default context.
+        assert_eq!(SIMULATION_HANDLE, resp.new_context_handle);
+        assert_eq!(QuoteFlags::SIMULATION, resp.quote.flags);
+        assert_eq!(1, resp.quote.tci_count);
+    }
+}
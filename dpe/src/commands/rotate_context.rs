@@ -6,25 +6,25 @@ use crate::{
     response::{DpeErrorCode, NewHandleResp, Response, ResponseHdr},
 };
 use bitflags::bitflags;
+use platform::Platform;
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct RotateCtxFlags(u32);
 
 bitflags! {
     impl RotateCtxFlags: u32 {
         const TARGET_IS_DEFAULT = 1u32 << 31;
+        const CHANGE_LOCALITY = 1u32 << 30;
     }
 }
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct RotateCtxCmd {
-    handle: ContextHandle,
-    flags: RotateCtxFlags,
-    target_locality: u32,
+    pub handle: ContextHandle,
+    pub flags: RotateCtxFlags,
+    pub target_locality: u32,
 }
 
 impl RotateCtxCmd {
@@ -33,6 +33,10 @@ impl RotateCtxCmd {
     const fn uses_target_is_default(&self) -> bool {
         self.flags.contains(RotateCtxFlags::TARGET_IS_DEFAULT)
     }
+
+    const fn changes_locality(&self) -> bool {
+        self.flags.contains(RotateCtxFlags::CHANGE_LOCALITY)
+    }
 }
 
 impl CommandExecution for RotateCtxCmd {
@@ -47,10 +51,23 @@ impl CommandExecution for RotateCtxCmd {
         }
         let idx = dpe.get_active_context_pos(&self.handle, locality)?;
 
-        // Make sure caller's locality does not already have a default context.
+        let target_locality = if !self.changes_locality() || self.target_locality == locality {
+            locality
+        } else if env
+            .platform
+            .can_transfer_locality(locality, self.target_locality)
+            .map_err(|_| DpeErrorCode::PlatformError)?
+        {
+            dpe.check_locality_quota(env, self.target_locality)?;
+            self.target_locality
+        } else {
+            return Err(DpeErrorCode::InvalidLocality);
+        };
+
+        // Make sure the target locality does not already have a default context.
         if self.uses_target_is_default() {
             let default_context_idx =
-                dpe.get_active_context_pos(&ContextHandle::default(), locality);
+                dpe.get_active_context_pos(&ContextHandle::default(), target_locality);
             if default_context_idx.is_ok() {
                 return Err(DpeErrorCode::InvalidArgument);
             }
@@ -61,7 +78,10 @@ impl CommandExecution for RotateCtxCmd {
         } else {
             dpe.generate_new_handle(env)?
         };
-        dpe.contexts[idx].handle = new_handle;
+        let context = &mut dpe.contexts[idx];
+        context.handle = new_handle;
+        context.locality = target_locality;
+        context.tci.locality = target_locality;
         Ok(Response::RotateCtx(NewHandleResp {
             handle: new_handle,
             resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
@@ -74,7 +94,10 @@ mod tests {
     use super::*;
     use crate::{
         commands::{Command, CommandHdr, InitCtxCmd},
-        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_HANDLE, TEST_LOCALITIES},
+        dpe_instance::tests::{
+            RestrictedPlatform, RestrictedTypes, TestTypes, SIMULATION_HANDLE, TEST_HANDLE,
+            TEST_LOCALITIES,
+        },
         support::Support,
     };
     use crypto::OpensslCrypto;
@@ -103,7 +126,8 @@ mod tests {
     fn test_rotate_context() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::default()).unwrap();
         // Make sure it returns an error if the command is marked unsupported.
@@ -184,4 +208,89 @@ mod tests {
             .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
         );
     }
+
+    #[test]
+    fn test_rotate_context_change_locality() {
+        let mut env = DpeEnv::<RestrictedTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: RestrictedPlatform(DefaultPlatform::new()),
+            event_sink: (),
+        };
+        let mut dpe =
+            DpeInstance::new(&mut env, Support::ROTATE_CONTEXT | Support::SIMULATION).unwrap();
+        InitCtxCmd::new_use_default()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        InitCtxCmd::new_simulation()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+            .unwrap();
+        let sim_idx = dpe
+            .get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[1])
+            .unwrap();
+        dpe.contexts[sim_idx].handle = TEST_HANDLE;
+
+        // The platform only lets the boot locality hand contexts off.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidLocality),
+            RotateCtxCmd {
+                handle: TEST_HANDLE,
+                flags: RotateCtxFlags::CHANGE_LOCALITY,
+                target_locality: TEST_LOCALITIES[0],
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        // Staying in the caller's locality doesn't need the platform's permission.
+        assert_eq!(
+            Ok(Response::RotateCtx(NewHandleResp {
+                handle: ContextHandle::default(),
+                resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            })),
+            RotateCtxCmd {
+                handle: TEST_HANDLE,
+                flags: RotateCtxFlags::CHANGE_LOCALITY | RotateCtxFlags::TARGET_IS_DEFAULT,
+                target_locality: TEST_LOCALITIES[1],
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        // The target locality already has a default context.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            RotateCtxCmd {
+                handle: ContextHandle::default(),
+                flags: RotateCtxFlags::CHANGE_LOCALITY | RotateCtxFlags::TARGET_IS_DEFAULT,
+                target_locality: TEST_LOCALITIES[1],
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        // Hand the default context off to the other locality.
+        dpe.contexts[sim_idx].handle = TEST_HANDLE;
+        let idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        assert_eq!(
+            Ok(Response::RotateCtx(NewHandleResp {
+                handle: SIMULATION_HANDLE,
+                resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            })),
+            RotateCtxCmd {
+                handle: ContextHandle::default(),
+                flags: RotateCtxFlags::CHANGE_LOCALITY,
+                target_locality: TEST_LOCALITIES[1],
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(TEST_LOCALITIES[1], dpe.contexts[idx].locality);
+        assert_eq!(TEST_LOCALITIES[1], dpe.contexts[idx].tci.locality);
+        assert_eq!(
+            Err(DpeErrorCode::InvalidLocality),
+            dpe.get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Ok(idx),
+            dpe.get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[1])
+        );
+    }
 }
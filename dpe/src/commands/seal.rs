@@ -0,0 +1,463 @@
+// Licensed under the Apache-2.0 license.
+use super::CommandExecution;
+use crate::{
+    context::{ContextHandle, ContextType},
+    dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
+    response::{DpeErrorCode, Response, ResponseHdr, SealResp, UnsealResp},
+    DPE_PROFILE, MAX_SEALED_DATA_SIZE,
+};
+use crypto::{Crypto, AEAD_IV_SIZE, AEAD_TAG_SIZE};
+use zerocopy::AsBytes;
+
+/// Authenticated header of a sealed blob.
+///
+/// The whole header is passed to the AEAD as additional data, so none of it can be modified
+/// without the blob failing to unseal.
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct SealedBlobHdr {
+    pub magic: u32,
+    pub version: u32,
+    pub profile: u32,
+    pub data_size: u32,
+    pub iv: [u8; AEAD_IV_SIZE],
+}
+
+/// A blob of data encrypted and authenticated with a key derived from a context's CDI.
+///
+/// Only the first `hdr.data_size` bytes of `data` are meaningful.
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct SealedBlob {
+    pub hdr: SealedBlobHdr,
+    pub tag: [u8; AEAD_TAG_SIZE],
+    pub data: [u8; MAX_SEALED_DATA_SIZE],
+}
+
+impl SealedBlob {
+    pub const MAGIC: u32 = u32::from_be_bytes(*b"DPES");
+    pub const VERSION: u32 = 1;
+}
+
+impl Default for SealedBlob {
+    fn default() -> Self {
+        Self {
+            hdr: SealedBlobHdr {
+                magic: Self::MAGIC,
+                version: Self::VERSION,
+                profile: DPE_PROFILE as u32,
+                data_size: 0,
+                iv: [0; AEAD_IV_SIZE],
+            },
+            tag: [0; AEAD_TAG_SIZE],
+            data: [0; MAX_SEALED_DATA_SIZE],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct SealCmd {
+    pub handle: ContextHandle,
+    pub label: [u8; DPE_PROFILE.get_hash_size()],
+    pub data_size: u32,
+    pub data: [u8; MAX_SEALED_DATA_SIZE],
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct UnsealCmd {
+    pub handle: ContextHandle,
+    pub label: [u8; DPE_PROFILE.get_hash_size()],
+    pub blob: SealedBlob,
+}
+
+/// Info string used when deriving the sealing key from the CDI.
+const SEAL_INFO: &[u8] = b"SEAL";
+
+/// Returns the index of the context to seal or unseal with.
+fn get_sealing_context_pos(
+    dpe: &DpeInstance,
+    handle: &ContextHandle,
+    locality: u32,
+) -> Result<usize, DpeErrorCode> {
+    // Make sure the operation is supported.
+    if !dpe.support.seal() {
+        return Err(DpeErrorCode::InvalidCommand);
+    }
+
+    let idx = dpe.get_active_context_pos(handle, locality)?;
+
+    // Simulation contexts must not be able to recover data sealed by a real context.
+    if dpe.contexts[idx].context_type == ContextType::Simulation {
+        return Err(DpeErrorCode::InvalidArgument);
+    }
+
+    Ok(idx)
+}
+
+impl CommandExecution for SealCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        let data_size = self.data_size as usize;
+        if data_size > MAX_SEALED_DATA_SIZE {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let idx = get_sealing_context_pos(dpe, &self.handle, locality)?;
+        let cdi_digest = dpe.compute_measurement_hash(env, idx)?;
+        let cdi = env
+            .crypto
+            .derive_cdi(DPE_PROFILE.alg_len(), &cdi_digest, b"DPE")
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+
+        let mut blob = SealedBlob::default();
+        blob.hdr.data_size = self.data_size;
+        env.crypto
+            .rand_bytes(&mut blob.hdr.iv)
+            .map_err(|_| DpeErrorCode::RandError)?;
+        blob.data[..data_size].copy_from_slice(&self.data[..data_size]);
+
+        blob.tag = env
+            .crypto
+            .aead_encrypt_with_derived(
+                DPE_PROFILE.alg_len(),
+                &cdi,
+                &self.label,
+                SEAL_INFO,
+                &blob.hdr.iv,
+                blob.hdr.as_bytes(),
+                &mut blob.data[..data_size],
+            )
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+
+        let new_context_handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.contexts[idx].handle = new_context_handle;
+
+        Ok(Response::Seal(SealResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            new_context_handle,
+            blob,
+        }))
+    }
+}
+
+impl CommandExecution for UnsealCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        let hdr = &self.blob.hdr;
+        if hdr.magic != SealedBlob::MAGIC
+            || hdr.version != SealedBlob::VERSION
+            || hdr.profile != DPE_PROFILE as u32
+        {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        let data_size = hdr.data_size as usize;
+        if data_size > MAX_SEALED_DATA_SIZE {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let idx = get_sealing_context_pos(dpe, &self.handle, locality)?;
+        let cdi_digest = dpe.compute_measurement_hash(env, idx)?;
+        let cdi = env
+            .crypto
+            .derive_cdi(DPE_PROFILE.alg_len(), &cdi_digest, b"DPE")
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+
+        let mut response = UnsealResp {
+            data_size: hdr.data_size,
+            ..Default::default()
+        };
+        response.data[..data_size].copy_from_slice(&self.blob.data[..data_size]);
+
+        // Decryption failure means the blob was modified or was sealed to a different TCI
+        // chain or label.
+        env.crypto
+            .aead_decrypt_with_derived(
+                DPE_PROFILE.alg_len(),
+                &cdi,
+                &self.label,
+                SEAL_INFO,
+                &hdr.iv,
+                hdr.as_bytes(),
+                &mut response.data[..data_size],
+                &self.blob.tag,
+            )
+            .map_err(|_| DpeErrorCode::CryptoError)?;
+
+        response.new_context_handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.contexts[idx].handle = response.new_context_handle;
+
+        Ok(Response::Unseal(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{
+            tests::TEST_DIGEST, Command, CommandHdr, DeriveChildCmd, DeriveChildFlags, InitCtxCmd,
+        },
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        support::{test::SUPPORT, Support},
+    };
+    use crypto::OpensslCrypto;
+    use platform::default::DefaultPlatform;
+
+    const TEST_DATA_SIZE: usize = 100;
+
+    fn test_seal_cmd() -> SealCmd {
+        let mut data = [0; MAX_SEALED_DATA_SIZE];
+        for (i, b) in data.iter_mut().take(TEST_DATA_SIZE).enumerate() {
+            *b = i as u8;
+        }
+        SealCmd {
+            handle: ContextHandle::default(),
+            label: TEST_DIGEST,
+            data_size: TEST_DATA_SIZE as u32,
+            data,
+        }
+    }
+
+    fn seal(dpe: &mut DpeInstance, env: &mut DpeEnv<TestTypes>, cmd: &SealCmd) -> SealedBlob {
+        match cmd.execute(dpe, env, TEST_LOCALITIES[0]).unwrap() {
+            Response::Seal(resp) => resp.blob,
+            _ => panic!("Incorrect response type"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_seal() {
+        let mut command = CommandHdr::new_for_test(Command::SEAL).as_bytes().to_vec();
+        command.extend(test_seal_cmd().as_bytes());
+        assert_eq!(
+            Ok(Command::Seal(test_seal_cmd())),
+            Command::deserialize(&command)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_unseal() {
+        let cmd = UnsealCmd {
+            handle: ContextHandle::default(),
+            label: TEST_DIGEST,
+            blob: SealedBlob::default(),
+        };
+        let mut command = CommandHdr::new_for_test(Command::UNSEAL)
+            .as_bytes()
+            .to_vec();
+        command.extend(cmd.as_bytes());
+        assert_eq!(Ok(Command::Unseal(cmd)), Command::deserialize(&command));
+    }
+
+    #[test]
+    fn test_bad_command_inputs() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+
+        // Unsupported.
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            test_seal_cmd().execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::SEAL).unwrap();
+
+        // Too much data.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            SealCmd {
+                data_size: MAX_SEALED_DATA_SIZE as u32 + 1,
+                ..test_seal_cmd()
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        // Wrong locality.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidLocality),
+            test_seal_cmd().execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        // Simulation contexts cannot seal.
+        InitCtxCmd::new_simulation()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            SealCmd {
+                handle: SIMULATION_HANDLE,
+                ..test_seal_cmd()
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        // Unrecognized blob header.
+        let blob = seal(&mut dpe, &mut env, &test_seal_cmd());
+        for hdr in [
+            SealedBlobHdr {
+                magic: 0,
+                ..blob.hdr
+            },
+            SealedBlobHdr {
+                version: SealedBlob::VERSION + 1,
+                ..blob.hdr
+            },
+            SealedBlobHdr {
+                data_size: MAX_SEALED_DATA_SIZE as u32 + 1,
+                ..blob.hdr
+            },
+        ] {
+            assert_eq!(
+                Err(DpeErrorCode::InvalidArgument),
+                UnsealCmd {
+                    handle: ContextHandle::default(),
+                    label: TEST_DIGEST,
+                    blob: SealedBlob {
+                        hdr,
+                        tag: blob.tag,
+                        data: blob.data,
+                    },
+                }
+                .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            );
+        }
+    }
+
+    #[test]
+    fn test_seal_unseal() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::SEAL).unwrap();
+
+        let cmd = test_seal_cmd();
+        let blob = seal(&mut dpe, &mut env, &cmd);
+        assert_eq!(SealedBlob::MAGIC, blob.hdr.magic);
+        assert_eq!(SealedBlob::VERSION, blob.hdr.version);
+        assert_eq!(TEST_DATA_SIZE as u32, blob.hdr.data_size);
+        assert_ne!(cmd.data[..TEST_DATA_SIZE], blob.data[..TEST_DATA_SIZE]);
+
+        let resp = match (UnsealCmd {
+            handle: ContextHandle::default(),
+            label: TEST_DIGEST,
+            blob,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::Unseal(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(ContextHandle::default(), resp.new_context_handle);
+        assert_eq!(TEST_DATA_SIZE as u32, resp.data_size);
+        assert_eq!(cmd.data[..TEST_DATA_SIZE], resp.data[..TEST_DATA_SIZE]);
+    }
+
+    #[test]
+    fn test_unseal_rejects_modified_blob() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::SEAL).unwrap();
+        let blob = seal(&mut dpe, &mut env, &test_seal_cmd());
+
+        let unseal =
+            |blob: SealedBlob, label, dpe: &mut DpeInstance, env: &mut DpeEnv<TestTypes>| {
+                UnsealCmd {
+                    handle: ContextHandle::default(),
+                    label,
+                    blob,
+                }
+                .execute(dpe, env, TEST_LOCALITIES[0])
+            };
+
+        // Modified ciphertext.
+        let mut data = blob.data;
+        data[0] ^= 1;
+        assert_eq!(
+            Err(DpeErrorCode::CryptoError),
+            unseal(SealedBlob { data, ..blob }, TEST_DIGEST, &mut dpe, &mut env)
+        );
+
+        // Modified authenticated header.
+        assert_eq!(
+            Err(DpeErrorCode::CryptoError),
+            unseal(
+                SealedBlob {
+                    hdr: SealedBlobHdr {
+                        data_size: TEST_DATA_SIZE as u32 - 1,
+                        ..blob.hdr
+                    },
+                    ..blob
+                },
+                TEST_DIGEST,
+                &mut dpe,
+                &mut env
+            )
+        );
+
+        // Modified tag.
+        let mut tag = blob.tag;
+        tag[0] ^= 1;
+        assert_eq!(
+            Err(DpeErrorCode::CryptoError),
+            unseal(SealedBlob { tag, ..blob }, TEST_DIGEST, &mut dpe, &mut env)
+        );
+
+        // Different label.
+        assert_eq!(
+            Err(DpeErrorCode::CryptoError),
+            unseal(blob, [0; DPE_PROFILE.get_hash_size()], &mut dpe, &mut env)
+        );
+    }
+
+    #[test]
+    fn test_unseal_requires_same_measurements() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::SEAL).unwrap();
+        let blob = seal(&mut dpe, &mut env, &test_seal_cmd());
+
+        // Measuring another layer changes the CDI, so the data can no longer be recovered.
+        DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [1; DPE_PROFILE.get_hash_size()],
+            flags: DeriveChildFlags::MAKE_DEFAULT,
+            tci_type: 0,
+            target_locality: 0,
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+
+        assert_eq!(
+            Err(DpeErrorCode::CryptoError),
+            UnsealCmd {
+                handle: ContextHandle::default(),
+                label: TEST_DIGEST,
+                blob,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+    }
+}
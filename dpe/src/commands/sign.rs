@@ -1,7 +1,7 @@
 // Licensed under the Apache-2.0 license.
 use super::CommandExecution;
 use crate::{
-    context::{ContextHandle, ContextType},
+    context::ContextHandle,
     dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
     response::{DpeErrorCode, Response, ResponseHdr, SignResp},
     DPE_PROFILE,
@@ -41,11 +41,7 @@ impl SignCmd {
         digest: &Digest,
     ) -> Result<EcdsaSig, DpeErrorCode> {
         let algs = DPE_PROFILE.alg_len();
-        let cdi_digest = dpe.compute_measurement_hash(env, idx)?;
-        let cdi = env
-            .crypto
-            .derive_cdi(DPE_PROFILE.alg_len(), &cdi_digest, b"DPE")
-            .map_err(|_| DpeErrorCode::CryptoError)?;
+        let cdi = dpe.derive_cdi(env, idx)?;
         let (priv_key, pub_key) = env
             .crypto
             .derive_key_pair(algs, &cdi, &self.label, b"ECC")
@@ -67,11 +63,7 @@ impl SignCmd {
         digest: &Digest,
     ) -> Result<HmacSig, DpeErrorCode> {
         let algs = DPE_PROFILE.alg_len();
-        let cdi_digest = dpe.compute_measurement_hash(env, idx)?;
-        let cdi = env
-            .crypto
-            .derive_cdi(DPE_PROFILE.alg_len(), &cdi_digest, b"DPE")
-            .map_err(|_| DpeErrorCode::CryptoError)?;
+        let cdi = dpe.derive_cdi(env, idx)?;
         env.crypto
             .hmac_sign_with_derived(algs, &cdi, &self.label, b"HMAC", digest)
             .map_err(|_| DpeErrorCode::CryptoError)
@@ -91,11 +83,6 @@ impl CommandExecution for SignCmd {
         }
 
         let idx = dpe.get_active_context_pos(&self.handle, locality)?;
-        let context = &dpe.contexts[idx];
-
-        if context.context_type == ContextType::Simulation {
-            return Err(DpeErrorCode::InvalidArgument);
-        }
 
         let algs = DPE_PROFILE.alg_len();
         let digest = Digest::new(&self.digest, algs).map_err(|_| DpeErrorCode::InternalError)?;
@@ -108,18 +95,22 @@ impl CommandExecution for SignCmd {
             EcdsaSig { r, s }
         };
 
-        dpe.roll_onetime_use_handle(env, idx)?;
+        let sig_r_or_hmac = r
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+        let sig_s = s
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+
+        let new_context_handle = dpe.next_onetime_use_handle(env, idx)?;
+        dpe.contexts[idx].handle = new_context_handle;
 
         Ok(Response::Sign(SignResp {
-            new_context_handle: dpe.contexts[idx].handle,
-            sig_r_or_hmac: r
-                .bytes()
-                .try_into()
-                .map_err(|_| DpeErrorCode::InternalError)?,
-            sig_s: s
-                .bytes()
-                .try_into()
-                .map_err(|_| DpeErrorCode::InternalError)?,
+            new_context_handle,
+            sig_r_or_hmac,
+            sig_s,
             resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
         }))
     }
@@ -134,7 +125,7 @@ mod tests {
             derive_child::DeriveChildFlags, tests::TEST_DIGEST, Command, CommandHdr,
             DeriveChildCmd, InitCtxCmd,
         },
-        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_HANDLE, TEST_LOCALITIES},
         support::{test::SUPPORT, Support},
     };
     use crypto::OpensslCrypto;
@@ -192,7 +183,8 @@ mod tests {
     fn test_bad_command_inputs() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
 
@@ -234,31 +226,14 @@ mod tests {
             }
             .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
         );
-
-        // Simulation contexts should not support the Sign command.
-        InitCtxCmd::new_simulation()
-            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
-            .unwrap();
-        assert!(dpe
-            .get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[0])
-            .is_ok());
-        assert_eq!(
-            Err(DpeErrorCode::InvalidArgument),
-            SignCmd {
-                handle: SIMULATION_HANDLE,
-                label: TEST_LABEL,
-                flags: SignFlags::empty(),
-                digest: TEST_DIGEST
-            }
-            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
-        );
     }
 
     #[test]
     fn test_asymmetric() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
 
@@ -313,11 +288,72 @@ mod tests {
         assert!(sig.verify(&TEST_DIGEST, &ec_pub_key).unwrap());
     }
 
+    #[test]
+    fn test_simulation() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+
+        InitCtxCmd::new_simulation()
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+        let idx = dpe
+            .get_active_context_pos(&SIMULATION_HANDLE, TEST_LOCALITIES[0])
+            .unwrap();
+
+        // The deterministic RNG used in tests always generates SIMULATION_HANDLE, so move the
+        // context to a different handle before each command rotates it.
+        dpe.contexts[idx].handle = TEST_HANDLE;
+        let sig = {
+            let cmd = SignCmd {
+                handle: TEST_HANDLE,
+                label: TEST_LABEL,
+                flags: SignFlags::empty(),
+                digest: TEST_DIGEST,
+            };
+            let resp = match cmd.execute(&mut dpe, &mut env, TEST_LOCALITIES[0]).unwrap() {
+                Response::Sign(resp) => resp,
+                _ => panic!("Incorrect response type"),
+            };
+            assert_eq!(SIMULATION_HANDLE, resp.new_context_handle);
+
+            EcdsaSig::from_private_components(
+                BigNum::from_slice(&resp.sig_r_or_hmac).unwrap(),
+                BigNum::from_slice(&resp.sig_s).unwrap(),
+            )
+            .unwrap()
+        };
+
+        dpe.contexts[idx].handle = TEST_HANDLE;
+        let ec_pub_key = {
+            let cmd = CertifyKeyCmd {
+                handle: TEST_HANDLE,
+                flags: CertifyKeyFlags::empty(),
+                label: TEST_LABEL,
+                format: CertifyKeyCmd::FORMAT_X509,
+            };
+            let certify_resp = match cmd.execute(&mut dpe, &mut env, TEST_LOCALITIES[0]).unwrap() {
+                Response::CertifyKey(resp) => resp,
+                _ => panic!("Incorrect response type"),
+            };
+            let x509 =
+                X509::from_der(&certify_resp.cert[..certify_resp.cert_size.try_into().unwrap()])
+                    .unwrap();
+            x509.public_key().unwrap().ec_key().unwrap()
+        };
+
+        assert!(sig.verify(&TEST_DIGEST, &ec_pub_key).unwrap());
+    }
+
     #[test]
     fn test_symmetric() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe =
             DpeInstance::new(&mut env, Support::AUTO_INIT | Support::IS_SYMMETRIC).unwrap();
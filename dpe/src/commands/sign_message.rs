@@ -0,0 +1,694 @@
+// Licensed under the Apache-2.0 license.
+
+//! Sign commands which hash the message to be signed inside DPE.
+//!
+//! `SignMessageCmd` signs a message that fits in a single command. Larger messages can be streamed
+//! with `SignStreamInitCmd`, any number of `SignStreamUpdateCmd`s, and `SignStreamFinalCmd`. Each
+//! step rotates the context handle, so the stream is bound to whoever holds the handle.
+//!
+//! DPE state must be serializable, so between commands the running hash of a streamed message is
+//! saved in the context and picked up again by the next command. The digest signed by
+//! `SignStreamFinalCmd` is the hash of the whole message, however it was split, and is returned
+//! alongside the signature.
+use super::{CommandExecution, SignCmd, SignFlags};
+use crate::{
+    context::ContextHandle,
+    dpe_instance::{DpeEnv, DpeInstance, DpeTypes},
+    response::{DpeErrorCode, NewHandleResp, Response, ResponseHdr, SignStreamFinalResp},
+    DPE_PROFILE, MAX_SIGN_MESSAGE_SIZE,
+};
+use crypto::{Crypto, Hasher, HASH_STATE_SIZE};
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct SignMessageCmd {
+    pub handle: ContextHandle,
+    pub label: [u8; DPE_PROFILE.get_hash_size()],
+    pub flags: SignFlags,
+    pub message_size: u32,
+    pub message: [u8; MAX_SIGN_MESSAGE_SIZE],
+}
+
+impl CommandExecution for SignMessageCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.sign_message() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let message = self
+            .message
+            .get(..self.message_size as usize)
+            .ok_or(DpeErrorCode::InvalidArgument)?;
+        let digest = env
+            .crypto
+            .hash(DPE_PROFILE.alg_len(), message)
+            .map_err(|_| DpeErrorCode::HashError)?;
+
+        SignCmd {
+            handle: self.handle,
+            label: self.label,
+            flags: SignFlags::from_bits_retain(self.flags.bits()),
+            digest: digest
+                .bytes()
+                .try_into()
+                .map_err(|_| DpeErrorCode::InternalError)?,
+        }
+        .execute(dpe, env, locality)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct SignStreamInitCmd {
+    pub handle: ContextHandle,
+}
+
+impl CommandExecution for SignStreamInitCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.sign_message() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let idx = dpe.get_active_context_pos(&self.handle, locality)?;
+
+        let mut state = [0; HASH_STATE_SIZE];
+        env.crypto
+            .hash_initialize(DPE_PROFILE.alg_len())
+            .and_then(|hasher| hasher.save(&mut state))
+            .map_err(|_| DpeErrorCode::HashError)?;
+        let handle = dpe.next_onetime_use_handle(env, idx)?;
+
+        // Starting a new stream abandons any previous one.
+        let context = &mut dpe.contexts[idx];
+        context.sign_stream_state = state;
+        context.has_sign_stream = true.into();
+        context.handle = handle;
+
+        Ok(Response::SignStreamInit(NewHandleResp {
+            handle,
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+        }))
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct SignStreamUpdateCmd {
+    pub handle: ContextHandle,
+    pub chunk_size: u32,
+    pub chunk: [u8; MAX_SIGN_MESSAGE_SIZE],
+}
+
+impl CommandExecution for SignStreamUpdateCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.sign_message() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let chunk = self
+            .chunk
+            .get(..self.chunk_size as usize)
+            .ok_or(DpeErrorCode::InvalidArgument)?;
+
+        let idx = dpe.get_active_context_pos(&self.handle, locality)?;
+        let context = &dpe.contexts[idx];
+        if !context.has_sign_stream() {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let mut state = [0; HASH_STATE_SIZE];
+        let mut hasher = env
+            .crypto
+            .hash_resume(DPE_PROFILE.alg_len(), &context.sign_stream_state)
+            .map_err(|_| DpeErrorCode::HashError)?;
+        hasher.update(chunk).map_err(|_| DpeErrorCode::HashError)?;
+        hasher
+            .save(&mut state)
+            .map_err(|_| DpeErrorCode::HashError)?;
+        let handle = dpe.next_onetime_use_handle(env, idx)?;
+
+        let context = &mut dpe.contexts[idx];
+        context.sign_stream_state = state;
+        context.handle = handle;
+
+        Ok(Response::SignStreamUpdate(NewHandleResp {
+            handle,
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+        }))
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
+pub struct SignStreamFinalCmd {
+    pub handle: ContextHandle,
+    pub label: [u8; DPE_PROFILE.get_hash_size()],
+    pub flags: SignFlags,
+}
+
+impl CommandExecution for SignStreamFinalCmd {
+    fn execute(
+        &self,
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<Response, DpeErrorCode> {
+        // Make sure this command is supported.
+        if !dpe.support.sign_message() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let idx = dpe.get_active_context_pos(&self.handle, locality)?;
+        let context = &dpe.contexts[idx];
+        if !context.has_sign_stream() {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+
+        let digest = env
+            .crypto
+            .hash_resume(DPE_PROFILE.alg_len(), &context.sign_stream_state)
+            .and_then(|hasher| hasher.finish())
+            .map_err(|_| DpeErrorCode::HashError)?
+            .bytes()
+            .try_into()
+            .map_err(|_| DpeErrorCode::InternalError)?;
+        let resp = match (SignCmd {
+            handle: self.handle,
+            label: self.label,
+            flags: SignFlags::from_bits_retain(self.flags.bits()),
+            digest,
+        })
+        .execute(dpe, env, locality)?
+        {
+            Response::Sign(resp) => resp,
+            _ => return Err(DpeErrorCode::InternalError),
+        };
+
+        // The stream is only consumed once the signature has been produced.
+        dpe.contexts[idx].reset_sign_stream();
+
+        Ok(Response::SignStreamFinal(SignStreamFinalResp {
+            resp_hdr: resp.resp_hdr,
+            new_context_handle: resp.new_context_handle,
+            sig_r_or_hmac: resp.sig_r_or_hmac,
+            sig_s: resp.sig_s,
+            digest,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{
+            certify_key::{CertifyKeyCmd, CertifyKeyFlags},
+            destroy_context::DestroyCtxFlags,
+            Command, CommandHdr, DestroyCtxCmd,
+        },
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        response::SignResp,
+        support::{test::SUPPORT, Support},
+    };
+    use crypto::OpensslCrypto;
+    use openssl::{bn::BigNum, ecdsa::EcdsaSig, x509::X509};
+    use platform::default::DefaultPlatform;
+    use zerocopy::AsBytes;
+
+    const TEST_LABEL: [u8; DPE_PROFILE.get_hash_size()] = [0xa5; DPE_PROFILE.get_hash_size()];
+
+    fn test_message() -> [u8; MAX_SIGN_MESSAGE_SIZE] {
+        let mut message = [0; MAX_SIGN_MESSAGE_SIZE];
+        for (i, b) in message.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        message
+    }
+
+    /// Streams `chunks` of `message` and returns the final response.
+    fn sign_stream(
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<TestTypes>,
+        message: &[u8],
+        chunks: &[usize],
+        flags: SignFlags,
+    ) -> SignStreamFinalResp {
+        SignStreamInitCmd {
+            handle: ContextHandle::default(),
+        }
+        .execute(dpe, env, TEST_LOCALITIES[0])
+        .unwrap();
+
+        let mut offset = 0;
+        for &chunk_size in chunks {
+            let mut chunk = [0; MAX_SIGN_MESSAGE_SIZE];
+            chunk[..chunk_size].copy_from_slice(&message[offset..offset + chunk_size]);
+            offset += chunk_size;
+            SignStreamUpdateCmd {
+                handle: ContextHandle::default(),
+                chunk_size: chunk_size as u32,
+                chunk,
+            }
+            .execute(dpe, env, TEST_LOCALITIES[0])
+            .unwrap();
+        }
+
+        match (SignStreamFinalCmd {
+            handle: ContextHandle::default(),
+            label: TEST_LABEL,
+            flags,
+        })
+        .execute(dpe, env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::SignStreamFinal(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        }
+    }
+
+    fn sign_digest(
+        dpe: &mut DpeInstance,
+        env: &mut DpeEnv<TestTypes>,
+        digest: [u8; DPE_PROFILE.get_hash_size()],
+    ) -> SignResp {
+        match (SignCmd {
+            handle: ContextHandle::default(),
+            label: TEST_LABEL,
+            flags: SignFlags::IS_SYMMETRIC,
+            digest,
+        })
+        .execute(dpe, env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::Sign(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_sign_message() {
+        let cmd = SignMessageCmd {
+            handle: SIMULATION_HANDLE,
+            label: TEST_LABEL,
+            flags: SignFlags::from_bits_retain(0x1234_5678),
+            message_size: 0x100,
+            message: test_message(),
+        };
+        let mut command = CommandHdr::new_for_test(Command::SIGN_MESSAGE)
+            .as_bytes()
+            .to_vec();
+        command.extend(cmd.as_bytes());
+        assert_eq!(
+            Ok(Command::SignMessage(cmd)),
+            Command::deserialize(&command)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_sign_stream() {
+        let cmd = SignStreamInitCmd {
+            handle: SIMULATION_HANDLE,
+        };
+        let mut command = CommandHdr::new_for_test(Command::SIGN_STREAM_INIT)
+            .as_bytes()
+            .to_vec();
+        command.extend(cmd.as_bytes());
+        assert_eq!(
+            Ok(Command::SignStreamInit(cmd)),
+            Command::deserialize(&command)
+        );
+
+        let cmd = SignStreamUpdateCmd {
+            handle: SIMULATION_HANDLE,
+            chunk_size: 0x100,
+            chunk: test_message(),
+        };
+        let mut command = CommandHdr::new_for_test(Command::SIGN_STREAM_UPDATE)
+            .as_bytes()
+            .to_vec();
+        command.extend(cmd.as_bytes());
+        assert_eq!(
+            Ok(Command::SignStreamUpdate(cmd)),
+            Command::deserialize(&command)
+        );
+
+        let cmd = SignStreamFinalCmd {
+            handle: SIMULATION_HANDLE,
+            label: TEST_LABEL,
+            flags: SignFlags::from_bits_retain(0x1234_5678),
+        };
+        let mut command = CommandHdr::new_for_test(Command::SIGN_STREAM_FINAL)
+            .as_bytes()
+            .to_vec();
+        command.extend(cmd.as_bytes());
+        assert_eq!(
+            Ok(Command::SignStreamFinal(cmd)),
+            Command::deserialize(&command)
+        );
+    }
+
+    #[test]
+    fn test_sign_message_unsupported() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        let handle = ContextHandle::default();
+        let label = [0; DPE_PROFILE.get_hash_size()];
+
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            SignMessageCmd {
+                handle,
+                label,
+                flags: SignFlags::empty(),
+                message_size: 0,
+                message: [0; MAX_SIGN_MESSAGE_SIZE],
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            SignStreamInitCmd { handle }.execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            SignStreamUpdateCmd {
+                handle,
+                chunk_size: 0,
+                chunk: [0; MAX_SIGN_MESSAGE_SIZE],
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            SignStreamFinalCmd {
+                handle,
+                label,
+                flags: SignFlags::empty(),
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+    }
+
+    #[test]
+    fn test_sign_message() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(
+            &mut env,
+            SUPPORT | Support::SIGN_MESSAGE | Support::IS_SYMMETRIC,
+        )
+        .unwrap();
+
+        // Message bigger than the buffer.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            SignMessageCmd {
+                handle: ContextHandle::default(),
+                label: TEST_LABEL,
+                flags: SignFlags::IS_SYMMETRIC,
+                message_size: MAX_SIGN_MESSAGE_SIZE as u32 + 1,
+                message: test_message(),
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        let message = test_message();
+        let resp = match (SignMessageCmd {
+            handle: ContextHandle::default(),
+            label: TEST_LABEL,
+            flags: SignFlags::IS_SYMMETRIC,
+            message_size: 100,
+            message,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::Sign(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+
+        // Should be the same as signing the hash of the message.
+        let digest = env
+            .crypto
+            .hash(DPE_PROFILE.alg_len(), &message[..100])
+            .unwrap();
+        let expected = sign_digest(&mut dpe, &mut env, digest.bytes().try_into().unwrap());
+        assert_eq!(expected, resp);
+    }
+
+    #[test]
+    fn test_sign_stream() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(
+            &mut env,
+            SUPPORT | Support::SIGN_MESSAGE | Support::IS_SYMMETRIC,
+        )
+        .unwrap();
+        let message = test_message();
+
+        // Can't update or finish before the stream is started.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            SignStreamUpdateCmd {
+                handle: ContextHandle::default(),
+                chunk_size: 0,
+                chunk: message,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            SignStreamFinalCmd {
+                handle: ContextHandle::default(),
+                label: TEST_LABEL,
+                flags: SignFlags::IS_SYMMETRIC,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        SignStreamInitCmd {
+            handle: ContextHandle::default(),
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+
+        // Chunk bigger than the buffer.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            SignStreamUpdateCmd {
+                handle: ContextHandle::default(),
+                chunk_size: MAX_SIGN_MESSAGE_SIZE as u32 + 1,
+                chunk: message,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        let mut hasher = env.crypto.hash_initialize(DPE_PROFILE.alg_len()).unwrap();
+        for chunk_size in [MAX_SIGN_MESSAGE_SIZE, 10, 0] {
+            SignStreamUpdateCmd {
+                handle: ContextHandle::default(),
+                chunk_size: chunk_size as u32,
+                chunk: message,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+            .unwrap();
+            hasher.update(&message[..chunk_size]).unwrap();
+        }
+        let expected_digest = hasher.finish().unwrap().bytes().try_into().unwrap();
+
+        let resp = match (SignStreamFinalCmd {
+            handle: ContextHandle::default(),
+            label: TEST_LABEL,
+            flags: SignFlags::IS_SYMMETRIC,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::SignStreamFinal(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(expected_digest, resp.digest);
+        let expected = sign_digest(&mut dpe, &mut env, expected_digest);
+        assert_eq!(expected.sig_r_or_hmac, resp.sig_r_or_hmac);
+        assert_eq!(expected.sig_s, resp.sig_s);
+
+        // The stream is consumed by the final command.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            SignStreamFinalCmd {
+                handle: ContextHandle::default(),
+                label: TEST_LABEL,
+                flags: SignFlags::IS_SYMMETRIC,
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+    }
+
+    #[test]
+    fn test_sign_stream_digest() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(
+            &mut env,
+            SUPPORT | Support::SIGN_MESSAGE | Support::IS_SYMMETRIC,
+        )
+        .unwrap();
+        let message = test_message();
+
+        let resp = sign_stream(&mut dpe, &mut env, &message, &[40, 60], SignFlags::empty());
+
+        // The returned digest is the hash of the whole message.
+        let expected_digest = env
+            .crypto
+            .hash(DPE_PROFILE.alg_len(), &message[..100])
+            .unwrap();
+        assert_eq!(expected_digest.bytes(), resp.digest);
+
+        // And it is the digest the signature is over.
+        let sig = EcdsaSig::from_private_components(
+            BigNum::from_slice(&resp.sig_r_or_hmac).unwrap(),
+            BigNum::from_slice(&resp.sig_s).unwrap(),
+        )
+        .unwrap();
+        let ec_pub_key = match (CertifyKeyCmd {
+            handle: ContextHandle::default(),
+            flags: CertifyKeyFlags::empty(),
+            label: TEST_LABEL,
+            format: CertifyKeyCmd::FORMAT_X509,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::CertifyKey(resp) => X509::from_der(&resp.cert[..resp.cert_size as usize])
+                .unwrap()
+                .public_key()
+                .unwrap()
+                .ec_key()
+                .unwrap(),
+            _ => panic!("Incorrect response type"),
+        };
+        assert!(sig.verify(&resp.digest, &ec_pub_key).unwrap());
+
+        // However the message is split, the digest is the same.
+        for chunks in [&[50, 50][..], &[100], &[0, 1, 99, 0], &[64, 36]] {
+            let split = sign_stream(&mut dpe, &mut env, &message, chunks, SignFlags::empty());
+            assert_eq!(resp.digest, split.digest);
+        }
+
+        // And matches signing the message in one go.
+        let resp = sign_stream(
+            &mut dpe,
+            &mut env,
+            &message,
+            &[30, 70],
+            SignFlags::IS_SYMMETRIC,
+        );
+        let expected = match (SignMessageCmd {
+            handle: ContextHandle::default(),
+            label: TEST_LABEL,
+            flags: SignFlags::IS_SYMMETRIC,
+            message_size: 100,
+            message,
+        })
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap()
+        {
+            Response::Sign(resp) => resp,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(expected.sig_r_or_hmac, resp.sig_r_or_hmac);
+    }
+
+    #[test]
+    fn test_sign_stream_reset() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::SIGN_MESSAGE).unwrap();
+
+        // Only the locality owning the context can use it for streaming.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidLocality),
+            SignStreamInitCmd {
+                handle: ContextHandle::default(),
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        SignStreamInitCmd {
+            handle: ContextHandle::default(),
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        SignStreamUpdateCmd {
+            handle: ContextHandle::default(),
+            chunk_size: 10,
+            chunk: test_message(),
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+
+        let idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        assert!(dpe.contexts[idx].has_sign_stream());
+
+        // Starting again throws away the data streamed so far.
+        SignStreamInitCmd {
+            handle: ContextHandle::default(),
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        assert!(dpe.contexts[idx].has_sign_stream());
+        let mut state = [0; HASH_STATE_SIZE];
+        env.crypto
+            .hash_initialize(DPE_PROFILE.alg_len())
+            .unwrap()
+            .save(&mut state)
+            .unwrap();
+        assert_eq!(state, dpe.contexts[idx].sign_stream_state);
+
+        // Destroying the context abandons the stream.
+        DestroyCtxCmd {
+            handle: ContextHandle::default(),
+            flags: DestroyCtxFlags::empty(),
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        assert!(!dpe.contexts[idx].has_sign_stream());
+    }
+}
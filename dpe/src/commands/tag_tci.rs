@@ -7,11 +7,10 @@ use crate::{
 };
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes)]
-#[cfg_attr(test, derive(zerocopy::AsBytes))]
+#[derive(Debug, PartialEq, Eq, zerocopy::FromBytes, zerocopy::AsBytes)]
 pub struct TagTciCmd {
-    handle: ContextHandle,
-    tag: u32,
+    pub handle: ContextHandle,
+    pub tag: u32,
 }
 
 impl CommandExecution for TagTciCmd {
@@ -41,8 +40,9 @@ impl CommandExecution for TagTciCmd {
         }
 
         // Because handles are one-time use, let's rotate the handle, if it isn't the default.
-        dpe.roll_onetime_use_handle(env, idx)?;
+        let new_handle = dpe.next_onetime_use_handle(env, idx)?;
         let context = &mut dpe.contexts[idx];
+        context.handle = new_handle;
         context.has_tag = true.into();
         context.tag = self.tag;
 
@@ -86,7 +86,8 @@ mod tests {
     fn test_tag_tci() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::default()).unwrap();
         // Make sure it returns an error if the command is marked unsupported.
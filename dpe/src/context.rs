@@ -1,6 +1,7 @@
 // Licensed under the Apache-2.0 license.
 use crate::{response::DpeErrorCode, tci::TciNodeData, U8Bool, MAX_HANDLES};
 use core::mem::size_of;
+use crypto::HASH_STATE_SIZE;
 use zerocopy::{AsBytes, FromBytes};
 
 #[repr(C, align(4))]
@@ -9,7 +10,7 @@ pub(crate) struct Context {
     pub handle: ContextHandle,
     pub tci: TciNodeData,
     /// Bitmap of the node indices that are children of this node
-    pub children: u32,
+    pub children: ContextBitmap,
 
     /// Which hardware locality owns the context.
     pub locality: u32,
@@ -35,6 +36,15 @@ pub(crate) struct Context {
     pub allow_ca: U8Bool,
     /// Whether this context can emit certificates in X.509 format
     pub allow_x509: U8Bool,
+    /// Whether this context can export a CDI with DeriveChild
+    pub allow_export: U8Bool,
+    /// Saved running hash of the message being signed with the streaming Sign commands
+    pub sign_stream_state: [u8; HASH_STATE_SIZE],
+    /// Whether a streaming Sign has been started and not yet finished
+    pub has_sign_stream: U8Bool,
+
+    // unused buffer added to make Context word aligned and remove padding
+    reserved: [u8; 2],
 }
 
 impl Context {
@@ -44,7 +54,7 @@ impl Context {
         Context {
             handle: ContextHandle::default(),
             tci: TciNodeData::new(),
-            children: 0,
+            children: ContextBitmap::empty(),
             parent_idx: Self::ROOT_INDEX,
             context_type: ContextType::Normal,
             state: ContextState::Inactive,
@@ -55,6 +65,10 @@ impl Context {
             uses_internal_input_dice: U8Bool::new(false),
             allow_ca: U8Bool::new(false),
             allow_x509: U8Bool::new(false),
+            allow_export: U8Bool::new(false),
+            sign_stream_state: [0; HASH_STATE_SIZE],
+            has_sign_stream: U8Bool::new(false),
+            reserved: [0; 2],
         }
     }
 
@@ -73,6 +87,42 @@ impl Context {
     pub fn allow_x509(&self) -> bool {
         self.allow_x509.get()
     }
+    pub fn allow_export(&self) -> bool {
+        self.allow_export.get()
+    }
+    pub fn has_sign_stream(&self) -> bool {
+        self.has_sign_stream.get()
+    }
+
+    /// Whether every enum and boolean field holds one of its defined values. Contexts read back
+    /// from saved state need to be checked before they are used.
+    pub fn has_valid_fields(&self) -> bool {
+        [
+            &self.has_tag,
+            &self.uses_internal_input_info,
+            &self.uses_internal_input_dice,
+            &self.allow_ca,
+            &self.allow_x509,
+            &self.allow_export,
+            &self.has_sign_stream,
+        ]
+        .iter()
+        .all(|b| b.is_bool())
+            && matches!(
+                self.state,
+                ContextState::Inactive | ContextState::Active | ContextState::Retired
+            )
+            && matches!(
+                self.context_type,
+                ContextType::Normal | ContextType::Simulation
+            )
+    }
+
+    /// Abandons any streaming Sign in progress on this context.
+    pub fn reset_sign_stream(&mut self) {
+        self.sign_stream_state = [0; HASH_STATE_SIZE];
+        self.has_sign_stream = false.into();
+    }
 
     /// Resets all values to a freshly initialized state.
     ///
@@ -87,13 +137,15 @@ impl Context {
         self.tci = TciNodeData::new();
         self.tci.tci_type = args.tci_type;
         self.tci.locality = args.locality;
-        self.children = 0;
+        self.children = ContextBitmap::empty();
         self.parent_idx = args.parent_idx;
         self.context_type = args.context_type;
         self.state = ContextState::Active;
         self.locality = args.locality;
         self.allow_ca = args.allow_ca.into();
         self.allow_x509 = args.allow_x509.into();
+        self.allow_export = args.allow_export.into();
+        self.reset_sign_stream();
     }
 
     /// Destroy this context so it can no longer be used until it is re-initialized. The default
@@ -103,17 +155,89 @@ impl Context {
         self.has_tag = false.into();
         self.tag = 0;
         self.state = ContextState::Inactive;
+        self.children = ContextBitmap::empty();
+        self.parent_idx = Self::ROOT_INDEX;
         self.uses_internal_input_info = false.into();
         self.uses_internal_input_dice = false.into();
+        self.reset_sign_stream();
+    }
+}
+
+const CONTEXT_BITMAP_WORDS: usize = MAX_HANDLES.div_ceil(u32::BITS as usize);
+
+/// Bitmap with one bit per slot in the context table, bit 0 of the first word being slot 0.
+///
+/// With the default capacity of 24 slots this is a single u32, so it has the same layout as the
+/// plain u32 bitmaps in older persisted state.
+#[repr(transparent)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct ContextBitmap([u32; CONTEXT_BITMAP_WORDS]);
+
+impl ContextBitmap {
+    pub const fn empty() -> ContextBitmap {
+        ContextBitmap([0; CONTEXT_BITMAP_WORDS])
+    }
+
+    /// Returns a bitmap with only `idx` set.
+    pub fn from_idx(idx: usize) -> ContextBitmap {
+        let mut bitmap = Self::empty();
+        bitmap.insert(idx);
+        bitmap
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|word| *word == 0)
+    }
+
+    pub fn contains(&self, idx: usize) -> bool {
+        idx < MAX_HANDLES && self.0[idx / u32::BITS as usize] & Self::bit(idx) != 0
+    }
+
+    /// Sets the bit for `idx`. Indices past the end of the table are ignored.
+    pub fn insert(&mut self, idx: usize) {
+        if idx < MAX_HANDLES {
+            self.0[idx / u32::BITS as usize] |= Self::bit(idx);
+        }
+    }
+
+    /// Clears the bit for `idx`. Indices past the end of the table are ignored.
+    pub fn remove(&mut self, idx: usize) {
+        if idx < MAX_HANDLES {
+            self.0[idx / u32::BITS as usize] &= !Self::bit(idx);
+        }
+    }
+
+    const fn bit(idx: usize) -> u32 {
+        1 << (idx % u32::BITS as usize)
+    }
+
+    /// Iterates over the set indices, lowest first. Bits past the end of the table are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = usize> {
+        let bitmap = *self;
+        (0..MAX_HANDLES).filter(move |idx| bitmap.contains(*idx))
     }
+}
+
+impl Default for ContextBitmap {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
 
-    /// Add a child to list of children in the context.
-    pub fn add_child(&mut self, idx: usize) -> Result<(), DpeErrorCode> {
-        if idx >= MAX_HANDLES {
-            return Err(DpeErrorCode::MaxTcis);
+impl core::ops::BitOr for ContextBitmap {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self {
+        self |= rhs;
+        self
+    }
+}
+
+impl core::ops::BitOrAssign for ContextBitmap {
+    fn bitor_assign(&mut self, rhs: Self) {
+        for (word, rhs) in self.0.iter_mut().zip(rhs.0) {
+            *word |= rhs;
         }
-        self.children |= 1 << idx;
-        Ok(())
     }
 }
 
@@ -162,7 +286,7 @@ impl TryFrom<&[u8]> for ContextHandle {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, AsBytes, FromBytes)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, AsBytes, FromBytes)]
 #[repr(u8, align(1))]
 #[rustfmt::skip]
 pub(crate) enum ContextState {
@@ -229,6 +353,7 @@ pub(crate) struct ActiveContextArgs<'a> {
     pub parent_idx: u8,
     pub allow_ca: bool,
     pub allow_x509: bool,
+    pub allow_export: bool,
 }
 
 pub(crate) struct ChildToRootIter<'a> {
@@ -290,11 +415,49 @@ mod tests {
 
     const CONTEXT_INITIALIZER: Context = Context::new();
 
+    #[test]
+    fn test_context_bitmap() {
+        let mut bitmap = ContextBitmap::empty();
+        assert!(bitmap.is_empty());
+
+        bitmap.insert(0);
+        bitmap.insert(MAX_HANDLES - 1);
+        bitmap.insert(MAX_HANDLES);
+        assert!(bitmap.contains(0));
+        assert!(bitmap.contains(MAX_HANDLES - 1));
+        assert!(!bitmap.contains(MAX_HANDLES));
+        assert_eq!(
+            [0, MAX_HANDLES - 1],
+            bitmap.iter().collect::<Vec<_>>().as_slice()
+        );
+
+        bitmap.remove(0);
+        assert_eq!(ContextBitmap::from_idx(MAX_HANDLES - 1), bitmap);
+        assert_eq!(
+            [1, MAX_HANDLES - 1],
+            (bitmap | ContextBitmap::from_idx(1))
+                .iter()
+                .collect::<Vec<_>>()
+                .as_slice()
+        );
+    }
+
+    #[cfg(not(any(feature = "max_handles_32", feature = "max_handles_64")))]
+    #[test]
+    fn test_context_bitmap_layout() {
+        // Persisted state from before the capacity was configurable stored a u32 bitmap.
+        assert_eq!(size_of::<u32>(), size_of::<ContextBitmap>());
+        assert_eq!(
+            (1u32 << 3).as_bytes(),
+            ContextBitmap::from_idx(3).as_bytes()
+        );
+    }
+
     #[test]
     fn test_child_to_root_iter() {
         let mut contexts = [CONTEXT_INITIALIZER; MAX_HANDLES];
         let root_index = CHAIN_INDICES[0];
-        assert_eq!(MAX_HANDLES, CHAIN_INDICES.len());
+        assert!(CHAIN_INDICES.len() <= MAX_HANDLES);
 
         // Lets put the context's index in the tag to make it easy to find later.
         contexts[root_index].tag = root_index as u32;
@@ -388,7 +551,7 @@ mod tests {
     ///
     /// So `dpe.contexts[2]` is the parent of `dpe.contexts[4]` which is the parent of
     /// `dpe.contexts[1]` etc.
-    const CHAIN_INDICES: [usize; MAX_HANDLES] = [
+    const CHAIN_INDICES: [usize; 24] = [
         2,
         4,
         1,
@@ -6,8 +6,13 @@ Abstract:
 --*/
 use crate::{
     commands::{Command, CommandExecution, InitCtxCmd},
-    context::{ChildToRootIter, Context, ContextHandle, ContextState},
-    response::{DpeErrorCode, GetProfileResp, Response, ResponseHdr},
+    context::{ChildToRootIter, Context, ContextBitmap, ContextHandle, ContextState, ContextType},
+    event_log::{EventLog, TciEventType},
+    event_sink::{DpeEvent, EventSink},
+    response::{
+        ContextInfo, ContextInfoFlags, DpeErrorCode, GetContextTableResp, GetLocalityQuotaResp,
+        GetProfileResp, Response, ResponseHdr,
+    },
     support::Support,
     tci::{TciMeasurement, TciNodeData},
     U8Bool, DPE_PROFILE, INTERNAL_INPUT_INFO_SIZE, MAX_HANDLES,
@@ -23,11 +28,16 @@ pub trait DpeTypes {
     type Platform<'a>: Platform
     where
         Self: 'a;
+    type EventSink<'a>: EventSink
+    where
+        Self: 'a;
 }
 
 pub struct DpeEnv<'a, T: DpeTypes + 'a> {
     pub crypto: T::Crypto<'a>,
     pub platform: T::Platform<'a>,
+    /// Receives what each command did. Use `()` to discard it.
+    pub event_sink: T::EventSink<'a>,
 }
 
 #[repr(C, align(4))]
@@ -42,6 +52,16 @@ pub struct DpeInstance {
 
     // unused buffer added to make DpeInstance word aligned and remove padding
     reserved: [u8; 3],
+
+    /// Every measurement folded into a TCI, so verifiers can replay the cumulative values.
+    pub(crate) event_log: EventLog,
+}
+
+/// The state and locality of each context and the number of events in the log, which is all
+/// `DpeInstance::report_changes` needs from before a command.
+struct ReportedState {
+    contexts: [(ContextState, u32); MAX_HANDLES],
+    event_count: u32,
 }
 
 impl DpeInstance {
@@ -57,14 +77,7 @@ impl DpeInstance {
         env: &mut DpeEnv<impl DpeTypes>,
         support: Support,
     ) -> Result<DpeInstance, DpeErrorCode> {
-        const CONTEXT_INITIALIZER: Context = Context::new();
-        let mut dpe = DpeInstance {
-            contexts: [CONTEXT_INITIALIZER; MAX_HANDLES],
-            support,
-            has_initialized: false.into(),
-            reserved: [0u8; 3],
-        };
-
+        let mut dpe = DpeInstance::new_empty(support);
         if dpe.support.auto_init() {
             let locality = env
                 .platform
@@ -75,6 +88,18 @@ impl DpeInstance {
         Ok(dpe)
     }
 
+    /// Creates an instance with every context inactive, without auto-initializing.
+    pub(crate) const fn new_empty(support: Support) -> DpeInstance {
+        const CONTEXT_INITIALIZER: Context = Context::new();
+        DpeInstance {
+            contexts: [CONTEXT_INITIALIZER; MAX_HANDLES],
+            support,
+            has_initialized: U8Bool::new(false),
+            reserved: [0u8; 3],
+            event_log: EventLog::new(),
+        }
+    }
+
     pub fn has_initialized(&self) -> bool {
         self.has_initialized.get()
     }
@@ -96,6 +121,75 @@ impl DpeInstance {
         ))
     }
 
+    /// Returns how many contexts `locality` may hold and how many it holds now.
+    pub fn get_locality_quota(
+        &self,
+        platform: &mut impl Platform,
+        locality: u32,
+    ) -> Result<GetLocalityQuotaResp, DpeErrorCode> {
+        let quota = platform
+            .get_locality_quota(locality)
+            .map_err(|_| DpeErrorCode::PlatformError)?;
+        Ok(GetLocalityQuotaResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            locality,
+            max_contexts: quota.min(MAX_HANDLES as u32),
+            used_contexts: self.count_locality_contexts(locality) as u32,
+        })
+    }
+
+    /// Returns a redacted view of every slot in the context table. This is meant for diagnosing
+    /// issues like running out of contexts, so it never includes handles or TCI values.
+    pub fn get_context_table(&self) -> Result<GetContextTableResp, DpeErrorCode> {
+        if !self.support.context_table() {
+            return Err(DpeErrorCode::InvalidCommand);
+        }
+
+        let mut resp = GetContextTableResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            num_contexts: MAX_HANDLES as u32,
+            contexts: [ContextInfo {
+                locality: 0,
+                tag: 0,
+                children: ContextBitmap::empty(),
+                flags: ContextInfoFlags::empty(),
+                parent_idx: 0,
+                state: 0,
+                context_type: 0,
+                reserved: 0,
+            }; MAX_HANDLES],
+        };
+        for (info, context) in resp.contexts.iter_mut().zip(self.contexts.iter()) {
+            let mut flags = ContextInfoFlags::empty();
+            flags.set(ContextInfoFlags::HAS_TAG, context.has_tag());
+            flags.set(
+                ContextInfoFlags::USES_INTERNAL_INPUT_INFO,
+                context.uses_internal_input_info(),
+            );
+            flags.set(
+                ContextInfoFlags::USES_INTERNAL_INPUT_DICE,
+                context.uses_internal_input_dice(),
+            );
+            flags.set(ContextInfoFlags::ALLOW_CA, context.allow_ca());
+            flags.set(ContextInfoFlags::ALLOW_X509, context.allow_x509());
+            flags.set(ContextInfoFlags::ALLOW_EXPORT, context.allow_export());
+            flags.set(ContextInfoFlags::HAS_SIGN_STREAM, context.has_sign_stream());
+
+            *info = ContextInfo {
+                locality: context.locality,
+                tag: if context.has_tag() { context.tag } else { 0 },
+                children: context.children,
+                flags,
+                parent_idx: context.parent_idx,
+                state: context.state.as_bytes()[0],
+                context_type: context.context_type.as_bytes()[0],
+                reserved: 0,
+            };
+        }
+
+        Ok(resp)
+    }
+
     /// Deserializes the command and executes it.
     ///
     /// # Arguments
@@ -110,7 +204,144 @@ impl DpeInstance {
         cmd: &[u8],
     ) -> Result<Response, DpeErrorCode> {
         let command = Command::deserialize(cmd)?;
-        let resp = match command {
+        match self.execute_command(env, locality, command) {
+            Ok(resp) => Ok(resp),
+            Err(err_code) => Ok(Response::Error(ResponseHdr::new(err_code))),
+        }
+    }
+
+    /// Executes an already deserialized command.
+    ///
+    /// The platform must allow `locality` to execute the command, or it fails with
+    /// `AccessDenied`. Commands check everything that can fail before they change the instance,
+    /// so a command that fails leaves it unchanged. What the command did is reported to the
+    /// event sink.
+    ///
+    /// # Arguments
+    ///
+    /// * `locality` - which hardware locality is making the request
+    /// * `command` - the command to execute
+    pub(crate) fn execute_command(
+        &mut self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+        command: Command,
+    ) -> Result<Response, DpeErrorCode> {
+        self.execute_and_record(env, locality, command, true)
+    }
+
+    /// Executes a command from a batch.
+    ///
+    /// Like `execute_command`, except that the contexts and measurements the command changed are
+    /// not reported. The batch reports them once it finishes, so changes that an ALL_OR_NOTHING
+    /// batch rolls back are never reported.
+    pub(crate) fn execute_batch_entry(
+        &mut self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+        command: Command,
+    ) -> Result<Response, DpeErrorCode> {
+        self.execute_and_record(env, locality, command, false)
+    }
+
+    fn execute_and_record(
+        &mut self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+        command: Command,
+        report_changes: bool,
+    ) -> Result<Response, DpeErrorCode> {
+        let command_id = u32::from(&command);
+        let result = self.execute_checked(env, locality, command, report_changes);
+        env.event_sink.record(DpeEvent::CommandExecuted {
+            command_id,
+            locality,
+            status: result
+                .as_ref()
+                .err()
+                .copied()
+                .unwrap_or(DpeErrorCode::NoError),
+        });
+        result
+    }
+
+    fn execute_checked(
+        &mut self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+        command: Command,
+        report_changes: bool,
+    ) -> Result<Response, DpeErrorCode> {
+        if !env
+            .platform
+            .can_execute_command(locality, u32::from(&command), command.flags())
+            .map_err(|_| DpeErrorCode::PlatformError)?
+        {
+            return Err(DpeErrorCode::AccessDenied);
+        }
+
+        let before = report_changes.then(|| self.reported_state());
+        let result = self.dispatch_command(env, locality, command);
+        // A command that leaves the context table inconsistent is a bug. Refuse to carry on rather
+        // than report success for it or run further commands against the table.
+        self.validate()?;
+        if let (Ok(_), Some(before)) = (&result, before) {
+            self.report_changes(&mut env.event_sink, &before);
+        }
+        result
+    }
+
+    fn reported_state(&self) -> ReportedState {
+        let mut contexts = [(ContextState::Inactive, 0); MAX_HANDLES];
+        for (summary, context) in contexts.iter_mut().zip(self.contexts.iter()) {
+            *summary = (context.state, context.locality);
+        }
+        ReportedState {
+            contexts,
+            event_count: self.event_log.count(),
+        }
+    }
+
+    /// Reports the contexts created and destroyed and the measurements made since `before`.
+    fn report_changes(&self, sink: &mut impl EventSink, before: &ReportedState) {
+        for (context_idx, (&(old_state, old_locality), new)) in
+            before.contexts.iter().zip(self.contexts.iter()).enumerate()
+        {
+            match (old_state, new.state) {
+                (ContextState::Inactive, ContextState::Inactive) => (),
+                (ContextState::Inactive, _) => sink.record(DpeEvent::ContextCreated {
+                    context_idx,
+                    locality: new.locality,
+                }),
+                (_, ContextState::Inactive) => sink.record(DpeEvent::ContextDestroyed {
+                    context_idx,
+                    locality: old_locality,
+                }),
+                _ => (),
+            }
+        }
+        for event in self.event_log.events_since(before.event_count) {
+            sink.record(DpeEvent::MeasurementExtended {
+                context_idx: event.context_idx as usize,
+                event_type: if event.event_type == TciEventType::Derive as u32 {
+                    TciEventType::Derive
+                } else {
+                    TciEventType::Extend
+                },
+                tci_type: event.tci_type,
+                locality: event.locality,
+                measurement: event.digest,
+            });
+        }
+    }
+
+    fn dispatch_command(
+        &mut self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+        command: Command,
+    ) -> Result<Response, DpeErrorCode> {
+        match command {
             Command::GetProfile => Ok(Response::GetProfile(self.get_profile(&mut env.platform)?)),
             Command::InitCtx(cmd) => cmd.execute(self, env, locality),
             Command::DeriveChild(cmd) => cmd.execute(self, env, locality),
@@ -122,12 +353,47 @@ impl DpeInstance {
             Command::TagTci(cmd) => cmd.execute(self, env, locality),
             Command::GetTaggedTci(cmd) => cmd.execute(self, env, locality),
             Command::GetCertificateChain(cmd) => cmd.execute(self, env, locality),
-        };
+            Command::Seal(cmd) => cmd.execute(self, env, locality),
+            Command::Unseal(cmd) => cmd.execute(self, env, locality),
+            Command::SignMessage(cmd) => cmd.execute(self, env, locality),
+            Command::SignStreamInit(cmd) => cmd.execute(self, env, locality),
+            Command::SignStreamUpdate(cmd) => cmd.execute(self, env, locality),
+            Command::SignStreamFinal(cmd) => cmd.execute(self, env, locality),
+            Command::GetTaggedTciInfo(cmd) => cmd.execute(self, env, locality),
+            Command::GetContextTable => self.get_context_table().map(Response::GetContextTable),
+            Command::Batch(cmd) => cmd.execute(self, env, locality),
+            Command::GetEventLog(cmd) => cmd.execute(self, env, locality),
+            Command::Quote(cmd) => cmd.execute(self, env, locality),
+            Command::GetEat(cmd) => cmd.execute(self, env, locality),
+            Command::GetLocalityQuota => Ok(Response::GetLocalityQuota(
+                self.get_locality_quota(&mut env.platform, locality)?,
+            )),
+        }
+    }
 
-        match resp {
-            Ok(resp) => Ok(resp),
-            Err(err_code) => Ok(Response::Error(ResponseHdr::new(err_code))),
+    /// Counts the active and retired contexts owned by `locality`. Retired contexts still take
+    /// up a slot in the table, so they count against the locality's quota.
+    fn count_locality_contexts(&self, locality: u32) -> usize {
+        self.contexts
+            .iter()
+            .filter(|c| c.state != ContextState::Inactive && c.locality == locality)
+            .count()
+    }
+
+    /// Makes sure `locality` has room for one more context under the platform's quota.
+    pub(crate) fn check_locality_quota(
+        &self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        locality: u32,
+    ) -> Result<(), DpeErrorCode> {
+        let quota = env
+            .platform
+            .get_locality_quota(locality)
+            .map_err(|_| DpeErrorCode::PlatformError)?;
+        if self.count_locality_contexts(locality) >= quota as usize {
+            return Err(DpeErrorCode::LocalityQuotaExceeded);
         }
+        Ok(())
     }
 
     // Inlined so the callsite optimizer knows that idx < self.contexts.len()
@@ -184,15 +450,63 @@ impl DpeInstance {
             .position(|context| context.state == ContextState::Inactive)
     }
 
-    /// Recursive function that will return all of a context's descendants. Returns a u32 that is
-    /// a bitmap of the node indices.
-    pub(crate) fn get_descendants(&self, context: &Context) -> Result<u32, DpeErrorCode> {
+    /// Checks that the context table is self-consistent.
+    ///
+    /// Parent and child links must agree and lead back to a root without cycles, retired contexts
+    /// must still have children, handles must be unique among active contexts and each locality
+    /// may have at most one default context.
+    pub fn validate(&self) -> Result<(), DpeErrorCode> {
+        for (idx, context) in self.contexts.iter().enumerate() {
+            match context.state {
+                ContextState::Inactive if context.children.is_empty() => continue,
+                ContextState::Inactive => return Err(DpeErrorCode::InvalidInternalState),
+                ContextState::Retired if context.children.is_empty() => {
+                    return Err(DpeErrorCode::InvalidInternalState)
+                }
+                _ => (),
+            }
+
+            let children_link_back = context.children.iter().all(|child| {
+                self.contexts.get(child).is_some_and(|child| {
+                    child.state != ContextState::Inactive && child.parent_idx as usize == idx
+                })
+            });
+            let parent_links_back = context.parent_idx == Context::ROOT_INDEX
+                || self
+                    .contexts
+                    .get(context.parent_idx as usize)
+                    .is_some_and(|parent| {
+                        parent.state != ContextState::Inactive && parent.children.contains(idx)
+                    });
+            // Fails on cycles, since it gives up after visiting every slot.
+            let reaches_root = ChildToRootIter::new(idx, &self.contexts).all(|c| c.is_ok());
+            if !children_link_back || !parent_links_back || !reaches_root {
+                return Err(DpeErrorCode::InvalidInternalState);
+            }
+
+            // Default handles only need to be unique within a locality.
+            let duplicate_handle = context.state == ContextState::Active
+                && self.contexts[idx + 1..].iter().any(|other| {
+                    other.state == ContextState::Active
+                        && other.handle == context.handle
+                        && (!context.handle.is_default() || other.locality == context.locality)
+                });
+            if duplicate_handle {
+                return Err(DpeErrorCode::InvalidInternalState);
+            }
+        }
+        Ok(())
+    }
+
+    /// Recursive function that will return all of a context's descendants as a bitmap of the node
+    /// indices.
+    pub(crate) fn get_descendants(&self, context: &Context) -> Result<ContextBitmap, DpeErrorCode> {
         if context.state == ContextState::Inactive {
             return Err(DpeErrorCode::InvalidHandle);
         }
 
         let mut descendants = context.children;
-        for idx in flags_iter(context.children, MAX_HANDLES) {
+        for idx in context.children.iter() {
             if idx >= self.contexts.len() {
                 return Err(DpeErrorCode::InternalError);
             }
@@ -217,23 +531,28 @@ impl DpeInstance {
         Err(DpeErrorCode::InternalError)
     }
 
-    /// Rolls the context handle if the context is not the default context.
+    /// Returns the handle that replaces the one-time use handle of a context once it is used. The
+    /// default handle is kept.
+    ///
+    /// Commands get the new handle before changing the instance and store it once nothing else
+    /// can fail.
     ///
     /// # Arguments
     ///
     /// * `idx` - the index of the context
-    pub(crate) fn roll_onetime_use_handle(
-        &mut self,
+    pub(crate) fn next_onetime_use_handle(
+        &self,
         env: &mut DpeEnv<impl DpeTypes>,
         idx: usize,
-    ) -> Result<(), DpeErrorCode> {
+    ) -> Result<ContextHandle, DpeErrorCode> {
         if idx >= MAX_HANDLES {
             return Err(DpeErrorCode::MaxTcis);
         }
-        if !self.contexts[idx].handle.is_default() {
-            self.contexts[idx].handle = self.generate_new_handle(env)?
-        };
-        Ok(())
+        if self.contexts[idx].handle.is_default() {
+            Ok(ContextHandle::default())
+        } else {
+            self.generate_new_handle(env)
+        }
     }
 
     /// Get the TCI nodes from the context at `start_idx` to the root node following parent
@@ -270,12 +589,13 @@ impl DpeInstance {
         idx: usize,
         measurement: &TciMeasurement,
         locality: u32,
+        event_type: TciEventType,
     ) -> Result<(), DpeErrorCode> {
         if idx >= MAX_HANDLES {
             return Err(DpeErrorCode::MaxTcis);
         }
 
-        let context = &mut self.contexts[idx];
+        let context = &self.contexts[idx];
 
         if context.state != ContextState::Active {
             return Err(DpeErrorCode::InvalidHandle);
@@ -284,13 +604,53 @@ impl DpeInstance {
             return Err(DpeErrorCode::InvalidLocality);
         }
 
+        let mut tci = context.tci;
+        Self::extend_tci(env, &mut tci, measurement)?;
+        self.record_measurement(idx, event_type, tci.tci_type, locality, measurement)?;
+        self.contexts[idx].tci = tci;
+        Ok(())
+    }
+
+    /// Records a measurement in the event log.
+    ///
+    /// If the instance supports the event log, this fails once the log is full instead of
+    /// dropping events, since verifiers couldn't replay the TCIs without them.
+    pub(crate) fn record_measurement(
+        &mut self,
+        idx: usize,
+        event_type: TciEventType,
+        tci_type: u32,
+        locality: u32,
+        measurement: &TciMeasurement,
+    ) -> Result<(), DpeErrorCode> {
+        self.event_log.record(
+            !self.support.event_log(),
+            idx,
+            event_type,
+            tci_type,
+            locality,
+            measurement,
+        )
+    }
+
+    /// Extend a TCI node with a new measurement.
+    ///
+    /// # Arguments
+    ///
+    /// * `tci` - the TCI node to extend
+    /// * `measurement` - the new current measurement of the node
+    pub(crate) fn extend_tci(
+        env: &mut DpeEnv<impl DpeTypes>,
+        tci: &mut TciNodeData,
+        measurement: &TciMeasurement,
+    ) -> Result<(), DpeErrorCode> {
         // Derive the new TCI as HASH(TCI_CUMULATIVE || INPUT_DATA).
         let mut hasher = env
             .crypto
             .hash_initialize(DPE_PROFILE.alg_len())
             .map_err(|_| DpeErrorCode::HashError)?;
         hasher
-            .update(&context.tci.tci_cumulative.0)
+            .update(&tci.tci_cumulative.0)
             .map_err(|_| DpeErrorCode::HashError)?;
         hasher
             .update(&measurement.0)
@@ -299,11 +659,11 @@ impl DpeInstance {
 
         let digest_bytes = digest.bytes();
 
-        if digest_bytes.len() != context.tci.tci_cumulative.0.len() {
+        if digest_bytes.len() != tci.tci_cumulative.0.len() {
             return Err(DpeErrorCode::InternalError);
         }
-        context.tci.tci_cumulative.0.copy_from_slice(digest_bytes);
-        context.tci.tci_current = *measurement;
+        tci.tci_cumulative.0.copy_from_slice(digest_bytes);
+        tci.tci_current = *measurement;
         Ok(())
     }
 
@@ -339,6 +699,56 @@ impl DpeInstance {
         &mut self,
         env: &mut DpeEnv<impl DpeTypes>,
         start_idx: usize,
+    ) -> Result<Digest, DpeErrorCode> {
+        self.compute_measurement_hash_internal(env, None, start_idx)
+    }
+
+    /// Derive the CDI for a context from its measurement hash.
+    ///
+    /// Simulation contexts are rooted in the crypto layer's simulation secret instead of the real
+    /// CDI.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - index of the context to derive the CDI for
+    pub(crate) fn derive_cdi<'a, T: DpeTypes + 'a>(
+        &mut self,
+        env: &mut DpeEnv<'a, T>,
+        idx: usize,
+    ) -> Result<<T::Crypto<'a> as Crypto>::Cdi, DpeErrorCode> {
+        let digest = self.compute_measurement_hash(env, idx)?;
+        let cdi = if self.contexts[idx].context_type == ContextType::Simulation {
+            env.crypto
+                .derive_simulation_cdi(DPE_PROFILE.alg_len(), &digest, b"DPE")
+        } else {
+            env.crypto
+                .derive_cdi(DPE_PROFILE.alg_len(), &digest, b"DPE")
+        };
+        cdi.map_err(|_| DpeErrorCode::CryptoError)
+    }
+
+    /// Compute measurement hash for a node that is not in the context table.
+    ///
+    /// The hash is the same as it would be if `leaf` were a child of the context at `parent_idx`.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf` - the node to measure, which is not in the context table
+    /// * `parent_idx` - index of the context to treat as the parent of `leaf`
+    pub(crate) fn compute_detached_measurement_hash(
+        &mut self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        leaf: &Context,
+        parent_idx: usize,
+    ) -> Result<Digest, DpeErrorCode> {
+        self.compute_measurement_hash_internal(env, Some(leaf), parent_idx)
+    }
+
+    fn compute_measurement_hash_internal(
+        &mut self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        leaf: Option<&Context>,
+        start_idx: usize,
     ) -> Result<Digest, DpeErrorCode> {
         let mut hasher = env
             .crypto
@@ -348,6 +758,14 @@ impl DpeInstance {
         let mut uses_internal_input_info = false;
         let mut uses_internal_input_dice = false;
 
+        if let Some(leaf) = leaf {
+            hasher
+                .update(leaf.tci.as_bytes())
+                .map_err(|_| DpeErrorCode::HashError)?;
+            uses_internal_input_info = leaf.uses_internal_input_info();
+            uses_internal_input_dice = leaf.uses_internal_input_dice();
+        }
+
         // Hash each node.
         for status in ChildToRootIter::new(start_idx, &self.contexts) {
             let context = status?;
@@ -391,52 +809,84 @@ impl DpeInstance {
     }
 }
 
-/// Iterate over all of the bits set to 1 in a u32. Each iteration returns the bit index 0 being the
-/// least significant.
-///
-/// # Arguments
-///
-/// * `flags` - bits to be iterated over
-/// * `max` - number of bits to be considered
-pub(crate) fn flags_iter(flags: u32, max: usize) -> FlagsIter {
-    assert!((1..=u32::BITS).contains(&(max as u32)));
-    FlagsIter {
-        flags: flags & (u32::MAX >> (u32::BITS - max as u32)),
-    }
-}
-
-pub(crate) struct FlagsIter {
-    flags: u32,
-}
-
-impl Iterator for FlagsIter {
-    type Item = usize;
-
-    fn next(&mut self) -> Option<usize> {
-        if self.flags == 0 {
-            return None;
-        }
-        let idx = self.flags.trailing_zeros() as usize;
-        self.flags &= !(1 << idx);
-        Some(idx)
-    }
-}
-
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::commands::{DeriveChildCmd, DeriveChildFlags};
+    use crate::commands::{
+        BatchCmd, BatchEntryFlags, BatchEntryHdr, BatchFlags, DeriveChildCmd, DeriveChildFlags,
+        RotateCtxCmd, RotateCtxFlags,
+    };
+    use crate::context::ActiveContextArgs;
     use crate::response::NewHandleResp;
     use crate::support::test::SUPPORT;
     use crate::{commands::CommandHdr, CURRENT_PROFILE_MAJOR_VERSION};
     use crypto::OpensslCrypto;
     use platform::default::{DefaultPlatform, AUTO_INIT_LOCALITY, TEST_CERT_CHAIN};
+    use platform::PlatformError;
     use zerocopy::AsBytes;
 
     pub struct TestTypes;
     impl DpeTypes for TestTypes {
         type Crypto<'a> = OpensslCrypto;
         type Platform<'a> = DefaultPlatform;
+        type EventSink<'a> = ();
+    }
+
+    /// The default platform, except that only the boot locality may hand contexts to other
+    /// localities, with either RotateContextHandle or DeriveChild.
+    pub struct RestrictedPlatform(pub DefaultPlatform);
+
+    impl Platform for RestrictedPlatform {
+        fn get_certificate_chain(
+            &mut self,
+            offset: u32,
+            size: u32,
+            out: &mut [u8; MAX_CHUNK_SIZE],
+        ) -> Result<u32, PlatformError> {
+            self.0.get_certificate_chain(offset, size, out)
+        }
+
+        fn get_issuer_name(
+            &mut self,
+            out: &mut [u8; MAX_CHUNK_SIZE],
+        ) -> Result<usize, PlatformError> {
+            self.0.get_issuer_name(out)
+        }
+
+        fn get_vendor_id(&mut self) -> Result<u32, PlatformError> {
+            self.0.get_vendor_id()
+        }
+
+        fn get_vendor_sku(&mut self) -> Result<u32, PlatformError> {
+            self.0.get_vendor_sku()
+        }
+
+        fn get_auto_init_locality(&mut self) -> Result<u32, PlatformError> {
+            self.0.get_auto_init_locality()
+        }
+
+        fn can_transfer_locality(&mut self, from: u32, _to: u32) -> Result<bool, PlatformError> {
+            Ok(from == AUTO_INIT_LOCALITY)
+        }
+
+        fn can_execute_command(
+            &mut self,
+            locality: u32,
+            command_id: u32,
+            flags: u32,
+        ) -> Result<bool, PlatformError> {
+            Ok(locality == AUTO_INIT_LOCALITY
+                || command_id != Command::DERIVE_CHILD
+                || !DeriveChildFlags::from_bits_retain(flags)
+                    .contains(DeriveChildFlags::CHANGE_LOCALITY))
+        }
+    }
+
+    pub struct RestrictedTypes;
+    impl DpeTypes for RestrictedTypes {
+        type Crypto<'a> = OpensslCrypto;
+        type Platform<'a> = RestrictedPlatform;
+        type EventSink<'a> = ();
     }
 
     pub const TEST_HANDLE: ContextHandle =
@@ -450,7 +900,8 @@ pub mod tests {
     fn test_execute_serialized_command() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
 
@@ -488,7 +939,8 @@ pub mod tests {
     fn test_get_profile() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
         let profile = dpe.get_profile(&mut env.platform).unwrap();
@@ -496,11 +948,267 @@ pub mod tests {
         assert_eq!(profile.flags, SUPPORT.bits());
     }
 
+    const LOCALITY_QUOTA: u32 = 8;
+
+    /// The default platform, but with every locality other than the boot locality limited to
+    /// `LOCALITY_QUOTA` contexts.
+    struct QuotaPlatform(DefaultPlatform);
+
+    impl Platform for QuotaPlatform {
+        fn get_certificate_chain(
+            &mut self,
+            offset: u32,
+            size: u32,
+            out: &mut [u8; MAX_CHUNK_SIZE],
+        ) -> Result<u32, PlatformError> {
+            self.0.get_certificate_chain(offset, size, out)
+        }
+
+        fn get_issuer_name(
+            &mut self,
+            out: &mut [u8; MAX_CHUNK_SIZE],
+        ) -> Result<usize, PlatformError> {
+            self.0.get_issuer_name(out)
+        }
+
+        fn get_vendor_id(&mut self) -> Result<u32, PlatformError> {
+            self.0.get_vendor_id()
+        }
+
+        fn get_vendor_sku(&mut self) -> Result<u32, PlatformError> {
+            self.0.get_vendor_sku()
+        }
+
+        fn get_auto_init_locality(&mut self) -> Result<u32, PlatformError> {
+            self.0.get_auto_init_locality()
+        }
+
+        fn get_locality_quota(&mut self, locality: u32) -> Result<u32, PlatformError> {
+            if locality == AUTO_INIT_LOCALITY {
+                Ok(u32::MAX)
+            } else {
+                Ok(LOCALITY_QUOTA)
+            }
+        }
+    }
+
+    struct QuotaTypes;
+    impl DpeTypes for QuotaTypes {
+        type Crypto<'a> = OpensslCrypto;
+        type Platform<'a> = QuotaPlatform;
+        type EventSink<'a> = ();
+    }
+
+    #[test]
+    fn test_locality_quota() {
+        let mut env = DpeEnv::<QuotaTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: QuotaPlatform(DefaultPlatform::new()),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+
+        // Fill up the other locality's quota. Every new simulation context gets the same handle
+        // from the deterministic RNG, so give each one a unique handle.
+        for i in 0..LOCALITY_QUOTA as u8 {
+            let idx = dpe.get_next_inactive_context_pos().unwrap();
+            InitCtxCmd::new_simulation()
+                .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+                .unwrap();
+            dpe.contexts[idx].handle = ContextHandle([i; ContextHandle::SIZE]);
+        }
+        assert_eq!(
+            Err(DpeErrorCode::LocalityQuotaExceeded),
+            InitCtxCmd::new_simulation().execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        // Other localities can't push contexts past the quota either.
+        assert_eq!(
+            Err(DpeErrorCode::LocalityQuotaExceeded),
+            DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [0; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::CHANGE_LOCALITY,
+                tci_type: 0,
+                target_locality: TEST_LOCALITIES[1],
+            }
+            .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        );
+
+        let quota = match dpe
+            .execute_serialized_command(
+                &mut env,
+                TEST_LOCALITIES[1],
+                CommandHdr::new_for_test(Command::GET_LOCALITY_QUOTA).as_bytes(),
+            )
+            .unwrap()
+        {
+            Response::GetLocalityQuota(quota) => quota,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(TEST_LOCALITIES[1], quota.locality);
+        assert_eq!(LOCALITY_QUOTA, quota.max_contexts);
+        assert_eq!(LOCALITY_QUOTA, quota.used_contexts);
+
+        // The boot locality isn't limited by the quota.
+        let quota = dpe
+            .get_locality_quota(&mut env.platform, TEST_LOCALITIES[0])
+            .unwrap();
+        assert_eq!(MAX_HANDLES as u32, quota.max_contexts);
+        assert_eq!(1, quota.used_contexts);
+    }
+
+    #[test]
+    fn test_no_locality_quota() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+
+        // The default platform lets any locality fill the rest of the table.
+        for i in 1..MAX_HANDLES as u8 {
+            let idx = dpe.get_next_inactive_context_pos().unwrap();
+            InitCtxCmd::new_simulation()
+                .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+                .unwrap();
+            dpe.contexts[idx].handle = ContextHandle([i; ContextHandle::SIZE]);
+        }
+        assert_eq!(
+            Err(DpeErrorCode::MaxTcis),
+            InitCtxCmd::new_simulation().execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+        );
+
+        let quota = dpe
+            .get_locality_quota(&mut env.platform, TEST_LOCALITIES[1])
+            .unwrap();
+        assert_eq!(MAX_HANDLES as u32, quota.max_contexts);
+        assert_eq!(MAX_HANDLES as u32 - 1, quota.used_contexts);
+    }
+
+    #[test]
+    fn test_access_denied() {
+        let mut env = DpeEnv::<RestrictedTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: RestrictedPlatform(DefaultPlatform::new()),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        let derive_child = |flags, target_locality| {
+            let mut command = CommandHdr::new_for_test(Command::DERIVE_CHILD)
+                .as_bytes()
+                .to_vec();
+            command.extend(
+                DeriveChildCmd {
+                    handle: ContextHandle::default(),
+                    data: [0; DPE_PROFILE.get_hash_size()],
+                    flags: DeriveChildFlags::MAKE_DEFAULT | flags,
+                    tci_type: 0,
+                    target_locality,
+                }
+                .as_bytes(),
+            );
+            command
+        };
+
+        // The boot locality may hand its default context to another locality.
+        assert!(matches!(
+            dpe.execute_serialized_command(
+                &mut env,
+                TEST_LOCALITIES[0],
+                &derive_child(DeriveChildFlags::CHANGE_LOCALITY, TEST_LOCALITIES[1]),
+            ),
+            Ok(Response::DeriveChild(_))
+        ));
+
+        // The platform doesn't let the other locality do the same.
+        let before = dpe.as_bytes().to_vec();
+        assert_eq!(
+            Ok(Response::Error(ResponseHdr::new(
+                DpeErrorCode::AccessDenied
+            ))),
+            dpe.execute_serialized_command(
+                &mut env,
+                TEST_LOCALITIES[1],
+                &derive_child(DeriveChildFlags::CHANGE_LOCALITY, TEST_LOCALITIES[0]),
+            )
+        );
+        assert_eq!(before, dpe.as_bytes());
+
+        // It can still use DeriveChild within its own locality.
+        assert!(matches!(
+            dpe.execute_serialized_command(
+                &mut env,
+                TEST_LOCALITIES[1],
+                &derive_child(DeriveChildFlags::empty(), TEST_LOCALITIES[1]),
+            ),
+            Ok(Response::DeriveChild(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_context_table() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        assert_eq!(Err(DpeErrorCode::InvalidCommand), dpe.get_context_table());
+
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::CONTEXT_TABLE).unwrap();
+        DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [0; DPE_PROFILE.get_hash_size()],
+            flags: DeriveChildFlags::MAKE_DEFAULT | DeriveChildFlags::INPUT_ALLOW_X509,
+            tci_type: 7,
+            target_locality: 0,
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        dpe.contexts[1].tag = 0x1234;
+        dpe.contexts[1].has_tag = true.into();
+
+        let table = match dpe
+            .execute_serialized_command(
+                &mut env,
+                TEST_LOCALITIES[1],
+                CommandHdr::new_for_test(Command::GET_CONTEXT_TABLE).as_bytes(),
+            )
+            .unwrap()
+        {
+            Response::GetContextTable(table) => table,
+            _ => panic!("Incorrect response type"),
+        };
+        assert_eq!(MAX_HANDLES as u32, table.num_contexts);
+
+        let root = &table.contexts[0];
+        assert_eq!(TEST_LOCALITIES[0], root.locality);
+        assert_eq!(ContextState::Retired.as_bytes()[0], root.state);
+        assert_eq!(Context::ROOT_INDEX, root.parent_idx);
+        assert_eq!(ContextBitmap::from_idx(1), root.children);
+
+        let child = &table.contexts[1];
+        assert_eq!(ContextState::Active.as_bytes()[0], child.state);
+        assert_eq!(0, child.parent_idx);
+        assert_eq!(0x1234, child.tag);
+        assert_eq!(
+            ContextInfoFlags::HAS_TAG | ContextInfoFlags::ALLOW_X509,
+            child.flags
+        );
+
+        assert!(table.contexts[2..]
+            .iter()
+            .all(|c| c.state == ContextState::Inactive.as_bytes()[0]));
+    }
+
     #[test]
     fn test_get_active_context_index() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::default()).unwrap();
         let expected_index = 7;
@@ -537,7 +1245,8 @@ pub mod tests {
     fn test_add_tci_measurement() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
 
         let mut dpe = DpeInstance::new(&mut env, Support::AUTO_INIT).unwrap();
@@ -550,12 +1259,19 @@ pub mod tests {
                 MAX_HANDLES,
                 &TciMeasurement::default(),
                 TEST_LOCALITIES[0],
+                TciEventType::Extend,
             )
         );
 
         let data = [1; DPE_PROFILE.get_hash_size()];
-        dpe.add_tci_measurement(&mut env, 0, &TciMeasurement(data), TEST_LOCALITIES[0])
-            .unwrap();
+        dpe.add_tci_measurement(
+            &mut env,
+            0,
+            &TciMeasurement(data),
+            TEST_LOCALITIES[0],
+            TciEventType::Extend,
+        )
+        .unwrap();
         let context = &dpe.contexts[0];
         assert_eq!(data, context.tci.tci_current.0);
 
@@ -569,8 +1285,14 @@ pub mod tests {
         assert_eq!(first_cumulative.bytes(), context.tci.tci_cumulative.0);
 
         let data = [2; DPE_PROFILE.get_hash_size()];
-        dpe.add_tci_measurement(&mut env, 0, &TciMeasurement(data), TEST_LOCALITIES[0])
-            .unwrap();
+        dpe.add_tci_measurement(
+            &mut env,
+            0,
+            &TciMeasurement(data),
+            TEST_LOCALITIES[0],
+            TciEventType::Extend,
+        )
+        .unwrap();
         // Make sure the current TCI was updated correctly.
         let context = &dpe.contexts[0];
         assert_eq!(data, context.tci.tci_current.0);
@@ -588,7 +1310,8 @@ pub mod tests {
     fn test_get_descendants() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, Support::default()).unwrap();
         let root = 7;
@@ -606,10 +1329,10 @@ pub mod tests {
 
         // No children.
         dpe.contexts[root].state = ContextState::Active;
-        assert_eq!(dpe.get_descendants(&dpe.contexts[root]).unwrap(), 0);
+        assert!(dpe.get_descendants(&dpe.contexts[root]).unwrap().is_empty());
 
         // Child not active.
-        dpe.contexts[root].children = 1 << child_1;
+        dpe.contexts[root].children = ContextBitmap::from_idx(child_1);
         assert_eq!(
             dpe.get_descendants(&dpe.contexts[root]),
             Err(DpeErrorCode::InvalidHandle)
@@ -624,13 +1347,15 @@ pub mod tests {
         dpe.contexts[child_1_1].state = ContextState::Active;
         dpe.contexts[child_1_2].state = ContextState::Active;
         dpe.contexts[child_1_3].state = ContextState::Active;
-        dpe.contexts[child_1].children = (1 << child_1_1) | (1 << child_1_2) | (1 << child_1_3);
+        dpe.contexts[child_1].children = ContextBitmap::from_idx(child_1_1)
+            | ContextBitmap::from_idx(child_1_2)
+            | ContextBitmap::from_idx(child_1_3);
         children |= dpe.contexts[child_1].children;
         assert_eq!(children, dpe.get_descendants(&dpe.contexts[root]).unwrap());
 
         // Add great-grandchildren.
         dpe.contexts[child_1_2_1].state = ContextState::Active;
-        dpe.contexts[child_1_2].children = 1 << child_1_2_1;
+        dpe.contexts[child_1_2].children = ContextBitmap::from_idx(child_1_2_1);
         children |= dpe.contexts[child_1_2].children;
         assert_eq!(
             dpe.contexts[child_1_2].children,
@@ -639,11 +1364,99 @@ pub mod tests {
         assert_eq!(children, dpe.get_descendants(&dpe.contexts[root]).unwrap());
     }
 
+    #[test]
+    fn test_validate() {
+        let mut env = DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        assert_eq!(Ok(()), dpe.validate());
+
+        // Retire the default context in favor of a new default child, then add a second child.
+        DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [0; DPE_PROFILE.get_hash_size()],
+            flags: DeriveChildFlags::MAKE_DEFAULT,
+            tci_type: 0,
+            target_locality: 0,
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [0; DPE_PROFILE.get_hash_size()],
+            flags: DeriveChildFlags::RETAIN_PARENT,
+            tci_type: 0,
+            target_locality: 0,
+        }
+        .execute(&mut dpe, &mut env, TEST_LOCALITIES[0])
+        .unwrap();
+        assert_eq!(ContextState::Retired, dpe.contexts[0].state);
+        assert_eq!(Ok(()), dpe.validate());
+
+        let broken: [&dyn Fn(&mut DpeInstance); 7] = [
+            // Child doesn't point back at its parent.
+            &|dpe| dpe.contexts[1].parent_idx = Context::ROOT_INDEX,
+            // Parent doesn't list its child.
+            &|dpe| dpe.contexts[1].children = ContextBitmap::empty(),
+            // Cycle.
+            &|dpe| {
+                dpe.contexts[0].parent_idx = 1;
+                dpe.contexts[1].children.insert(0);
+            },
+            // Retired without children.
+            &|dpe| dpe.contexts[2].state = ContextState::Retired,
+            // Inactive with children.
+            &|dpe| dpe.contexts[3].children = ContextBitmap::from_idx(2),
+            // Two default contexts in one locality.
+            &|dpe| dpe.contexts[2].handle = ContextHandle::default(),
+            // Duplicate non-default handles.
+            &|dpe| {
+                let handle = dpe.contexts[2].handle;
+                dpe.contexts[3].activate(&ActiveContextArgs {
+                    context_type: ContextType::Simulation,
+                    locality: TEST_LOCALITIES[1],
+                    handle: &handle,
+                    tci_type: 0,
+                    parent_idx: Context::ROOT_INDEX,
+                    allow_ca: false,
+                    allow_x509: false,
+                    allow_export: false,
+                })
+            },
+        ];
+        for breakage in broken {
+            let mut broken = DpeInstance::read_from(dpe.as_bytes()).unwrap();
+            breakage(&mut broken);
+            assert_eq!(Err(DpeErrorCode::InvalidInternalState), broken.validate());
+            assert_eq!(
+                Err(DpeErrorCode::InvalidInternalState),
+                broken.execute_command(&mut env, TEST_LOCALITIES[0], Command::GetProfile)
+            );
+        }
+
+        // Default contexts in different localities are fine.
+        dpe.contexts[3].activate(&ActiveContextArgs {
+            context_type: ContextType::Normal,
+            locality: TEST_LOCALITIES[1],
+            handle: &ContextHandle::default(),
+            tci_type: 0,
+            parent_idx: Context::ROOT_INDEX,
+            allow_ca: false,
+            allow_x509: false,
+            allow_export: false,
+        });
+        assert_eq!(Ok(()), dpe.validate());
+    }
+
     #[test]
     fn test_derive_cdi() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
 
@@ -698,7 +1511,8 @@ pub mod tests {
     fn test_hash_internal_input_info() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::INTERNAL_INFO).unwrap();
 
@@ -748,7 +1562,8 @@ pub mod tests {
     fn test_hash_internal_input_dice() {
         let mut env = DpeEnv::<TestTypes> {
             crypto: OpensslCrypto::new(),
-            platform: DefaultPlatform,
+            platform: DefaultPlatform::new(),
+            event_sink: (),
         };
         let mut dpe = DpeInstance::new(&mut env, SUPPORT | Support::INTERNAL_DICE).unwrap();
 
@@ -789,4 +1604,215 @@ pub mod tests {
             .unwrap();
         assert_eq!(answer, cdi_with_internal_input_dice)
     }
+
+    // Every command except Batch, which keeps the entries that succeeded unless it is asked to
+    // run all-or-nothing.
+    const ATOMIC_COMMANDS: [u32; 23] = [
+        Command::GET_PROFILE,
+        Command::INITIALIZE_CONTEXT,
+        Command::DERIVE_CHILD,
+        Command::CERTIFY_KEY,
+        Command::SIGN,
+        Command::ROTATE_CONTEXT_HANDLE,
+        Command::DESTROY_CONTEXT,
+        Command::GET_CERTIFICATE_CHAIN,
+        Command::EXTEND_TCI,
+        Command::TAG_TCI,
+        Command::GET_TAGGED_TCI,
+        Command::SEAL,
+        Command::UNSEAL,
+        Command::SIGN_MESSAGE,
+        Command::SIGN_STREAM_INIT,
+        Command::SIGN_STREAM_UPDATE,
+        Command::SIGN_STREAM_FINAL,
+        Command::GET_TAGGED_TCI_INFO,
+        Command::GET_CONTEXT_TABLE,
+        Command::GET_LOCALITY_QUOTA,
+        Command::GET_EVENT_LOG,
+        Command::QUOTE,
+        Command::GET_EAT,
+    ];
+
+    fn serialize_command(id: u32, cmd: &[u8]) -> Vec<u8> {
+        let mut command = CommandHdr::new_for_test(id).as_bytes().to_vec();
+        command.extend_from_slice(cmd);
+        command
+    }
+
+    fn arb_handle() -> impl proptest::strategy::Strategy<Value = ContextHandle> {
+        use proptest::prelude::*;
+
+        prop_oneof![
+            Just(ContextHandle::default()),
+            Just(SIMULATION_HANDLE),
+            any::<[u8; ContextHandle::SIZE]>().prop_map(ContextHandle),
+        ]
+    }
+
+    /// Serializes a command whose body starts with one of the handles in the table, so that
+    /// random input gets past handle validation and reaches the deeper error paths.
+    fn arb_random_command() -> impl proptest::strategy::Strategy<Value = Vec<u8>> {
+        use proptest::prelude::*;
+
+        (
+            proptest::sample::select(&ATOMIC_COMMANDS[..]),
+            arb_handle(),
+            proptest::collection::vec(any::<u8>(), 0..1200),
+        )
+            .prop_map(|(id, handle, body)| {
+                let mut command = serialize_command(id, handle.as_bytes());
+                command.extend_from_slice(&body);
+                command
+            })
+    }
+
+    /// Serializes a DeriveChild that recurses, exports a CDI or moves the child to another
+    /// locality. Random flags and TCI types almost never get those past argument checking.
+    fn arb_derive_child() -> impl proptest::strategy::Strategy<Value = Vec<u8>> {
+        use proptest::prelude::*;
+
+        let flags = [
+            DeriveChildFlags::RECURSIVE,
+            DeriveChildFlags::EXPORT_CDI,
+            DeriveChildFlags::EXPORT_CDI | DeriveChildFlags::INPUT_ALLOW_CA,
+            DeriveChildFlags::EXPORT_CDI | DeriveChildFlags::RECURSIVE,
+            DeriveChildFlags::INPUT_ALLOW_EXPORT | DeriveChildFlags::MAKE_DEFAULT,
+            DeriveChildFlags::CHANGE_LOCALITY,
+            DeriveChildFlags::CHANGE_LOCALITY | DeriveChildFlags::MAKE_DEFAULT,
+            DeriveChildFlags::CHANGE_LOCALITY | DeriveChildFlags::RETAIN_PARENT,
+        ];
+        (
+            arb_handle(),
+            any::<u8>(),
+            proptest::sample::select(flags.iter().map(|f| f.bits()).collect::<Vec<_>>()),
+            0u32..2,
+            proptest::sample::select(&TEST_LOCALITIES[..]),
+        )
+            .prop_map(|(handle, data, flags, tci_type, target_locality)| {
+                let cmd = DeriveChildCmd {
+                    handle,
+                    data: [data; DPE_PROFILE.get_hash_size()],
+                    flags: DeriveChildFlags::from_bits_retain(flags),
+                    tci_type,
+                    target_locality,
+                };
+                serialize_command(Command::DERIVE_CHILD, cmd.as_bytes())
+            })
+    }
+
+    /// Serializes a RotateCtx, possibly moving the context to another locality.
+    fn arb_rotate_context() -> impl proptest::strategy::Strategy<Value = Vec<u8>> {
+        use proptest::prelude::*;
+
+        let flags = [
+            RotateCtxFlags::empty(),
+            RotateCtxFlags::TARGET_IS_DEFAULT,
+            RotateCtxFlags::CHANGE_LOCALITY,
+            RotateCtxFlags::CHANGE_LOCALITY | RotateCtxFlags::TARGET_IS_DEFAULT,
+        ];
+        (
+            arb_handle(),
+            proptest::sample::select(flags.iter().map(|f| f.bits()).collect::<Vec<_>>()),
+            proptest::sample::select(&TEST_LOCALITIES[..]),
+        )
+            .prop_map(|(handle, flags, target_locality)| {
+                let cmd = RotateCtxCmd {
+                    handle,
+                    flags: RotateCtxFlags::from_bits_retain(flags),
+                    target_locality,
+                };
+                serialize_command(Command::ROTATE_CONTEXT_HANDLE, cmd.as_bytes())
+            })
+    }
+
+    fn arb_atomic_command() -> impl proptest::strategy::Strategy<Value = Vec<u8>> {
+        use proptest::prelude::*;
+
+        prop_oneof![
+            arb_random_command(),
+            arb_derive_child(),
+            arb_rotate_context()
+        ]
+    }
+
+    /// Serializes a Batch of a few DeriveChild and RotateCtx commands, some of which take the
+    /// handle returned by an earlier entry.
+    fn arb_batch() -> impl proptest::strategy::Strategy<Value = (Vec<u8>, bool)> {
+        use proptest::prelude::*;
+
+        let entry = (
+            prop_oneof![arb_derive_child(), arb_rotate_context()],
+            proptest::sample::select(vec![BatchEntryHdr::NO_HANDLE_REF, 0, 1]),
+            any::<bool>(),
+        );
+        (any::<bool>(), proptest::collection::vec(entry, 1..4)).prop_map(
+            |(all_or_nothing, entries)| {
+                let mut batch = BatchCmd::new_zeroed();
+                batch.flags.set(BatchFlags::ALL_OR_NOTHING, all_or_nothing);
+                for (command, handle_ref, use_parent_handle) in entries {
+                    let mut flags = BatchEntryFlags::empty();
+                    flags.set(BatchEntryFlags::USE_PARENT_HANDLE, use_parent_handle);
+                    let entry = BatchEntryHdr {
+                        cmd_size: command.len() as u32,
+                        handle_ref,
+                        flags,
+                    };
+                    let offset = batch.data_size as usize;
+                    let end = offset + entry.as_bytes().len() + command.len();
+                    batch.data[offset..end]
+                        .copy_from_slice(&[entry.as_bytes(), &command[..]].concat());
+                    batch.data_size = end as u32;
+                    batch.num_commands += 1;
+                }
+                (
+                    serialize_command(Command::BATCH, batch.as_bytes()),
+                    all_or_nothing,
+                )
+            },
+        )
+    }
+
+    /// Serializes a command, along with whether failing must leave the state untouched.
+    fn arb_command() -> impl proptest::strategy::Strategy<Value = (Vec<u8>, bool)> {
+        use proptest::prelude::*;
+
+        prop_oneof![
+            arb_atomic_command().prop_map(|command| (command, true)),
+            arb_batch()
+        ]
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::test_runner::Config::with_cases(64))]
+
+        #[test]
+        fn test_failed_command_leaves_state_unchanged(
+            commands in proptest::collection::vec(
+                (arb_command(), proptest::sample::select(&TEST_LOCALITIES[..])),
+                1..8,
+            )
+        ) {
+            let mut env = DpeEnv::<TestTypes> {
+                crypto: OpensslCrypto::new(),
+                platform: DefaultPlatform::new(),
+                event_sink: (),
+            };
+            let mut dpe = DpeInstance::new(&mut env, Support::all()).unwrap();
+            InitCtxCmd::new_simulation()
+                .execute(&mut dpe, &mut env, TEST_LOCALITIES[1])
+                .unwrap();
+
+            for ((command, atomic), locality) in commands {
+                let before = dpe.as_bytes().to_vec();
+                let failed = match dpe.execute_serialized_command(&mut env, locality, &command) {
+                    Ok(response) => response.status() != DpeErrorCode::NoError as u32,
+                    Err(_) => true,
+                };
+                if failed && atomic {
+                    proptest::prop_assert_eq!(&before[..], dpe.as_bytes());
+                }
+                proptest::prop_assert_eq!(Ok(()), dpe.validate());
+            }
+        }
+    }
 }
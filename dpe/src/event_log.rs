@@ -0,0 +1,386 @@
+// Licensed under the Apache-2.0 license.
+
+//! Bounded log of every measurement folded into a TCI.
+//!
+//! Each event is serialized as a TCG Canonical Event Log (CEL) TLV record so verifiers can replay
+//! the log and recompute every `tci_cumulative`. A record holds:
+//!
+//! * `recnum` - the event's sequence number.
+//! * `pcr` - the index of the context slot that was measured. DPE has no PCRs, so the slot index
+//!   stands in for one.
+//! * `digests` - the measurement, tagged with the profile's TPM_ALG_ID.
+//! * A DPE content TLV with the event type, `tci_type` and locality of the context.
+//!
+//! Every TLV has a one byte type, a big-endian u32 length and then the value.
+//!
+//! When the instance supports the event log, a measurement fails with `EventLogFull` once the log
+//! is full, so every measurement behind a TCI stays in the log. Otherwise the log only feeds the
+//! event sink and the oldest events are overwritten.
+
+use crate::{response::DpeErrorCode, tci::TciMeasurement, DpeProfile, DPE_PROFILE, MAX_TCI_EVENTS};
+use zerocopy::{AsBytes, FromBytes};
+
+const CEL_TYPE_RECNUM: u8 = 0;
+const CEL_TYPE_PCR: u8 = 1;
+const CEL_TYPE_DIGESTS: u8 = 3;
+/// CEL content type for DPE events. CEL leaves types past the ones it defines for other content.
+pub const CEL_TYPE_DPE_CONTENT: u8 = 0x80;
+
+const DPE_CONTENT_TYPE_EVENT_TYPE: u8 = 0;
+const DPE_CONTENT_TYPE_TCI_TYPE: u8 = 1;
+const DPE_CONTENT_TYPE_LOCALITY: u8 = 2;
+
+const TLV_HDR_SIZE: usize = 5;
+const U32_TLV_SIZE: usize = TLV_HDR_SIZE + 4;
+const DIGEST_TLV_SIZE: usize = TLV_HDR_SIZE + DPE_PROFILE.get_hash_size();
+const DPE_CONTENT_SIZE: usize = 3 * U32_TLV_SIZE;
+
+/// Size of one serialized event. Every field has a fixed size, so all records are the same length.
+pub const EVENT_RECORD_SIZE: usize =
+    2 * U32_TLV_SIZE + TLV_HDR_SIZE + DIGEST_TLV_SIZE + TLV_HDR_SIZE + DPE_CONTENT_SIZE;
+
+/// TPM_ALG_ID of the profile's hash algorithm.
+const fn tpm_alg_id(profile: DpeProfile) -> u8 {
+    match profile {
+        DpeProfile::P256Sha256 => 0x0B,
+        DpeProfile::P384Sha384 => 0x0C,
+    }
+}
+
+#[repr(u32)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TciEventType {
+    /// A new context was derived with this measurement, so its cumulative TCI starts from zero.
+    Derive = 1,
+    /// An existing context was extended, either with ExtendTci or a recursive DeriveChild.
+    Extend = 2,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AsBytes, FromBytes)]
+pub(crate) struct TciEvent {
+    pub recnum: u32,
+    pub context_idx: u32,
+    pub event_type: u32,
+    pub tci_type: u32,
+    pub locality: u32,
+    pub digest: TciMeasurement,
+}
+
+impl TciEvent {
+    const fn new() -> TciEvent {
+        TciEvent {
+            recnum: 0,
+            context_idx: 0,
+            event_type: 0,
+            tci_type: 0,
+            locality: 0,
+            digest: TciMeasurement([0; DPE_PROFILE.get_tci_size()]),
+        }
+    }
+
+    fn serialize(&self, dst: &mut [u8; EVENT_RECORD_SIZE]) {
+        let mut writer = TlvWriter { dst, offset: 0 };
+        writer.write_u32(CEL_TYPE_RECNUM, self.recnum);
+        writer.write_u32(CEL_TYPE_PCR, self.context_idx);
+
+        writer.write_hdr(CEL_TYPE_DIGESTS, DIGEST_TLV_SIZE);
+        writer.write_hdr(tpm_alg_id(DPE_PROFILE), DPE_PROFILE.get_hash_size());
+        writer.write(&self.digest.0);
+
+        writer.write_hdr(CEL_TYPE_DPE_CONTENT, DPE_CONTENT_SIZE);
+        writer.write_u32(DPE_CONTENT_TYPE_EVENT_TYPE, self.event_type);
+        writer.write_u32(DPE_CONTENT_TYPE_TCI_TYPE, self.tci_type);
+        writer.write_u32(DPE_CONTENT_TYPE_LOCALITY, self.locality);
+    }
+}
+
+struct TlvWriter<'a> {
+    dst: &'a mut [u8; EVENT_RECORD_SIZE],
+    offset: usize,
+}
+
+impl TlvWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.dst[self.offset..self.offset + bytes.len()].copy_from_slice(bytes);
+        self.offset += bytes.len();
+    }
+
+    fn write_hdr(&mut self, tlv_type: u8, len: usize) {
+        self.write(&[tlv_type]);
+        self.write(&(len as u32).to_be_bytes());
+    }
+
+    fn write_u32(&mut self, tlv_type: u8, val: u32) {
+        self.write_hdr(tlv_type, 4);
+        self.write(&val.to_be_bytes());
+    }
+}
+
+#[repr(C)]
+#[derive(AsBytes, FromBytes)]
+pub(crate) struct EventLog {
+    events: [TciEvent; MAX_TCI_EVENTS],
+    /// Number of events ever recorded, including ones that have since been overwritten.
+    count: u32,
+}
+
+impl EventLog {
+    pub const fn new() -> EventLog {
+        const EVENT_INITIALIZER: TciEvent = TciEvent::new();
+        EventLog {
+            events: [EVENT_INITIALIZER; MAX_TCI_EVENTS],
+            count: 0,
+        }
+    }
+
+    /// Records a measurement of the context in slot `context_idx`.
+    ///
+    /// Fails with `EventLogFull` if the log is full, unless `overwrite` is set, in which case the
+    /// oldest event is dropped. Nothing is recorded if it fails.
+    pub fn record(
+        &mut self,
+        overwrite: bool,
+        context_idx: usize,
+        event_type: TciEventType,
+        tci_type: u32,
+        locality: u32,
+        digest: &TciMeasurement,
+    ) -> Result<(), DpeErrorCode> {
+        if !overwrite && self.len() == MAX_TCI_EVENTS {
+            return Err(DpeErrorCode::EventLogFull);
+        }
+        // Record numbers are never reused.
+        let count = self
+            .count
+            .checked_add(1)
+            .ok_or(DpeErrorCode::EventLogFull)?;
+        self.events[self.count as usize % MAX_TCI_EVENTS] = TciEvent {
+            recnum: self.count,
+            context_idx: context_idx as u32,
+            event_type: event_type as u32,
+            tci_type,
+            locality,
+            digest: *digest,
+        };
+        self.count = count;
+        Ok(())
+    }
+
+    /// Number of events ever recorded.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Events recorded after the first `count`, oldest first. Ones that have been overwritten
+    /// since are skipped.
+    pub fn events_since(&self, count: u32) -> impl Iterator<Item = &TciEvent> {
+        let first = count.max(self.first_recnum());
+        (first..self.count).map(|i| &self.events[i as usize % MAX_TCI_EVENTS])
+    }
+
+    /// `recnum` of the oldest event still held in the log.
+    pub fn first_recnum(&self) -> u32 {
+        self.count.saturating_sub(MAX_TCI_EVENTS as u32)
+    }
+
+    /// Number of events still held in the log.
+    fn len(&self) -> usize {
+        (self.count - self.first_recnum()) as usize
+    }
+
+    /// Size in bytes of the serialized log.
+    pub fn size(&self) -> usize {
+        self.len() * EVENT_RECORD_SIZE
+    }
+
+    /// Copies the serialized log, oldest event first, starting at `offset` into `dst`. Returns
+    /// the number of bytes copied, which is less than `dst.len()` at the end of the log.
+    pub fn read(&self, offset: usize, dst: &mut [u8]) -> usize {
+        let end = self.size().min(offset.saturating_add(dst.len()));
+        let first = self.first_recnum() as usize;
+        let mut record = [0; EVENT_RECORD_SIZE];
+        let mut pos = offset;
+        while pos < end {
+            let i = pos / EVENT_RECORD_SIZE;
+            self.events[(first + i) % MAX_TCI_EVENTS].serialize(&mut record);
+            let start = pos - i * EVENT_RECORD_SIZE;
+            let len = (EVENT_RECORD_SIZE - start).min(end - pos);
+            dst[pos - offset..pos - offset + len].copy_from_slice(&record[start..start + len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub struct ParsedEvent {
+        pub recnum: u32,
+        pub context_idx: usize,
+        pub event_type: u32,
+        pub tci_type: u32,
+        pub locality: u32,
+        pub digest: Vec<u8>,
+    }
+
+    fn parse_tlv(bytes: &[u8]) -> (u8, &[u8], &[u8]) {
+        let len = u32::from_be_bytes(bytes[1..TLV_HDR_SIZE].try_into().unwrap()) as usize;
+        (
+            bytes[0],
+            &bytes[TLV_HDR_SIZE..TLV_HDR_SIZE + len],
+            &bytes[TLV_HDR_SIZE + len..],
+        )
+    }
+
+    fn parse_u32_tlv(bytes: &[u8], expected_type: u8) -> (u32, &[u8]) {
+        let (tlv_type, value, rest) = parse_tlv(bytes);
+        assert_eq!(expected_type, tlv_type);
+        (u32::from_be_bytes(value.try_into().unwrap()), rest)
+    }
+
+    /// Parses a serialized log back into its events.
+    pub fn parse_log(mut log: &[u8]) -> Vec<ParsedEvent> {
+        let mut events = vec![];
+        while !log.is_empty() {
+            let (recnum, rest) = parse_u32_tlv(log, CEL_TYPE_RECNUM);
+            let (context_idx, rest) = parse_u32_tlv(rest, CEL_TYPE_PCR);
+
+            let (tlv_type, digests, rest) = parse_tlv(rest);
+            assert_eq!(CEL_TYPE_DIGESTS, tlv_type);
+            let (alg_id, digest, _) = parse_tlv(digests);
+            assert_eq!(tpm_alg_id(DPE_PROFILE), alg_id);
+
+            let (tlv_type, content, rest) = parse_tlv(rest);
+            assert_eq!(CEL_TYPE_DPE_CONTENT, tlv_type);
+            let (event_type, content) = parse_u32_tlv(content, DPE_CONTENT_TYPE_EVENT_TYPE);
+            let (tci_type, content) = parse_u32_tlv(content, DPE_CONTENT_TYPE_TCI_TYPE);
+            let (locality, _) = parse_u32_tlv(content, DPE_CONTENT_TYPE_LOCALITY);
+
+            events.push(ParsedEvent {
+                recnum,
+                context_idx: context_idx as usize,
+                event_type,
+                tci_type,
+                locality,
+                digest: digest.to_vec(),
+            });
+            log = rest;
+        }
+        events
+    }
+
+    fn read_all(log: &EventLog) -> Vec<u8> {
+        let mut out = vec![0; log.size()];
+        assert_eq!(log.size(), log.read(0, &mut out));
+        out
+    }
+
+    #[test]
+    fn test_record_and_read() {
+        let mut log = EventLog::new();
+        assert_eq!(0, log.size());
+        assert_eq!(0, log.read(0, &mut [0; 16]));
+
+        log.record(
+            false,
+            3,
+            TciEventType::Derive,
+            0x1234,
+            7,
+            &TciMeasurement([0xaa; DPE_PROFILE.get_tci_size()]),
+        )
+        .unwrap();
+        log.record(
+            false,
+            3,
+            TciEventType::Extend,
+            0x1234,
+            7,
+            &TciMeasurement([0xbb; DPE_PROFILE.get_tci_size()]),
+        )
+        .unwrap();
+        assert_eq!(2 * EVENT_RECORD_SIZE, log.size());
+
+        let serialized = read_all(&log);
+        let events = parse_log(&serialized);
+        assert_eq!(2, events.len());
+        assert_eq!(0, events[0].recnum);
+        assert_eq!(3, events[0].context_idx);
+        assert_eq!(TciEventType::Derive as u32, events[0].event_type);
+        assert_eq!(0x1234, events[0].tci_type);
+        assert_eq!(7, events[0].locality);
+        assert_eq!(vec![0xaa; DPE_PROFILE.get_tci_size()], events[0].digest);
+        assert_eq!(1, events[1].recnum);
+        assert_eq!(TciEventType::Extend as u32, events[1].event_type);
+
+        // Reading in odd-sized chunks gives the same bytes.
+        let mut chunked = vec![];
+        let mut chunk = [0; 37];
+        loop {
+            let len = log.read(chunked.len(), &mut chunk);
+            if len == 0 {
+                break;
+            }
+            chunked.extend_from_slice(&chunk[..len]);
+        }
+        assert_eq!(serialized, chunked);
+    }
+
+    fn record_n(log: &mut EventLog, overwrite: bool, n: usize) -> Result<(), DpeErrorCode> {
+        for i in 0..n {
+            log.record(
+                overwrite,
+                i % 4,
+                TciEventType::Extend,
+                0,
+                0,
+                &TciMeasurement([i as u8; DPE_PROFILE.get_tci_size()]),
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_log_rejects_events() {
+        let mut log = EventLog::new();
+        record_n(&mut log, false, MAX_TCI_EVENTS).unwrap();
+        let serialized = read_all(&log);
+        assert_eq!(
+            Err(DpeErrorCode::EventLogFull),
+            record_n(&mut log, false, 1)
+        );
+        assert_eq!(MAX_TCI_EVENTS as u32, log.count());
+        assert_eq!(0, log.first_recnum());
+        assert_eq!(serialized, read_all(&log));
+    }
+
+    #[test]
+    fn test_oldest_events_are_overwritten() {
+        let mut log = EventLog::new();
+        record_n(&mut log, true, MAX_TCI_EVENTS + 2).unwrap();
+        assert_eq!(MAX_TCI_EVENTS * EVENT_RECORD_SIZE, log.size());
+        assert_eq!(2, log.first_recnum());
+        assert_eq!(2, log.events_since(MAX_TCI_EVENTS as u32).count());
+        assert_eq!(MAX_TCI_EVENTS, log.events_since(0).count());
+
+        let events = parse_log(&read_all(&log));
+        assert_eq!(MAX_TCI_EVENTS, events.len());
+        for (i, event) in events.iter().enumerate() {
+            assert_eq!(i as u32 + 2, event.recnum);
+            assert_eq!(vec![i as u8 + 2; DPE_PROFILE.get_tci_size()], event.digest);
+        }
+    }
+
+    #[test]
+    fn test_recnum_does_not_wrap() {
+        let mut log = EventLog::new();
+        log.count = u32::MAX - 1;
+        record_n(&mut log, true, 1).unwrap();
+        assert_eq!(Err(DpeErrorCode::EventLogFull), record_n(&mut log, true, 1));
+        assert_eq!(u32::MAX, log.count());
+        assert_eq!(MAX_TCI_EVENTS * EVENT_RECORD_SIZE, read_all(&log).len());
+    }
+}
@@ -0,0 +1,286 @@
+// Licensed under the Apache-2.0 license.
+
+//! Hooks that report what a `DpeInstance` does.
+//!
+//! Every command executed through `DpeInstance::execute_serialized_command` reports the contexts
+//! it created and destroyed and the measurements it made, followed by its result. Commands that
+//! fail report only their result, as none of their changes are kept. The commands in a batch
+//! report their results as they run, but their changes are reported by the batch once it
+//! finishes. Events never include handles, CDIs or other secrets.
+
+use crate::{event_log::TciEventType, response::DpeErrorCode, tci::TciMeasurement};
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub enum DpeEvent {
+    /// A command finished executing.
+    CommandExecuted {
+        command_id: u32,
+        locality: u32,
+        status: DpeErrorCode,
+    },
+    /// A context was created in slot `context_idx`.
+    ContextCreated { context_idx: usize, locality: u32 },
+    /// The context in slot `context_idx` was destroyed.
+    ContextDestroyed { context_idx: usize, locality: u32 },
+    /// A measurement was folded into the TCI of the context in slot `context_idx`.
+    MeasurementExtended {
+        context_idx: usize,
+        event_type: TciEventType,
+        tci_type: u32,
+        locality: u32,
+        measurement: TciMeasurement,
+    },
+}
+
+pub trait EventSink {
+    fn record(&mut self, event: DpeEvent);
+}
+
+/// Discards every event.
+impl EventSink for () {
+    fn record(&mut self, _event: DpeEvent) {}
+}
+
+impl<S: EventSink + ?Sized> EventSink for &mut S {
+    fn record(&mut self, event: DpeEvent) {
+        (**self).record(event)
+    }
+}
+
+/// Keeps the first `N` events so tests can check them.
+pub struct RecordingSink<const N: usize> {
+    events: [Option<DpeEvent>; N],
+    len: usize,
+    /// Number of events that didn't fit.
+    pub dropped: usize,
+}
+
+impl<const N: usize> RecordingSink<N> {
+    pub const fn new() -> Self {
+        Self {
+            events: [None; N],
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// The recorded events, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &DpeEvent> {
+        self.events[..self.len].iter().flatten()
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.dropped = 0;
+    }
+}
+
+impl<const N: usize> Default for RecordingSink<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EventSink for RecordingSink<N> {
+    fn record(&mut self, event: DpeEvent) {
+        match self.events.get_mut(self.len) {
+            Some(slot) => {
+                *slot = Some(event);
+                self.len += 1;
+            }
+            None => self.dropped += 1,
+        }
+    }
+}
+
+/// Writes every event to the `log` crate at debug level.
+#[cfg(feature = "log")]
+pub struct LogSink;
+
+#[cfg(feature = "log")]
+impl EventSink for LogSink {
+    fn record(&mut self, event: DpeEvent) {
+        match event {
+            DpeEvent::CommandExecuted {
+                command_id,
+                locality,
+                status,
+            } => log::debug!(
+                "Locality {locality:#010x} executed command {command_id:#x}: {status:?}"
+            ),
+            DpeEvent::ContextCreated {
+                context_idx,
+                locality,
+            } => log::debug!("Created context {context_idx} in locality {locality:#010x}"),
+            DpeEvent::ContextDestroyed {
+                context_idx,
+                locality,
+            } => log::debug!("Destroyed context {context_idx} in locality {locality:#010x}"),
+            DpeEvent::MeasurementExtended {
+                context_idx,
+                event_type,
+                tci_type,
+                locality,
+                measurement,
+            } => log::debug!(
+                "{event_type:?} measurement of context {context_idx} in locality \
+                 {locality:#010x}, TCI type {tci_type:#x}: {:02x?}",
+                measurement.0
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{
+            Command, CommandHdr, DeriveChildCmd, DeriveChildFlags, DestroyCtxCmd, DestroyCtxFlags,
+        },
+        context::ContextHandle,
+        dpe_instance::{
+            tests::{TEST_HANDLE, TEST_LOCALITIES},
+            DpeEnv, DpeTypes,
+        },
+        support::test::SUPPORT,
+        DpeInstance, DPE_PROFILE,
+    };
+    use crypto::OpensslCrypto;
+    use platform::default::DefaultPlatform;
+    use zerocopy::AsBytes;
+
+    struct RecordingTypes;
+    impl DpeTypes for RecordingTypes {
+        type Crypto<'a> = OpensslCrypto;
+        type Platform<'a> = DefaultPlatform;
+        type EventSink<'a> = RecordingSink<8>;
+    }
+
+    fn command(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut command = CommandHdr::new_for_test(id).as_bytes().to_vec();
+        command.extend(body);
+        command
+    }
+
+    fn take_events(env: &mut DpeEnv<RecordingTypes>) -> Vec<DpeEvent> {
+        let events = env.event_sink.events().copied().collect();
+        env.event_sink.clear();
+        events
+    }
+
+    #[test]
+    fn test_command_events() {
+        let mut env = DpeEnv::<RecordingTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: RecordingSink::new(),
+        };
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        assert!(take_events(&mut env).is_empty());
+
+        let derive_child = DeriveChildCmd {
+            handle: ContextHandle::default(),
+            data: [1; DPE_PROFILE.get_hash_size()],
+            flags: DeriveChildFlags::MAKE_DEFAULT,
+            tci_type: 7,
+            target_locality: 0,
+        };
+        dpe.execute_serialized_command(
+            &mut env,
+            TEST_LOCALITIES[0],
+            &command(Command::DERIVE_CHILD, derive_child.as_bytes()),
+        )
+        .unwrap();
+        let child_idx = dpe
+            .get_active_context_pos(&ContextHandle::default(), TEST_LOCALITIES[0])
+            .unwrap();
+        assert_eq!(
+            vec![
+                DpeEvent::ContextCreated {
+                    context_idx: child_idx,
+                    locality: TEST_LOCALITIES[0],
+                },
+                DpeEvent::MeasurementExtended {
+                    context_idx: child_idx,
+                    event_type: TciEventType::Derive,
+                    tci_type: 7,
+                    locality: TEST_LOCALITIES[0],
+                    measurement: TciMeasurement(derive_child.data),
+                },
+                DpeEvent::CommandExecuted {
+                    command_id: Command::DERIVE_CHILD,
+                    locality: TEST_LOCALITIES[0],
+                    status: DpeErrorCode::NoError,
+                },
+            ],
+            take_events(&mut env)
+        );
+
+        // A failed command only reports its result.
+        let destroy_ctx = |handle| DestroyCtxCmd {
+            handle,
+            flags: DestroyCtxFlags::empty(),
+        };
+        dpe.execute_serialized_command(
+            &mut env,
+            TEST_LOCALITIES[0],
+            &command(
+                Command::DESTROY_CONTEXT,
+                destroy_ctx(TEST_HANDLE).as_bytes(),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![DpeEvent::CommandExecuted {
+                command_id: Command::DESTROY_CONTEXT,
+                locality: TEST_LOCALITIES[0],
+                status: DpeErrorCode::InvalidHandle,
+            }],
+            take_events(&mut env)
+        );
+
+        // Destroying the child also destroys its retired parent.
+        dpe.execute_serialized_command(
+            &mut env,
+            TEST_LOCALITIES[0],
+            &command(
+                Command::DESTROY_CONTEXT,
+                destroy_ctx(ContextHandle::default()).as_bytes(),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                DpeEvent::ContextDestroyed {
+                    context_idx: 0,
+                    locality: TEST_LOCALITIES[0],
+                },
+                DpeEvent::ContextDestroyed {
+                    context_idx: child_idx,
+                    locality: TEST_LOCALITIES[0],
+                },
+                DpeEvent::CommandExecuted {
+                    command_id: Command::DESTROY_CONTEXT,
+                    locality: TEST_LOCALITIES[0],
+                    status: DpeErrorCode::NoError,
+                },
+            ],
+            take_events(&mut env)
+        );
+    }
+
+    #[test]
+    fn test_recording_sink_drops_overflow() {
+        let mut sink = RecordingSink::<1>::new();
+        let event = DpeEvent::ContextCreated {
+            context_idx: 0,
+            locality: 0,
+        };
+        sink.record(event);
+        sink.record(event);
+        assert_eq!(vec![event], sink.events().copied().collect::<Vec<_>>());
+        assert_eq!(1, sink.dropped);
+    }
+}
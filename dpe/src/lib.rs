@@ -8,10 +8,14 @@ Abstract:
 
 pub use dpe_instance::DpeInstance;
 
+pub mod cbor;
 pub mod commands;
 pub mod context;
 pub mod dpe_instance;
+pub mod event_log;
+pub mod event_sink;
 pub mod response;
+pub mod state;
 pub mod support;
 
 use core::mem::size_of;
@@ -22,7 +26,24 @@ pub mod x509;
 use zerocopy::{AsBytes, FromBytes};
 
 const MAX_CERT_SIZE: usize = 2048;
+#[cfg(not(any(feature = "max_handles_32", feature = "max_handles_64")))]
 const MAX_HANDLES: usize = 24;
+#[cfg(all(feature = "max_handles_32", not(feature = "max_handles_64")))]
+const MAX_HANDLES: usize = 32;
+#[cfg(feature = "max_handles_64")]
+const MAX_HANDLES: usize = 64;
+// Parent indices are stored in a u8 and 0xFF is reserved for the root.
+const _: () = assert!(MAX_HANDLES < context::Context::ROOT_INDEX as usize);
+const MAX_SEALED_DATA_SIZE: usize = 1024;
+const MAX_SIGN_MESSAGE_SIZE: usize = 1024;
+const MAX_BATCH_COMMANDS: usize = 16;
+const MAX_BATCH_DATA_SIZE: usize = 2048;
+const MAX_BATCH_RESPONSE_SIZE: usize = 4096;
+const MAX_TCI_EVENTS: usize = 32;
+const MAX_EVENT_LOG_CHUNK_SIZE: usize = 2048;
+const QUOTE_NONCE_SIZE: usize = 32;
+const EAT_NONCE_SIZE: usize = 32;
+const MAX_EAT_SIZE: usize = 4096;
 const CURRENT_PROFILE_MAJOR_VERSION: u16 = 0;
 const CURRENT_PROFILE_MINOR_VERSION: u16 = 8;
 
@@ -5,13 +5,20 @@ Abstract:
     DPE reponses and serialization.
 --*/
 use crate::{
-    context::ContextHandle, tci::TciMeasurement, CURRENT_PROFILE_MAJOR_VERSION,
-    CURRENT_PROFILE_MINOR_VERSION, DPE_PROFILE, MAX_CERT_SIZE, MAX_HANDLES,
+    commands::{Command, SealedBlob},
+    context::{ContextBitmap, ContextHandle},
+    tci::{TciMeasurement, TciNodeData},
+    CURRENT_PROFILE_MAJOR_VERSION, CURRENT_PROFILE_MINOR_VERSION, DPE_PROFILE,
+    MAX_BATCH_RESPONSE_SIZE, MAX_CERT_SIZE, MAX_EAT_SIZE, MAX_EVENT_LOG_CHUNK_SIZE, MAX_HANDLES,
+    MAX_SEALED_DATA_SIZE, QUOTE_NONCE_SIZE,
 };
-use zerocopy::AsBytes;
+use bitflags::bitflags;
+use core::mem::{size_of, size_of_val};
+use crypto::ExportedCdiHandle;
+use platform::MAX_UEID_SIZE;
+use zerocopy::{AsBytes, FromBytes};
 
 #[cfg_attr(test, derive(PartialEq, Debug, Eq))]
-#[allow(clippy::large_enum_variant)]
 pub enum Response {
     GetProfile(GetProfileResp),
     InitCtx(NewHandleResp),
@@ -24,6 +31,19 @@ pub enum Response {
     TagTci(NewHandleResp),
     GetTaggedTci(GetTaggedTciResp),
     GetCertificateChain(GetCertificateChainResp),
+    Seal(SealResp),
+    Unseal(UnsealResp),
+    SignStreamInit(NewHandleResp),
+    SignStreamUpdate(NewHandleResp),
+    SignStreamFinal(SignStreamFinalResp),
+    DeriveChildExportedCdi(DeriveChildExportedCdiResp),
+    GetTaggedTciInfo(GetTaggedTciInfoResp),
+    GetContextTable(GetContextTableResp),
+    Batch(BatchResp),
+    GetLocalityQuota(GetLocalityQuotaResp),
+    GetEventLog(GetEventLogResp),
+    Quote(QuoteResp),
+    GetEat(GetEatResp),
     Error(ResponseHdr),
 }
 
@@ -41,9 +61,225 @@ impl Response {
             Response::TagTci(res) => res.as_bytes(),
             Response::GetTaggedTci(res) => res.as_bytes(),
             Response::GetCertificateChain(res) => res.as_bytes(),
+            Response::Seal(res) => res.as_bytes(),
+            Response::Unseal(res) => res.as_bytes(),
+            Response::SignStreamInit(res) => res.as_bytes(),
+            Response::SignStreamUpdate(res) => res.as_bytes(),
+            Response::SignStreamFinal(res) => res.as_bytes(),
+            Response::DeriveChildExportedCdi(res) => res.as_bytes(),
+            Response::GetTaggedTciInfo(res) => res.as_bytes(),
+            Response::GetContextTable(res) => res.as_bytes(),
+            Response::Batch(res) => res.as_bytes(),
+            Response::GetLocalityQuota(res) => res.as_bytes(),
+            Response::GetEventLog(res) => res.as_bytes(),
+            Response::Quote(res) => res.as_bytes(),
+            Response::GetEat(res) => res.as_bytes(),
             Response::Error(res) => res.as_bytes(),
         }
     }
+
+    /// Serializes the response for sending to the caller.
+    ///
+    /// Most responses are sent exactly as they are laid out in memory, which is what `as_bytes`
+    /// returns. Responses that end in a variable-length buffer are cut short after the part of
+    /// the buffer the size field before it says is in use:
+    ///
+    /// * `CertifyKey` and `DeriveChildExportedCdi` - `cert_size` bytes of `cert`.
+    /// * `GetCertificateChain` - `certificate_size` bytes of `certificate_chain`.
+    /// * `Unseal` - `data_size` bytes of `data`.
+    /// * `GetTaggedTciInfo` - `node_count` entries of `nodes`.
+    /// * `Batch` - `data_size` bytes of `data`.
+    /// * `GetEventLog` - `chunk_size` bytes of `chunk`.
+    /// * `GetEat` - `token_size` bytes of `token`.
+    ///
+    /// Every other field keeps its offset, so callers can read a response into a zeroed buffer
+    /// of the full struct size.
+    pub fn serialize(&self) -> &[u8] {
+        match self {
+            Response::CertifyKey(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.cert),
+                res.cert_size as usize,
+            ),
+            Response::DeriveChildExportedCdi(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.cert),
+                res.cert_size as usize,
+            ),
+            Response::GetCertificateChain(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.certificate_chain),
+                res.certificate_size as usize,
+            ),
+            Response::Unseal(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.data),
+                res.data_size as usize,
+            ),
+            Response::GetTaggedTciInfo(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.nodes),
+                (res.node_count as usize).saturating_mul(size_of::<TciNodeInfo>()),
+            ),
+            Response::Batch(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.data),
+                res.data_size as usize,
+            ),
+            Response::GetEventLog(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.chunk),
+                res.chunk_size as usize,
+            ),
+            Response::GetEat(res) => trim_tail(
+                res.as_bytes(),
+                size_of_val(&res.token),
+                res.token_size as usize,
+            ),
+            _ => self.as_bytes(),
+        }
+    }
+
+    pub fn status(&self) -> u32 {
+        self.resp_hdr().status
+    }
+
+    pub fn resp_hdr(&self) -> &ResponseHdr {
+        match self {
+            Response::GetProfile(res) => &res.resp_hdr,
+            Response::InitCtx(res) => &res.resp_hdr,
+            Response::DeriveChild(res) => &res.resp_hdr,
+            Response::RotateCtx(res) => &res.resp_hdr,
+            Response::CertifyKey(res) => &res.resp_hdr,
+            Response::Sign(res) => &res.resp_hdr,
+            Response::DestroyCtx(res) => res,
+            Response::ExtendTci(res) => &res.resp_hdr,
+            Response::TagTci(res) => &res.resp_hdr,
+            Response::GetTaggedTci(res) => &res.resp_hdr,
+            Response::GetCertificateChain(res) => &res.resp_hdr,
+            Response::Seal(res) => &res.resp_hdr,
+            Response::Unseal(res) => &res.resp_hdr,
+            Response::SignStreamInit(res) => &res.resp_hdr,
+            Response::SignStreamUpdate(res) => &res.resp_hdr,
+            Response::SignStreamFinal(res) => &res.resp_hdr,
+            Response::DeriveChildExportedCdi(res) => &res.resp_hdr,
+            Response::GetTaggedTciInfo(res) => &res.resp_hdr,
+            Response::GetContextTable(res) => &res.resp_hdr,
+            Response::Batch(res) => &res.resp_hdr,
+            Response::GetLocalityQuota(res) => &res.resp_hdr,
+            Response::GetEventLog(res) => &res.resp_hdr,
+            Response::Quote(res) => &res.resp_hdr,
+            Response::GetEat(res) => &res.resp_hdr,
+            Response::Error(res) => res,
+        }
+    }
+
+    /// The new context handle and parent context handle returned by the command, if any.
+    ///
+    /// For commands that only return one handle, both are the same.
+    pub fn handles(&self) -> Option<(ContextHandle, ContextHandle)> {
+        let handle = match self {
+            Response::DeriveChild(res) => return Some((res.handle, res.parent_handle)),
+            Response::InitCtx(res)
+            | Response::RotateCtx(res)
+            | Response::ExtendTci(res)
+            | Response::TagTci(res)
+            | Response::SignStreamInit(res)
+            | Response::SignStreamUpdate(res) => res.handle,
+            Response::CertifyKey(res) => res.new_context_handle,
+            Response::Sign(res) => res.new_context_handle,
+            Response::SignStreamFinal(res) => res.new_context_handle,
+            Response::Seal(res) => res.new_context_handle,
+            Response::Unseal(res) => res.new_context_handle,
+            Response::Quote(res) => res.new_context_handle,
+            Response::GetEat(res) => res.new_context_handle,
+            Response::DeriveChildExportedCdi(res) => res.parent_handle,
+            _ => return None,
+        };
+        Some((handle, handle))
+    }
+}
+
+impl Response {
+    /// Parses a response serialized by `serialize`.
+    ///
+    /// A response only says which command it answers through its layout, so the caller passes the
+    /// ID of the command it sent. An error response, which is only a `ResponseHdr`, is returned as
+    /// `Response::Error` whatever the command was.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_id` - ID of the command the response is for
+    /// * `bytes` - serialized response
+    pub fn deserialize(cmd_id: u32, bytes: &[u8]) -> Result<Response, DpeErrorCode> {
+        let header = ResponseHdr::read_from_prefix(bytes).ok_or(DpeErrorCode::InvalidArgument)?;
+        if header.magic != ResponseHdr::DPE_RESPONSE_MAGIC {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        if header.status != DpeErrorCode::NoError as u32 && bytes.len() == size_of::<ResponseHdr>()
+        {
+            return Ok(Response::Error(header));
+        }
+
+        let response = match cmd_id {
+            Command::GET_PROFILE => Self::parse_response(Response::GetProfile, bytes),
+            Command::INITIALIZE_CONTEXT => Self::parse_response(Response::InitCtx, bytes),
+            // Exporting the CDI changes the response, which shows in its size.
+            Command::DERIVE_CHILD if bytes.len() == size_of::<DeriveChildResp>() => {
+                Self::parse_response(Response::DeriveChild, bytes)
+            }
+            Command::DERIVE_CHILD => Self::parse_response(Response::DeriveChildExportedCdi, bytes),
+            Command::CERTIFY_KEY => Self::parse_response(Response::CertifyKey, bytes),
+            Command::SIGN | Command::SIGN_MESSAGE => Self::parse_response(Response::Sign, bytes),
+            Command::ROTATE_CONTEXT_HANDLE => Self::parse_response(Response::RotateCtx, bytes),
+            Command::DESTROY_CONTEXT => Self::parse_response(Response::DestroyCtx, bytes),
+            Command::GET_CERTIFICATE_CHAIN => {
+                Self::parse_response(Response::GetCertificateChain, bytes)
+            }
+            Command::EXTEND_TCI => Self::parse_response(Response::ExtendTci, bytes),
+            Command::TAG_TCI => Self::parse_response(Response::TagTci, bytes),
+            Command::GET_TAGGED_TCI => Self::parse_response(Response::GetTaggedTci, bytes),
+            Command::SEAL => Self::parse_response(Response::Seal, bytes),
+            Command::UNSEAL => Self::parse_response(Response::Unseal, bytes),
+            Command::SIGN_STREAM_INIT => Self::parse_response(Response::SignStreamInit, bytes),
+            Command::SIGN_STREAM_UPDATE => Self::parse_response(Response::SignStreamUpdate, bytes),
+            Command::SIGN_STREAM_FINAL => Self::parse_response(Response::SignStreamFinal, bytes),
+            Command::GET_TAGGED_TCI_INFO => Self::parse_response(Response::GetTaggedTciInfo, bytes),
+            Command::GET_CONTEXT_TABLE => Self::parse_response(Response::GetContextTable, bytes),
+            Command::BATCH => Self::parse_response(Response::Batch, bytes),
+            Command::GET_LOCALITY_QUOTA => Self::parse_response(Response::GetLocalityQuota, bytes),
+            Command::GET_EVENT_LOG => Self::parse_response(Response::GetEventLog, bytes),
+            Command::QUOTE => Self::parse_response(Response::Quote, bytes),
+            Command::GET_EAT => Self::parse_response(Response::GetEat, bytes),
+            _ => return Err(DpeErrorCode::InvalidCommand),
+        }?;
+
+        // The size fields have to account for exactly the bytes that were sent.
+        if response.serialize().len() != bytes.len() {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        Ok(response)
+    }
+
+    /// Reads `bytes` into a zeroed `T`, so responses cut short by `serialize` can be parsed.
+    fn parse_response<T: FromBytes + AsBytes>(
+        build: impl FnOnce(T) -> Response,
+        bytes: &[u8],
+    ) -> Result<Response, DpeErrorCode> {
+        let mut response = T::new_zeroed();
+        response
+            .as_bytes_mut()
+            .get_mut(..bytes.len())
+            .ok_or(DpeErrorCode::InvalidArgument)?
+            .copy_from_slice(bytes);
+        Ok(build(response))
+    }
+}
+
+/// Drops the unused end of the `tail_size` byte buffer at the end of `bytes`, keeping `used`
+/// bytes of it.
+fn trim_tail(bytes: &[u8], tail_size: usize, used: usize) -> &[u8] {
+    &bytes[..bytes.len() - tail_size + used.min(tail_size)]
 }
 
 // ABI Response structures
@@ -113,6 +349,18 @@ pub struct DeriveChildResp {
     pub parent_handle: ContextHandle,
 }
 
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct DeriveChildExportedCdiResp {
+    pub resp_hdr: ResponseHdr,
+    pub parent_handle: ContextHandle,
+    pub exported_cdi: ExportedCdiHandle,
+    pub derived_pubkey_x: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub derived_pubkey_y: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub cert_size: u32,
+    pub cert: [u8; MAX_CERT_SIZE],
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
 pub struct CertifyKeyResp {
@@ -146,8 +394,86 @@ pub struct SignResp {
     pub sig_s: [u8; DPE_PROFILE.get_ecc_int_size()],
 }
 
+/// The signature over a streamed message, along with the digest that was signed.
+///
+/// The digest depends on how the message was split into chunks, so verifiers either replay the
+/// chunks the same way or check the signature over `digest` directly.
 #[repr(C)]
-#[derive(Debug, zerocopy::AsBytes)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct SignStreamFinalResp {
+    pub resp_hdr: ResponseHdr,
+    pub new_context_handle: ContextHandle,
+    pub sig_r_or_hmac: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub sig_s: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub digest: [u8; DPE_PROFILE.get_hash_size()],
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct QuoteFlags(u32);
+
+bitflags! {
+    impl QuoteFlags: u32 {
+        /// The quote was signed by a simulation context.
+        const SIMULATION = 1u32 << 31;
+    }
+}
+
+/// The structure signed by a Quote. Verifiers check the signature over the hash of its bytes.
+#[repr(C)]
+#[derive(Debug, zerocopy::AsBytes, zerocopy::FromBytes)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct Quote {
+    pub magic: u32,
+    pub profile: u32,
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub vendor_id: u32,
+    pub vendor_sku: u32,
+    pub max_tci_nodes: u32,
+    /// Support flags, as reported by GetProfile.
+    pub support: u32,
+    pub flags: QuoteFlags,
+    pub nonce: [u8; QUOTE_NONCE_SIZE],
+    pub ueid_size: u32,
+    pub ueid: [u8; MAX_UEID_SIZE],
+    pub reserved: [u8; 3],
+    pub tci_count: u32,
+    /// The quoted context's TCI chain, starting with the context itself and ending at the root.
+    /// Unused entries are zeroed.
+    pub tci_nodes: [TciNodeData; MAX_HANDLES],
+}
+
+impl Quote {
+    pub const MAGIC: u32 = u32::from_be_bytes(*b"DPEQ");
+}
+
+#[repr(C)]
+#[derive(Debug, zerocopy::AsBytes, zerocopy::FromBytes)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct QuoteResp {
+    pub resp_hdr: ResponseHdr,
+    pub new_context_handle: ContextHandle,
+    pub derived_pubkey_x: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub derived_pubkey_y: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub sig_r: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub sig_s: [u8; DPE_PROFILE.get_ecc_int_size()],
+    pub quote: Quote,
+}
+
+/// An Entity Attestation Token, encoded as a CWT in a tagged COSE_Sign1.
+#[repr(C)]
+#[derive(Debug, zerocopy::AsBytes, zerocopy::FromBytes)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct GetEatResp {
+    pub resp_hdr: ResponseHdr,
+    pub new_context_handle: ContextHandle,
+    pub token_size: u32,
+    pub token: [u8; MAX_EAT_SIZE],
+}
+
+#[repr(C)]
+#[derive(Debug, zerocopy::AsBytes, zerocopy::FromBytes)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct GetTaggedTciResp {
     pub resp_hdr: ResponseHdr,
@@ -155,6 +481,110 @@ pub struct GetTaggedTciResp {
     pub tci_current: TciMeasurement,
 }
 
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct TciNodeInfoFlags(u32);
+
+bitflags! {
+    impl TciNodeInfoFlags: u32 {
+        const HAS_TAG = 1u32 << 31;
+        const RETIRED = 1u32 << 30;
+        const SIMULATION = 1u32 << 29;
+        const PARENT_HAS_TAG = 1u32 << 28;
+        const IS_ROOT = 1u32 << 27;
+    }
+}
+
+/// A TCI node and its position in the context tree.
+#[repr(C)]
+#[derive(Debug, zerocopy::AsBytes, zerocopy::FromBytes)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct TciNodeInfo {
+    pub tci: TciNodeData,
+    /// Only valid if `flags` has HAS_TAG.
+    pub tag: u32,
+    /// Only valid if `flags` has PARENT_HAS_TAG.
+    pub parent_tag: u32,
+    pub flags: TciNodeInfoFlags,
+}
+
+#[repr(C)]
+#[derive(Debug, zerocopy::AsBytes, zerocopy::FromBytes)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct GetTaggedTciInfoResp {
+    pub resp_hdr: ResponseHdr,
+    /// Number of valid entries in `nodes`, ordered from the tagged node towards the root.
+    pub node_count: u32,
+    pub nodes: [TciNodeInfo; MAX_HANDLES],
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct ContextInfoFlags(u32);
+
+bitflags! {
+    impl ContextInfoFlags: u32 {
+        const HAS_TAG = 1u32 << 31;
+        const USES_INTERNAL_INPUT_INFO = 1u32 << 30;
+        const USES_INTERNAL_INPUT_DICE = 1u32 << 29;
+        const ALLOW_CA = 1u32 << 28;
+        const ALLOW_X509 = 1u32 << 27;
+        const ALLOW_EXPORT = 1u32 << 26;
+        const HAS_SIGN_STREAM = 1u32 << 25;
+    }
+}
+
+/// Redacted view of a single slot in the context table. Handles, TCI values and any other
+/// secret or attestable state are deliberately left out.
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct ContextInfo {
+    pub locality: u32,
+    /// Only valid if `flags` has HAS_TAG.
+    pub tag: u32,
+    /// Bitmap of the slot indices that are children of this slot.
+    pub children: ContextBitmap,
+    pub flags: ContextInfoFlags,
+    /// 0xFF if the slot is a root.
+    pub parent_idx: u8,
+    /// 0 = Inactive, 1 = Active, 2 = Retired.
+    pub state: u8,
+    /// 0 = Normal, 1 = Simulation.
+    pub context_type: u8,
+    pub reserved: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct GetContextTableResp {
+    pub resp_hdr: ResponseHdr,
+    pub num_contexts: u32,
+    pub contexts: [ContextInfo; MAX_HANDLES],
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct GetLocalityQuotaResp {
+    pub resp_hdr: ResponseHdr,
+    pub locality: u32,
+    /// Maximum number of contexts the locality may hold, capped at the size of the table.
+    pub max_contexts: u32,
+    /// Active and retired contexts currently owned by the locality.
+    pub used_contexts: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct GetEventLogResp {
+    pub resp_hdr: ResponseHdr,
+    /// Size of the whole serialized log, so callers know when they have read all of it.
+    pub log_size: u32,
+    /// `recnum` of the first event in the log. Offsets are relative to this event.
+    pub first_recnum: u32,
+    pub chunk_size: u32,
+    pub chunk: [u8; MAX_EVENT_LOG_CHUNK_SIZE],
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
 pub struct GetCertificateChainResp {
@@ -173,6 +603,76 @@ impl Default for GetCertificateChainResp {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct SealResp {
+    pub resp_hdr: ResponseHdr,
+    pub new_context_handle: ContextHandle,
+    pub blob: SealedBlob,
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct UnsealResp {
+    pub resp_hdr: ResponseHdr,
+    pub new_context_handle: ContextHandle,
+    pub data_size: u32,
+    pub data: [u8; MAX_SEALED_DATA_SIZE],
+}
+
+impl Default for UnsealResp {
+    fn default() -> Self {
+        Self {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            new_context_handle: ContextHandle::default(),
+            data_size: 0,
+            data: [0; MAX_SEALED_DATA_SIZE],
+        }
+    }
+}
+
+/// Concatenated responses of the commands in a batch.
+///
+/// Each response is serialized with `Response::serialize` and prefixed with its size as a u32. If
+/// a command fails, its error response is the last one and its status is copied into `resp_hdr`.
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, zerocopy::AsBytes, zerocopy::FromBytes)]
+pub struct BatchResp {
+    pub resp_hdr: ResponseHdr,
+    pub num_responses: u32,
+    pub data_size: u32,
+    pub data: [u8; MAX_BATCH_RESPONSE_SIZE],
+}
+
+impl BatchResp {
+    pub(crate) fn append(&mut self, response: &Response) -> Result<(), DpeErrorCode> {
+        let bytes = response.serialize();
+        let size = (bytes.len() as u32).to_le_bytes();
+        let offset = self.data_size as usize;
+        let end = offset + size.len() + bytes.len();
+        let dst = self
+            .data
+            .get_mut(offset..end)
+            .ok_or(DpeErrorCode::InvalidArgument)?;
+        dst[..size.len()].copy_from_slice(&size);
+        dst[size.len()..].copy_from_slice(bytes);
+        self.data_size = end as u32;
+        self.num_responses += 1;
+        Ok(())
+    }
+}
+
+impl Default for BatchResp {
+    fn default() -> Self {
+        Self {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            num_responses: 0,
+            data_size: 0,
+            data: [0; MAX_BATCH_RESPONSE_SIZE],
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum DpeErrorCode {
     NoError = 0,
@@ -189,4 +689,199 @@ pub enum DpeErrorCode {
     HashError = 0x1006,
     RandError = 0x1007,
     InvalidInternalState = 0x1008,
+    AccessDenied = 0x1009,
+    LocalityQuotaExceeded = 0x100A,
+    EventLogFull = 0x100B,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A successful response of type `T` whose other bytes count up from 0.
+    fn patterned<T: FromBytes + AsBytes>() -> T {
+        let mut t = T::new_zeroed();
+        for (i, b) in t.as_bytes_mut().iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        t.as_bytes_mut()[..size_of::<ResponseHdr>()]
+            .copy_from_slice(ResponseHdr::new(DpeErrorCode::NoError).as_bytes());
+        t
+    }
+
+    /// Variable-length responses have to be given with the unused part of their buffer zeroed,
+    /// since it isn't sent.
+    fn round_trip(cmd_id: u32, response: Response) {
+        let bytes = response.serialize().to_vec();
+        assert_eq!(Ok(response), Response::deserialize(cmd_id, &bytes));
+    }
+
+    #[test]
+    fn test_deserialize_round_trip() {
+        round_trip(Command::GET_PROFILE, Response::GetProfile(patterned()));
+        round_trip(Command::INITIALIZE_CONTEXT, Response::InitCtx(patterned()));
+        round_trip(Command::DERIVE_CHILD, Response::DeriveChild(patterned()));
+        let mut exported: DeriveChildExportedCdiResp = patterned();
+        exported.cert_size = 100;
+        exported.cert[100..].fill(0);
+        round_trip(
+            Command::DERIVE_CHILD,
+            Response::DeriveChildExportedCdi(exported),
+        );
+        let mut certify: CertifyKeyResp = patterned();
+        certify.cert_size = 400;
+        certify.cert[400..].fill(0);
+        round_trip(Command::CERTIFY_KEY, Response::CertifyKey(certify));
+        for cmd_id in [Command::SIGN, Command::SIGN_MESSAGE] {
+            round_trip(cmd_id, Response::Sign(patterned()));
+        }
+        round_trip(
+            Command::ROTATE_CONTEXT_HANDLE,
+            Response::RotateCtx(patterned()),
+        );
+        round_trip(
+            Command::DESTROY_CONTEXT,
+            Response::DestroyCtx(ResponseHdr::new(DpeErrorCode::NoError)),
+        );
+        round_trip(Command::EXTEND_TCI, Response::ExtendTci(patterned()));
+        round_trip(Command::TAG_TCI, Response::TagTci(patterned()));
+        round_trip(Command::GET_TAGGED_TCI, Response::GetTaggedTci(patterned()));
+        let mut chain: GetCertificateChainResp = patterned();
+        chain.certificate_size = 0;
+        chain.certificate_chain.fill(0);
+        round_trip(
+            Command::GET_CERTIFICATE_CHAIN,
+            Response::GetCertificateChain(chain),
+        );
+        round_trip(Command::SEAL, Response::Seal(patterned()));
+        let mut unseal: UnsealResp = patterned();
+        unseal.data_size = MAX_SEALED_DATA_SIZE as u32;
+        round_trip(Command::UNSEAL, Response::Unseal(unseal));
+        round_trip(
+            Command::SIGN_STREAM_INIT,
+            Response::SignStreamInit(patterned()),
+        );
+        round_trip(
+            Command::SIGN_STREAM_UPDATE,
+            Response::SignStreamUpdate(patterned()),
+        );
+        round_trip(
+            Command::SIGN_STREAM_FINAL,
+            Response::SignStreamFinal(patterned()),
+        );
+        let mut info: GetTaggedTciInfoResp = patterned();
+        info.node_count = 3;
+        info.nodes[3..]
+            .iter_mut()
+            .for_each(|node| node.as_bytes_mut().fill(0));
+        round_trip(
+            Command::GET_TAGGED_TCI_INFO,
+            Response::GetTaggedTciInfo(info),
+        );
+        round_trip(
+            Command::GET_CONTEXT_TABLE,
+            Response::GetContextTable(patterned()),
+        );
+        let mut batch: BatchResp = patterned();
+        batch.data_size = 10;
+        batch.data[10..].fill(0);
+        round_trip(Command::BATCH, Response::Batch(batch));
+        round_trip(
+            Command::GET_LOCALITY_QUOTA,
+            Response::GetLocalityQuota(patterned()),
+        );
+        let mut log: GetEventLogResp = patterned();
+        log.chunk_size = 77;
+        log.chunk[77..].fill(0);
+        round_trip(Command::GET_EVENT_LOG, Response::GetEventLog(log));
+        round_trip(Command::QUOTE, Response::Quote(patterned()));
+        let mut eat: GetEatResp = patterned();
+        eat.token_size = 1000;
+        eat.token[1000..].fill(0);
+        round_trip(Command::GET_EAT, Response::GetEat(eat));
+
+        // Errors parse the same way whatever the command was.
+        for cmd_id in [Command::CERTIFY_KEY, Command::GET_PROFILE, Command::BATCH] {
+            round_trip(
+                cmd_id,
+                Response::Error(ResponseHdr::new(DpeErrorCode::InvalidHandle)),
+            );
+        }
+    }
+
+    #[test]
+    fn test_deserialize_malformed() {
+        let mut certify: CertifyKeyResp = patterned();
+        certify.cert_size = 400;
+        let bytes = Response::CertifyKey(certify).serialize().to_vec();
+
+        // Too short for the size field.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            Response::deserialize(Command::CERTIFY_KEY, &bytes[..bytes.len() - 1])
+        );
+        // Trailing bytes.
+        let mut long = bytes.clone();
+        long.push(0);
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            Response::deserialize(Command::CERTIFY_KEY, &long)
+        );
+        // Bad magic.
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] ^= 1;
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            Response::deserialize(Command::CERTIFY_KEY, &bad_magic)
+        );
+        // Unknown command.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidCommand),
+            Response::deserialize(0xff, &bytes)
+        );
+        // Too short for a header.
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            Response::deserialize(Command::CERTIFY_KEY, &bytes[..4])
+        );
+    }
+
+    #[test]
+    fn test_serialize_trims_variable_length_buffers() {
+        let mut certify = CertifyKeyResp {
+            resp_hdr: ResponseHdr::new(DpeErrorCode::NoError),
+            new_context_handle: ContextHandle::default(),
+            derived_pubkey_x: [0; DPE_PROFILE.get_ecc_int_size()],
+            derived_pubkey_y: [0; DPE_PROFILE.get_ecc_int_size()],
+            cert_size: 400,
+            cert: [0xaa; MAX_CERT_SIZE],
+        };
+        let fixed_size = size_of::<CertifyKeyResp>() - MAX_CERT_SIZE;
+        let full = certify.as_bytes().to_vec();
+        let resp = Response::CertifyKey(certify);
+        assert_eq!(&full[..fixed_size + 400], resp.serialize());
+        assert_eq!(full, resp.as_bytes());
+
+        // A size past the end of the buffer is clamped to the buffer.
+        certify = match resp {
+            Response::CertifyKey(res) => res,
+            _ => unreachable!(),
+        };
+        certify.cert_size = u32::MAX;
+        assert_eq!(
+            size_of::<CertifyKeyResp>(),
+            Response::CertifyKey(certify).serialize().len()
+        );
+
+        let mut info = GetTaggedTciInfoResp::new_zeroed();
+        info.node_count = 2;
+        assert_eq!(
+            size_of::<GetTaggedTciInfoResp>() - (MAX_HANDLES - 2) * size_of::<TciNodeInfo>(),
+            Response::GetTaggedTciInfo(info).serialize().len()
+        );
+
+        // Fixed-size responses are sent whole.
+        let profile = Response::GetProfile(GetProfileResp::new(0, 0, 0));
+        assert_eq!(profile.as_bytes(), profile.serialize());
+    }
 }
@@ -0,0 +1,575 @@
+// Licensed under the Apache-2.0 license.
+
+//! Saving and restoring a `DpeInstance` across resets.
+//!
+//! Saved state is laid out as:
+//!
+//! * A `SavedStateHdr` with the format version, the profile, the size of the instance and the
+//!   value of the platform's state counter when it was saved.
+//! * The `DpeInstance` itself.
+//! * An HMAC over the header and the instance, keyed from the platform's state secret.
+//!
+//! Every save records the value the platform's monotonic state counter will have once the save
+//! is committed. The caller persists the state and then calls `DpeInstance::commit_save`, which
+//! increments the counter so state saved before can no longer be restored. Only committed state
+//! can be restored, so until then the previous state is the one that is restored, and a failed
+//! save or a reset before the new state is persisted doesn't lose it. Every save made since the
+//! last commit waits for the same counter value, so DPE can't tell them apart. If a reset comes
+//! between persisting and committing, the caller has to pass the state it persisted to
+//! `commit_save` itself before restoring it.
+//!
+//! The header's version identifies the layout of the instance. Restoring state saved with an
+//! older layout migrates it to the current one, so a firmware update keeps the measurements of
+//! the running contexts. Version 0 is the layout from before saved state existed, when firmware
+//! persisted the raw instance bytes, and can also be restored from those bytes directly.
+//!
+//! When a change to `DpeInstance`, `Context` or `TciNodeData` alters their layout, freeze the
+//! layout being replaced in a new `vN` module with a `migrate` to the layout after it, add it to
+//! `read_instance`, bump `SavedStateHdr::VERSION` and add a golden blob of the frozen layout to
+//! the tests.
+
+use crate::{
+    dpe_instance::{DpeEnv, DpeTypes},
+    response::DpeErrorCode,
+    support::Support,
+    DpeInstance, DPE_PROFILE,
+};
+use core::mem::size_of;
+use crypto::Crypto;
+use platform::{Platform, MAX_STATE_SECRET_SIZE};
+use zerocopy::{AsBytes, FromBytes};
+
+mod v0;
+mod v1;
+
+/// Header of saved state. It is covered by the MAC along with the instance.
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, AsBytes, FromBytes)]
+pub struct SavedStateHdr {
+    pub magic: u32,
+    pub version: u32,
+    pub profile: u32,
+    pub state_size: u32,
+    pub counter: u32,
+}
+
+impl SavedStateHdr {
+    pub const MAGIC: u32 = u32::from_be_bytes(*b"DPEP");
+    pub const VERSION: u32 = 2;
+}
+
+const MAC_SIZE: usize = DPE_PROFILE.get_hash_size();
+
+/// Size of saved state in the current version.
+pub const SAVED_STATE_SIZE: usize =
+    size_of::<SavedStateHdr>() + size_of::<DpeInstance>() + MAC_SIZE;
+
+/// Info string used when deriving the MAC key from the platform's state secret.
+const STATE_MAC_INFO: &[u8] = b"DPE_STATE";
+
+/// Computes the MAC over the header and instance bytes of saved state.
+fn compute_mac(
+    env: &mut DpeEnv<impl DpeTypes>,
+    state: &[u8],
+) -> Result<[u8; MAC_SIZE], DpeErrorCode> {
+    let mut secret = [0u8; MAX_STATE_SECRET_SIZE];
+    let secret_size = env
+        .platform
+        .get_state_secret(&mut secret)
+        .map_err(|_| DpeErrorCode::PlatformError)?;
+    let secret = secret
+        .get(..secret_size)
+        .ok_or(DpeErrorCode::PlatformError)?;
+
+    let mac = env
+        .crypto
+        .hmac_with_secret(DPE_PROFILE.alg_len(), secret, STATE_MAC_INFO, state)
+        .map_err(|_| DpeErrorCode::CryptoError)?;
+    mac.bytes()
+        .try_into()
+        .map_err(|_| DpeErrorCode::InternalError)
+}
+
+/// Checks the header and MAC of saved state, and returns the header and the instance bytes.
+fn verify_saved_state<'a>(
+    env: &mut DpeEnv<impl DpeTypes>,
+    src: &'a [u8],
+) -> Result<(SavedStateHdr, &'a [u8]), DpeErrorCode> {
+    let hdr = SavedStateHdr::read_from_prefix(src).ok_or(DpeErrorCode::InvalidInternalState)?;
+    let state_len = size_of::<SavedStateHdr>()
+        .checked_add(hdr.state_size as usize)
+        .ok_or(DpeErrorCode::InvalidInternalState)?;
+    if hdr.magic != SavedStateHdr::MAGIC
+        || hdr.profile != DPE_PROFILE as u32
+        || src.len().checked_sub(MAC_SIZE) != Some(state_len)
+    {
+        return Err(DpeErrorCode::InvalidInternalState);
+    }
+    let (state, mac) = src.split_at(state_len);
+
+    // Compare without exiting early so the time taken doesn't leak the expected MAC.
+    let expected = compute_mac(env, state)?;
+    let diff = expected
+        .iter()
+        .zip(mac)
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    if diff != 0 {
+        return Err(DpeErrorCode::InvalidInternalState);
+    }
+    Ok((hdr, &state[size_of::<SavedStateHdr>()..]))
+}
+
+/// Reads an instance with the layout of `version`, migrating it to the current layout.
+fn read_instance(version: u32, src: &[u8]) -> Option<DpeInstance> {
+    match version {
+        0 => v0::DpeInstanceV0::read_from(src)?.migrate(),
+        1 => Some(v1::DpeInstanceV1::read_from(src)?.migrate()),
+        SavedStateHdr::VERSION => DpeInstance::read_from(src),
+        _ => None,
+    }
+}
+
+impl DpeInstance {
+    /// Writes the instance to `dst` as MAC-protected saved state and returns its size.
+    ///
+    /// The state is not protected against rollback until it is passed to `commit_save` after
+    /// being persisted. Until then, the state saved before can still be restored.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst` - Buffer of at least `SAVED_STATE_SIZE` bytes.
+    pub fn save(
+        &self,
+        env: &mut DpeEnv<impl DpeTypes>,
+        dst: &mut [u8],
+    ) -> Result<usize, DpeErrorCode> {
+        let dst = dst
+            .get_mut(..SAVED_STATE_SIZE)
+            .ok_or(DpeErrorCode::InvalidArgument)?;
+        let counter = env
+            .platform
+            .get_state_counter()
+            .map_err(|_| DpeErrorCode::PlatformError)?
+            .checked_add(1)
+            .ok_or(DpeErrorCode::PlatformError)?;
+
+        let hdr = SavedStateHdr {
+            magic: SavedStateHdr::MAGIC,
+            version: SavedStateHdr::VERSION,
+            profile: DPE_PROFILE as u32,
+            state_size: size_of::<DpeInstance>() as u32,
+            counter,
+        };
+        let (state, mac) = dst.split_at_mut(SAVED_STATE_SIZE - MAC_SIZE);
+        let (hdr_bytes, instance) = state.split_at_mut(size_of::<SavedStateHdr>());
+        hdr_bytes.copy_from_slice(hdr.as_bytes());
+        instance.copy_from_slice(self.as_bytes());
+        mac.copy_from_slice(&compute_mac(env, state)?);
+        Ok(SAVED_STATE_SIZE)
+    }
+
+    /// Commits state written by `save` once it has been persisted, by incrementing the platform's
+    /// state counter. State saved before can no longer be restored.
+    ///
+    /// Committing state that is already committed does nothing. State that fails its MAC is
+    /// rejected with `InvalidInternalState`, and state that was saved before the latest commit
+    /// with `InvalidArgument`.
+    ///
+    /// # Arguments
+    ///
+    /// * `saved` - The persisted state.
+    pub fn commit_save(env: &mut DpeEnv<impl DpeTypes>, saved: &[u8]) -> Result<(), DpeErrorCode> {
+        let (hdr, _) = verify_saved_state(env, saved)?;
+        let counter = env
+            .platform
+            .get_state_counter()
+            .map_err(|_| DpeErrorCode::PlatformError)?;
+        if hdr.counter == counter {
+            return Ok(());
+        }
+        if Some(hdr.counter) != counter.checked_add(1) {
+            return Err(DpeErrorCode::InvalidArgument);
+        }
+        env.platform
+            .increment_state_counter()
+            .map_err(|_| DpeErrorCode::PlatformError)?;
+        Ok(())
+    }
+
+    /// Restores an instance from committed state written by `save`, migrating it if it was saved
+    /// by an older version.
+    ///
+    /// State that is for an unknown version or another profile, fails its MAC, isn't the latest
+    /// committed save, holds fields with undefined values or has an inconsistent context table is
+    /// rejected with `InvalidInternalState`.
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - Saved state.
+    pub fn restore(
+        env: &mut DpeEnv<impl DpeTypes>,
+        src: &[u8],
+    ) -> Result<DpeInstance, DpeErrorCode> {
+        let (hdr, instance) = verify_saved_state(env, src)?;
+        let counter = env
+            .platform
+            .get_state_counter()
+            .map_err(|_| DpeErrorCode::PlatformError)?;
+        if hdr.counter != counter {
+            return Err(DpeErrorCode::InvalidInternalState);
+        }
+        Self::checked_instance(read_instance(hdr.version, instance))
+    }
+
+    /// Restores an instance from the raw bytes firmware persisted before saved state was
+    /// versioned, migrating it to the current layout.
+    ///
+    /// The bytes carry no MAC or counter, so they must come from storage that is as trusted as the
+    /// instance itself. Fields with undefined values and inconsistent context tables are rejected
+    /// with `InvalidInternalState`.
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - Bytes of the unversioned instance.
+    pub fn restore_unversioned(src: &[u8]) -> Result<DpeInstance, DpeErrorCode> {
+        Self::checked_instance(read_instance(0, src))
+    }
+
+    fn checked_instance(dpe: Option<DpeInstance>) -> Result<DpeInstance, DpeErrorCode> {
+        let dpe = dpe.ok_or(DpeErrorCode::InvalidInternalState)?;
+        if !dpe.has_valid_fields() {
+            return Err(DpeErrorCode::InvalidInternalState);
+        }
+        dpe.validate()?;
+        Ok(dpe)
+    }
+
+    /// Whether every enum and boolean field holds one of its defined values.
+    fn has_valid_fields(&self) -> bool {
+        self.has_initialized.is_bool()
+            && Support::from_bits(self.support.bits()).is_some()
+            && self
+                .contexts
+                .iter()
+                .all(|context| context.has_valid_fields())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{Command, CommandHdr, DeriveChildCmd, DeriveChildFlags, InitCtxCmd, TagTciCmd},
+        context::{ContextHandle, ContextState, ContextType},
+        dpe_instance::tests::{TestTypes, SIMULATION_HANDLE, TEST_LOCALITIES},
+        response::Response,
+        support::test::SUPPORT,
+        U8Bool,
+    };
+    use crypto::OpensslCrypto;
+    use platform::default::DefaultPlatform;
+
+    #[cfg(feature = "dpe_profile_p256_sha256")]
+    const GOLDEN_V0: &[u8] = include_bytes!("test_data/state_v0_p256.bin");
+    #[cfg(feature = "dpe_profile_p384_sha384")]
+    const GOLDEN_V0: &[u8] = include_bytes!("test_data/state_v0_p384.bin");
+    #[cfg(feature = "dpe_profile_p256_sha256")]
+    const GOLDEN_V1: &[u8] = include_bytes!("test_data/state_v1_p256.bin");
+    #[cfg(feature = "dpe_profile_p384_sha384")]
+    const GOLDEN_V1: &[u8] = include_bytes!("test_data/state_v1_p384.bin");
+    #[cfg(feature = "dpe_profile_p256_sha256")]
+    const GOLDEN_V2: &[u8] = include_bytes!("test_data/state_v2_p256.bin");
+    #[cfg(feature = "dpe_profile_p384_sha384")]
+    const GOLDEN_V2: &[u8] = include_bytes!("test_data/state_v2_p384.bin");
+
+    fn new_env() -> DpeEnv<'static, TestTypes> {
+        DpeEnv::<TestTypes> {
+            crypto: OpensslCrypto::new(),
+            platform: DefaultPlatform::new(),
+            event_sink: (),
+        }
+    }
+
+    /// Saves an instance modified by `patch`, which gets a valid MAC, and checks it is rejected.
+    fn assert_rejected(patch: impl FnOnce(&mut DpeInstance)) {
+        let mut env = new_env();
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        patch(&mut dpe);
+        let mut saved = [0; SAVED_STATE_SIZE];
+        dpe.save(&mut env, &mut saved).unwrap();
+        DpeInstance::commit_save(&mut env, &saved).unwrap();
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::restore(&mut env, &saved).map(|_| ())
+        );
+    }
+
+    fn execute(dpe: &mut DpeInstance, env: &mut DpeEnv<TestTypes>, locality: u32, cmd: &[u8]) {
+        let response = dpe.execute_serialized_command(env, locality, cmd);
+        assert!(response.is_ok(), "{response:?}");
+    }
+
+    /// Builds the instance the golden blobs were saved from: a retired auto-initialized context
+    /// with a default child, and a tagged simulation context in another locality.
+    fn golden_instance(env: &mut DpeEnv<TestTypes>) -> DpeInstance {
+        let mut dpe = DpeInstance::new(env, SUPPORT).unwrap();
+        let mut derive_child = CommandHdr::new(Command::DERIVE_CHILD).as_bytes().to_vec();
+        derive_child.extend(
+            DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [1; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::MAKE_DEFAULT
+                    | DeriveChildFlags::INPUT_ALLOW_X509
+                    | DeriveChildFlags::INTERNAL_INPUT_INFO,
+                tci_type: 7,
+                target_locality: 0,
+            }
+            .as_bytes(),
+        );
+        execute(&mut dpe, env, TEST_LOCALITIES[0], &derive_child);
+
+        let mut init_ctx = CommandHdr::new(Command::INITIALIZE_CONTEXT)
+            .as_bytes()
+            .to_vec();
+        init_ctx.extend(InitCtxCmd::new_simulation().as_bytes());
+        execute(&mut dpe, env, TEST_LOCALITIES[1], &init_ctx);
+
+        let mut tag_tci = CommandHdr::new(Command::TAG_TCI).as_bytes().to_vec();
+        tag_tci.extend(
+            TagTciCmd {
+                handle: SIMULATION_HANDLE,
+                tag: 0x1234,
+            }
+            .as_bytes(),
+        );
+        execute(&mut dpe, env, TEST_LOCALITIES[1], &tag_tci);
+        dpe
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let mut env = new_env();
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        let mut command = CommandHdr::new(Command::DERIVE_CHILD).as_bytes().to_vec();
+        command.extend(
+            DeriveChildCmd {
+                handle: ContextHandle::default(),
+                data: [1; DPE_PROFILE.get_hash_size()],
+                flags: DeriveChildFlags::MAKE_DEFAULT,
+                tci_type: 7,
+                target_locality: 0,
+            }
+            .as_bytes(),
+        );
+        assert!(matches!(
+            dpe.execute_serialized_command(&mut env, TEST_LOCALITIES[0], &command),
+            Ok(Response::DeriveChild(_))
+        ));
+
+        let mut saved = [0; SAVED_STATE_SIZE];
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            dpe.save(&mut env, &mut saved[..SAVED_STATE_SIZE - 1])
+        );
+        assert_eq!(Ok(SAVED_STATE_SIZE), dpe.save(&mut env, &mut saved));
+        DpeInstance::commit_save(&mut env, &saved).unwrap();
+
+        let mut restored = DpeInstance::restore(&mut env, &saved).unwrap();
+        assert_eq!(dpe.as_bytes(), restored.as_bytes());
+        assert!(matches!(
+            restored.execute_serialized_command(&mut env, TEST_LOCALITIES[0], &command),
+            Ok(Response::DeriveChild(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_rejects_tampering() {
+        let mut env = new_env();
+        let dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        let mut saved = [0; SAVED_STATE_SIZE];
+        dpe.save(&mut env, &mut saved).unwrap();
+        DpeInstance::commit_save(&mut env, &saved).unwrap();
+
+        // Any changed byte breaks the MAC, including in the header and the MAC itself.
+        for offset in [
+            0,
+            size_of::<SavedStateHdr>(),
+            SAVED_STATE_SIZE / 2,
+            SAVED_STATE_SIZE - 1,
+        ] {
+            let mut tampered = saved;
+            tampered[offset] ^= 1;
+            assert_eq!(
+                Err(DpeErrorCode::InvalidInternalState),
+                DpeInstance::restore(&mut env, &tampered).map(|_| ())
+            );
+        }
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::restore(&mut env, &saved[..SAVED_STATE_SIZE - 1]).map(|_| ())
+        );
+        assert!(DpeInstance::restore(&mut env, &saved).is_ok());
+    }
+
+    #[test]
+    fn test_restore_rejects_rollback() {
+        let mut env = new_env();
+        let dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        let mut old = [0; SAVED_STATE_SIZE];
+        dpe.save(&mut env, &mut old).unwrap();
+        DpeInstance::commit_save(&mut env, &old).unwrap();
+        let mut new = [0; SAVED_STATE_SIZE];
+        dpe.save(&mut env, &mut new).unwrap();
+        DpeInstance::commit_save(&mut env, &new).unwrap();
+        // Committing again does nothing.
+        DpeInstance::commit_save(&mut env, &new).unwrap();
+
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::restore(&mut env, &old).map(|_| ())
+        );
+        assert_eq!(
+            Err(DpeErrorCode::InvalidArgument),
+            DpeInstance::commit_save(&mut env, &old)
+        );
+        assert!(DpeInstance::restore(&mut env, &new).is_ok());
+    }
+
+    #[test]
+    fn test_uncommitted_save() {
+        let mut env = new_env();
+        let mut dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        let mut old = [0; SAVED_STATE_SIZE];
+        dpe.save(&mut env, &mut old).unwrap();
+        DpeInstance::commit_save(&mut env, &old).unwrap();
+        let counter = env.platform.get_state_counter().unwrap();
+
+        // A save that is never committed, like one interrupted by a reset, can't be restored and
+        // leaves the previous state restorable.
+        let mut command = CommandHdr::new(Command::INITIALIZE_CONTEXT)
+            .as_bytes()
+            .to_vec();
+        command.extend(InitCtxCmd::new_simulation().as_bytes());
+        execute(&mut dpe, &mut env, TEST_LOCALITIES[1], &command);
+        let mut new = [0; SAVED_STATE_SIZE];
+        dpe.save(&mut env, &mut new).unwrap();
+        assert_eq!(counter, env.platform.get_state_counter().unwrap());
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::restore(&mut env, &new).map(|_| ())
+        );
+        let restored = DpeInstance::restore(&mut env, &old).unwrap();
+        assert_ne!(dpe.as_bytes(), restored.as_bytes());
+        assert_eq!(counter, env.platform.get_state_counter().unwrap());
+
+        // Once the caller commits it, the new state is restored and the previous one is rejected.
+        DpeInstance::commit_save(&mut env, &new).unwrap();
+        let restored = DpeInstance::restore(&mut env, &new).unwrap();
+        assert_eq!(dpe.as_bytes(), restored.as_bytes());
+        assert_eq!(counter + 1, env.platform.get_state_counter().unwrap());
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::restore(&mut env, &old).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_commit_rejects_tampering() {
+        let mut env = new_env();
+        let dpe = DpeInstance::new(&mut env, SUPPORT).unwrap();
+        let mut saved = [0; SAVED_STATE_SIZE];
+        dpe.save(&mut env, &mut saved).unwrap();
+        let counter = env.platform.get_state_counter().unwrap();
+
+        let mut tampered = saved;
+        tampered[SAVED_STATE_SIZE - 1] ^= 1;
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::commit_save(&mut env, &tampered)
+        );
+        assert_eq!(counter, env.platform.get_state_counter().unwrap());
+    }
+
+    /// Restores a golden blob saved with the layout of `version`.
+    fn restore_golden(env: &mut DpeEnv<TestTypes>, golden: &[u8], version: u32) -> DpeInstance {
+        let hdr = SavedStateHdr::read_from_prefix(golden).unwrap();
+        assert_eq!(version, hdr.version);
+        while env.platform.get_state_counter().unwrap() < hdr.counter {
+            env.platform.increment_state_counter().unwrap();
+        }
+        DpeInstance::restore(env, golden).unwrap()
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "max_handles_32", feature = "max_handles_64")))]
+    fn test_restore_golden_v2() {
+        let mut env = new_env();
+        let restored = restore_golden(&mut env, GOLDEN_V2, 2);
+        assert_eq!(golden_instance(&mut env).as_bytes(), restored.as_bytes());
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "max_handles_32", feature = "max_handles_64")))]
+    fn test_restore_golden_v1() {
+        let mut env = new_env();
+        let restored = restore_golden(&mut env, GOLDEN_V1, 1);
+        assert_eq!(golden_instance(&mut env).as_bytes(), restored.as_bytes());
+    }
+
+    #[test]
+    fn test_restore_golden_v0() {
+        let mut env = new_env();
+        let mut migrated = DpeInstance::restore_unversioned(GOLDEN_V0).unwrap();
+        let expected = golden_instance(&mut env);
+        assert_eq!(expected.contexts.as_bytes(), migrated.contexts.as_bytes());
+        assert_eq!(expected.support.bits(), migrated.support.bits());
+        assert_eq!(0, migrated.event_log.size());
+
+        // The migrated contexts keep working and the next save uses the current version.
+        let mut extend_tci = CommandHdr::new(Command::EXTEND_TCI).as_bytes().to_vec();
+        extend_tci.extend(ContextHandle::default().0);
+        extend_tci.extend([2; DPE_PROFILE.get_hash_size()]);
+        execute(&mut migrated, &mut env, TEST_LOCALITIES[0], &extend_tci);
+        let mut saved = [0; SAVED_STATE_SIZE];
+        migrated.save(&mut env, &mut saved).unwrap();
+        DpeInstance::commit_save(&mut env, &saved).unwrap();
+        assert_eq!(
+            SavedStateHdr::VERSION,
+            SavedStateHdr::read_from_prefix(saved.as_slice())
+                .unwrap()
+                .version
+        );
+        assert_eq!(
+            migrated.as_bytes(),
+            DpeInstance::restore(&mut env, &saved).unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_restore_unversioned_rejects_invalid() {
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::restore_unversioned(&GOLDEN_V0[1..]).map(|_| ())
+        );
+        // A child bit past the end of the old context table.
+        let mut invalid = GOLDEN_V0.to_vec();
+        let children = ContextHandle::SIZE + size_of::<v0::TciNodeDataV0>();
+        invalid[children + 3] = 0x80;
+        assert_eq!(
+            Err(DpeErrorCode::InvalidInternalState),
+            DpeInstance::restore_unversioned(&invalid).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_restore_rejects_invalid_fields() {
+        assert_rejected(|dpe| dpe.has_initialized = U8Bool { val: 0xff });
+        assert_rejected(|dpe| dpe.support = Support::from_bits_retain(1));
+        assert_rejected(|dpe| dpe.contexts[0].state = ContextState::_03);
+        assert_rejected(|dpe| dpe.contexts[1].context_type = ContextType::_02);
+        assert_rejected(|dpe| dpe.contexts[2].allow_x509 = U8Bool { val: 2 });
+        assert_rejected(|dpe| dpe.contexts[3].has_sign_stream = U8Bool { val: 0x80 });
+        // A consistent table is required too.
+        assert_rejected(|dpe| dpe.contexts[0].state = ContextState::Retired);
+    }
+}
@@ -0,0 +1,117 @@
+// Licensed under the Apache-2.0 license.
+
+//! Layout of a `DpeInstance` from before saved state was versioned, when firmware persisted the
+//! instance as raw bytes. It always had 24 contexts, each with a single `u32` of children.
+
+use crate::{
+    context::{Context, ContextBitmap, ContextHandle, ContextState, ContextType},
+    support::Support,
+    tci::{TciMeasurement, TciNodeData},
+    DpeInstance, U8Bool,
+};
+use zerocopy::{AsBytes, FromBytes};
+
+const MAX_HANDLES: usize = 24;
+
+#[repr(C, align(4))]
+#[derive(AsBytes, FromBytes)]
+pub(crate) struct TciNodeDataV0 {
+    tci_type: u32,
+    tci_cumulative: TciMeasurement,
+    tci_current: TciMeasurement,
+    locality: u32,
+}
+
+#[repr(C, align(4))]
+#[derive(AsBytes, FromBytes)]
+pub(crate) struct ContextV0 {
+    handle: ContextHandle,
+    tci: TciNodeDataV0,
+    children: u32,
+    locality: u32,
+    tag: u32,
+    has_tag: U8Bool,
+    parent_idx: u8,
+    context_type: ContextType,
+    state: ContextState,
+    uses_internal_input_info: U8Bool,
+    uses_internal_input_dice: U8Bool,
+    allow_ca: U8Bool,
+    allow_x509: U8Bool,
+}
+
+#[repr(C, align(4))]
+#[derive(AsBytes, FromBytes)]
+pub(crate) struct DpeInstanceV0 {
+    contexts: [ContextV0; MAX_HANDLES],
+    support: Support,
+    has_initialized: U8Bool,
+    reserved: [u8; 3],
+}
+
+impl ContextV0 {
+    fn migrate(&self) -> Option<Context> {
+        let mut children = ContextBitmap::empty();
+        for idx in 0..u32::BITS as usize {
+            if self.children & (1 << idx) != 0 {
+                if idx >= MAX_HANDLES {
+                    return None;
+                }
+                children.insert(idx);
+            }
+        }
+
+        let mut context = Context::new();
+        context.handle = self.handle;
+        context.tci = TciNodeData {
+            tci_type: self.tci.tci_type,
+            tci_cumulative: self.tci.tci_cumulative,
+            tci_current: self.tci.tci_current,
+            locality: self.tci.locality,
+        };
+        context.children = children;
+        context.locality = self.locality;
+        context.tag = self.tag;
+        context.has_tag = U8Bool {
+            val: self.has_tag.val,
+        };
+        context.parent_idx = self.parent_idx;
+        context.context_type = self.context_type;
+        context.state = self.state;
+        context.uses_internal_input_info = U8Bool {
+            val: self.uses_internal_input_info.val,
+        };
+        context.uses_internal_input_dice = U8Bool {
+            val: self.uses_internal_input_dice.val,
+        };
+        context.allow_ca = U8Bool {
+            val: self.allow_ca.val,
+        };
+        context.allow_x509 = U8Bool {
+            val: self.allow_x509.val,
+        };
+        // InitializeContext lets new roots export CDIs, so roots keep that permission.
+        context.allow_export = U8Bool::new(
+            self.state != ContextState::Inactive && self.parent_idx == Context::ROOT_INDEX,
+        );
+        Some(context)
+    }
+}
+
+impl DpeInstanceV0 {
+    /// Converts to the current layout.
+    ///
+    /// Fields added since keep the values a new context starts with, except that root contexts
+    /// may export CDIs as if InitializeContext had created them today. The event log starts
+    /// empty because the events behind the migrated measurements were never recorded.
+    pub fn migrate(&self) -> Option<DpeInstance> {
+        let mut dpe = DpeInstance::new_empty(Support::from_bits_retain(self.support.bits()));
+        dpe.has_initialized = U8Bool {
+            val: self.has_initialized.val,
+        };
+        for (context, old) in dpe.contexts.iter_mut().zip(self.contexts.iter()) {
+            *context = old.migrate()?;
+        }
+        Some(dpe)
+    }
+}
@@ -0,0 +1,96 @@
+// Licensed under the Apache-2.0 license.
+
+//! Layout of a `DpeInstance` from before streamed signing kept a running hash. Each context had a
+//! digest that chunks were folded into instead.
+
+use crate::{
+    context::{Context, ContextBitmap, ContextHandle, ContextState, ContextType},
+    event_log::EventLog,
+    support::Support,
+    tci::{TciMeasurement, TciNodeData},
+    DpeInstance, U8Bool, MAX_HANDLES,
+};
+use zerocopy::{AsBytes, FromBytes};
+
+#[repr(C, align(4))]
+#[derive(AsBytes, FromBytes)]
+pub(crate) struct ContextV1 {
+    handle: ContextHandle,
+    tci: TciNodeData,
+    children: ContextBitmap,
+    locality: u32,
+    tag: u32,
+    has_tag: U8Bool,
+    parent_idx: u8,
+    context_type: ContextType,
+    state: ContextState,
+    uses_internal_input_info: U8Bool,
+    uses_internal_input_dice: U8Bool,
+    allow_ca: U8Bool,
+    allow_x509: U8Bool,
+    allow_export: U8Bool,
+    sign_stream_digest: TciMeasurement,
+    has_sign_stream: U8Bool,
+    reserved: [u8; 2],
+}
+
+#[repr(C, align(4))]
+#[derive(AsBytes, FromBytes)]
+pub(crate) struct DpeInstanceV1 {
+    contexts: [ContextV1; MAX_HANDLES],
+    support: Support,
+    has_initialized: U8Bool,
+    reserved: [u8; 3],
+    event_log: EventLog,
+}
+
+impl ContextV1 {
+    fn migrate(&self) -> Context {
+        let mut context = Context::new();
+        context.handle = self.handle;
+        context.tci = self.tci;
+        context.children = self.children;
+        context.locality = self.locality;
+        context.tag = self.tag;
+        context.has_tag = U8Bool {
+            val: self.has_tag.val,
+        };
+        context.parent_idx = self.parent_idx;
+        context.context_type = self.context_type;
+        context.state = self.state;
+        context.uses_internal_input_info = U8Bool {
+            val: self.uses_internal_input_info.val,
+        };
+        context.uses_internal_input_dice = U8Bool {
+            val: self.uses_internal_input_dice.val,
+        };
+        context.allow_ca = U8Bool {
+            val: self.allow_ca.val,
+        };
+        context.allow_x509 = U8Bool {
+            val: self.allow_x509.val,
+        };
+        context.allow_export = U8Bool {
+            val: self.allow_export.val,
+        };
+        context
+    }
+}
+
+impl DpeInstanceV1 {
+    /// Converts to the current layout.
+    ///
+    /// A folded digest can't be turned back into a running hash, so streamed signing in progress
+    /// is abandoned and has to be started again.
+    pub fn migrate(self) -> DpeInstance {
+        let mut dpe = DpeInstance::new_empty(Support::from_bits_retain(self.support.bits()));
+        dpe.has_initialized = U8Bool {
+            val: self.has_initialized.val,
+        };
+        for (context, old) in dpe.contexts.iter_mut().zip(self.contexts.iter()) {
+            *context = old.migrate();
+        }
+        dpe.event_log = self.event_log;
+        dpe
+    }
+}
@@ -19,6 +19,13 @@ bitflags! {
         const INTERNAL_INFO = 1u32 << 23;
         const INTERNAL_DICE = 1u32 << 22;
         const IS_CA = 1u32 << 21;
+        const SEAL = 1u32 << 20;
+        const CDI_EXPORT = 1u32 << 19;
+        const CONTEXT_TABLE = 1u32 << 18;
+        const EVENT_LOG = 1u32 << 17;
+        const QUOTE = 1u32 << 16;
+        const EAT = 1u32 << 15;
+        const SIGN_MESSAGE = 1u32 << 14;
     }
 }
 
@@ -56,6 +63,27 @@ impl Support {
     pub fn is_ca(&self) -> bool {
         self.contains(Support::IS_CA)
     }
+    pub fn seal(&self) -> bool {
+        self.contains(Support::SEAL)
+    }
+    pub fn cdi_export(&self) -> bool {
+        self.contains(Support::CDI_EXPORT)
+    }
+    pub fn context_table(&self) -> bool {
+        self.contains(Support::CONTEXT_TABLE)
+    }
+    pub fn event_log(&self) -> bool {
+        self.contains(Support::EVENT_LOG)
+    }
+    pub fn quote(&self) -> bool {
+        self.contains(Support::QUOTE)
+    }
+    pub fn eat(&self) -> bool {
+        self.contains(Support::EAT)
+    }
+    pub fn sign_message(&self) -> bool {
+        self.contains(Support::SIGN_MESSAGE)
+    }
 }
 
 #[cfg(test)]
@@ -108,6 +136,27 @@ pub mod test {
         // Supports is ca.
         let flags = Support::IS_CA.bits();
         assert_eq!(flags, 1 << 21);
+        // Supports seal.
+        let flags = Support::SEAL.bits();
+        assert_eq!(flags, 1 << 20);
+        // Supports CDI export.
+        let flags = Support::CDI_EXPORT.bits();
+        assert_eq!(flags, 1 << 19);
+        // Supports context table introspection.
+        let flags = Support::CONTEXT_TABLE.bits();
+        assert_eq!(flags, 1 << 18);
+        // Supports the measurement event log.
+        let flags = Support::EVENT_LOG.bits();
+        assert_eq!(flags, 1 << 17);
+        // Supports quotes.
+        let flags = Support::QUOTE.bits();
+        assert_eq!(flags, 1 << 16);
+        // Supports entity attestation tokens.
+        let flags = Support::EAT.bits();
+        assert_eq!(flags, 1 << 15);
+        // Supports signing messages hashed inside DPE.
+        let flags = Support::SIGN_MESSAGE.bits();
+        assert_eq!(flags, 1 << 14);
         // Supports a couple combos.
         let flags = (Support::SIMULATION
             | Support::AUTO_INIT
@@ -144,6 +193,13 @@ pub mod test {
                 | (1 << 23)
                 | (1 << 22)
                 | (1 << 21)
+                | (1 << 20)
+                | (1 << 19)
+                | (1 << 18)
+                | (1 << 17)
+                | (1 << 16)
+                | (1 << 15)
+                | (1 << 14)
         );
     }
 }
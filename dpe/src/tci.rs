@@ -3,11 +3,14 @@ use crate::DPE_PROFILE;
 use zerocopy::{AsBytes, FromBytes};
 
 #[repr(C, align(4))]
-#[derive(Default, Copy, Clone, AsBytes, FromBytes)]
+#[derive(Default, Copy, Clone, Debug, AsBytes, FromBytes)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct TciNodeData {
     pub tci_type: u32,
     pub tci_cumulative: TciMeasurement,
     pub tci_current: TciMeasurement,
+    /// Locality that currently owns the node. It follows the context when RotateContextHandle
+    /// hands it to another locality, so a transferred context derives a different CDI.
     pub locality: u32,
 }
 
@@ -24,6 +24,8 @@ pub struct MeasurementData<'a> {
     pub label: &'a [u8],
     pub tci_nodes: &'a [TciNodeData],
     pub is_ca: bool,
+    /// Whether the key belongs to a simulation context.
+    pub is_simulation: bool,
 }
 
 pub struct X509CertWriter<'a> {
@@ -37,6 +39,7 @@ impl X509CertWriter<'_> {
     const INTEGER_TAG: u8 = 0x2;
     const BIT_STRING_TAG: u8 = 0x3;
     const OCTET_STRING_TAG: u8 = 0x4;
+    const NULL_TAG: u8 = 0x5;
     const OID_TAG: u8 = 0x6;
     const PRINTABLE_STRING_TAG: u8 = 0x13;
     const GENERALIZE_TIME_TAG: u8 = 0x18;
@@ -90,6 +93,13 @@ impl X509CertWriter<'_> {
     // tcg-dice-kp-attestLoc 2.23.133.5.4.100.9
     const ATTEST_LOC_OID: &[u8] = &[0x67, 0x81, 0x05, 0x05, 0x04, 0x64, 0x09];
 
+    // dpe-simulation 2.25.52483794066590491150682596598545988285
+    // (UUID 277c02d4-bfb5-4825-a626-c0c8d4b13abd)
+    pub(crate) const SIMULATION_OID: &[u8] = &[
+        0x69, 0xCE, 0xFC, 0x81, 0xB5, 0x97, 0xFB, 0xAA, 0xA0, 0xCB, 0xA6, 0x93, 0xB0, 0x99, 0x8D,
+        0xA5, 0xC4, 0xF5, 0x3D,
+    ];
+
     // RFC 5280 2.5.29.19
     const BASIC_CONSTRAINTS_OID: &[u8] = &[0x55, 0x1D, 0x13];
 
@@ -369,6 +379,18 @@ impl X509CertWriter<'_> {
         Self::get_structure_size(size, tagged)
     }
 
+    /// Get the size of the simulation marker extension, including the
+    /// extension OID and critical bits.
+    fn get_simulation_marker_size(tagged: bool) -> Result<usize, DpeErrorCode> {
+        // Extension data is octet string -> NULL.
+        let ext_size = Self::get_structure_size(0, /*tagged=*/ true)?;
+        let size = Self::get_structure_size(Self::SIMULATION_OID.len(), /*tagged=*/true)? // Extension OID
+            + Self::get_structure_size(Self::BOOL_SIZE, /*tagged=*/true)? // Critical bool
+            + Self::get_structure_size(ext_size, /*tagged=*/true)?; // OCTET STRING
+
+        Self::get_structure_size(size, tagged)
+    }
+
     /// Get the size of the TBS Extensions field.
     fn get_extensions_size(
         measurements: &MeasurementData,
@@ -380,6 +402,9 @@ impl X509CertWriter<'_> {
             + Self::get_basic_constraints_size(/*tagged=*/ true)?
             + Self::get_key_usage_size(/*tagged=*/ true)?
             + Self::get_extended_key_usage_size(measurements, /*tagged=*/ true)?;
+        if measurements.is_simulation {
+            size += Self::get_simulation_marker_size(/*tagged=*/ true)?;
+        }
 
         // Determine whether to include the explicit tag wrapping in the size calculation
         size = Self::get_structure_size(size, /*tagged=*/ explicit)?;
@@ -989,6 +1014,33 @@ impl X509CertWriter<'_> {
         Ok(bytes_written)
     }
 
+    /// Encode the simulation marker extension
+    ///
+    /// The extension is always critical so that verifiers which do not know
+    /// about it reject the certificate rather than treating it as production
+    /// evidence.
+    fn encode_simulation_marker(&mut self) -> Result<usize, DpeErrorCode> {
+        let marker_size = Self::get_simulation_marker_size(/*tagged=*/ false)?;
+
+        // Encode Extension
+        let mut bytes_written = self.encode_byte(Self::SEQUENCE_TAG)?;
+        bytes_written += self.encode_size_field(marker_size)?;
+        bytes_written += self.encode_oid(Self::SIMULATION_OID)?;
+
+        bytes_written += self.encode_byte(Self::BOOL_TAG)?;
+        bytes_written += self.encode_size_field(Self::BOOL_SIZE)?;
+        bytes_written += self.encode_byte(0xFF)?;
+
+        // Extension data is octet string -> NULL.
+        bytes_written += self.encode_byte(Self::OCTET_STRING_TAG)?;
+        bytes_written +=
+            self.encode_size_field(Self::get_structure_size(0, /*tagged=*/ true)?)?;
+        bytes_written += self.encode_byte(Self::NULL_TAG)?;
+        bytes_written += self.encode_size_field(0)?;
+
+        Ok(bytes_written)
+    }
+
     fn encode_extensions(&mut self, measurements: &MeasurementData) -> Result<usize, DpeErrorCode> {
         // Extensions is EXPLICIT field number 3
         let mut bytes_written =
@@ -1012,6 +1064,9 @@ impl X509CertWriter<'_> {
         bytes_written += self.encode_basic_constraints(measurements)?;
         bytes_written += self.encode_key_usage()?;
         bytes_written += self.encode_extended_key_usage(measurements)?;
+        if measurements.is_simulation {
+            bytes_written += self.encode_simulation_marker()?;
+        }
 
         Ok(bytes_written)
     }
@@ -1329,6 +1384,7 @@ mod tests {
             label: &[0xCC; DPE_PROFILE.get_hash_size()],
             tci_nodes: &[node],
             is_ca: false,
+            is_simulation: false,
         };
 
         let bytes_written = w
@@ -1360,6 +1416,57 @@ mod tests {
         assert_eq!(parsed_ueid.ueid, measurements.label);
     }
 
+    #[test]
+    fn test_simulation_marker() {
+        let test_subject_name = Name {
+            cn: b"DPE Leaf",
+            serial: [0x00; DPE_PROFILE.get_hash_size() * 2],
+        };
+
+        const ECC_INT_SIZE: usize = DPE_PROFILE.get_ecc_int_size();
+        const ALG_LEN: AlgLen = DPE_PROFILE.alg_len();
+        let test_pub = EcdsaPub {
+            x: CryptoBuf::new(&[0xAA; ECC_INT_SIZE], ALG_LEN).unwrap(),
+            y: CryptoBuf::new(&[0xBB; ECC_INT_SIZE], ALG_LEN).unwrap(),
+        };
+
+        let node = TciNodeData::new();
+
+        for is_simulation in [false, true] {
+            let mut cert = [0u8; 1024];
+            let mut w = X509CertWriter::new(&mut cert, true);
+            let measurements = MeasurementData {
+                label: &[0xCC; DPE_PROFILE.get_hash_size()],
+                tci_nodes: &[node],
+                is_ca: false,
+                is_simulation,
+            };
+            w.encode_ecdsa_tbs(
+                &[0x1F; 20],
+                &encode_test_issuer(),
+                &test_subject_name,
+                &test_pub,
+                &measurements,
+            )
+            .unwrap();
+
+            let mut parser = TbsCertificateParser::new().with_deep_parse_extensions(false);
+            let (_, cert) = parser.parse(&cert).unwrap();
+            let marker = cert
+                .extensions()
+                .iter()
+                .find(|ext| ext.oid.as_bytes() == X509CertWriter::SIMULATION_OID);
+            match marker {
+                Some(marker) => {
+                    assert!(is_simulation);
+                    assert!(marker.critical);
+                    assert_eq!(marker.value, &[0x05, 0x00]);
+                }
+                None => assert!(!is_simulation),
+            }
+        }
+    }
+
     #[test]
     fn test_full_cert() {
         let test_serial = [0x1F; 20];
@@ -1393,6 +1500,7 @@ mod tests {
             label: &[0; DPE_PROFILE.get_hash_size()],
             tci_nodes: &[node],
             is_ca: true,
+            is_simulation: false,
         };
 
         let mut tbs = [0u8; 1024];
@@ -1,14 +1,23 @@
 // Licensed under the Apache-2.0 license
 
-use crate::{Platform, PlatformError, MAX_CHUNK_SIZE};
+use crate::{Platform, PlatformError, MAX_CHUNK_SIZE, MAX_STATE_SECRET_SIZE, MAX_UEID_SIZE};
 use core::cmp::min;
 use openssl::x509::X509;
 
-pub struct DefaultPlatform;
+#[derive(Default)]
+pub struct DefaultPlatform {
+    state_counter: u32,
+}
 
 pub const AUTO_INIT_LOCALITY: u32 = 0;
 pub const VENDOR_ID: u32 = 0;
 pub const VENDOR_SKU: u32 = 0;
+/// A random (type 0x01) UEID.
+pub const TEST_UEID: [u8; 17] = [
+    0x01, 0x5a, 0x1e, 0x7d, 0x3c, 0x90, 0x4b, 0x2f, 0x66, 0xa1, 0x08, 0xd4, 0x73, 0xe9, 0x15, 0xbc,
+    0x42,
+];
+pub const TEST_STATE_SECRET: [u8; 32] = [0x5e; 32];
 
 // Run ./generate.sh to generate all test certs and test private keys
 #[cfg(feature = "dpe_profile_p256_sha256")]
@@ -17,6 +26,12 @@ pub const TEST_CERT_CHAIN: &[u8; 613] = include_bytes!("test_data/cert_256.der")
 #[cfg(feature = "dpe_profile_p384_sha384")]
 pub const TEST_CERT_CHAIN: &[u8; 674] = include_bytes!("test_data/cert_384.der");
 
+impl DefaultPlatform {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
 impl Platform for DefaultPlatform {
     fn get_certificate_chain(
         &mut self,
@@ -64,4 +79,29 @@ impl Platform for DefaultPlatform {
     fn get_auto_init_locality(&mut self) -> Result<u32, PlatformError> {
         Ok(AUTO_INIT_LOCALITY)
     }
+
+    fn get_ueid(&mut self, out: &mut [u8; MAX_UEID_SIZE]) -> Result<usize, PlatformError> {
+        out[..TEST_UEID.len()].copy_from_slice(&TEST_UEID);
+        Ok(TEST_UEID.len())
+    }
+
+    fn get_state_secret(
+        &mut self,
+        out: &mut [u8; MAX_STATE_SECRET_SIZE],
+    ) -> Result<usize, PlatformError> {
+        out[..TEST_STATE_SECRET.len()].copy_from_slice(&TEST_STATE_SECRET);
+        Ok(TEST_STATE_SECRET.len())
+    }
+
+    fn get_state_counter(&mut self) -> Result<u32, PlatformError> {
+        Ok(self.state_counter)
+    }
+
+    fn increment_state_counter(&mut self) -> Result<u32, PlatformError> {
+        self.state_counter = self
+            .state_counter
+            .checked_add(1)
+            .ok_or(PlatformError::CounterError)?;
+        Ok(self.state_counter)
+    }
 }
@@ -12,12 +12,16 @@ pub use openssl::x509::X509;
 pub mod default;
 
 pub const MAX_CHUNK_SIZE: usize = 2048;
+pub const MAX_UEID_SIZE: usize = 33;
+pub const MAX_STATE_SECRET_SIZE: usize = 64;
 
 #[derive(Debug)]
 pub enum PlatformError {
     CertificateChainError,
     NotImplemented,
     IssuerNameError,
+    StateSecretError,
+    CounterError,
 }
 
 pub trait Platform {
@@ -47,4 +51,81 @@ pub trait Platform {
     fn get_vendor_sku(&mut self) -> Result<u32, PlatformError>;
 
     fn get_auto_init_locality(&mut self) -> Result<u32, PlatformError>;
+
+    /// Retrieves the device's Universal Entity ID (UEID), as defined by the Entity Attestation
+    /// Token spec. The first byte is the UEID type.
+    ///
+    /// Platforms without a UEID don't need to implement this, but can't produce quotes or EATs.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - Output buffer for the UEID to be written to.
+    fn get_ueid(&mut self, _out: &mut [u8; MAX_UEID_SIZE]) -> Result<usize, PlatformError> {
+        Err(PlatformError::NotImplemented)
+    }
+
+    /// Checks whether a context may be handed from one locality to another. Any transfer is
+    /// allowed unless the platform restricts them.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Locality that currently owns the context.
+    /// * `to` - Locality the context would be handed to.
+    fn can_transfer_locality(&mut self, _from: u32, _to: u32) -> Result<bool, PlatformError> {
+        Ok(true)
+    }
+
+    /// Retrieves the maximum number of contexts a locality may hold at once. Platforms that don't
+    /// limit localities return `u32::MAX`.
+    ///
+    /// # Arguments
+    ///
+    /// * `locality` - Locality to look up.
+    fn get_locality_quota(&mut self, _locality: u32) -> Result<u32, PlatformError> {
+        Ok(u32::MAX)
+    }
+
+    /// Checks whether a locality may execute a command. DPE asks before executing every command,
+    /// including each command in a batch. Every command is allowed unless the platform restricts
+    /// them.
+    ///
+    /// # Arguments
+    ///
+    /// * `locality` - Locality issuing the command.
+    /// * `command_id` - ID of the command.
+    /// * `flags` - The command's flags, or 0 if it has none.
+    fn can_execute_command(
+        &mut self,
+        _locality: u32,
+        _command_id: u32,
+        _flags: u32,
+    ) -> Result<bool, PlatformError> {
+        Ok(true)
+    }
+
+    /// Retrieves the secret DPE keys the MAC over its saved state with. It must stay the same for
+    /// as long as saved state has to be restored, and must not be readable outside of DPE.
+    ///
+    /// Platforms that don't save DPE state don't need to implement this or the state counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - Output buffer for the secret to be written to.
+    fn get_state_secret(
+        &mut self,
+        _out: &mut [u8; MAX_STATE_SECRET_SIZE],
+    ) -> Result<usize, PlatformError> {
+        Err(PlatformError::NotImplemented)
+    }
+
+    /// Reads the monotonic counter that protects saved state against rollback.
+    fn get_state_counter(&mut self) -> Result<u32, PlatformError> {
+        Err(PlatformError::NotImplemented)
+    }
+
+    /// Increments the monotonic counter that protects saved state against rollback, and returns
+    /// its new value.
+    fn increment_state_counter(&mut self) -> Result<u32, PlatformError> {
+        Err(PlatformError::NotImplemented)
+    }
 }
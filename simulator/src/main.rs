@@ -2,22 +2,24 @@
 
 use clap::Parser;
 use crypto::OpensslCrypto;
-use log::{error, info, trace, warn};
-use platform::default::DefaultPlatform;
+use log::{debug, error, info, warn};
+use policy::{Policy, PolicyPlatform};
 use std::fs;
 use std::io::{Error, ErrorKind, Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 
 use dpe::{
-    commands::Command,
     dpe_instance::{DpeEnv, DpeTypes},
-    response::Response,
+    event_sink::LogSink,
+    response::{Response, ResponseHdr},
     support::Support,
     DpeInstance,
 };
 
+mod policy;
+
 const SOCKET_PATH: &str = "/tmp/dpe-sim.socket";
 
 fn handle_request(dpe: &mut DpeInstance, env: &mut DpeEnv<impl DpeTypes>, stream: &mut UnixStream) {
@@ -30,35 +32,16 @@ fn handle_request(dpe: &mut DpeInstance, env: &mut DpeEnv<impl DpeTypes>, stream
         )
     };
 
-    trace!("----------------------------------");
-    if let Ok(command) = Command::deserialize(cmd) {
-        trace!("| Locality `{locality:#x}` requested {command:x?}",);
-    } else {
-        trace!("| Locality `{locality:#010x}` requested invalid command. {cmd:02x?}")
-    }
-    trace!("|");
-
-    let response = dpe.execute_serialized_command(env, locality, cmd).unwrap();
-
-    let response_code = match response {
-        Response::GetProfile(ref res) => res.resp_hdr.status,
-        Response::InitCtx(ref res) => res.resp_hdr.status,
-        Response::DeriveChild(ref res) => res.resp_hdr.status,
-        Response::RotateCtx(ref res) => res.resp_hdr.status,
-        Response::CertifyKey(ref res) => res.resp_hdr.status,
-        Response::Sign(ref res) => res.resp_hdr.status,
-        Response::DestroyCtx(ref resp_hdr) => resp_hdr.status,
-        Response::ExtendTci(ref res) => res.resp_hdr.status,
-        Response::TagTci(ref res) => res.resp_hdr.status,
-        Response::GetTaggedTci(ref res) => res.resp_hdr.status,
-        Response::GetCertificateChain(ref res) => res.resp_hdr.status,
-        Response::Error(ref resp_hdr) => resp_hdr.status,
-    };
-    // There are a few vendor error codes starting at 0x1000, so this can be a 2 bytes.
-    trace!("| Response Code {response_code:#06x}");
-    trace!("----------------------------------");
+    // Commands that can't be parsed still get a response so the client sees the error. Ones that
+    // can are logged by the event sink.
+    let response = dpe
+        .execute_serialized_command(env, locality, cmd)
+        .unwrap_or_else(|err| {
+            debug!("Locality {locality:#010x} sent an invalid command: {err:?} {cmd:02x?}");
+            Response::Error(ResponseHdr::new(err))
+        });
 
-    stream.write_all(response.as_bytes()).unwrap();
+    stream.write_all(response.serialize()).unwrap();
 }
 
 fn cleanup() {
@@ -114,18 +97,53 @@ struct Args {
     /// Supports the INTERNAL_INPUT_DICE extension to DeriveChild
     #[arg(long)]
     supports_internal_dice: bool,
+
+    /// Supports the Seal and Unseal commands.
+    #[arg(long)]
+    supports_seal: bool,
+
+    /// Supports exporting CDIs with DeriveChild.
+    #[arg(long)]
+    supports_cdi_export: bool,
+
+    /// Supports the GetContextTable diagnostic command.
+    #[arg(long)]
+    supports_context_table: bool,
+
+    /// Supports the GetEventLog command.
+    #[arg(long)]
+    supports_event_log: bool,
+
+    /// Supports the Quote command.
+    #[arg(long)]
+    supports_quote: bool,
+
+    /// Supports the GetEat command.
+    #[arg(long)]
+    supports_eat: bool,
+
+    /// Supports the SignMessage and streamed Sign commands.
+    #[arg(long)]
+    supports_sign_message: bool,
+
+    /// File of rules restricting which localities may execute which commands. Without one, only
+    /// the auto-init locality may use DeriveChild with CHANGE_LOCALITY.
+    #[arg(long)]
+    policy: Option<PathBuf>,
 }
 
 struct SimTypes {}
 
 impl DpeTypes for SimTypes {
     type Crypto<'a> = OpensslCrypto;
-    type Platform<'a> = DefaultPlatform;
+    type Platform<'a> = PolicyPlatform;
+    type EventSink<'a> = LogSink;
 }
 
 fn main() -> std::io::Result<()> {
     env_logger::init();
     let args = Args::parse();
+    let policy = args.policy.as_deref().map(Policy::load).transpose()?;
 
     let socket = Path::new(SOCKET_PATH);
     // Delete old socket if necessary
@@ -133,7 +151,12 @@ fn main() -> std::io::Result<()> {
         cleanup();
     }
 
-    let listener = UnixListener::bind(socket)?;
+    // Bind under a temporary name and move the socket into place once it's listening, so clients
+    // waiting for the socket to appear never find it refusing connections.
+    let bound = format!("{SOCKET_PATH}.new");
+    let _ = fs::remove_file(&bound);
+    let listener = UnixListener::bind(&bound)?;
+    fs::rename(&bound, socket)?;
 
     ctrlc::set_handler(move || {
         cleanup();
@@ -153,10 +176,18 @@ fn main() -> std::io::Result<()> {
     support.set(Support::IS_CA, args.supports_is_ca);
     support.set(Support::IS_SYMMETRIC, args.supports_is_symmetric);
     support.set(Support::TAGGING, args.supports_tagging);
+    support.set(Support::SEAL, args.supports_seal);
+    support.set(Support::CDI_EXPORT, args.supports_cdi_export);
+    support.set(Support::CONTEXT_TABLE, args.supports_context_table);
+    support.set(Support::EVENT_LOG, args.supports_event_log);
+    support.set(Support::QUOTE, args.supports_quote);
+    support.set(Support::EAT, args.supports_eat);
+    support.set(Support::SIGN_MESSAGE, args.supports_sign_message);
 
     let mut env = DpeEnv::<SimTypes> {
         crypto: OpensslCrypto::new(),
-        platform: DefaultPlatform,
+        platform: PolicyPlatform::new(policy),
+        event_sink: LogSink,
     };
 
     let mut dpe = DpeInstance::new(&mut env, support).map_err(|err| {
@@ -0,0 +1,336 @@
+// Licensed under the Apache-2.0 license
+
+use dpe::commands::{Command, DeriveChildFlags};
+use platform::{
+    default::{DefaultPlatform, AUTO_INIT_LOCALITY},
+    Platform, PlatformError, MAX_CHUNK_SIZE, MAX_STATE_SECRET_SIZE, MAX_UEID_SIZE,
+};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Which localities may execute which commands, read from a policy file.
+///
+/// Each line of the file is a rule of the form `allow|deny <locality> <command> [<flags>]`.
+/// Localities, command IDs and flags are decimal or `0x`-prefixed hex, and `*` matches any
+/// locality or command. A rule with flags only matches commands that set one of them. The first
+/// matching rule decides, and commands no rule matches are allowed. `#` starts a comment.
+///
+/// For example, this only lets locality 0 use DeriveChild with CHANGE_LOCALITY:
+///
+/// ```text
+/// allow 0 0x08 0x08000000
+/// deny  * 0x08 0x08000000
+/// ```
+#[derive(Debug)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    allow: bool,
+    locality: Option<u32>,
+    command_id: Option<u32>,
+    flags: u32,
+}
+
+impl Rule {
+    fn matches(&self, locality: u32, command_id: u32, flags: u32) -> bool {
+        self.locality.is_none_or(|l| l == locality)
+            && self.command_id.is_none_or(|id| id == command_id)
+            && (self.flags == 0 || self.flags & flags != 0)
+    }
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_pattern(s: &str) -> Option<Option<u32>> {
+    if s == "*" {
+        Some(None)
+    } else {
+        parse_number(s).map(Some)
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let mut fields = line.split_whitespace();
+    let allow = match fields.next()? {
+        "allow" => true,
+        "deny" => false,
+        _ => return None,
+    };
+    let locality = parse_pattern(fields.next()?)?;
+    let command_id = parse_pattern(fields.next()?)?;
+    let flags = match fields.next() {
+        Some(flags) => parse_number(flags)?,
+        None => 0,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(Rule {
+        allow,
+        locality,
+        command_id,
+        flags,
+    })
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> std::io::Result<Policy> {
+        Self::parse(&path.display().to_string(), &fs::read_to_string(path)?)
+    }
+
+    /// Parses the rules in `text`. `name` is where they came from, for error messages.
+    fn parse(name: &str, text: &str) -> std::io::Result<Policy> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let rule = parse_rule(line).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{name}:{}: invalid rule `{line}`", i + 1),
+                )
+            })?;
+            rules.push(rule);
+        }
+        Ok(Policy { rules })
+    }
+
+    pub fn allows(&self, locality: u32, command_id: u32, flags: u32) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(locality, command_id, flags))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// The default platform, except that only the boot locality may hand contexts to other localities.
+///
+/// A policy, if there is one, decides which commands each locality may execute. Without one, only
+/// the boot locality may use DeriveChild with CHANGE_LOCALITY.
+pub struct PolicyPlatform {
+    pub policy: Option<Policy>,
+    platform: DefaultPlatform,
+}
+
+impl PolicyPlatform {
+    pub fn new(policy: Option<Policy>) -> Self {
+        Self {
+            policy,
+            platform: DefaultPlatform::new(),
+        }
+    }
+}
+
+impl Platform for PolicyPlatform {
+    fn get_certificate_chain(
+        &mut self,
+        offset: u32,
+        size: u32,
+        out: &mut [u8; MAX_CHUNK_SIZE],
+    ) -> Result<u32, PlatformError> {
+        self.platform.get_certificate_chain(offset, size, out)
+    }
+
+    fn get_issuer_name(&mut self, out: &mut [u8; MAX_CHUNK_SIZE]) -> Result<usize, PlatformError> {
+        self.platform.get_issuer_name(out)
+    }
+
+    fn get_vendor_id(&mut self) -> Result<u32, PlatformError> {
+        self.platform.get_vendor_id()
+    }
+
+    fn get_vendor_sku(&mut self) -> Result<u32, PlatformError> {
+        self.platform.get_vendor_sku()
+    }
+
+    fn get_auto_init_locality(&mut self) -> Result<u32, PlatformError> {
+        self.platform.get_auto_init_locality()
+    }
+
+    fn get_ueid(&mut self, out: &mut [u8; MAX_UEID_SIZE]) -> Result<usize, PlatformError> {
+        self.platform.get_ueid(out)
+    }
+
+    fn can_transfer_locality(&mut self, from: u32, _to: u32) -> Result<bool, PlatformError> {
+        Ok(from == AUTO_INIT_LOCALITY)
+    }
+
+    fn get_locality_quota(&mut self, locality: u32) -> Result<u32, PlatformError> {
+        self.platform.get_locality_quota(locality)
+    }
+
+    fn can_execute_command(
+        &mut self,
+        locality: u32,
+        command_id: u32,
+        flags: u32,
+    ) -> Result<bool, PlatformError> {
+        match &self.policy {
+            Some(policy) => Ok(policy.allows(locality, command_id, flags)),
+            None => Ok(locality == AUTO_INIT_LOCALITY
+                || command_id != Command::DERIVE_CHILD
+                || !DeriveChildFlags::from_bits_retain(flags)
+                    .contains(DeriveChildFlags::CHANGE_LOCALITY)),
+        }
+    }
+
+    fn get_state_secret(
+        &mut self,
+        out: &mut [u8; MAX_STATE_SECRET_SIZE],
+    ) -> Result<usize, PlatformError> {
+        self.platform.get_state_secret(out)
+    }
+
+    fn get_state_counter(&mut self) -> Result<u32, PlatformError> {
+        self.platform.get_state_counter()
+    }
+
+    fn increment_state_counter(&mut self) -> Result<u32, PlatformError> {
+        self.platform.increment_state_counter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DERIVE_CHILD: u32 = Command::DERIVE_CHILD;
+    const SIGN: u32 = Command::SIGN;
+    const CHANGE_LOCALITY: u32 = DeriveChildFlags::CHANGE_LOCALITY.bits();
+
+    fn parse(text: &str) -> Policy {
+        Policy::parse("test", text).unwrap()
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let policy = parse("deny 1 0x0A\nallow 2 10");
+        assert!(!policy.allows(1, SIGN, 0));
+        assert!(policy.allows(2, SIGN, 0));
+        // Nothing matches, so it's allowed.
+        assert!(policy.allows(3, SIGN, 0));
+        assert!(policy.allows(1, DERIVE_CHILD, 0));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let policy = parse("deny * 0x0A\ndeny 7 *");
+        assert!(!policy.allows(0, SIGN, 0));
+        assert!(!policy.allows(u32::MAX, SIGN, 0));
+        assert!(!policy.allows(7, DERIVE_CHILD, 0));
+        assert!(policy.allows(0, DERIVE_CHILD, 0));
+    }
+
+    #[test]
+    fn test_numbers() {
+        // Hex and decimal spell the same rule.
+        for text in ["deny 0x10 0x08 0x08000000", "deny 16 8 134217728"] {
+            let policy = parse(text);
+            assert!(!policy.allows(16, DERIVE_CHILD, CHANGE_LOCALITY));
+            assert!(policy.allows(17, DERIVE_CHILD, CHANGE_LOCALITY));
+        }
+    }
+
+    #[test]
+    fn test_flags_mask() {
+        let policy = parse("deny * 0x08 0x08000001");
+        // The rule matches if any of its flags are set.
+        assert!(!policy.allows(0, DERIVE_CHILD, CHANGE_LOCALITY));
+        assert!(!policy.allows(0, DERIVE_CHILD, 1));
+        assert!(!policy.allows(0, DERIVE_CHILD, CHANGE_LOCALITY | 2));
+        assert!(policy.allows(0, DERIVE_CHILD, 2));
+        assert!(policy.allows(0, DERIVE_CHILD, 0));
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let policy = parse(
+            "allow 0 0x08 0x08000000\n\
+             deny  * 0x08 0x08000000\n\
+             allow * 0x08",
+        );
+        assert!(policy.allows(0, DERIVE_CHILD, CHANGE_LOCALITY));
+        assert!(!policy.allows(1, DERIVE_CHILD, CHANGE_LOCALITY));
+        assert!(policy.allows(1, DERIVE_CHILD, 0));
+
+        let policy = parse("deny * *\nallow 0 *");
+        assert!(!policy.allows(0, SIGN, 0));
+    }
+
+    #[test]
+    fn test_comments() {
+        let policy = parse(
+            "# Only locality 0 may sign.\n\
+             \n\
+             allow 0 0x0A # the boot locality\n\
+             deny * 0x0A\n\
+             # deny 0 0x0A",
+        );
+        assert!(policy.allows(0, SIGN, 0));
+        assert!(!policy.allows(1, SIGN, 0));
+        assert!(parse("# nothing but a comment").rules.is_empty());
+    }
+
+    #[test]
+    fn test_malformed() {
+        for text in [
+            "permit 0 0x0A",
+            "allow",
+            "allow 0",
+            "allow x 0x0A",
+            "allow 0 0xZZ",
+            "allow 0 0x0A flags",
+            "allow 0 0x0A 1 2",
+            "allow -1 0x0A",
+            "allow 0 0x100000000",
+            "allow 0 0x0A *",
+        ] {
+            assert!(Policy::parse("test", text).is_err(), "{text}");
+        }
+
+        // The error points at the offending line.
+        let err = Policy::parse("policy.txt", "allow 0 0x0A\n# fine\ndeny 0").unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+        assert_eq!("policy.txt:3: invalid rule `deny 0`", err.to_string());
+    }
+
+    #[test]
+    fn test_default_policy() {
+        // Without a policy file, only the boot locality may derive for other localities.
+        let mut platform = PolicyPlatform::new(None);
+        assert!(platform
+            .can_execute_command(0, DERIVE_CHILD, CHANGE_LOCALITY)
+            .unwrap());
+        assert!(!platform
+            .can_execute_command(1, DERIVE_CHILD, CHANGE_LOCALITY)
+            .unwrap());
+        assert!(platform.can_execute_command(1, DERIVE_CHILD, 0).unwrap());
+
+        let mut platform = PolicyPlatform::new(Some(parse("allow * *")));
+        assert!(platform
+            .can_execute_command(1, DERIVE_CHILD, CHANGE_LOCALITY)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_transfer_locality() {
+        let mut platform = PolicyPlatform::new(None);
+        assert!(platform
+            .can_transfer_locality(AUTO_INIT_LOCALITY, 1)
+            .unwrap());
+        assert!(!platform
+            .can_transfer_locality(1, AUTO_INIT_LOCALITY)
+            .unwrap());
+    }
+}
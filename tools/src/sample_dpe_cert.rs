@@ -2,14 +2,13 @@
 
 use {
     crypto::OpensslCrypto,
-    dpe::commands::{self, CertifyKeyCmd, CertifyKeyFlags, CommandHdr},
+    dpe::commands::{self, CertifyKeyCmd, CertifyKeyFlags, Command},
     dpe::context::ContextHandle,
     dpe::dpe_instance::{DpeEnv, DpeTypes},
     dpe::response::Response,
     dpe::{support::Support, DpeInstance, DPE_PROFILE},
     pem::{encode_config, EncodeConfig, LineEnding, Pem},
     platform::default::DefaultPlatform,
-    zerocopy::AsBytes,
 };
 
 pub struct TestTypes {}
@@ -17,6 +16,7 @@ pub struct TestTypes {}
 impl DpeTypes for TestTypes {
     type Crypto<'a> = OpensslCrypto;
     type Platform<'a> = DefaultPlatform;
+    type EventSink<'a> = ();
 }
 
 fn main() {
@@ -24,7 +24,8 @@ fn main() {
 
     let mut env = DpeEnv::<TestTypes> {
         crypto: OpensslCrypto::new(),
-        platform: DefaultPlatform,
+        platform: DefaultPlatform::new(),
+        event_sink: (),
     };
 
     let mut dpe = DpeInstance::new(&mut env, support).unwrap();
@@ -35,15 +36,13 @@ fn main() {
         label: [0; DPE_PROFILE.get_hash_size()],
         format: commands::CertifyKeyCmd::FORMAT_X509,
     };
-    let cmd_body = certify_key_cmd.as_bytes().to_vec();
-    let cmd_hdr = CommandHdr::new_for_test(dpe::commands::Command::CERTIFY_KEY)
-        .as_bytes()
-        .to_vec();
-    let mut command = cmd_hdr;
-    command.extend(cmd_body);
+    let mut command = [0u8; 512];
+    let size = Command::CertifyKey(certify_key_cmd)
+        .serialize(&mut command)
+        .unwrap();
 
     let resp = dpe
-        .execute_serialized_command(&mut env, 0, &command)
+        .execute_serialized_command(&mut env, 0, &command[..size])
         .unwrap();
 
     let certify_key_response = match resp {
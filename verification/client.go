@@ -3,6 +3,7 @@
 package verification
 
 import (
+	"encoding/binary"
 	"fmt"
 )
 
@@ -165,30 +166,33 @@ func (c *Client[_, _]) DestroyContext(cmd *DestroyCtxCmd) error {
 
 // CertifyKey calls the DPE CertifyKey command.
 func (c *Client[CurveParameter, Digest]) CertifyKey(cmd *CertifyKeyReq[Digest]) (*CertifyKeyResp[CurveParameter, Digest], error) {
-	// Define an anonymous struct for the response, because we have to accept the variable-sized certificate.
+	// Define an anonymous struct for the fixed part of the response. The certificate that follows is only
+	// CertificateSize bytes long.
 	respStruct := struct {
 		NewContextHandle  [16]byte
 		DerivedPublicKeyX CurveParameter
 		DerivedPublicKeyY CurveParameter
 		CertificateSize   uint32
-		Certificate       [2048]byte
 	}{}
 
-	_, err := execCommand(c.transport, CommandCertifyKey, c.Profile, cmd, &respStruct)
+	_, r, err := sendCommand(c.transport, CommandCertifyKey, c.Profile, cmd)
 	if err != nil {
 		return nil, err
 	}
+	if err = binary.Read(r, binary.LittleEndian, &respStruct); err != nil {
+		return nil, err
+	}
 
-	// Check that the reported cert size makes sense.
-	if respStruct.CertificateSize > 2048 {
-		return nil, fmt.Errorf("DPE reported a %d-byte cert, which was larger than 2048", respStruct.CertificateSize)
+	certificate, err := readTrailingBuffer(r, respStruct.CertificateSize, 2048)
+	if err != nil {
+		return nil, err
 	}
 
 	return &CertifyKeyResp[CurveParameter, Digest]{
 		NewContextHandle:  respStruct.NewContextHandle,
 		DerivedPublicKeyX: respStruct.DerivedPublicKeyX,
 		DerivedPublicKeyY: respStruct.DerivedPublicKeyY,
-		Certificate:       respStruct.Certificate[:respStruct.CertificateSize],
+		Certificate:       certificate,
 	}, nil
 }
 
@@ -7,6 +7,7 @@ import (
 	"encoding/binary"
 	"errors"
 	"fmt"
+	"io"
 )
 
 // checkRespHdr checks that the response header has all expected values and did not indicate an error.
@@ -24,6 +25,21 @@ func checkRespHdr(hdr RespHdr) error {
 // execCommand executes the command. It returns the response header in the case of success, for internal use (i.e., GetProfile).
 // cmd must be a struct of fixed-size values (or pointer to such), and rsp must be a pointer to such a struct.
 func execCommand(t Transport, code CommandCode, profile Profile, cmd any, rsp any) (*RespHdr, error) {
+	respHdr, r, err := sendCommand(t, code, profile, cmd)
+	if err != nil {
+		return nil, err
+	}
+
+	if err = binary.Read(r, binary.LittleEndian, rsp); err != nil {
+		return nil, err
+	}
+
+	return respHdr, nil
+}
+
+// sendCommand sends the command and checks the response header. It returns a reader over the rest of the response,
+// for commands whose response ends in a variable-length buffer.
+func sendCommand(t Transport, code CommandCode, profile Profile, cmd any) (*RespHdr, *bytes.Reader, error) {
 	hdr := CommandHdr{
 		magic:   CmdMagic,
 		cmd:     code,
@@ -36,22 +52,31 @@ func execCommand(t Transport, code CommandCode, profile Profile, cmd any, rsp an
 
 	resp, err := t.SendCmd(buf.Bytes())
 	if err != nil {
-		return nil, err
+		return nil, nil, err
 	}
 
 	respHdr := RespHdr{}
 
 	r := bytes.NewReader(resp)
 	if err = binary.Read(r, binary.LittleEndian, &respHdr); err != nil {
-		return nil, err
+		return nil, nil, err
 	}
 	if err = checkRespHdr(respHdr); err != nil {
-		return nil, err
+		return nil, nil, err
 	}
 
-	if err = binary.Read(r, binary.LittleEndian, rsp); err != nil {
+	return &respHdr, r, nil
+}
+
+// readTrailingBuffer reads a variable-length buffer of size bytes from the end of a response. Responses are cut short
+// after the used part of their trailing buffer, so it must not read more than size bytes.
+func readTrailingBuffer(r *bytes.Reader, size uint32, maxSize uint32) ([]byte, error) {
+	if size > maxSize {
+		return nil, fmt.Errorf("DPE reported a %d-byte buffer, which was larger than %d", size, maxSize)
+	}
+	out := make([]byte, size)
+	if _, err := io.ReadFull(r, out); err != nil {
 		return nil, err
 	}
-
-	return &respHdr, nil
+	return out, nil
 }